@@ -1,3 +1,7 @@
+// the wasm front-end build is disabled (see main), but kept for when
+// it is fixed
+#![allow(dead_code)]
+
 use std::env;
 use std::fs::read_to_string;
 use std::path::Path;
@@ -6,7 +10,6 @@ use std::process::Command;
 use anyhow::{anyhow, ensure, Context, Result};
 use ignore::Walk;
 use serde_derive::Deserialize;
-use toml;
 use tr::tr;
 
 fn main() {
@@ -34,7 +37,7 @@ fn read_toml_config() -> Result<BuildConfig> {
 /// Runs the command `wasm-pack build --target web --out-dir
 /// ../public/js/gui`
 fn build_wasm_frontend() -> Result<()> {
-    let config = read_toml_config()?;
+    let _config = read_toml_config()?;
     println!("cargo:rerun-if-changed=Build.toml");
     ensure_gui_watch_rerun()?;
     build_wasm()?;
@@ -87,7 +90,7 @@ fn build_wasm() -> Result<()> {
 
     let mut wasm_pack = Command::new("wasm-pack");
     wasm_pack.current_dir("./gui");
-    wasm_pack.args(&["build", "--target", "web", "--out-dir", "../public/js/gui"]);
+    wasm_pack.args(["build", "--target", "web", "--out-dir", "../public/js/gui"]);
 
     if profile == "debug" {
         wasm_pack.arg("--dev");
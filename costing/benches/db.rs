@@ -0,0 +1,123 @@
+//! Benchmarks for reading and writing large [Tab]s with each of the
+//! available [DatabaseValueEncoding]s.
+
+use chrono::NaiveDate;
+use commodity::{Commodity, CommodityType};
+use costing::db::{DatabaseValueEncoding, DatabaseValueRead, DatabaseValueWrite, KeyValueDBStore};
+use costing::{Expense, Tab, User};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use kvdb::KeyValueDB;
+use rust_decimal::Decimal;
+use std::rc::Rc;
+use uuid::Uuid;
+
+struct BenchStore(DatabaseValueEncoding);
+
+impl KeyValueDBStore for BenchStore {
+    fn name(&self) -> &str {
+        "Bench"
+    }
+    fn db_col(&self) -> u32 {
+        0
+    }
+    fn n_db_cols() -> u32 {
+        1
+    }
+    fn encoding(&self) -> DatabaseValueEncoding {
+        self.0
+    }
+}
+
+const ENCODINGS: [DatabaseValueEncoding; 2] =
+    [DatabaseValueEncoding::Json, DatabaseValueEncoding::Cbor];
+
+/// Create a tab with 10 users, and `n_expenses` expenses shared
+/// between them.
+fn create_large_tab(n_expenses: usize) -> Tab {
+    let aud = CommodityType::from_currency_alpha3("AUD").unwrap();
+    let users: Vec<Rc<User>> = (0..10)
+        .map(|id| Rc::new(User::new(id, &format!("User {}", id), None)))
+        .collect();
+    let user_ids: Vec<i32> = users.iter().map(|user| user.id).collect();
+
+    let expenses = (0..n_expenses)
+        .map(|id| {
+            Expense::new(
+                id as i32,
+                format!("Expense {}", id),
+                "Food",
+                NaiveDate::from_ymd(2020, 1, 1),
+                user_ids[id % user_ids.len()],
+                user_ids.clone(),
+                Commodity::new(Decimal::new(id as i64 * 100 + 1, 2), aud.id),
+                None,
+            )
+        })
+        .collect();
+
+    Tab::new(
+        Uuid::parse_str("936DA01F9ABD4d9d80C702AF85C822A8").unwrap(),
+        "Large Tab",
+        aud.id,
+        users,
+        expenses,
+    )
+}
+
+fn write_tab(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write_tab");
+    for n_expenses in [100, 10_000].iter() {
+        let tab = create_large_tab(*n_expenses);
+        group.throughput(Throughput::Elements(*n_expenses as u64));
+
+        for encoding in ENCODINGS.iter() {
+            let store = BenchStore(*encoding);
+            let database = kvdb_memorydb::create(BenchStore::n_db_cols());
+
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", encoding), n_expenses),
+                &tab,
+                |b, tab| {
+                    b.iter(|| {
+                        let mut transaction = database.transaction();
+                        tab.write_to_db(None, &mut transaction, &store).unwrap();
+                        database.write(transaction).unwrap();
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn read_tab(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read_tab");
+    for n_expenses in [100, 10_000].iter() {
+        let tab = create_large_tab(*n_expenses);
+        group.throughput(Throughput::Elements(*n_expenses as u64));
+
+        for encoding in ENCODINGS.iter() {
+            let store = BenchStore(*encoding);
+            let database = kvdb_memorydb::create(BenchStore::n_db_cols());
+            let mut transaction = database.transaction();
+            tab.write_to_db(None, &mut transaction, &store).unwrap();
+            database.write(transaction).unwrap();
+
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", encoding), n_expenses),
+                &tab.id,
+                |b, id| {
+                    b.iter(|| {
+                        Tab::read_from_db(id, None, &database, &store)
+                            .unwrap()
+                            .unwrap()
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, write_tab, read_tab);
+criterion_main!(benches);
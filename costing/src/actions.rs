@@ -1,9 +1,11 @@
 use crate::error::CostingError;
 use crate::expense::{Expense, ExpenseID};
+use crate::settlement::Settlement;
 use crate::tab::Tab;
 use crate::user::{User, UserID};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt;
 use std::hash::Hash;
 
@@ -11,6 +13,127 @@ use std::hash::Hash;
 pub enum TabUserActionType {
     AddExpense(AddExpense),
     AddUser(AddUser),
+    RemoveExpense(RemoveExpense),
+    EditExpense(EditExpense),
+    ChangeTabName(ChangeTabName),
+    RemoveUser(RemoveUser),
+    ClosePeriod(ClosePeriod),
+    RecordSettlement(RecordSettlement),
+}
+
+impl TabUserActionType {
+    /// The [TabUserAction] wrapped by this value.
+    pub fn action(&self) -> &dyn TabUserAction {
+        match self {
+            TabUserActionType::AddExpense(action) => action,
+            TabUserActionType::AddUser(action) => action,
+            TabUserActionType::RemoveExpense(action) => action,
+            TabUserActionType::EditExpense(action) => action,
+            TabUserActionType::ChangeTabName(action) => action,
+            TabUserActionType::RemoveUser(action) => action,
+            TabUserActionType::ClosePeriod(action) => action,
+            TabUserActionType::RecordSettlement(action) => action,
+        }
+    }
+}
+
+impl TabUserAction for TabUserActionType {
+    fn metadata(&self) -> &TabUserActionMetadata {
+        self.action().metadata()
+    }
+    fn perform(&self, tab: &mut Tab) -> Result<(), CostingError> {
+        self.action().perform(tab)
+    }
+}
+
+/// The ids of the expenses and users added by actions which were
+/// recorded offline, and were reassigned when the actions were merged
+/// into a [Tab](Tab), because another client had already used them.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct IdRemapping {
+    /// The reassigned [ExpenseID](ExpenseID)s, by the recorded id.
+    #[serde(default)]
+    pub expenses: HashMap<ExpenseID, ExpenseID>,
+    /// The reassigned [UserID](UserID)s, by the recorded id.
+    #[serde(default)]
+    pub users: HashMap<UserID, UserID>,
+}
+
+impl IdRemapping {
+    /// Whether no ids have been reassigned.
+    pub fn is_empty(&self) -> bool {
+        self.expenses.is_empty() && self.users.is_empty()
+    }
+
+    /// Prepare an `action` recorded offline to be performed on the
+    /// `tab`. The ids which were already reassigned are replaced, and
+    /// if the action adds an expense or user with an id which is
+    /// already used on the `tab`, it is reassigned the tab's next id.
+    pub fn remap(&mut self, tab: &Tab, action: TabUserActionType) -> TabUserActionType {
+        let mut action = self.replace(action);
+        let expense_exists = |id: ExpenseID| {
+            tab.closed_periods
+                .iter()
+                .flat_map(|period| period.expenses.iter())
+                .chain(tab.expenses.iter())
+                .any(|expense| expense.id == id)
+        };
+
+        match &mut action {
+            TabUserActionType::AddExpense(action) if expense_exists(action.expense.id) => {
+                let id = tab.next_expense_id();
+                self.expenses.insert(action.expense.id, id);
+                action.expense.id = id;
+            }
+            TabUserActionType::RecordSettlement(action) if expense_exists(action.expense_id) => {
+                let id = tab.next_expense_id();
+                self.expenses.insert(action.expense_id, id);
+                action.expense_id = id;
+            }
+            TabUserActionType::AddUser(action) if tab.user(&action.user_to_add.id).is_ok() => {
+                let id = tab.next_user_id();
+                self.users.insert(action.user_to_add.id, id);
+                action.user_to_add.id = id;
+            }
+            _ => {}
+        }
+        action
+    }
+
+    /// Replace the ids referred to by the `action` which have been
+    /// reassigned.
+    pub fn replace(&self, mut action: TabUserActionType) -> TabUserActionType {
+        let expense_id = |id: &mut ExpenseID| {
+            if let Some(remapped) = self.expenses.get(id) {
+                *id = *remapped;
+            }
+        };
+        let user_id = |id: &mut UserID| {
+            if let Some(remapped) = self.users.get(id) {
+                *id = *remapped;
+            }
+        };
+        let expense = |expense: &mut Expense| {
+            expense_id(&mut expense.id);
+            user_id(&mut expense.paid_by);
+            expense.shared_by.iter_mut().for_each(user_id);
+        };
+
+        match &mut action {
+            TabUserActionType::AddExpense(action) => expense(&mut action.expense),
+            TabUserActionType::EditExpense(action) => expense(&mut action.expense),
+            TabUserActionType::RemoveExpense(action) => expense_id(&mut action.expense_id),
+            TabUserActionType::AddUser(action) => user_id(&mut action.user_to_add.id),
+            TabUserActionType::RemoveUser(action) => user_id(&mut action.user_id),
+            TabUserActionType::RecordSettlement(action) => {
+                expense_id(&mut action.expense_id);
+                user_id(&mut action.settlement.sender);
+                user_id(&mut action.settlement.receiver);
+            }
+            TabUserActionType::ChangeTabName(_) | TabUserActionType::ClosePeriod(_) => {}
+        }
+        action
+    }
 }
 
 /// Represents an action that a [User](crate::user::User) can perform to modify a [Tab](Tab).
@@ -63,9 +186,16 @@ impl TabUserAction for AddExpense {
         &self.metadata
     }
     fn perform(&self, tab: &mut Tab) -> Result<(), CostingError> {
+        if let Some(start) = tab.current_period_start() {
+            if self.expense.date < start {
+                return Err(CostingError::ExpenseInClosedPeriod(self.expense.id, tab.id));
+            }
+        }
+
         match tab.expenses.iter().find(|e| e.id == self.expense.id) {
             Some(expense) => Err(CostingError::ExpenseAlreadyExistsOnTab(expense.id, tab.id)),
             None => {
+                tab.add_expense_category_account(&self.expense);
                 tab.expenses.push(self.expense.clone());
                 Ok(())
             }
@@ -73,7 +203,50 @@ impl TabUserAction for AddExpense {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EditExpense {
+    /// Metadata about this action.
+    pub metadata: TabUserActionMetadata,
+    /// The new version of the expense, replacing the existing expense
+    /// on the [Tab](Tab) with the same id.
+    pub expense: Expense,
+}
+
+impl EditExpense {
+    pub fn new(action_user_id: UserID, expense: Expense) -> EditExpense {
+        EditExpense {
+            metadata: TabUserActionMetadata::new(action_user_id, Utc::now()),
+            expense,
+        }
+    }
+}
+
+impl TabUserAction for EditExpense {
+    fn metadata(&self) -> &TabUserActionMetadata {
+        &self.metadata
+    }
+    fn perform(&self, tab: &mut Tab) -> Result<(), CostingError> {
+        if let Some(start) = tab.current_period_start() {
+            if self.expense.date < start {
+                return Err(CostingError::ExpenseInClosedPeriod(self.expense.id, tab.id));
+            }
+        }
+
+        match tab.expenses.iter().position(|e| e.id == self.expense.id) {
+            Some(i) => {
+                tab.add_expense_category_account(&self.expense);
+                tab.expenses[i] = self.expense.clone();
+                Ok(())
+            }
+            None => Err(CostingError::ExpenseDoesNotExistOnTab(
+                self.expense.id,
+                tab.id,
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RemoveExpense {
     /// Metadata about this action.
     pub metadata: TabUserActionMetadata,
@@ -101,14 +274,14 @@ impl TabUserAction for RemoveExpense {
             }
         }
 
-        return Err(CostingError::ExpenseDoesNotExistOnTab(
+        Err(CostingError::ExpenseDoesNotExistOnTab(
             self.expense_id,
             tab.id,
-        ));
+        ))
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChangeTabName {
     /// Metadata about this action.
     pub metadata: TabUserActionMetadata,
@@ -160,7 +333,7 @@ impl TabUserAction for AddUser {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RemoveUser {
     /// Metadata about this action.
     pub metadata: TabUserActionMetadata,
@@ -186,10 +359,104 @@ impl TabUserAction for RemoveUser {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClosePeriod {
+    /// Metadata about this action.
+    pub metadata: TabUserActionMetadata,
+    /// Expenses which occurred before this date will be archived in
+    /// the closed period.
+    pub end: NaiveDate,
+}
+
+impl ClosePeriod {
+    pub fn new(action_user_id: UserID, end: NaiveDate) -> ClosePeriod {
+        ClosePeriod {
+            metadata: TabUserActionMetadata::new(action_user_id, Utc::now()),
+            end,
+        }
+    }
+}
+
+impl TabUserAction for ClosePeriod {
+    fn metadata(&self) -> &TabUserActionMetadata {
+        &self.metadata
+    }
+    fn perform(&self, tab: &mut Tab) -> Result<(), CostingError> {
+        tab.close_period(self.end).map(|_| ())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecordSettlement {
+    /// Metadata about this action.
+    pub metadata: TabUserActionMetadata,
+    /// The id of the [Expense](Expense) used to record the settlement.
+    pub expense_id: ExpenseID,
+    /// The date that the settlement was paid.
+    pub date: NaiveDate,
+    /// The settlement that was paid.
+    pub settlement: Settlement,
+}
+
+impl RecordSettlement {
+    /// The category of the expenses used to record settlements.
+    pub const CATEGORY: &'static str = "Settlement";
+
+    pub fn new(
+        action_user_id: UserID,
+        expense_id: ExpenseID,
+        date: NaiveDate,
+        settlement: Settlement,
+    ) -> RecordSettlement {
+        RecordSettlement {
+            metadata: TabUserActionMetadata::new(action_user_id, Utc::now()),
+            expense_id,
+            date,
+            settlement,
+        }
+    }
+
+    /// The settlement is recorded as an expense paid by the `sender`
+    /// which is entirely shared by the `receiver`.
+    pub fn to_expense(&self) -> Expense {
+        Expense::new(
+            self.expense_id,
+            "Settlement",
+            RecordSettlement::CATEGORY,
+            self.date,
+            self.settlement.sender,
+            vec![self.settlement.receiver],
+            self.settlement.amount,
+            None,
+        )
+    }
+}
+
+impl TabUserAction for RecordSettlement {
+    fn metadata(&self) -> &TabUserActionMetadata {
+        &self.metadata
+    }
+    fn perform(&self, tab: &mut Tab) -> Result<(), CostingError> {
+        tab.user(&self.settlement.sender)?;
+        tab.user(&self.settlement.receiver)?;
+
+        AddExpense {
+            metadata: self.metadata.clone(),
+            expense: self.to_expense(),
+        }
+        .perform(tab)
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
-    use super::{AddExpense, AddUser, ChangeTabName, RemoveExpense, RemoveUser, TabUserAction};
+    use super::{
+        AddExpense, AddUser, ChangeTabName, ClosePeriod, EditExpense, IdRemapping,
+        RecordSettlement, RemoveExpense, RemoveUser, TabUserAction, TabUserActionType,
+    };
+    use crate::error::CostingError;
     use crate::expense::{Expense, ExpenseCategory, ExpenseID};
+    use crate::settlement::Settlement;
     use crate::tab::Tab;
     use crate::user::{User, UserID};
     use chrono::NaiveDate;
@@ -228,7 +495,7 @@ pub mod tests {
             id,
             description,
             category,
-            NaiveDate::from_ymd(2020, 05, 01),
+            NaiveDate::from_ymd(2020, 5, 1),
             paid_by,
             shared_by,
             Commodity::new(Decimal::new(1, 0), create_test_commodity()),
@@ -248,7 +515,7 @@ pub mod tests {
         action.perform(&mut tab).unwrap();
 
         assert_eq!(1, tab.users().len());
-        assert_eq!(1, tab.users().get(0).unwrap().id);
+        assert_eq!(1, tab.users().first().unwrap().id);
     }
 
     #[test]
@@ -268,6 +535,35 @@ pub mod tests {
         assert_eq!(0, tab.users().len());
     }
 
+    #[test]
+    fn remove_user_with_expenses() {
+        let mut tab = create_test_tab();
+
+        let user0 = create_test_user(0, "User 0");
+        let user1 = create_test_user(1, "User 1");
+        tab.add_user((*user0).clone()).unwrap();
+        tab.add_user((*user1).clone()).unwrap();
+
+        let expense = create_test_expense(0, "General".to_string(), user0.id, vec![user1.id]);
+        AddExpense::new(user0.id, expense)
+            .perform(&mut tab)
+            .unwrap();
+
+        let action = RemoveUser::new(user0.id, user1.id);
+        assert!(matches!(
+            action.perform(&mut tab),
+            Err(CostingError::UserHasExpensesOnTab(1, _))
+        ));
+        assert_eq!(2, tab.users().len());
+        assert_eq!(2, tab.next_user_id());
+
+        // the expense still prevents removing the user after its
+        // period has been closed
+        let end = tab.expenses[0].date.succ();
+        tab.close_period(end).unwrap();
+        assert!(action.perform(&mut tab).is_err());
+    }
+
     #[test]
     fn add_expense() {
         let mut tab = create_test_tab();
@@ -286,7 +582,90 @@ pub mod tests {
         assert_eq!(0, tab.expenses.len());
         action.perform(&mut tab).unwrap();
         assert_eq!(1, tab.expenses.len());
-        assert_eq!(0, tab.expenses.get(0).unwrap().id);
+        assert_eq!(0, tab.expenses.first().unwrap().id);
+    }
+
+    #[test]
+    fn edit_expense() {
+        let mut tab = create_test_tab();
+
+        let user0 = create_test_user(0, "User 0");
+        let user1 = create_test_user(1, "User 1");
+
+        tab.add_user((*user0).clone()).unwrap();
+        tab.add_user((*user1).clone()).unwrap();
+
+        let expense =
+            create_test_expense(0, "General".to_string(), user0.id, vec![user0.id, user1.id]);
+        AddExpense::new(user0.id, expense.clone())
+            .perform(&mut tab)
+            .unwrap();
+
+        let mut edited = expense;
+        edited.category = "Food".to_string();
+        edited.shared_by = vec![user1.id];
+        EditExpense::new(user1.id, edited)
+            .perform(&mut tab)
+            .unwrap();
+
+        assert_eq!(1, tab.expenses.len());
+        assert_eq!("Food", tab.expenses.first().unwrap().category);
+        assert_eq!(vec![user1.id], tab.expenses.first().unwrap().shared_by);
+        // the account for the new category is available for balancing
+        assert!(tab.user_balances(NaiveDate::from_ymd(2020, 6, 1)).is_ok());
+
+        let missing =
+            create_test_expense(1, "General".to_string(), user0.id, vec![user0.id, user1.id]);
+        assert!(EditExpense::new(user0.id, missing)
+            .perform(&mut tab)
+            .is_err());
+    }
+
+    #[test]
+    fn record_settlement() {
+        let user0 = create_test_user(0, "User 0");
+        let user1 = create_test_user(1, "User 1");
+
+        let expense =
+            create_test_expense(0, "General".to_string(), user0.id, vec![user0.id, user1.id]);
+
+        let mut tab = Tab::new(
+            Uuid::parse_str("936DA01F9ABD4d9d80C702AF85C822A8").unwrap(),
+            "Test Tab",
+            create_test_commodity(),
+            vec![user0.clone(), user1.clone()],
+            vec![expense],
+        );
+
+        let settlement = Settlement::new(
+            user1.id,
+            user0.id,
+            Commodity::new(Decimal::new(5, 1), create_test_commodity()),
+        );
+        let action = TabUserActionType::RecordSettlement(RecordSettlement::new(
+            user1.id,
+            1,
+            NaiveDate::from_ymd(2020, 5, 2),
+            settlement,
+        ));
+        action.perform(&mut tab).unwrap();
+
+        assert_eq!(2, tab.expenses.len());
+        let balances = tab.user_balances(NaiveDate::from_ymd(2020, 6, 1)).unwrap();
+        assert_eq!(Decimal::new(0, 0), balances.get(&user0.id).unwrap().value);
+        assert_eq!(Decimal::new(0, 0), balances.get(&user1.id).unwrap().value);
+
+        // settlements can only be recorded between users on the tab
+        let settlement = Settlement::new(
+            user1.id,
+            2,
+            Commodity::new(Decimal::new(5, 1), create_test_commodity()),
+        );
+        assert!(
+            RecordSettlement::new(user1.id, 2, NaiveDate::from_ymd(2020, 5, 2), settlement)
+                .perform(&mut tab)
+                .is_err()
+        );
     }
 
     #[test]
@@ -319,4 +698,82 @@ pub mod tests {
         action.perform(&mut tab).unwrap();
         assert_eq!("New Name", tab.name);
     }
+
+    #[test]
+    fn close_period() {
+        let user0 = create_test_user(0, "User 0");
+        let user1 = create_test_user(1, "User 1");
+
+        let expense =
+            create_test_expense(0, "General".to_string(), user0.id, vec![user0.id, user1.id]);
+
+        let mut tab = Tab::new(
+            Uuid::parse_str("936DA01F9ABD4d9d80C702AF85C822A8").unwrap(),
+            "Test Tab",
+            create_test_commodity(),
+            vec![user0.clone(), user1.clone()],
+            vec![expense],
+        );
+
+        let action = ClosePeriod::new(user0.id, NaiveDate::from_ymd(2020, 6, 1));
+        action.perform(&mut tab).unwrap();
+
+        assert_eq!(0, tab.expenses.len());
+        assert_eq!(1, tab.closed_periods.len());
+        assert_eq!(1, tab.closed_periods.first().unwrap().expenses.len());
+
+        // expenses can no longer be added to the closed period
+        let expense =
+            create_test_expense(1, "General".to_string(), user0.id, vec![user0.id, user1.id]);
+        assert!(AddExpense::new(user0.id, expense)
+            .perform(&mut tab)
+            .is_err());
+    }
+
+    #[test]
+    fn remap_ids() {
+        let mut tab = create_test_tab();
+        let user0 = create_test_user(0, "User 0");
+        let user1 = create_test_user(1, "User 1");
+        tab.add_user((*user0).clone()).unwrap();
+        tab.add_user((*user1).clone()).unwrap();
+
+        // another client has already added an expense with the id 0
+        let expense = create_test_expense(0, "General".to_string(), user0.id, vec![user0.id]);
+        AddExpense::new(user0.id, expense)
+            .perform(&mut tab)
+            .unwrap();
+
+        // while this one added a user and an expense with the same ids
+        let offline_user = create_test_user(1, "User 2");
+        let offline_expense =
+            create_test_expense(0, "General".to_string(), user0.id, vec![offline_user.id]);
+        let actions = vec![
+            TabUserActionType::AddUser(AddUser::new(user0.id, (*offline_user).clone())),
+            TabUserActionType::AddExpense(AddExpense::new(user0.id, offline_expense)),
+            TabUserActionType::RemoveExpense(RemoveExpense::new(user0.id, 0)),
+        ];
+
+        let mut remapping = IdRemapping::default();
+        let mut remapped = Vec::new();
+        for action in actions {
+            let action = remapping.remap(&tab, action);
+            action.perform(&mut tab).unwrap();
+            remapped.push(action);
+        }
+
+        assert_eq!(Some(&2), remapping.users.get(&1));
+        assert_eq!(Some(&1), remapping.expenses.get(&0));
+        assert_eq!("User 2", tab.user(&2).unwrap().name);
+        match remapped.get(1).unwrap() {
+            TabUserActionType::AddExpense(action) => {
+                assert_eq!(1, action.expense.id);
+                assert_eq!(vec![2], action.expense.shared_by);
+            }
+            action => panic!("unexpected action {:?}", action),
+        }
+        // the expense added by the other client is left unchanged
+        assert_eq!(1, tab.expenses.len());
+        assert_eq!(0, tab.expenses.first().unwrap().id);
+    }
 }
@@ -1,6 +1,87 @@
 use kvdb::{DBTransaction, KeyValueDB};
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{io, rc::Rc};
+use thiserror::Error;
+
+pub mod tabs;
+
+/// An error which occurs while reading or writing values in a
+/// [KeyValueDB].
+#[derive(Error, Debug)]
+pub enum DatabaseError {
+    #[error("the item with key {0} is missing from the database")]
+    MissingItem(String),
+    #[error("the value stored with key {0} is corrupt")]
+    CorruptValue(String, #[source] EncodingError),
+    #[error("the value stored with key {0} could not be migrated to the current schema")]
+    Migration(String, #[source] MigrationError),
+    #[error("unable to serialize the value to store with key {0}")]
+    Serialize(String, #[source] EncodingError),
+    #[error("IO error while accessing the database")]
+    IO(#[from] io::Error),
+}
+
+/// An error which occurs while encoding or decoding a value using a
+/// [DatabaseValueEncoding].
+#[derive(Error, Debug)]
+pub enum EncodingError {
+    #[error("error encoding/decoding JSON")]
+    Json(#[from] serde_json::Error),
+    #[error("error encoding/decoding CBOR")]
+    Cbor(#[from] serde_cbor::Error),
+}
+
+/// The first byte of values stored using the
+/// [Cbor](DatabaseValueEncoding::Cbor) encoding. This is never the
+/// first byte of a valid JSON document, which allows values stored
+/// as JSON (including those stored before binary encodings were
+/// introduced) to be read transparently.
+const CBOR_PREFIX: u8 = 0x01;
+
+/// The format used to encode the values stored in a [KeyValueDB].
+///
+/// Values can always be read regardless of the encoding they were
+/// written with, the encoding only selects the format used when
+/// values are written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DatabaseValueEncoding {
+    /// Human readable JSON, which is the most convenient for
+    /// debugging.
+    Json,
+    /// The compact binary [CBOR](https://cbor.io/) format, which is
+    /// smaller and faster to read and write than JSON.
+    Cbor,
+}
+
+impl DatabaseValueEncoding {
+    /// Detect the encoding of a value stored in the database.
+    pub fn detect(bytes: &[u8]) -> DatabaseValueEncoding {
+        match bytes.first() {
+            Some(&CBOR_PREFIX) => DatabaseValueEncoding::Cbor,
+            _ => DatabaseValueEncoding::Json,
+        }
+    }
+
+    /// Encode a value using this encoding.
+    pub fn encode<V: Serialize>(&self, value: &V) -> Result<Vec<u8>, EncodingError> {
+        match self {
+            DatabaseValueEncoding::Json => Ok(serde_json::to_vec(value)?),
+            DatabaseValueEncoding::Cbor => {
+                let mut bytes = vec![CBOR_PREFIX];
+                serde_cbor::to_writer(&mut bytes, value)?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Decode a value, using the encoding that it was written with.
+    pub fn decode<V: DeserializeOwned>(bytes: &[u8]) -> Result<V, EncodingError> {
+        match Self::detect(bytes) {
+            DatabaseValueEncoding::Json => Ok(serde_json::from_slice(bytes)?),
+            DatabaseValueEncoding::Cbor => Ok(serde_cbor::from_slice(&bytes[1..])?),
+        }
+    }
+}
 
 // A value that has an id that can be used in a [KeyValueDB].
 pub trait DatabaseValueID<ID> {
@@ -23,7 +104,7 @@ pub trait DatabaseValueRead<ID, TID>: Sized {
         path: P,
         database: &dyn KeyValueDB,
         db_store: &S,
-    ) -> Option<Self>
+    ) -> Result<Option<Self>, DatabaseError>
     where
         S: KeyValueDBStore,
         P: Into<Option<&'a str>>;
@@ -32,36 +113,50 @@ pub trait DatabaseValueRead<ID, TID>: Sized {
 impl<T, TID> DatabaseValueRead<String, TID> for Vec<T>
 where
     T: DatabaseValueRead<TID, ()>,
-    TID: DeserializeOwned,
+    TID: DeserializeOwned + ToString,
 {
     fn read_from_db<'a, S, P>(
         id: &String,
         path: P,
         database: &dyn KeyValueDB,
         db_store: &S,
-    ) -> Option<Self>
+    ) -> Result<Option<Self>, DatabaseError>
     where
         S: KeyValueDBStore,
         P: Into<Option<&'a str>>,
     {
         let key = match path.into() {
-            Some(path) => format!("{}/{}", path, id.to_string()),
+            Some(path) => format!("{}/{}", path, id),
             None => id.clone(),
         };
 
-        let item_ids_option: Option<Vec<TID>> = database
-            .get_deserialize(db_store, key.clone())
-            .expect("unable to read from database");
+        let item_ids: Vec<TID> = match database.get_deserialize(db_store, key.clone())? {
+            Some(item_ids) => item_ids,
+            None => return Ok(None),
+        };
+
+        // items which are corrupt or missing are skipped (and
+        // logged), so that they don't prevent reading the others.
+        let items = item_ids
+            .iter()
+            .filter_map(|item_id| {
+                match T::read_from_db(item_id, key.as_str(), database, db_store) {
+                    Ok(Some(item)) => Some(item),
+                    Ok(None) => {
+                        let error =
+                            DatabaseError::MissingItem(format!("{}/{}", key, item_id.to_string()));
+                        log::error!("Skipping item in {}: {}", key, error);
+                        None
+                    }
+                    Err(error) => {
+                        log::error!("Skipping item in {}: {}", key, error);
+                        None
+                    }
+                }
+            })
+            .collect();
 
-        item_ids_option.map(|item_ids| {
-            item_ids
-                .iter()
-                .map(|item_id| {
-                    T::read_from_db(item_id, key.as_str(), database, db_store)
-                        .expect("unable to read tab from database")
-                })
-                .collect()
-        })
+        Ok(Some(items))
     }
 }
 
@@ -74,18 +169,23 @@ where
         path: P,
         database: &dyn KeyValueDB,
         db_store: &S,
-    ) -> Option<Self>
+    ) -> Result<Option<Self>, DatabaseError>
     where
         S: KeyValueDBStore,
         P: Into<Option<&'a str>>,
     {
-        T::read_from_db(id, path, database, db_store).map(|v| Rc::new(v))
+        T::read_from_db(id, path, database, db_store).map(|v| v.map(|v| Rc::new(v)))
     }
 }
 
 /// A value that can be written to a [KeyValueDB].
 pub trait DatabaseValueWrite<ID>: DatabaseValueID<ID> {
-    fn write_to_db<'a, TR, S, P>(&self, path: P, transaction: &mut TR, db_store: &S)
+    fn write_to_db<'a, TR, S, P>(
+        &self,
+        path: P,
+        transaction: &mut TR,
+        db_store: &S,
+    ) -> Result<(), DatabaseError>
     where
         TR: DBTransactionSerde,
         S: KeyValueDBStore,
@@ -96,19 +196,30 @@ impl<T, ID> DatabaseValueWrite<ID> for Rc<T>
 where
     T: DatabaseValueWrite<ID>,
 {
-    fn write_to_db<'a, TR, S, P>(&self, path: P, transaction: &mut TR, db_store: &S)
+    fn write_to_db<'a, TR, S, P>(
+        &self,
+        path: P,
+        transaction: &mut TR,
+        db_store: &S,
+    ) -> Result<(), DatabaseError>
     where
         TR: DBTransactionSerde,
         S: KeyValueDBStore,
         P: Into<Option<&'a str>>,
     {
-        (**self).write_to_db(path, transaction, db_store);
+        (**self).write_to_db(path, transaction, db_store)
     }
 }
 
 /// A value which doesn't have its own id, which can be written to a [KeyValueDB].
 pub trait DatabaseValueWriteID<ID, TID> {
-    fn write_to_db_id<'a, T, S, P>(&self, id: &ID, path: P, transaction: &mut T, db_store: &S)
+    fn write_to_db_id<'a, T, S, P>(
+        &self,
+        id: &ID,
+        path: P,
+        transaction: &mut T,
+        db_store: &S,
+    ) -> Result<(), DatabaseError>
     where
         T: DBTransactionSerde,
         S: KeyValueDBStore,
@@ -120,23 +231,332 @@ where
     T: DatabaseValueWrite<TID> + DatabaseValueID<TID> + Serialize,
     TID: Serialize + ToString,
 {
-    fn write_to_db_id<'a, TR, S, P>(&self, id: &String, path: P, transaction: &mut TR, db_store: &S)
+    fn write_to_db_id<'a, TR, S, P>(
+        &self,
+        id: &String,
+        path: P,
+        transaction: &mut TR,
+        db_store: &S,
+    ) -> Result<(), DatabaseError>
     where
         TR: DBTransactionSerde,
         S: KeyValueDBStore,
         P: Into<Option<&'a str>>,
     {
         let key = match path.into() {
-            Some(path) => format!("{}/{}", path, id.to_string()),
+            Some(path) => format!("{}/{}", path, id),
             None => id.to_string(),
         };
 
         let item_ids: Vec<TID> = self.iter().map(|item| item.id()).collect();
 
-        transaction.put_serialize(db_store, key.clone(), item_ids);
+        transaction.put_serialize(db_store, key.clone(), item_ids)?;
 
         for item in self {
-            item.write_to_db(key.as_str(), transaction, db_store);
+            item.write_to_db(key.as_str(), transaction, db_store)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A value that can be deleted from a [KeyValueDB], along with any
+/// child values which are stored beneath it.
+pub trait DatabaseValueDelete<ID, TID> {
+    fn delete_from_db<'a, S, P>(
+        id: &ID,
+        path: P,
+        database: &dyn KeyValueDB,
+        transaction: &mut DBTransaction,
+        db_store: &S,
+    ) -> Result<(), DatabaseError>
+    where
+        S: KeyValueDBStore,
+        P: Into<Option<&'a str>>;
+}
+
+impl<T, TID> DatabaseValueDelete<String, TID> for Vec<T>
+where
+    T: DatabaseValueDelete<TID, ()>,
+    TID: DeserializeOwned,
+{
+    fn delete_from_db<'a, S, P>(
+        id: &String,
+        path: P,
+        database: &dyn KeyValueDB,
+        transaction: &mut DBTransaction,
+        db_store: &S,
+    ) -> Result<(), DatabaseError>
+    where
+        S: KeyValueDBStore,
+        P: Into<Option<&'a str>>,
+    {
+        let key = match path.into() {
+            Some(path) => format!("{}/{}", path, id),
+            None => id.clone(),
+        };
+
+        let item_ids: Vec<TID> = database
+            .get_deserialize(db_store, key.clone())?
+            .unwrap_or_default();
+
+        for item_id in &item_ids {
+            T::delete_from_db(item_id, key.as_str(), database, transaction, db_store)?;
+        }
+
+        transaction.delete(db_store.db_col(), key.as_bytes());
+
+        Ok(())
+    }
+}
+
+impl<T, ID> DatabaseValueDelete<ID, ()> for Rc<T>
+where
+    T: DatabaseValueDelete<ID, ()>,
+{
+    fn delete_from_db<'a, S, P>(
+        id: &ID,
+        path: P,
+        database: &dyn KeyValueDB,
+        transaction: &mut DBTransaction,
+        db_store: &S,
+    ) -> Result<(), DatabaseError>
+    where
+        S: KeyValueDBStore,
+        P: Into<Option<&'a str>>,
+    {
+        T::delete_from_db(id, path, database, transaction, db_store)
+    }
+}
+
+/// Delete the value stored with `key`, and all the values stored with
+/// keys beneath it (starting with `{key}/`).
+pub(crate) fn delete_with_children<S: KeyValueDBStore>(
+    transaction: &mut DBTransaction,
+    db_store: &S,
+    key: &str,
+) {
+    transaction.delete(db_store.db_col(), key.as_bytes());
+    transaction.delete_prefix(db_store.db_col(), format!("{}/", key).as_bytes());
+}
+
+/// The version of the schema used to serialize a value stored in a
+/// [KeyValueDB].
+pub type SchemaVersion = u32;
+
+/// A function which upgrades the json representation of a value from
+/// one [SchemaVersion] to the next.
+pub type Migration = fn(serde_json::Value) -> Result<serde_json::Value, MigrationError>;
+
+#[derive(Error, Debug)]
+pub enum MigrationError {
+    #[error(
+        "the stored value has schema version {0}, which is newer than the current version {1}"
+    )]
+    NewerVersion(SchemaVersion, SchemaVersion),
+    #[error("there is no migration registered from schema version {0}")]
+    MissingMigration(SchemaVersion),
+    #[error("the stored value is not in the expected format for schema version {0}: {1}")]
+    InvalidValue(SchemaVersion, String),
+    #[error("error (de)serializing the stored value")]
+    Serde(#[from] serde_json::Error),
+}
+
+/// A registry of [Migration]s, used to upgrade the stored
+/// representation of a value from an old [SchemaVersion] to the
+/// current version.
+#[derive(Default)]
+pub struct Migrations {
+    /// Migrations, indexed by the version that they upgrade from.
+    migrations: Vec<Option<Migration>>,
+}
+
+impl Migrations {
+    /// Create a new empty [Migrations](Migrations) registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a migration which upgrades a value from schema
+    /// version `from` to `from + 1`.
+    pub fn register(mut self, from: SchemaVersion, migration: Migration) -> Self {
+        let index = from as usize;
+        if self.migrations.len() <= index {
+            self.migrations.resize(index + 1, None);
+        }
+        self.migrations[index] = Some(migration);
+        self
+    }
+
+    /// Upgrade a `value` from schema version `from`, to schema
+    /// version `to`, by applying each of the registered migrations in
+    /// turn.
+    pub fn migrate(
+        &self,
+        mut value: serde_json::Value,
+        from: SchemaVersion,
+        to: SchemaVersion,
+    ) -> Result<serde_json::Value, MigrationError> {
+        if from > to {
+            return Err(MigrationError::NewerVersion(from, to));
+        }
+
+        for version in from..to {
+            let migration = self
+                .migrations
+                .get(version as usize)
+                .copied()
+                .flatten()
+                .ok_or(MigrationError::MissingMigration(version))?;
+            value = migration(value)?;
+        }
+
+        Ok(value)
+    }
+}
+
+/// A value with a versioned schema, which is stored in a [KeyValueDB]
+/// along with its [SchemaVersion], and upgraded using its
+/// [Migrations] when it is read from an older version.
+///
+/// Values which were stored before schema versions were introduced
+/// are read as version `0`.
+///
+/// Values nested inside this value are stored without their own
+/// schema version, so a new version of this schema is needed whenever
+/// the schema of a nested value changes, and the nested values are
+/// upgraded in [migrate_nested()](DatabaseValueSchema::migrate_nested).
+pub trait DatabaseValueSchema: Serialize + DeserializeOwned {
+    /// The current version of this value's schema.
+    const SCHEMA_VERSION: SchemaVersion;
+
+    /// The migrations used to upgrade this value from previous
+    /// versions of its schema.
+    fn migrations() -> Migrations;
+
+    /// Upgrade the values of other [DatabaseValueSchema] types which
+    /// are nested inside the json representation of this value (after
+    /// its own migrations have been applied), where `from` is the
+    /// version of this value's schema that it was stored with. See
+    /// [migrate_nested()].
+    fn migrate_nested(
+        value: serde_json::Value,
+        _from: SchemaVersion,
+    ) -> Result<serde_json::Value, MigrationError> {
+        Ok(value)
+    }
+
+    /// Upgrade the json representation of this value from the
+    /// `from` version of its schema, and deserialize it.
+    fn from_versioned_json(
+        value: serde_json::Value,
+        from: SchemaVersion,
+    ) -> Result<Self, MigrationError> {
+        let mut migrated = Self::migrations().migrate(value, from, Self::SCHEMA_VERSION)?;
+        if from != Self::SCHEMA_VERSION {
+            migrated = Self::migrate_nested(migrated, from)?;
+        }
+        Ok(serde_json::from_value(migrated)?)
+    }
+}
+
+/// Upgrade a `value` of type `V`, which is nested inside a value of
+/// type `C` that was stored with version `from` of its schema.
+/// `versions` lists the version of `V`'s schema which was current
+/// for each version of `C`'s schema. A missing `value` is left for
+/// deserialization to report.
+pub fn migrate_nested<C: DatabaseValueSchema, V: DatabaseValueSchema>(
+    value: Option<&mut serde_json::Value>,
+    versions: &[SchemaVersion],
+    from: SchemaVersion,
+) -> Result<(), MigrationError> {
+    debug_assert_eq!(C::SCHEMA_VERSION as usize + 1, versions.len());
+    debug_assert_eq!(Some(&V::SCHEMA_VERSION), versions.last());
+
+    let nested_from = *versions
+        .get(from as usize)
+        .ok_or(MigrationError::NewerVersion(from, C::SCHEMA_VERSION))?;
+
+    match value {
+        Some(value) if nested_from != V::SCHEMA_VERSION => {
+            let migrated = V::migrations().migrate(value.take(), nested_from, V::SCHEMA_VERSION)?;
+            *value = V::migrate_nested(migrated, nested_from)?;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Upgrade each of the values of type `V` in an array, which is
+/// nested inside a value of type `C`, see [migrate_nested()].
+pub fn migrate_nested_array<C: DatabaseValueSchema, V: DatabaseValueSchema>(
+    values: Option<&mut serde_json::Value>,
+    versions: &[SchemaVersion],
+    from: SchemaVersion,
+) -> Result<(), MigrationError> {
+    match values {
+        Some(serde_json::Value::Array(values)) => values
+            .iter_mut()
+            .try_for_each(|value| migrate_nested::<C, V>(Some(value), versions, from)),
+        Some(serde_json::Value::Null) | None => Ok(()),
+        Some(_) => Err(MigrationError::InvalidValue(
+            from,
+            "nested values are not an array".to_string(),
+        )),
+    }
+}
+
+/// The representation of a [DatabaseValueSchema] value in the database.
+#[derive(Serialize, Deserialize)]
+struct VersionedValue<V> {
+    schema_version: SchemaVersion,
+    value: V,
+}
+
+/// Deserialize a value stored with `key`, which may be stored with
+/// or without a [SchemaVersion], and upgrade it to the current
+/// version.
+fn deserialize_versioned<V: DatabaseValueSchema>(
+    key: &str,
+    bytes: &[u8],
+) -> Result<V, DatabaseError> {
+    let corrupt = |error: EncodingError| DatabaseError::CorruptValue(key.to_string(), error);
+    let migration = |error: MigrationError| DatabaseError::Migration(key.to_string(), error);
+
+    match DatabaseValueEncoding::detect(bytes) {
+        DatabaseValueEncoding::Json => {
+            let json: serde_json::Value = DatabaseValueEncoding::decode(bytes).map_err(corrupt)?;
+
+            let is_versioned = match &json {
+                serde_json::Value::Object(map) => {
+                    map.len() == 2
+                        && map.contains_key("schema_version")
+                        && map.contains_key("value")
+                }
+                _ => false,
+            };
+
+            if is_versioned {
+                let versioned: VersionedValue<serde_json::Value> =
+                    serde_json::from_value(json).map_err(|error| corrupt(error.into()))?;
+                V::from_versioned_json(versioned.value, versioned.schema_version).map_err(migration)
+            } else {
+                V::from_versioned_json(json, 0).map_err(migration)
+            }
+        }
+        // binary encoded values are always stored with a schema version.
+        DatabaseValueEncoding::Cbor => {
+            let versioned: VersionedValue<serde_cbor::Value> =
+                DatabaseValueEncoding::decode(bytes).map_err(corrupt)?;
+
+            if versioned.schema_version == V::SCHEMA_VERSION {
+                serde_cbor::value::from_value(versioned.value)
+                    .map_err(|error| corrupt(error.into()))
+            } else {
+                let json = serde_json::to_value(&versioned.value)
+                    .map_err(|error| migration(error.into()))?;
+                V::from_versioned_json(json, versioned.schema_version).map_err(migration)
+            }
         }
     }
 }
@@ -149,6 +569,10 @@ pub trait KeyValueDBStore {
     fn db_col(&self) -> u32;
     /// The number of database columns.
     fn n_db_cols() -> u32;
+    /// The encoding used for values written to this store.
+    fn encoding(&self) -> DatabaseValueEncoding {
+        DatabaseValueEncoding::Json
+    }
 }
 
 /// A method to get a value (which implements [DeserializeOwned]) from a [KeyValueDB].
@@ -157,7 +581,15 @@ pub trait KeyValueDBSerde {
         &self,
         store: &S,
         key: K,
-    ) -> io::Result<Option<V>>;
+    ) -> Result<Option<V>, DatabaseError>;
+
+    /// Get a value (which implements [DatabaseValueSchema]), upgrading
+    /// it to the current version of its schema.
+    fn get_deserialize_versioned<S: KeyValueDBStore, K: AsRef<str>, V: DatabaseValueSchema>(
+        &self,
+        store: &S,
+        key: K,
+    ) -> Result<Option<V>, DatabaseError>;
 }
 
 /// A method to insert a value (which implements [DeserializeOwned])
@@ -168,7 +600,16 @@ pub trait DBTransactionSerde {
         db_store: &S,
         key: K,
         value: V,
-    );
+    ) -> Result<(), DatabaseError>;
+
+    /// Insert a value (which implements [DatabaseValueSchema]), along
+    /// with the current version of its schema.
+    fn put_serialize_versioned<S: KeyValueDBStore, K: AsRef<str>, V: DatabaseValueSchema>(
+        &mut self,
+        db_store: &S,
+        key: K,
+        value: &V,
+    ) -> Result<(), DatabaseError>;
 }
 
 impl KeyValueDBSerde for &dyn KeyValueDB {
@@ -176,14 +617,26 @@ impl KeyValueDBSerde for &dyn KeyValueDB {
         &self,
         db_store: &S,
         key: K,
-    ) -> io::Result<Option<V>> {
-        self.get(db_store.db_col(), key.as_ref().as_bytes())
-            .map(|value_option| {
-                value_option.map(|value_bytes| {
-                    serde_json::from_slice(&value_bytes)
-                        .expect("unable to desrialize database value")
-                })
-            })
+    ) -> Result<Option<V>, DatabaseError> {
+        let key = key.as_ref();
+        match self.get(db_store.db_col(), key.as_bytes())? {
+            Some(value_bytes) => DatabaseValueEncoding::decode(&value_bytes)
+                .map(Some)
+                .map_err(|error| DatabaseError::CorruptValue(key.to_string(), error)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_deserialize_versioned<S: KeyValueDBStore, K: AsRef<str>, V: DatabaseValueSchema>(
+        &self,
+        db_store: &S,
+        key: K,
+    ) -> Result<Option<V>, DatabaseError> {
+        let key = key.as_ref();
+        match self.get(db_store.db_col(), key.as_bytes())? {
+            Some(value_bytes) => deserialize_versioned(key, &value_bytes).map(Some),
+            None => Ok(None),
+        }
     }
 }
 
@@ -193,14 +646,444 @@ impl DBTransactionSerde for DBTransaction {
         store: &S,
         key: K,
         value: V,
-    ) {
-        let value_string =
-            serde_json::to_string(&value).expect("unable to serialize database value");
-
-        self.put(
-            store.db_col(),
-            key.as_ref().as_bytes(),
-            value_string.as_bytes(),
+    ) -> Result<(), DatabaseError> {
+        let key = key.as_ref();
+        let value_bytes = store
+            .encoding()
+            .encode(&value)
+            .map_err(|error| DatabaseError::Serialize(key.to_string(), error))?;
+
+        self.put(store.db_col(), key.as_bytes(), &value_bytes);
+        Ok(())
+    }
+
+    fn put_serialize_versioned<S: KeyValueDBStore, K: AsRef<str>, V: DatabaseValueSchema>(
+        &mut self,
+        store: &S,
+        key: K,
+        value: &V,
+    ) -> Result<(), DatabaseError> {
+        self.put_serialize(
+            store,
+            key,
+            VersionedValue {
+                schema_version: V::SCHEMA_VERSION,
+                value,
+            },
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        migrate_nested_array, tabs::TabStorage, DBTransactionSerde, DatabaseError,
+        DatabaseValueDelete, DatabaseValueEncoding, DatabaseValueRead, DatabaseValueSchema,
+        DatabaseValueWrite, DatabaseValueWriteID, KeyValueDBSerde, KeyValueDBStore, MigrationError,
+        Migrations, SchemaVersion,
+    };
+    use crate::{Expense, Tab, TabData, User};
+    use chrono::NaiveDate;
+    use commodity::Commodity;
+    use kvdb::KeyValueDB;
+    use serde::{Deserialize, Serialize};
+    use std::str::FromStr;
+    use uuid::Uuid;
+
+    struct TestStore;
+
+    impl KeyValueDBStore for TestStore {
+        fn name(&self) -> &str {
+            "Test"
+        }
+        fn db_col(&self) -> u32 {
+            0
+        }
+        fn n_db_cols() -> u32 {
+            1
+        }
+    }
+
+    struct TestCborStore;
+
+    impl KeyValueDBStore for TestCborStore {
+        fn name(&self) -> &str {
+            "TestCbor"
+        }
+        fn db_col(&self) -> u32 {
+            0
+        }
+        fn n_db_cols() -> u32 {
+            1
+        }
+        fn encoding(&self) -> DatabaseValueEncoding {
+            DatabaseValueEncoding::Cbor
+        }
+    }
+
+    fn create_test_database(key: &str, value: &str) -> kvdb_memorydb::InMemory {
+        let database = kvdb_memorydb::create(TestStore::n_db_cols());
+        let mut transaction = database.transaction();
+        transaction.put(TestStore.db_col(), key.as_bytes(), value.as_bytes());
+        database.write(transaction).unwrap();
+        database
+    }
+
+    fn test_tab_id() -> Uuid {
+        Uuid::parse_str("936DA01F9ABD4d9d80C702AF85C822A8").unwrap()
+    }
+
+    #[test]
+    fn read_tab_data_v0() {
+        let id = test_tab_id();
+        let database = create_test_database(
+            &id.to_string(),
+            include_str!("../fixtures/db/tab_data_v0.json"),
+        );
+
+        let tab = Tab::read_from_db(&id, None, &database, &TestStore)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!("Road Trip", tab.name);
+        assert_eq!(2, tab.users.len());
+        assert_eq!(1, tab.expenses.len());
+        assert_eq!(1, tab.user_actions.len());
+        assert!(tab.closed_periods.is_empty());
+    }
+
+    #[test]
+    fn read_tab_data_v1() {
+        let id = test_tab_id();
+        let database = create_test_database(
+            &id.to_string(),
+            include_str!("../fixtures/db/tab_data_v1.json"),
+        );
+
+        let tab = Tab::read_from_db(&id, None, &database, &TestStore)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!("Road Trip", tab.name);
+        assert_eq!(1, tab.expenses.len());
+        assert_eq!(1, tab.closed_periods.len());
+
+        let period = tab.closed_periods.first().unwrap();
+        assert_eq!(NaiveDate::from_ymd(2020, 3, 1), period.end);
+        assert_eq!(1, period.expenses.len());
+        assert_eq!(
+            Commodity::from_str("-150.0 AUD").unwrap(),
+            *period.closing_balances.get(&2).unwrap()
+        );
+    }
+
+    #[test]
+    fn deserialize_tab_data_without_closed_periods() {
+        // clients may still send the TabData shape from before
+        // closed periods were introduced, which isn't migrated.
+        let tab_data: TabData =
+            serde_json::from_str(include_str!("../fixtures/db/tab_data_v0.json")).unwrap();
+
+        assert_eq!("Road Trip", tab_data.name);
+        assert!(tab_data.closed_periods.is_empty());
+    }
+
+    #[test]
+    fn read_expense_v0() {
+        let database =
+            create_test_database("expense", include_str!("../fixtures/db/expense_v0.json"));
+        let database: &dyn KeyValueDB = &database;
+
+        let expense: Expense = database
+            .get_deserialize_versioned(&TestStore, "expense")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(1, expense.id);
+        assert_eq!("Petrol", expense.description);
+        assert_eq!(vec![1, 2], expense.shared_by);
+    }
+
+    #[test]
+    fn read_user_v0() {
+        let database = create_test_database("user", include_str!("../fixtures/db/user_v0.json"));
+        let database: &dyn KeyValueDB = &database;
+
+        let user: User = database
+            .get_deserialize_versioned(&TestStore, "user")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(1, user.id);
+        assert_eq!(Some("user1@example.com".to_string()), user.email);
+    }
+
+    #[test]
+    fn read_tab_header_v0() {
+        let id = test_tab_id();
+        let database = create_test_database(
+            &format!("tabs/{}/header", id),
+            include_str!("../fixtures/db/tab_header_v0.json"),
+        );
+
+        let tab = TabStorage::new("tabs", &TestStore)
+            .read_tab(&database, &id)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!("Road Trip", tab.name);
+        assert_eq!(2, tab.users.len());
+        assert!(tab.expenses.is_empty());
+        assert_eq!(1, tab.closed_periods.len());
+        let expense = &tab.closed_periods[0].expenses[0];
+        assert_eq!("Petrol", expense.description);
+        assert_eq!(vec![1, 2], expense.shared_by);
+    }
+
+    /// A value nested in [TestContainer], where version `1` of its
+    /// schema renamed `title` to `name`.
+    #[derive(Serialize, Deserialize)]
+    struct TestItem {
+        name: String,
+    }
+
+    impl DatabaseValueSchema for TestItem {
+        const SCHEMA_VERSION: SchemaVersion = 1;
+
+        fn migrations() -> Migrations {
+            Migrations::new().register(0, |mut value| {
+                let title = value["title"].take();
+                value["name"] = title;
+                Ok(value)
+            })
+        }
+    }
+
+    /// Version `1` of this schema nests version `1` of [TestItem].
+    #[derive(Serialize, Deserialize)]
+    struct TestContainer {
+        items: Vec<TestItem>,
+    }
+
+    impl DatabaseValueSchema for TestContainer {
+        const SCHEMA_VERSION: SchemaVersion = 1;
+
+        fn migrations() -> Migrations {
+            Migrations::new().register(0, Ok)
+        }
+
+        fn migrate_nested(
+            mut value: serde_json::Value,
+            from: SchemaVersion,
+        ) -> Result<serde_json::Value, MigrationError> {
+            migrate_nested_array::<Self, TestItem>(value.get_mut("items"), &[0, 1], from)?;
+            Ok(value)
+        }
+    }
+
+    #[test]
+    fn migrate_nested_values() {
+        let database = create_test_database(
+            "container",
+            r#"{"schema_version": 0, "value": {"items": [{"title": "Item 1"}]}}"#,
+        );
+        let database: &dyn KeyValueDB = &database;
+
+        let container: TestContainer = database
+            .get_deserialize_versioned(&TestStore, "container")
+            .unwrap()
+            .unwrap();
+        assert_eq!("Item 1", container.items[0].name);
+
+        // the items of a current container are already current
+        let mut transaction = database.transaction();
+        transaction
+            .put_serialize_versioned(&TestStore, "container", &container)
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        let container: TestContainer = database
+            .get_deserialize_versioned(&TestStore, "container")
+            .unwrap()
+            .unwrap();
+        assert_eq!("Item 1", container.items[0].name);
+    }
+
+    #[test]
+    fn write_tab_current_version() {
+        let id = test_tab_id();
+        let database = create_test_database(
+            &id.to_string(),
+            include_str!("../fixtures/db/tab_data_v0.json"),
+        );
+        let tab = Tab::read_from_db(&id, None, &database, &TestStore)
+            .unwrap()
+            .unwrap();
+
+        let mut transaction = database.transaction();
+        tab.write_to_db(None, &mut transaction, &TestStore).unwrap();
+        database.write(transaction).unwrap();
+
+        let value_bytes = database
+            .get(TestStore.db_col(), id.to_string().as_bytes())
+            .unwrap()
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&value_bytes).unwrap();
+        assert_eq!(
+            serde_json::json!(TabData::SCHEMA_VERSION),
+            value["schema_version"]
+        );
+
+        let tab = Tab::read_from_db(&id, None, &database, &TestStore)
+            .unwrap()
+            .unwrap();
+        assert_eq!("Road Trip", tab.name);
+    }
+
+    #[test]
+    fn read_newer_version() {
+        let database = create_test_database(
+            "user",
+            r#"{"schema_version": 100, "value": {"id": 1, "name": "User 1", "email": null}}"#,
+        );
+        let database: &dyn KeyValueDB = &database;
+
+        let result: Result<Option<User>, DatabaseError> =
+            database.get_deserialize_versioned(&TestStore, "user");
+        assert!(matches!(
+            result,
+            Err(DatabaseError::Migration(
+                _,
+                MigrationError::NewerVersion(100, _)
+            ))
+        ));
+
+        let mut transaction = database.transaction();
+        transaction
+            .put_serialize_versioned(&TestStore, "user", &User::new(1, "User 1", None))
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        let user: Option<User> = database
+            .get_deserialize_versioned(&TestStore, "user")
+            .unwrap();
+        assert!(user.is_some());
+    }
+
+    #[test]
+    fn read_corrupt_value() {
+        let database = create_test_database("user", r#"{"id": 1, "name": "#);
+        let database: &dyn KeyValueDB = &database;
+
+        let result: Result<Option<User>, DatabaseError> =
+            database.get_deserialize(&TestStore, "user");
+        assert!(matches!(result, Err(DatabaseError::CorruptValue(_, _))));
+    }
+
+    #[test]
+    fn read_tabs_skips_unreadable_items() {
+        let id = test_tab_id();
+        let corrupt_id = Uuid::parse_str("5B5B0C2C8A6B4B0E9C3A6A4C1C1C3C3C").unwrap();
+        let missing_id = Uuid::parse_str("0F0E4C3B3A2B4C1D9E8F7A6B5C4D3E2F").unwrap();
+
+        let database = create_test_database(
+            "tabs",
+            &format!(r#"["{}", "{}", "{}"]"#, corrupt_id, id, missing_id),
+        );
+        let mut transaction = database.transaction();
+        transaction.put(
+            TestStore.db_col(),
+            format!("tabs/{}", id).as_bytes(),
+            include_bytes!("../fixtures/db/tab_data_v1.json"),
+        );
+        transaction.put(
+            TestStore.db_col(),
+            format!("tabs/{}", corrupt_id).as_bytes(),
+            br#"{"id": "#,
+        );
+        database.write(transaction).unwrap();
+
+        let tabs = Vec::<Tab>::read_from_db(&"tabs".to_string(), None, &database, &TestStore)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(1, tabs.len());
+        assert_eq!(id, tabs[0].id);
+    }
+
+    #[test]
+    fn write_tab_cbor() {
+        let id = test_tab_id();
+        let database = create_test_database(
+            &id.to_string(),
+            include_str!("../fixtures/db/tab_data_v1.json"),
+        );
+
+        // legacy JSON values can be read from a store using a binary encoding
+        let tab = Tab::read_from_db(&id, None, &database, &TestCborStore)
+            .unwrap()
+            .unwrap();
+
+        let mut transaction = database.transaction();
+        tab.write_to_db(None, &mut transaction, &TestCborStore)
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        let value_bytes = database
+            .get(TestCborStore.db_col(), id.to_string().as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            DatabaseValueEncoding::Cbor,
+            DatabaseValueEncoding::detect(&value_bytes)
+        );
+
+        let tab = Tab::read_from_db(&id, None, &database, &TestCborStore)
+            .unwrap()
+            .unwrap();
+        assert_eq!("Road Trip", tab.name);
+        assert_eq!(1, tab.expenses.len());
+        assert_eq!(1, tab.closed_periods.len());
+        assert_eq!(
+            Commodity::from_str("150.0 AUD").unwrap(),
+            *tab.closed_periods[0].closing_balances.get(&1).unwrap()
+        );
+    }
+
+    #[test]
+    fn delete_tabs() {
+        let id = test_tab_id();
+        let database = create_test_database(
+            &id.to_string(),
+            include_str!("../fixtures/db/tab_data_v1.json"),
+        );
+        let tab = Tab::read_from_db(&id, None, &database, &TestStore)
+            .unwrap()
+            .unwrap();
+
+        let mut transaction = database.transaction();
+        vec![tab]
+            .write_to_db_id(&"tabs".to_string(), None, &mut transaction, &TestStore)
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        let mut transaction = database.transaction();
+        Vec::<Tab>::delete_from_db(
+            &"tabs".to_string(),
+            None,
+            &database,
+            &mut transaction,
+            &TestStore,
+        )
+        .unwrap();
+        database.write(transaction).unwrap();
+
+        // the tab stored outside of "tabs" is not a child, and is kept
+        assert_eq!(1, database.iter(TestStore.db_col()).count());
+        assert!(
+            Vec::<Tab>::read_from_db(&"tabs".to_string(), None, &database, &TestStore)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,1265 @@
+//! Granular storage of [Tab]s in a [KeyValueDB], where each part of
+//! a tab is stored as a separate record, so that modifying a tab only
+//! requires writing the records which have changed.
+//!
+//! For a [TabStorage] with the path `tabs`, the records are stored
+//! with the following keys:
+//!
+//! + `tabs`: the index, a list of the ids of all the stored tabs.
+//! + `tabs/{tab_id}/header`: the [TabHeader] of a tab.
+//! + `tabs/{tab_id}/periods/{n}`: the `n`th [TabPeriod] closed on a
+//!   tab, with the expenses archived in it.
+//! + `tabs/{tab_id}/expenses/{expense_id}`: each [Expense] on a tab.
+//! + `tabs/{tab_id}/actions/{n}`: the `n`th [TabUserActionType]
+//!   performed on a tab.
+//! + `tabs/trash`: a list of [TrashedTab]s, tabs which have been
+//!   removed from the index, but whose records have been kept so
+//!   that they can be restored.
+//!
+//! Tabs stored as a single record at `tabs/{tab_id}` (using the
+//! [DatabaseValueWrite](super::DatabaseValueWrite) implementation for
+//! [Tab]) can still be read, and are replaced by the granular records
+//! the next time they are written.
+
+use super::{
+    delete_with_children, deserialize_versioned, migrate_nested, migrate_nested_array,
+    DBTransactionSerde, DatabaseError, DatabaseValueDelete, DatabaseValueRead, DatabaseValueSchema,
+    KeyValueDBSerde, KeyValueDBStore, MigrationError, Migrations, SchemaVersion,
+};
+use crate::actions::TabUserActionType;
+use crate::expense::{Expense, ExpenseID};
+use crate::period::TabPeriod;
+use crate::tab::{Tab, TabData, TabID};
+use crate::user::User;
+use chrono::{DateTime, NaiveDate, Utc};
+use commodity::CommodityTypeID;
+use kvdb::{DBTransaction, KeyValueDB};
+use serde::{Deserialize, Serialize};
+use std::{borrow::Borrow, collections::HashMap, rc::Rc};
+
+/// A key and its value, as read from a [KeyValueDB].
+type Record = (Box<[u8]>, Box<[u8]>);
+
+/// The part of a [Tab] which is stored in a single record, without
+/// its expenses, user actions or closed periods.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TabHeader {
+    /// The id of the tab
+    pub id: TabID,
+    /// The name of the tab
+    pub name: String,
+    /// The working currency of the tab
+    pub working_currency: CommodityTypeID,
+    /// The users involved with the tab
+    pub users: Vec<Rc<User>>,
+    /// The number of periods of the tab which have been closed
+    pub closed_periods: usize,
+    /// The date that the most recently closed period ended, or `None`
+    /// if no period has been closed on the tab yet
+    pub latest_close: Option<NaiveDate>,
+    /// The closed periods which were stored in the header by version
+    /// `0` of the schema, until the tab is next written in full.
+    #[serde(default, skip_serializing)]
+    legacy_closed_periods: Vec<TabPeriod>,
+}
+
+impl TabHeader {
+    pub fn from_tab(tab: &Tab) -> Self {
+        TabHeader {
+            id: tab.id,
+            name: tab.name.clone(),
+            working_currency: tab.working_currency,
+            users: tab.users.clone(),
+            closed_periods: tab.closed_periods.len(),
+            latest_close: tab.current_period_start(),
+            legacy_closed_periods: Vec::new(),
+        }
+    }
+}
+
+impl DatabaseValueSchema for TabHeader {
+    const SCHEMA_VERSION: SchemaVersion = 1;
+
+    fn migrations() -> Migrations {
+        Migrations::new().register(0, migrate_tab_header_v0)
+    }
+
+    fn migrate_nested(
+        mut value: serde_json::Value,
+        from: SchemaVersion,
+    ) -> Result<serde_json::Value, MigrationError> {
+        migrate_nested_array::<Self, User>(
+            value.get_mut("users"),
+            &TAB_HEADER_USER_VERSIONS,
+            from,
+        )?;
+        migrate_nested_array::<Self, TabPeriod>(
+            value.get_mut("legacy_closed_periods"),
+            &TAB_HEADER_PERIOD_VERSIONS,
+            from,
+        )?;
+        Ok(value)
+    }
+}
+
+/// The version of the [User] schema for each version of the
+/// [TabHeader] schema, and likewise for its legacy [TabPeriod]s.
+const TAB_HEADER_USER_VERSIONS: [SchemaVersion; 2] = [0, 0];
+const TAB_HEADER_PERIOD_VERSIONS: [SchemaVersion; 2] = [0, 0];
+
+/// Version `1` of the [TabHeader] schema moved the closed periods
+/// into their own records, keeping only their number and the date of
+/// the latest close in the header. The periods of a version `0`
+/// header are kept aside until the tab is rewritten.
+fn migrate_tab_header_v0(
+    mut value: serde_json::Value,
+) -> Result<serde_json::Value, MigrationError> {
+    let map = value
+        .as_object_mut()
+        .ok_or_else(|| MigrationError::InvalidValue(0, "TabHeader is not an object".to_string()))?;
+
+    let periods = map
+        .remove("closed_periods")
+        .unwrap_or_else(|| serde_json::Value::Array(Vec::new()));
+    let (count, latest_close) = match &periods {
+        serde_json::Value::Array(periods) => (
+            periods.len(),
+            periods
+                .last()
+                .and_then(|period| period.get("end"))
+                .cloned()
+                .unwrap_or(serde_json::Value::Null),
+        ),
+        _ => {
+            return Err(MigrationError::InvalidValue(
+                0,
+                "TabHeader closed_periods is not an array".to_string(),
+            ))
+        }
+    };
+
+    map.insert("closed_periods".to_string(), count.into());
+    map.insert("latest_close".to_string(), latest_close);
+    map.insert("legacy_closed_periods".to_string(), periods);
+
+    Ok(value)
+}
+
+impl DatabaseValueSchema for TabUserActionType {
+    const SCHEMA_VERSION: SchemaVersion = 0;
+
+    fn migrations() -> Migrations {
+        Migrations::new()
+    }
+
+    fn migrate_nested(
+        mut value: serde_json::Value,
+        from: SchemaVersion,
+    ) -> Result<serde_json::Value, MigrationError> {
+        if let Some(action) = value.get_mut("AddExpense") {
+            migrate_nested::<Self, Expense>(
+                action.get_mut("expense"),
+                &ACTION_EXPENSE_VERSIONS,
+                from,
+            )?;
+        }
+        if let Some(action) = value.get_mut("EditExpense") {
+            migrate_nested::<Self, Expense>(
+                action.get_mut("expense"),
+                &ACTION_EXPENSE_VERSIONS,
+                from,
+            )?;
+        }
+        if let Some(action) = value.get_mut("AddUser") {
+            migrate_nested::<Self, User>(
+                action.get_mut("user_to_add"),
+                &ACTION_USER_VERSIONS,
+                from,
+            )?;
+        }
+        Ok(value)
+    }
+}
+
+/// The version of the [Expense] schema for each version of the
+/// [TabUserActionType] schema, and likewise for its [User]s.
+const ACTION_EXPENSE_VERSIONS: [SchemaVersion; 1] = [0];
+const ACTION_USER_VERSIONS: [SchemaVersion; 1] = [0];
+
+/// Whether two values are the same when they are serialized, which
+/// is used to find the records of a tab which have changed, because
+/// not all the types stored in them implement [PartialEq].
+fn same_value<V: Serialize>(a: &V, b: &V) -> bool {
+    match (serde_json::to_value(a), serde_json::to_value(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// A tab which has been moved to the trash.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TrashedTab {
+    /// The id of the tab
+    pub id: TabID,
+    /// When the tab was moved to the trash
+    pub trashed_at: DateTime<Utc>,
+}
+
+/// Reads and writes [Tab]s as granular records, under a `path` in a
+/// [KeyValueDBStore].
+pub struct TabStorage<'s, S> {
+    path: String,
+    store: &'s S,
+}
+
+impl<'s, S> TabStorage<'s, S>
+where
+    S: KeyValueDBStore,
+{
+    /// Create a new [TabStorage](TabStorage) for tabs stored under
+    /// the specified `path` in the `store`.
+    pub fn new<P: Into<String>>(path: P, store: &'s S) -> Self {
+        TabStorage {
+            path: path.into(),
+            store,
+        }
+    }
+
+    fn tab_key(&self, tab_id: &TabID) -> String {
+        format!("{}/{}", self.path, tab_id)
+    }
+
+    fn trash_key(&self) -> String {
+        format!("{}/trash", self.path)
+    }
+
+    fn header_key(&self, tab_id: &TabID) -> String {
+        format!("{}/header", self.tab_key(tab_id))
+    }
+
+    fn expenses_prefix(&self, tab_id: &TabID) -> String {
+        format!("{}/expenses/", self.tab_key(tab_id))
+    }
+
+    fn expense_key(&self, tab_id: &TabID, expense_id: ExpenseID) -> String {
+        format!("{}{}", self.expenses_prefix(tab_id), expense_id)
+    }
+
+    fn actions_prefix(&self, tab_id: &TabID) -> String {
+        format!("{}/actions/", self.tab_key(tab_id))
+    }
+
+    /// The index is zero padded so that the actions are iterated in
+    /// the order they were performed.
+    fn action_key(&self, tab_id: &TabID, index: usize) -> String {
+        format!("{}{:010}", self.actions_prefix(tab_id), index)
+    }
+
+    fn periods_prefix(&self, tab_id: &TabID) -> String {
+        format!("{}/periods/", self.tab_key(tab_id))
+    }
+
+    /// The index is zero padded so that the periods are iterated in
+    /// the order they were closed.
+    fn period_key(&self, tab_id: &TabID, index: usize) -> String {
+        format!("{}{:010}", self.periods_prefix(tab_id), index)
+    }
+
+    /// Read the ids of all the stored tabs from the index.
+    pub fn read_tab_ids(&self, database: &dyn KeyValueDB) -> Result<Vec<TabID>, DatabaseError> {
+        let ids: Option<Vec<TabID>> = database.get_deserialize(self.store, &self.path)?;
+        Ok(ids.unwrap_or_default())
+    }
+
+    fn write_tab_ids(
+        &self,
+        transaction: &mut DBTransaction,
+        ids: &[TabID],
+    ) -> Result<(), DatabaseError> {
+        transaction.put_serialize(self.store, &self.path, ids)
+    }
+
+    /// Write a new `tab`, and add it to the index.
+    pub fn add_tab(
+        &self,
+        database: &dyn KeyValueDB,
+        transaction: &mut DBTransaction,
+        tab: &Tab,
+    ) -> Result<(), DatabaseError> {
+        let mut ids = self.read_tab_ids(database)?;
+
+        if !ids.contains(&tab.id) {
+            ids.push(tab.id);
+            self.write_tab_ids(transaction, &ids)?;
+        }
+
+        self.write_tab(transaction, tab)
+    }
+
+    /// Replace all the stored tabs (and the index) with `tabs`.
+    pub fn write_tabs<T: Borrow<Tab>>(
+        &self,
+        database: &dyn KeyValueDB,
+        transaction: &mut DBTransaction,
+        tabs: &[T],
+    ) -> Result<(), DatabaseError> {
+        let ids: Vec<TabID> = tabs.iter().map(|tab| tab.borrow().id).collect();
+
+        for old_id in self.read_tab_ids(database)? {
+            if !ids.contains(&old_id) {
+                self.delete_tab_records(transaction, &old_id);
+            }
+        }
+
+        self.write_tab_ids(transaction, &ids)?;
+
+        for tab in tabs {
+            self.write_tab(transaction, tab.borrow())?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete all the records for the tab with the specified id
+    /// (without updating the index).
+    fn delete_tab_records(&self, transaction: &mut DBTransaction, tab_id: &TabID) {
+        delete_with_children(transaction, self.store, &self.tab_key(tab_id));
+    }
+
+    /// Read the tabs which are currently in the trash.
+    pub fn read_trashed_tabs(
+        &self,
+        database: &dyn KeyValueDB,
+    ) -> Result<Vec<TrashedTab>, DatabaseError> {
+        let trashed: Option<Vec<TrashedTab>> =
+            database.get_deserialize(self.store, self.trash_key())?;
+        Ok(trashed.unwrap_or_default())
+    }
+
+    fn write_trashed_tabs(
+        &self,
+        transaction: &mut DBTransaction,
+        trashed: &[TrashedTab],
+    ) -> Result<(), DatabaseError> {
+        transaction.put_serialize(self.store, self.trash_key(), trashed)
+    }
+
+    /// Move the tab with the specified id from the index to the
+    /// trash. Its records are kept so that it can be restored with
+    /// [restore_tab()](TabStorage::restore_tab).
+    pub fn trash_tab(
+        &self,
+        database: &dyn KeyValueDB,
+        transaction: &mut DBTransaction,
+        tab_id: &TabID,
+        trashed_at: DateTime<Utc>,
+    ) -> Result<(), DatabaseError> {
+        let mut ids = self.read_tab_ids(database)?;
+        let index = ids
+            .iter()
+            .position(|id| id == tab_id)
+            .ok_or_else(|| DatabaseError::MissingItem(self.tab_key(tab_id)))?;
+        ids.remove(index);
+        self.write_tab_ids(transaction, &ids)?;
+
+        let mut trashed = self.read_trashed_tabs(database)?;
+        trashed.retain(|trashed_tab| trashed_tab.id != *tab_id);
+        trashed.push(TrashedTab {
+            id: *tab_id,
+            trashed_at,
+        });
+        self.write_trashed_tabs(transaction, &trashed)
+    }
+
+    /// Move the tab with the specified id out of the trash, and back
+    /// into the index.
+    pub fn restore_tab(
+        &self,
+        database: &dyn KeyValueDB,
+        transaction: &mut DBTransaction,
+        tab_id: &TabID,
+    ) -> Result<(), DatabaseError> {
+        let mut trashed = self.read_trashed_tabs(database)?;
+        let index = trashed
+            .iter()
+            .position(|trashed_tab| trashed_tab.id == *tab_id)
+            .ok_or_else(|| {
+                DatabaseError::MissingItem(format!("{}/{}", self.trash_key(), tab_id))
+            })?;
+        trashed.remove(index);
+        self.write_trashed_tabs(transaction, &trashed)?;
+
+        let mut ids = self.read_tab_ids(database)?;
+        if !ids.contains(tab_id) {
+            ids.push(*tab_id);
+            self.write_tab_ids(transaction, &ids)?;
+        }
+
+        Ok(())
+    }
+
+    /// Permanently delete the tab with the specified id, whether it
+    /// is in the index or in the trash.
+    pub fn delete_tab(
+        &self,
+        database: &dyn KeyValueDB,
+        transaction: &mut DBTransaction,
+        tab_id: &TabID,
+    ) -> Result<(), DatabaseError> {
+        let mut ids = self.read_tab_ids(database)?;
+        if ids.contains(tab_id) {
+            ids.retain(|id| id != tab_id);
+            self.write_tab_ids(transaction, &ids)?;
+        }
+
+        let mut trashed = self.read_trashed_tabs(database)?;
+        if trashed.iter().any(|trashed_tab| trashed_tab.id == *tab_id) {
+            trashed.retain(|trashed_tab| trashed_tab.id != *tab_id);
+            self.write_trashed_tabs(transaction, &trashed)?;
+        }
+
+        Tab::delete_from_db(
+            tab_id,
+            self.path.as_str(),
+            database,
+            transaction,
+            self.store,
+        )
+    }
+
+    /// Permanently delete all the tabs which were moved to the trash
+    /// before the specified time, returning their ids.
+    pub fn empty_trash(
+        &self,
+        database: &dyn KeyValueDB,
+        transaction: &mut DBTransaction,
+        trashed_before: DateTime<Utc>,
+    ) -> Result<Vec<TabID>, DatabaseError> {
+        let (expired, remaining): (Vec<TrashedTab>, Vec<TrashedTab>) = self
+            .read_trashed_tabs(database)?
+            .into_iter()
+            .partition(|trashed_tab| trashed_tab.trashed_at < trashed_before);
+
+        if expired.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.write_trashed_tabs(transaction, &remaining)?;
+
+        for trashed_tab in &expired {
+            Tab::delete_from_db(
+                &trashed_tab.id,
+                self.path.as_str(),
+                database,
+                transaction,
+                self.store,
+            )?;
+        }
+
+        Ok(expired
+            .into_iter()
+            .map(|trashed_tab| trashed_tab.id)
+            .collect())
+    }
+
+    /// Write all the records for a `tab` (without updating the
+    /// index), replacing any previously stored records for the tab.
+    /// This is for storing a tab for the first time, or replacing it
+    /// entirely, see [write_tab_changes()](TabStorage::write_tab_changes).
+    pub fn write_tab(
+        &self,
+        transaction: &mut DBTransaction,
+        tab: &Tab,
+    ) -> Result<(), DatabaseError> {
+        self.delete_tab_records(transaction, &tab.id);
+
+        self.write_header(transaction, tab)?;
+
+        for expense in &tab.expenses {
+            self.write_expense(transaction, &tab.id, expense)?;
+        }
+
+        for (index, action) in tab.user_actions.iter().enumerate() {
+            self.write_user_action(transaction, &tab.id, index, action)?;
+        }
+
+        for (index, period) in tab.closed_periods.iter().enumerate() {
+            self.write_period(transaction, &tab.id, index, period)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the records of a `tab` which differ from those of the
+    /// `previous` copy of it (without updating the index), such as
+    /// the action which was just performed and the records it
+    /// touched. This is used instead of [write_tab()](TabStorage::write_tab)
+    /// when the stored tab is modified, so that the records which are
+    /// unchanged aren't rewritten. A tab which is still stored as a
+    /// single record, or with its closed periods in its header, is
+    /// rewritten entirely.
+    pub fn write_tab_changes(
+        &self,
+        database: &dyn KeyValueDB,
+        transaction: &mut DBTransaction,
+        previous: &Tab,
+        tab: &Tab,
+    ) -> Result<(), DatabaseError> {
+        let stored_header: Option<TabHeader> =
+            database.get_deserialize_versioned(self.store, self.header_key(&tab.id))?;
+        match stored_header {
+            Some(header) if header.legacy_closed_periods.is_empty() => {}
+            _ => return self.write_tab(transaction, tab),
+        }
+
+        if !same_value(&TabHeader::from_tab(previous), &TabHeader::from_tab(tab)) {
+            self.write_header(transaction, tab)?;
+        }
+
+        let mut previous_expenses: HashMap<ExpenseID, &Expense> = previous
+            .expenses
+            .iter()
+            .map(|expense| (expense.id, expense))
+            .collect();
+        for expense in &tab.expenses {
+            match previous_expenses.remove(&expense.id) {
+                Some(previous_expense) if same_value(previous_expense, expense) => {}
+                _ => self.write_expense(transaction, &tab.id, expense)?,
+            }
+        }
+        for expense_id in previous_expenses.keys() {
+            self.remove_expense(transaction, &tab.id, *expense_id);
+        }
+
+        // actions are usually only appended, but those after the
+        // first which differs are rewritten, e.g. when the tab is
+        // replaced by a copy which was merged with other clients'
+        // actions
+        let unchanged = previous
+            .user_actions
+            .iter()
+            .zip(&tab.user_actions)
+            .take_while(|(previous_action, action)| same_value(*previous_action, *action))
+            .count();
+        for (index, action) in tab.user_actions.iter().enumerate().skip(unchanged) {
+            self.write_user_action(transaction, &tab.id, index, action)?;
+        }
+        for index in tab.user_actions.len()..previous.user_actions.len() {
+            transaction.delete(
+                self.store.db_col(),
+                self.action_key(&tab.id, index).as_bytes(),
+            );
+        }
+
+        let unchanged = previous
+            .closed_periods
+            .iter()
+            .zip(&tab.closed_periods)
+            .take_while(|(previous_period, period)| same_value(*previous_period, *period))
+            .count();
+        for (index, period) in tab.closed_periods.iter().enumerate().skip(unchanged) {
+            self.write_period(transaction, &tab.id, index, period)?;
+        }
+        for index in tab.closed_periods.len()..previous.closed_periods.len() {
+            transaction.delete(
+                self.store.db_col(),
+                self.period_key(&tab.id, index).as_bytes(),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Write the [TabHeader] of a `tab`, which should be done after
+    /// its name or users have been modified, or a period is closed.
+    pub fn write_header(
+        &self,
+        transaction: &mut DBTransaction,
+        tab: &Tab,
+    ) -> Result<(), DatabaseError> {
+        transaction.put_serialize_versioned(
+            self.store,
+            self.header_key(&tab.id),
+            &TabHeader::from_tab(tab),
+        )
+    }
+
+    /// Write an `expense` which has been added to (or modified on)
+    /// the tab with the specified id.
+    pub fn write_expense(
+        &self,
+        transaction: &mut DBTransaction,
+        tab_id: &TabID,
+        expense: &Expense,
+    ) -> Result<(), DatabaseError> {
+        transaction.put_serialize_versioned(
+            self.store,
+            self.expense_key(tab_id, expense.id),
+            expense,
+        )
+    }
+
+    /// Remove an expense from the tab with the specified id.
+    pub fn remove_expense(
+        &self,
+        transaction: &mut DBTransaction,
+        tab_id: &TabID,
+        expense_id: ExpenseID,
+    ) {
+        transaction.delete(
+            self.store.db_col(),
+            self.expense_key(tab_id, expense_id).as_bytes(),
+        );
+    }
+
+    /// Write an `action` which was performed on the tab with the
+    /// specified id, where `index` is its position in the tab's
+    /// [user_actions](Tab::user_actions).
+    pub fn write_user_action(
+        &self,
+        transaction: &mut DBTransaction,
+        tab_id: &TabID,
+        index: usize,
+        action: &TabUserActionType,
+    ) -> Result<(), DatabaseError> {
+        transaction.put_serialize_versioned(self.store, self.action_key(tab_id, index), action)
+    }
+
+    /// Write a `period` which was closed on the tab with the
+    /// specified id, where `index` is its position in the tab's
+    /// [closed_periods](Tab::closed_periods).
+    pub fn write_period(
+        &self,
+        transaction: &mut DBTransaction,
+        tab_id: &TabID,
+        index: usize,
+        period: &TabPeriod,
+    ) -> Result<(), DatabaseError> {
+        transaction.put_serialize_versioned(self.store, self.period_key(tab_id, index), period)
+    }
+
+    /// Read and reassemble the tab with the specified id from its
+    /// records. The expenses are ordered by their id.
+    pub fn read_tab(
+        &self,
+        database: &dyn KeyValueDB,
+        tab_id: &TabID,
+    ) -> Result<Option<Tab>, DatabaseError> {
+        self.read_tab_with_periods(database, tab_id, None)
+    }
+
+    /// Read the tab with the specified id, with only the closed
+    /// periods needed to calculate its balances as of the specified
+    /// date. Unless `as_of` predates the latest close, this is just
+    /// the latest period, which holds the balances carried forward.
+    ///
+    /// The tab which is read must not be modified and written back,
+    /// because its other closed periods are missing.
+    pub fn read_tab_as_of(
+        &self,
+        database: &dyn KeyValueDB,
+        tab_id: &TabID,
+        as_of: NaiveDate,
+    ) -> Result<Option<Tab>, DatabaseError> {
+        self.read_tab_with_periods(database, tab_id, Some(as_of))
+    }
+
+    /// Read the tab with the specified id, with all of its closed
+    /// periods, or only those needed as of a date (see
+    /// [read_tab_as_of()](TabStorage::read_tab_as_of)).
+    fn read_tab_with_periods(
+        &self,
+        database: &dyn KeyValueDB,
+        tab_id: &TabID,
+        as_of: Option<NaiveDate>,
+    ) -> Result<Option<Tab>, DatabaseError> {
+        let header: TabHeader =
+            match database.get_deserialize_versioned(self.store, self.header_key(tab_id))? {
+                Some(header) => header,
+                // fall back to reading a tab stored as a single record
+                None => return Tab::read_from_db(tab_id, self.path.as_str(), database, self.store),
+            };
+
+        let mut expenses: Vec<Expense> =
+            self.read_with_prefix(database, &self.expenses_prefix(tab_id))?;
+        expenses.sort_by_key(|expense| expense.id);
+
+        let user_actions: Vec<TabUserActionType> =
+            self.read_with_prefix(database, &self.actions_prefix(tab_id))?;
+
+        let closed_periods = if !header.legacy_closed_periods.is_empty() {
+            header.legacy_closed_periods
+        } else if header.closed_periods == 0 {
+            Vec::new()
+        } else if as_of.is_some() && as_of >= header.latest_close {
+            let key = self.period_key(tab_id, header.closed_periods - 1);
+            let latest: Option<TabPeriod> = database.get_deserialize_versioned(self.store, &key)?;
+            vec![latest.ok_or(DatabaseError::MissingItem(key))?]
+        } else {
+            self.read_with_prefix(database, &self.periods_prefix(tab_id))?
+        };
+
+        let tab_data = TabData {
+            id: header.id,
+            name: header.name,
+            working_currency: header.working_currency,
+            users: header.users,
+            expenses,
+            user_actions,
+            closed_periods,
+        };
+
+        Ok(Some(tab_data.into()))
+    }
+
+    /// Read all the values with keys that start with `prefix`, in the
+    /// order of their keys.
+    fn read_with_prefix<V: DatabaseValueSchema>(
+        &self,
+        database: &dyn KeyValueDB,
+        prefix: &str,
+    ) -> Result<Vec<V>, DatabaseError> {
+        let mut records: Vec<Record> = database
+            .iter_with_prefix(self.store.db_col(), prefix.as_bytes())
+            .collect();
+        records.sort_by(|(key_a, _), (key_b, _)| key_a.cmp(key_b));
+
+        records
+            .iter()
+            .map(|(key, value)| deserialize_versioned(&String::from_utf8_lossy(key), value))
+            .collect()
+    }
+
+    /// Lazily read all the tabs in the index. Each tab is only read
+    /// from the database when the iterator reaches it, and an error
+    /// reading one tab does not prevent reading the others.
+    pub fn read_tabs<'a>(
+        &'a self,
+        database: &'a dyn KeyValueDB,
+    ) -> Result<impl Iterator<Item = Result<Tab, DatabaseError>> + 'a, DatabaseError> {
+        let ids = self.read_tab_ids(database)?;
+
+        Ok(ids.into_iter().map(move |id| {
+            self.read_tab(database, &id)?
+                .ok_or_else(|| DatabaseError::MissingItem(self.tab_key(&id)))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TabStorage, TrashedTab};
+    use crate::actions::{AddExpense, AddUser, RemoveExpense, TabUserAction, TabUserActionType};
+    use crate::db::{DBTransactionSerde, DatabaseValueWriteID, KeyValueDBStore};
+    use crate::{Expense, Tab, User};
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use commodity::{Commodity, CommodityType};
+    use kvdb::KeyValueDB;
+    use std::rc::Rc;
+    use std::str::FromStr;
+    use uuid::Uuid;
+
+    struct TestStore;
+
+    impl KeyValueDBStore for TestStore {
+        fn name(&self) -> &str {
+            "Test"
+        }
+        fn db_col(&self) -> u32 {
+            0
+        }
+        fn n_db_cols() -> u32 {
+            1
+        }
+    }
+
+    fn create_test_expense(id: i32) -> Expense {
+        Expense::new(
+            id,
+            format!("Test Expense {}", id),
+            "Test",
+            NaiveDate::from_ymd(2020, 5, 1),
+            1,
+            vec![1, 2],
+            Commodity::from_str("100.0 AUD").unwrap(),
+            None,
+        )
+    }
+
+    fn create_test_tab(id: &str) -> Tab {
+        let aud = CommodityType::from_currency_alpha3("AUD").unwrap();
+        let user1 = Rc::new(User::new(1, "User 1", None));
+        let user2 = Rc::new(User::new(2, "User 2", None));
+        let mut tab = Tab::new(
+            Uuid::parse_str(id).unwrap(),
+            "Test Tab",
+            aud.id,
+            vec![user1, user2.clone()],
+            vec![create_test_expense(1), create_test_expense(2)],
+        );
+        tab.user_actions
+            .push(TabUserActionType::AddUser(AddUser::new(
+                1,
+                (*user2).clone(),
+            )));
+        tab
+    }
+
+    #[test]
+    fn add_and_read_tab() {
+        let database = kvdb_memorydb::create(TestStore::n_db_cols());
+        let storage = TabStorage::new("tabs", &TestStore);
+        let tab = create_test_tab("936DA01F9ABD4d9d80C702AF85C822A8");
+
+        let mut transaction = database.transaction();
+        storage.add_tab(&database, &mut transaction, &tab).unwrap();
+        database.write(transaction).unwrap();
+
+        assert_eq!(vec![tab.id], storage.read_tab_ids(&database).unwrap());
+
+        let read_tab = storage.read_tab(&database, &tab.id).unwrap().unwrap();
+        assert_eq!("Test Tab", read_tab.name);
+        assert_eq!(2, read_tab.users.len());
+        assert_eq!(
+            vec![1, 2],
+            read_tab.expenses.iter().map(|e| e.id).collect::<Vec<_>>()
+        );
+        assert_eq!(1, read_tab.user_actions.len());
+    }
+
+    #[test]
+    fn add_expense_writes_single_record() {
+        let database = kvdb_memorydb::create(TestStore::n_db_cols());
+        let storage = TabStorage::new("tabs", &TestStore);
+        let mut tab = create_test_tab("936DA01F9ABD4d9d80C702AF85C822A8");
+
+        let mut transaction = database.transaction();
+        storage.add_tab(&database, &mut transaction, &tab).unwrap();
+        database.write(transaction).unwrap();
+
+        let expense = create_test_expense(3);
+        tab.expenses.push(expense.clone());
+
+        let mut transaction = database.transaction();
+        storage
+            .write_expense(&mut transaction, &tab.id, &expense)
+            .unwrap();
+        assert_eq!(1, transaction.ops.len());
+        database.write(transaction).unwrap();
+
+        let read_tab = storage.read_tab(&database, &tab.id).unwrap().unwrap();
+        assert_eq!(3, read_tab.expenses.len());
+
+        let mut transaction = database.transaction();
+        storage.remove_expense(&mut transaction, &tab.id, 1);
+        database.write(transaction).unwrap();
+
+        let read_tab = storage.read_tab(&database, &tab.id).unwrap().unwrap();
+        assert_eq!(
+            vec![2, 3],
+            read_tab.expenses.iter().map(|e| e.id).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn write_tab_changes_only_writes_changed_records() {
+        let database = kvdb_memorydb::create(TestStore::n_db_cols());
+        let storage = TabStorage::new("tabs", &TestStore);
+        let previous = create_test_tab("936DA01F9ABD4d9d80C702AF85C822A8");
+
+        let mut transaction = database.transaction();
+        storage
+            .add_tab(&database, &mut transaction, &previous)
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        // performing an action which adds an expense writes the
+        // action and the expense
+        let mut tab = previous.clone();
+        let expense = create_test_expense(3);
+        let action = TabUserActionType::AddExpense(AddExpense::new(1, expense));
+        action.perform(&mut tab).unwrap();
+        tab.user_actions.push(action);
+
+        let mut transaction = database.transaction();
+        storage
+            .write_tab_changes(&database, &mut transaction, &previous, &tab)
+            .unwrap();
+        assert_eq!(2, transaction.ops.len());
+        database.write(transaction).unwrap();
+
+        // removing an expense and renaming the tab also writes the
+        // header, and deletes the expense's record
+        let previous = tab.clone();
+        let action = TabUserActionType::RemoveExpense(RemoveExpense::new(1, 1));
+        action.perform(&mut tab).unwrap();
+        tab.user_actions.push(action);
+        tab.name = "Renamed".to_string();
+
+        let mut transaction = database.transaction();
+        storage
+            .write_tab_changes(&database, &mut transaction, &previous, &tab)
+            .unwrap();
+        assert_eq!(3, transaction.ops.len());
+        database.write(transaction).unwrap();
+
+        let read_tab = storage.read_tab(&database, &tab.id).unwrap().unwrap();
+        assert_eq!("Renamed", read_tab.name);
+        assert_eq!(
+            vec![2, 3],
+            read_tab.expenses.iter().map(|e| e.id).collect::<Vec<_>>()
+        );
+        assert_eq!(3, read_tab.user_actions.len());
+
+        // unchanged tabs aren't written
+        let mut transaction = database.transaction();
+        storage
+            .write_tab_changes(&database, &mut transaction, &read_tab, &tab)
+            .unwrap();
+        assert!(transaction.ops.is_empty());
+    }
+
+    #[test]
+    fn closed_periods_stored_as_separate_records() {
+        let database = kvdb_memorydb::create(TestStore::n_db_cols());
+        let storage = TabStorage::new("tabs", &TestStore);
+        let tab = create_test_tab("936DA01F9ABD4d9d80C702AF85C822A8");
+        let mut expense = create_test_expense(3);
+        expense.date = NaiveDate::from_ymd(2020, 6, 10);
+        let mut tab = Tab::new(
+            tab.id,
+            tab.name,
+            tab.working_currency,
+            tab.users,
+            vec![create_test_expense(1), create_test_expense(2), expense],
+        );
+        tab.close_period(NaiveDate::from_ymd(2020, 6, 1)).unwrap();
+        tab.close_period(NaiveDate::from_ymd(2020, 7, 1)).unwrap();
+
+        let mut transaction = database.transaction();
+        storage.add_tab(&database, &mut transaction, &tab).unwrap();
+        database.write(transaction).unwrap();
+
+        let periods_prefix = format!("tabs/{}/periods/", tab.id);
+        assert_eq!(
+            2,
+            database
+                .iter_with_prefix(TestStore.db_col(), periods_prefix.as_bytes())
+                .count()
+        );
+
+        // renaming the tab only writes its header
+        let previous = tab.clone();
+        tab.name = "Renamed".to_string();
+        let mut transaction = database.transaction();
+        storage
+            .write_tab_changes(&database, &mut transaction, &previous, &tab)
+            .unwrap();
+        assert_eq!(1, transaction.ops.len());
+        database.write(transaction).unwrap();
+
+        let read_tab = storage.read_tab(&database, &tab.id).unwrap().unwrap();
+        assert_eq!("Renamed", read_tab.name);
+        assert_eq!(2, read_tab.closed_periods.len());
+        assert_eq!(2, read_tab.closed_periods[0].expenses.len());
+
+        // only the latest period is read for balances after it
+        let as_of = NaiveDate::from_ymd(2020, 7, 15);
+        let read_tab = storage
+            .read_tab_as_of(&database, &tab.id, as_of)
+            .unwrap()
+            .unwrap();
+        assert_eq!(1, read_tab.closed_periods.len());
+        assert_eq!(
+            tab.user_balances(as_of).unwrap(),
+            read_tab.user_balances(as_of).unwrap()
+        );
+
+        // and all of them for balances before the latest close
+        let as_of = NaiveDate::from_ymd(2020, 6, 15);
+        let read_tab = storage
+            .read_tab_as_of(&database, &tab.id, as_of)
+            .unwrap()
+            .unwrap();
+        assert_eq!(2, read_tab.closed_periods.len());
+        assert_eq!(
+            tab.user_balances(as_of).unwrap(),
+            read_tab.user_balances(as_of).unwrap()
+        );
+    }
+
+    #[test]
+    fn write_tab_changes_to_header_with_closed_periods() {
+        let database = kvdb_memorydb::create(TestStore::n_db_cols());
+        let storage = TabStorage::new("tabs", &TestStore);
+        let mut tab = create_test_tab("936DA01F9ABD4d9d80C702AF85C822A8");
+        tab.close_period(NaiveDate::from_ymd(2020, 6, 1)).unwrap();
+
+        // a version 0 header, which stored the closed periods
+        let mut transaction = database.transaction();
+        storage.add_tab(&database, &mut transaction, &tab).unwrap();
+        transaction
+            .put_serialize(
+                &TestStore,
+                format!("tabs/{}/header", tab.id),
+                serde_json::json!({
+                    "schema_version": 0,
+                    "value": {
+                        "id": tab.id,
+                        "name": tab.name,
+                        "working_currency": tab.working_currency,
+                        "users": tab.users,
+                        "closed_periods": tab.closed_periods,
+                    },
+                }),
+            )
+            .unwrap();
+        transaction.delete_prefix(
+            TestStore.db_col(),
+            format!("tabs/{}/periods/", tab.id).as_bytes(),
+        );
+        database.write(transaction).unwrap();
+
+        let previous = storage.read_tab(&database, &tab.id).unwrap().unwrap();
+        assert_eq!(1, previous.closed_periods.len());
+
+        // the whole tab is written, to move the periods out of the
+        // header
+        let mut tab = previous.clone();
+        tab.name = "Renamed".to_string();
+        let mut transaction = database.transaction();
+        storage
+            .write_tab_changes(&database, &mut transaction, &previous, &tab)
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        let read_tab = storage
+            .read_tab_as_of(&database, &tab.id, NaiveDate::from_ymd(2020, 7, 1))
+            .unwrap()
+            .unwrap();
+        assert_eq!("Renamed", read_tab.name);
+        assert_eq!(1, read_tab.closed_periods.len());
+        assert_eq!(2, read_tab.closed_periods[0].expenses.len());
+    }
+
+    #[test]
+    fn read_legacy_tabs() {
+        let database = kvdb_memorydb::create(TestStore::n_db_cols());
+        let tabs = vec![
+            create_test_tab("936DA01F9ABD4d9d80C702AF85C822A8"),
+            create_test_tab("A6F2E6C5B9D24C1B9A4E5B6C7D8E9F01"),
+        ];
+
+        let mut transaction = database.transaction();
+        tabs.write_to_db_id(&"tabs".to_string(), None, &mut transaction, &TestStore)
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        let storage = TabStorage::new("tabs", &TestStore);
+        let read_tabs: Vec<Tab> = storage
+            .read_tabs(&database)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(2, read_tabs.len());
+        assert_eq!(2, read_tabs[1].expenses.len());
+
+        // rewriting the tabs replaces the legacy records
+        let mut transaction = database.transaction();
+        storage
+            .write_tabs(&database, &mut transaction, &read_tabs[..1])
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        assert_eq!(vec![tabs[0].id], storage.read_tab_ids(&database).unwrap());
+        let legacy_key = format!("tabs/{}", tabs[1].id);
+        assert!(database
+            .get(TestStore.db_col(), legacy_key.as_bytes())
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            1,
+            storage
+                .read_tab(&database, &tabs[0].id)
+                .unwrap()
+                .unwrap()
+                .user_actions
+                .len()
+        );
+    }
+
+    #[test]
+    fn write_tab_changes_to_legacy_tab() {
+        let database = kvdb_memorydb::create(TestStore::n_db_cols());
+        let tabs = vec![create_test_tab("936DA01F9ABD4d9d80C702AF85C822A8")];
+
+        let mut transaction = database.transaction();
+        tabs.write_to_db_id(&"tabs".to_string(), None, &mut transaction, &TestStore)
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        // the whole tab is written, because it doesn't have separate
+        // records for its expenses yet
+        let storage = TabStorage::new("tabs", &TestStore);
+        let previous = storage.read_tab(&database, &tabs[0].id).unwrap().unwrap();
+        let mut tab = previous.clone();
+        tab.name = "Renamed".to_string();
+        let mut transaction = database.transaction();
+        storage
+            .write_tab_changes(&database, &mut transaction, &previous, &tab)
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        let read_tab = storage.read_tab(&database, &tab.id).unwrap().unwrap();
+        assert_eq!("Renamed", read_tab.name);
+        assert_eq!(2, read_tab.expenses.len());
+        assert_eq!(1, read_tab.user_actions.len());
+    }
+
+    #[test]
+    fn read_tabs_skips_corrupt_tab() {
+        let database = kvdb_memorydb::create(TestStore::n_db_cols());
+        let storage = TabStorage::new("tabs", &TestStore);
+        let tab1 = create_test_tab("936DA01F9ABD4d9d80C702AF85C822A8");
+        let tab2 = create_test_tab("A6F2E6C5B9D24C1B9A4E5B6C7D8E9F01");
+
+        let mut transaction = database.transaction();
+        storage
+            .write_tabs(&database, &mut transaction, &[tab1.clone(), tab2.clone()])
+            .unwrap();
+        transaction.put(
+            TestStore.db_col(),
+            format!("tabs/{}/header", tab1.id).as_bytes(),
+            b"{",
+        );
+        database.write(transaction).unwrap();
+
+        let results: Vec<_> = storage.read_tabs(&database).unwrap().collect();
+        assert_eq!(2, results.len());
+        assert!(results[0].is_err());
+        assert_eq!(tab2.id, results[1].as_ref().unwrap().id);
+    }
+
+    #[test]
+    fn trash_and_restore_tab() {
+        let database = kvdb_memorydb::create(TestStore::n_db_cols());
+        let storage = TabStorage::new("tabs", &TestStore);
+        let tab1 = create_test_tab("936DA01F9ABD4d9d80C702AF85C822A8");
+        let tab2 = create_test_tab("A6F2E6C5B9D24C1B9A4E5B6C7D8E9F01");
+        let trashed_at = Utc.ymd(2020, 6, 1).and_hms(12, 0, 0);
+
+        let mut transaction = database.transaction();
+        storage
+            .write_tabs(&database, &mut transaction, &[tab1.clone(), tab2.clone()])
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        let mut transaction = database.transaction();
+        storage
+            .trash_tab(&database, &mut transaction, &tab1.id, trashed_at)
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        assert_eq!(vec![tab2.id], storage.read_tab_ids(&database).unwrap());
+        assert_eq!(
+            vec![TrashedTab {
+                id: tab1.id,
+                trashed_at
+            }],
+            storage.read_trashed_tabs(&database).unwrap()
+        );
+        // the records of the trashed tab are kept
+        assert!(storage.read_tab(&database, &tab1.id).unwrap().is_some());
+
+        let mut transaction = database.transaction();
+        storage
+            .restore_tab(&database, &mut transaction, &tab1.id)
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        assert_eq!(
+            vec![tab2.id, tab1.id],
+            storage.read_tab_ids(&database).unwrap()
+        );
+        assert!(storage.read_trashed_tabs(&database).unwrap().is_empty());
+
+        let mut transaction = database.transaction();
+        assert!(storage
+            .restore_tab(&database, &mut transaction, &tab1.id)
+            .is_err());
+    }
+
+    #[test]
+    fn delete_and_empty_trash() {
+        let database = kvdb_memorydb::create(TestStore::n_db_cols());
+        let storage = TabStorage::new("tabs", &TestStore);
+        let tab1 = create_test_tab("936DA01F9ABD4d9d80C702AF85C822A8");
+        let tab2 = create_test_tab("A6F2E6C5B9D24C1B9A4E5B6C7D8E9F01");
+        let tab3 = create_test_tab("C6F2E6C5B9D24C1B9A4E5B6C7D8E9F02");
+
+        let mut transaction = database.transaction();
+        storage
+            .write_tabs(
+                &database,
+                &mut transaction,
+                &[tab1.clone(), tab2.clone(), tab3.clone()],
+            )
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        let mut transaction = database.transaction();
+        storage
+            .delete_tab(&database, &mut transaction, &tab1.id)
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        assert_eq!(
+            vec![tab2.id, tab3.id],
+            storage.read_tab_ids(&database).unwrap()
+        );
+        assert!(storage.read_tab(&database, &tab1.id).unwrap().is_none());
+        let tab1_prefix = format!("tabs/{}", tab1.id);
+        assert_eq!(
+            0,
+            database
+                .iter_with_prefix(TestStore.db_col(), tab1_prefix.as_bytes())
+                .count()
+        );
+
+        for (tab, day) in [(&tab2, 1), (&tab3, 10)].iter() {
+            let mut transaction = database.transaction();
+            storage
+                .trash_tab(
+                    &database,
+                    &mut transaction,
+                    &tab.id,
+                    Utc.ymd(2020, 6, *day).and_hms(0, 0, 0),
+                )
+                .unwrap();
+            database.write(transaction).unwrap();
+        }
+
+        let mut transaction = database.transaction();
+        let deleted = storage
+            .empty_trash(
+                &database,
+                &mut transaction,
+                Utc.ymd(2020, 6, 5).and_hms(0, 0, 0),
+            )
+            .unwrap();
+        database.write(transaction).unwrap();
+
+        assert_eq!(vec![tab2.id], deleted);
+        assert!(storage.read_tab(&database, &tab2.id).unwrap().is_none());
+        assert!(storage.read_tab(&database, &tab3.id).unwrap().is_some());
+        assert_eq!(
+            vec![tab3.id],
+            storage
+                .read_trashed_tabs(&database)
+                .unwrap()
+                .iter()
+                .map(|trashed_tab| trashed_tab.id)
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,5 +1,7 @@
+use crate::db::DatabaseError;
 use crate::expense::{ExpenseCategory, ExpenseID};
 use crate::user::UserID;
+use chrono::NaiveDate;
 use commodity::CommodityError;
 use doublecount::AccountingError;
 use thiserror::Error;
@@ -11,10 +13,14 @@ pub enum CostingError {
     Accounting(#[from] AccountingError),
     #[error("error relating to currencies")]
     Currency(#[from] CommodityError),
+    #[error("error relating to the database")]
+    Database(#[from] DatabaseError),
     #[error("the specified User with id {0}, already exists on the Tab with id {1}")]
     UserAlreadyExistsOnTab(UserID, Uuid),
     #[error("the specified User with id {0}, does not exist on the Tab with id {1}")]
     UserDoesNotExistOnTab(UserID, Uuid),
+    #[error("the specified User with id {0}, is involved in expenses on the Tab with id {1}")]
+    UserHasExpensesOnTab(UserID, Uuid),
     #[error("there is no Account associated with the User with id {0} on the Tab with id {1}")]
     UserAccountDoesNotExistOnTab(UserID, Uuid),
     #[error("the specified Expense with id {0}, already exists on the Tab with id {1}")]
@@ -23,4 +29,10 @@ pub enum CostingError {
     ExpenseDoesNotExistOnTab(ExpenseID, Uuid),
     #[error("the specified Expense category {0}, does not have an account on the tab with id {1}")]
     NoExpenseCategoryAccountOnTab(ExpenseCategory, Uuid),
+    #[error("the specified date {0} is not after the end of the last closed period on the Tab with id {1}")]
+    PeriodAlreadyClosed(NaiveDate, Uuid),
+    #[error(
+        "the specified Expense with id {0}, occurs during a closed period on the Tab with id {1}"
+    )]
+    ExpenseInClosedPeriod(ExpenseID, Uuid),
 }
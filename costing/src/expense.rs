@@ -1,7 +1,8 @@
+use crate::db::{DatabaseValueSchema, Migrations, SchemaVersion};
 use crate::error::CostingError;
 use crate::tab::Tab;
 use crate::user::UserID;
-use chrono::{Local, NaiveDate};
+use chrono::NaiveDate;
 use commodity::{exchange_rate::ExchangeRate, Commodity};
 use doublecount::{Transaction, TransactionElement};
 use serde::{Deserialize, Serialize};
@@ -63,6 +64,7 @@ impl Expense {
     /// assert_eq!(vec![user1.id, user2.id], expense.shared_by);
     /// assert_eq!(Commodity::from_str("300.0 AUD").unwrap(), expense.amount);
     /// ```
+    #[allow(clippy::too_many_arguments)]
     pub fn new<S: Into<String>, EC: Into<ExpenseCategory>>(
         id: ExpenseID,
         description: S,
@@ -158,7 +160,8 @@ impl Expense {
         ))
     }
 
-    /// Get a transaction where this expense is shared by all the users involved
+    /// Get a transaction where this expense is shared by all the users
+    /// involved, which occurs on the same `date` as the expense.
     ///
     /// # Example
     /// ```
@@ -205,6 +208,7 @@ impl Expense {
     ///                                      .get_shared_transaction(&tab)
     ///                                      .unwrap();
     ///
+    /// assert_eq!(NaiveDate::from_ymd(2020, 2, 27), shared_transaction.date);
     /// assert_eq!(3, shared_transaction.elements.len());
     /// assert!(shared_transaction.get_element(&user1_account.id).is_none());
     /// let user2_element = shared_transaction.get_element(&user2_account.id).unwrap();
@@ -242,8 +246,16 @@ impl Expense {
 
         Ok(Transaction::new(
             Some(self.description.clone()),
-            Local::today().naive_local(),
+            self.date,
             elements,
         ))
     }
 }
+
+impl DatabaseValueSchema for Expense {
+    const SCHEMA_VERSION: SchemaVersion = 0;
+
+    fn migrations() -> Migrations {
+        Migrations::new()
+    }
+}
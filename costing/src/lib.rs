@@ -4,12 +4,14 @@ pub mod actions;
 pub mod db;
 mod error;
 mod expense;
+mod period;
 mod settlement;
 mod tab;
 mod user;
 
 pub use error::*;
 pub use expense::*;
+pub use period::*;
 pub use settlement::*;
 pub use tab::*;
 pub use user::*;
@@ -51,7 +53,9 @@ mod tests {
             vec![expense],
         );
 
-        let settlements = tab.balance_transactions().unwrap();
+        let settlements = tab
+            .balance_transactions(NaiveDate::from_ymd(2020, 3, 31))
+            .unwrap();
 
         assert_eq!(2, settlements.len());
 
@@ -135,7 +139,9 @@ mod tests {
             expenses,
         );
 
-        let settlements = tab.balance_transactions().unwrap();
+        let settlements = tab
+            .balance_transactions(NaiveDate::from_ymd(2020, 3, 31))
+            .unwrap();
 
         assert_eq!(2, settlements.len());
 
@@ -153,4 +159,227 @@ mod tests {
             Commodity::default_epsilon()
         ));
     }
+
+    #[test]
+    fn balance_debtor_with_several_creditors() {
+        let aud = Rc::from(CommodityType::from_currency_alpha3("AUD").unwrap());
+
+        let user1 = Rc::from(User::new(1, "User 1", None));
+        let user2 = Rc::from(User::new(2, "User 2", None));
+        let user3 = Rc::from(User::new(3, "User 3", None));
+
+        let expenses = vec![
+            Expense::new(
+                1,
+                "Petrol",
+                "Test",
+                NaiveDate::from_ymd(2020, 2, 27),
+                user1.id,
+                vec![user3.id],
+                Commodity::from_str("100.0 AUD").unwrap(),
+                None,
+            ),
+            Expense::new(
+                2,
+                "Groceries",
+                "Test",
+                NaiveDate::from_ymd(2020, 2, 28),
+                user2.id,
+                vec![user3.id],
+                Commodity::from_str("200.0 AUD").unwrap(),
+                None,
+            ),
+            // user3 owes 100.0 to user1, and 200.0 to user2, which
+            // settles both of the creditors while balancing user3's
+            // debt.
+        ];
+
+        let tab = Tab::new(
+            Uuid::parse_str("936DA01F9ABD4d9d80C702AF85C822A8").unwrap(),
+            "Test",
+            aud.id,
+            vec![user1.clone(), user2.clone(), user3.clone()],
+            expenses,
+        );
+
+        let settlements = tab
+            .balance_transactions(NaiveDate::from_ymd(2020, 3, 31))
+            .unwrap();
+
+        assert_eq!(2, settlements.len());
+
+        let user1_settlement = settlements.iter().find(|s| s.receiver == user1.id).unwrap();
+        assert_eq!(user3.id, user1_settlement.sender);
+        assert_eq!(
+            Commodity::from_str("100.0 AUD").unwrap(),
+            user1_settlement.amount
+        );
+
+        let user2_settlement = settlements.iter().find(|s| s.receiver == user2.id).unwrap();
+        assert_eq!(user3.id, user2_settlement.sender);
+        assert_eq!(
+            Commodity::from_str("200.0 AUD").unwrap(),
+            user2_settlement.amount
+        );
+    }
+
+    #[test]
+    fn balance_with_closed_period() {
+        let aud = Rc::from(CommodityType::from_currency_alpha3("AUD").unwrap());
+
+        let user1 = Rc::from(User::new(1, "User 1", None));
+        let user2 = Rc::from(User::new(2, "User 2", None));
+        let user3 = Rc::from(User::new(3, "User 3", None));
+
+        let expenses = vec![
+            Expense::new(
+                1,
+                "Petrol",
+                "Test",
+                NaiveDate::from_ymd(2020, 2, 27),
+                user1.id,
+                vec![user2.id, user3.id],
+                Commodity::from_str("300.0 AUD").unwrap(),
+                None,
+            ),
+            // user2 and user3 each owe 150.0 to user1.
+            Expense::new(
+                2,
+                "Groceries",
+                "Test",
+                NaiveDate::from_ymd(2020, 3, 10),
+                user2.id,
+                vec![user1.id, user3.id],
+                Commodity::from_str("300.0 AUD").unwrap(),
+                None,
+            ),
+            // user1 and user3 each owe 150.0 to user2.
+
+            // Expected totals after all this:
+            // user3 owes 150.0 to user1, and 150.0 to user2
+        ];
+
+        let mut tab = Tab::new(
+            Uuid::parse_str("936DA01F9ABD4d9d80C702AF85C822A8").unwrap(),
+            "Test",
+            aud.id,
+            vec![user1.clone(), user2.clone(), user3.clone()],
+            expenses,
+        );
+
+        let balances_before = tab.user_balances(NaiveDate::from_ymd(2020, 3, 31)).unwrap();
+
+        let period = tab.close_period(NaiveDate::from_ymd(2020, 3, 1)).unwrap();
+        assert_eq!(1, period.expenses.len());
+        assert_eq!(
+            Commodity::from_str("300.0 AUD").unwrap(),
+            *period.closing_balances.get(&user1.id).unwrap()
+        );
+        assert_eq!(1, tab.expenses.len());
+
+        // closing the period doesn't change the balance of the tab
+        let balances_after = tab.user_balances(NaiveDate::from_ymd(2020, 3, 31)).unwrap();
+        for (user_id, balance) in &balances_before {
+            assert!(balances_after
+                .get(user_id)
+                .unwrap()
+                .eq_approx(*balance, Commodity::default_epsilon()));
+        }
+
+        // a period can only be closed after the previous one
+        assert!(tab.close_period(NaiveDate::from_ymd(2020, 2, 1)).is_err());
+
+        let settlements = tab
+            .balance_transactions(NaiveDate::from_ymd(2020, 3, 31))
+            .unwrap();
+
+        assert_eq!(2, settlements.len());
+
+        for settlement in &settlements {
+            assert_eq!(user3.id, settlement.sender);
+            assert_eq!(Commodity::from_str("150.0 AUD").unwrap(), settlement.amount);
+        }
+
+        assert!(settlements.iter().any(|s| s.receiver == user1.id));
+        assert!(settlements.iter().any(|s| s.receiver == user2.id));
+    }
+
+    #[test]
+    fn balance_as_of() {
+        let aud = Rc::from(CommodityType::from_currency_alpha3("AUD").unwrap());
+
+        let user1 = Rc::from(User::new(1, "User 1", None));
+        let user2 = Rc::from(User::new(2, "User 2", None));
+
+        let expenses = vec![
+            Expense::new(
+                1,
+                "Petrol",
+                "Test",
+                NaiveDate::from_ymd(2020, 2, 10),
+                user1.id,
+                vec![user1.id, user2.id],
+                Commodity::from_str("100.0 AUD").unwrap(),
+                None,
+            ),
+            Expense::new(
+                2,
+                "Groceries",
+                "Test",
+                NaiveDate::from_ymd(2020, 2, 20),
+                user1.id,
+                vec![user1.id, user2.id],
+                Commodity::from_str("200.0 AUD").unwrap(),
+                None,
+            ),
+            Expense::new(
+                3,
+                "Dinner",
+                "Test",
+                NaiveDate::from_ymd(2020, 3, 10),
+                user1.id,
+                vec![user1.id, user2.id],
+                Commodity::from_str("400.0 AUD").unwrap(),
+                None,
+            ),
+        ];
+
+        let mut tab = Tab::new(
+            Uuid::parse_str("936DA01F9ABD4d9d80C702AF85C822A8").unwrap(),
+            "Test",
+            aud.id,
+            vec![user1.clone(), user2.clone()],
+            expenses,
+        );
+
+        tab.close_period(NaiveDate::from_ymd(2020, 3, 1)).unwrap();
+
+        let owed_as_of = |date: NaiveDate| -> Commodity {
+            let settlements = tab.balance_transactions(date).unwrap();
+            assert_eq!(1, settlements.len());
+            let settlement = settlements.first().unwrap();
+            assert_eq!(user2.id, settlement.sender);
+            assert_eq!(user1.id, settlement.receiver);
+            settlement.amount
+        };
+
+        // within the closed period
+        assert_eq!(
+            Commodity::from_str("50.0 AUD").unwrap(),
+            owed_as_of(NaiveDate::from_ymd(2020, 2, 15))
+        );
+        assert_eq!(
+            Commodity::from_str("150.0 AUD").unwrap(),
+            owed_as_of(NaiveDate::from_ymd(2020, 2, 25))
+        );
+        // within the current period, before and after the last expense
+        assert_eq!(
+            Commodity::from_str("150.0 AUD").unwrap(),
+            owed_as_of(NaiveDate::from_ymd(2020, 3, 5))
+        );
+        assert_eq!(
+            Commodity::from_str("350.0 AUD").unwrap(),
+            owed_as_of(NaiveDate::from_ymd(2020, 3, 10))
+        );
+    }
 }
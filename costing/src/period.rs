@@ -0,0 +1,72 @@
+use crate::db::{
+    migrate_nested_array, DatabaseValueSchema, MigrationError, Migrations, SchemaVersion,
+};
+use crate::expense::Expense;
+use crate::user::UserID;
+use chrono::NaiveDate;
+use commodity::Commodity;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A period of a [Tab](crate::Tab) which has been closed. The
+/// [Expense](Expense)s incurred during the period are archived here,
+/// and no longer take part in balancing the tab, instead the balance
+/// of each user at the end of the period is carried forward into the
+/// next period.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TabPeriod {
+    /// The date that the previous period was closed (and this period
+    /// started), or `None` if this was the first period of the tab.
+    pub start: Option<NaiveDate>,
+    /// The date that this period was closed. All the expenses in this
+    /// period occurred before this date.
+    pub end: NaiveDate,
+    /// The expenses which were incurred during this period.
+    pub expenses: Vec<Expense>,
+    /// The balance of each [User](crate::User) at the end of this
+    /// period, which is carried forward into the next period. A
+    /// positive amount is owed to the user, and a negative amount is
+    /// owed by the user.
+    pub closing_balances: HashMap<UserID, Commodity>,
+}
+
+impl TabPeriod {
+    /// Create a new [TabPeriod](TabPeriod).
+    pub fn new(
+        start: Option<NaiveDate>,
+        end: NaiveDate,
+        expenses: Vec<Expense>,
+        closing_balances: HashMap<UserID, Commodity>,
+    ) -> TabPeriod {
+        TabPeriod {
+            start,
+            end,
+            expenses,
+            closing_balances,
+        }
+    }
+}
+
+impl DatabaseValueSchema for TabPeriod {
+    const SCHEMA_VERSION: SchemaVersion = 0;
+
+    fn migrations() -> Migrations {
+        Migrations::new()
+    }
+
+    fn migrate_nested(
+        mut value: serde_json::Value,
+        from: SchemaVersion,
+    ) -> Result<serde_json::Value, MigrationError> {
+        migrate_nested_array::<Self, Expense>(
+            value.get_mut("expenses"),
+            &TAB_PERIOD_EXPENSE_VERSIONS,
+            from,
+        )?;
+        Ok(value)
+    }
+}
+
+/// The version of the [Expense] schema for each version of the
+/// [TabPeriod] schema.
+const TAB_PERIOD_EXPENSE_VERSIONS: [SchemaVersion; 1] = [0];
@@ -8,7 +8,7 @@ use doublecount::Transaction;
 use serde::{Deserialize, Serialize};
 
 /// Represents the settlement of a debt that one user owes another.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Settlement {
     /// The user who has a debt and needs to send the money.
     pub sender: UserID,
@@ -1,21 +1,24 @@
 use crate::db::{
-    DBTransactionSerde, DatabaseValueID, DatabaseValueRead, DatabaseValueWrite, KeyValueDBSerde,
-    KeyValueDBStore,
+    delete_with_children, migrate_nested_array, DBTransactionSerde, DatabaseError,
+    DatabaseValueDelete, DatabaseValueID, DatabaseValueRead, DatabaseValueSchema,
+    DatabaseValueWrite, KeyValueDBSerde, KeyValueDBStore, MigrationError, Migrations,
+    SchemaVersion,
 };
 use crate::error::CostingError;
-use crate::expense::{Expense, ExpenseCategory};
+use crate::expense::{Expense, ExpenseCategory, ExpenseID};
+use crate::period::TabPeriod;
 use crate::settlement::Settlement;
 use crate::{
     actions::TabUserActionType,
     user::{User, UserID},
 };
-use chrono::{Local, NaiveDate};
+use chrono::NaiveDate;
 use commodity::{Commodity, CommodityTypeID};
 use doublecount::{
     sum_account_states, Account, AccountID, AccountState, AccountStatus, AccountingError,
     ActionTypeValue, Program, ProgramState, Transaction, TransactionElement,
 };
-use kvdb::KeyValueDB;
+use kvdb::{DBTransaction, KeyValueDB};
 use serde::{Deserialize, Serialize};
 use std::{cmp::Reverse, collections::HashMap, fmt::Display, rc::Rc};
 use uuid::Uuid;
@@ -41,18 +44,17 @@ impl Accounts {
             HashMap::with_capacity(expenses.len());
 
         for user in users {
-            let account = Rc::from(Tab::new_account_for_user(&user, working_currency));
+            let account = Rc::from(Tab::new_account_for_user(user, working_currency));
 
-            match user_accounts.insert(user.id, account) {
-                Some(_) => panic!("There are duplicate users with id {0}", user.id),
-                None => {}
+            if user_accounts.insert(user.id, account).is_some() {
+                panic!("There are duplicate users with id {0}", user.id)
             }
         }
 
         for expense in expenses {
-            if !expense_category_accounts.get(&expense.category).is_some() {
+            if !expense_category_accounts.contains_key(&expense.category) {
                 let account = Rc::from(Tab::new_account_for_expense_category(
-                    &expense,
+                    expense,
                     working_currency,
                 ));
                 expense_category_accounts.insert(expense.category.clone(), account);
@@ -66,6 +68,22 @@ impl Accounts {
     }
 }
 
+/// The results of executing the transactions on a [Tab], used to
+/// calculate the balance of each user.
+struct BalanceStates {
+    /// All the [Account]s involved in the transactions.
+    accounts: Vec<Rc<Account>>,
+    /// The transactions that actually occurred (the expenses being
+    /// paid, and the opening balances).
+    actual_transactions: Vec<Rc<ActionTypeValue>>,
+    /// The desired end-state of the accounts, where all users have
+    /// fairly shared the expenses they have participated in.
+    shared_states: HashMap<AccountID, AccountState>,
+    /// The difference between the actual and the desired state of
+    /// each user's account.
+    user_differences: HashMap<AccountID, AccountState>,
+}
+
 /// A deserializeable version of [Tab], designed for wire transfers,
 /// without the [Account]s.
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -82,6 +100,9 @@ pub struct TabData {
     pub expenses: Vec<Expense>,
     /// Actions performed by the users of this tab
     pub user_actions: Vec<TabUserActionType>,
+    /// The periods of this tab which have been closed
+    #[serde(default)]
+    pub closed_periods: Vec<TabPeriod>,
 }
 
 impl TabData {
@@ -93,17 +114,86 @@ impl TabData {
             users: tab.users.clone(),
             expenses: tab.expenses.clone(),
             user_actions: tab.user_actions.clone(),
+            closed_periods: tab.closed_periods.clone(),
         }
     }
 }
 
+impl DatabaseValueSchema for TabData {
+    const SCHEMA_VERSION: SchemaVersion = 1;
+
+    fn migrations() -> Migrations {
+        Migrations::new().register(0, migrate_tab_data_v0)
+    }
+
+    fn migrate_nested(
+        mut value: serde_json::Value,
+        from: SchemaVersion,
+    ) -> Result<serde_json::Value, MigrationError> {
+        migrate_nested_array::<Self, User>(value.get_mut("users"), &TAB_DATA_USER_VERSIONS, from)?;
+        migrate_nested_array::<Self, Expense>(
+            value.get_mut("expenses"),
+            &TAB_DATA_EXPENSE_VERSIONS,
+            from,
+        )?;
+        migrate_nested_array::<Self, TabUserActionType>(
+            value.get_mut("user_actions"),
+            &TAB_DATA_ACTION_VERSIONS,
+            from,
+        )?;
+        migrate_nested_array::<Self, TabPeriod>(
+            value.get_mut("closed_periods"),
+            &TAB_DATA_PERIOD_VERSIONS,
+            from,
+        )?;
+        Ok(value)
+    }
+}
+
+/// The version of the [User] schema for each version of the [TabData]
+/// schema, and likewise for the other values nested in a [TabData].
+const TAB_DATA_USER_VERSIONS: [SchemaVersion; 2] = [0, 0];
+const TAB_DATA_EXPENSE_VERSIONS: [SchemaVersion; 2] = [0, 0];
+const TAB_DATA_ACTION_VERSIONS: [SchemaVersion; 2] = [0, 0];
+const TAB_DATA_PERIOD_VERSIONS: [SchemaVersion; 2] = [0, 0];
+
+/// Version `1` of the [TabData] schema introduced `closed_periods`.
+fn migrate_tab_data_v0(mut value: serde_json::Value) -> Result<serde_json::Value, MigrationError> {
+    let map = value
+        .as_object_mut()
+        .ok_or_else(|| MigrationError::InvalidValue(0, "TabData is not an object".to_string()))?;
+
+    map.entry("closed_periods")
+        .or_insert_with(|| serde_json::Value::Array(Vec::new()));
+
+    Ok(value)
+}
+
 impl From<TabData> for Tab {
     fn from(tab_data: TabData) -> Self {
-        let accounts = Accounts::new(
+        let mut accounts = Accounts::new(
             &tab_data.users,
             &tab_data.expenses,
             tab_data.working_currency,
         );
+        // the expenses archived in closed periods need an account for
+        // their category too, to calculate the balances before the
+        // periods were closed
+        for expense in tab_data
+            .closed_periods
+            .iter()
+            .flat_map(|period| period.expenses.iter())
+        {
+            if !accounts.expense_categories.contains_key(&expense.category) {
+                let account = Rc::new(Tab::new_account_for_expense_category(
+                    expense,
+                    tab_data.working_currency,
+                ));
+                accounts
+                    .expense_categories
+                    .insert(expense.category.clone(), account);
+            }
+        }
         Tab {
             id: tab_data.id,
             name: tab_data.name,
@@ -111,6 +201,7 @@ impl From<TabData> for Tab {
             users: tab_data.users,
             expenses: tab_data.expenses,
             user_actions: tab_data.user_actions,
+            closed_periods: tab_data.closed_periods,
             accounts,
         }
     }
@@ -132,6 +223,8 @@ pub struct Tab {
     pub expenses: Vec<Expense>,
     /// Actions performed by the users of this tab
     pub user_actions: Vec<TabUserActionType>,
+    /// The periods of this tab which have been closed
+    pub closed_periods: Vec<TabPeriod>,
     accounts: Accounts,
 }
 
@@ -153,13 +246,14 @@ impl Tab {
             users,
             expenses,
             user_actions: vec![],
+            closed_periods: vec![],
             accounts,
         }
     }
 
     fn new_account_for_user(user: &User, working_currency: CommodityTypeID) -> Account {
         Account::new_with_id(
-            Some(format!("User-{}-{}", user.id.to_string(), user.name)),
+            Some(format!("User-{}-{}", user.id, user.name)),
             working_currency,
             Some("Users".to_string()),
         )
@@ -176,6 +270,14 @@ impl Tab {
         )
     }
 
+    fn new_opening_balances_account(working_currency: CommodityTypeID) -> Account {
+        Account::new_with_id(
+            Some("Opening Balances".to_string()),
+            working_currency,
+            Some("Equity".to_string()),
+        )
+    }
+
     pub fn user(&self, user_id: &UserID) -> Result<&Rc<User>, CostingError> {
         for u in self.users.iter() {
             if &u.id == user_id {
@@ -190,7 +292,9 @@ impl Tab {
         self.accounts
             .users
             .get(user_id)
-            .ok_or_else(|| CostingError::UserAccountDoesNotExistOnTab(*user_id, self.id))
+            .ok_or(CostingError::UserAccountDoesNotExistOnTab(
+                *user_id, self.id,
+            ))
     }
 
     pub fn get_expense_category_account(
@@ -203,7 +307,45 @@ impl Tab {
             .ok_or_else(|| CostingError::NoExpenseCategoryAccountOnTab(category.clone(), self.id))
     }
 
+    /// Add an [Account] for the category of the `expense` to this
+    /// tab, if it does not already have one.
+    pub(crate) fn add_expense_category_account(&mut self, expense: &Expense) {
+        if !self
+            .accounts
+            .expense_categories
+            .contains_key(&expense.category)
+        {
+            let account = Rc::new(Tab::new_account_for_expense_category(
+                expense,
+                self.working_currency,
+            ));
+            self.accounts
+                .expense_categories
+                .insert(expense.category.clone(), account);
+        }
+    }
+
+    /// Remove the user with the specified id from this tab. A user
+    /// who is involved in any expenses (including those in closed
+    /// periods), or who has a balance carried forward, cannot be
+    /// removed.
     pub fn remove_user(&mut self, user_id: &UserID) -> Result<(), CostingError> {
+        let has_expenses = self
+            .closed_periods
+            .iter()
+            .flat_map(|period| period.expenses.iter())
+            .chain(self.expenses.iter())
+            .any(|expense| expense.paid_by == *user_id || expense.shared_by.contains(user_id));
+        let has_balance = self
+            .closed_periods
+            .iter()
+            .any(|period| period.closing_balances.contains_key(user_id));
+
+        self.user(user_id)?;
+        if has_expenses || has_balance {
+            return Err(CostingError::UserHasExpensesOnTab(*user_id, self.id));
+        }
+
         for (i, u) in self.users.iter().enumerate() {
             if &u.id == user_id {
                 self.users.remove(i);
@@ -234,26 +376,149 @@ impl Tab {
         &self.users
     }
 
-    /// Produce a set of transactions, that, when applied to the
-    /// result of the actual transactions generated by this Tab's
-    /// expenses, will ensure that each user has fairly shared each
-    /// expense that they have participated in.
-    ///
-    /// The aim here, is to produce a minimal set of transactions,
-    /// which favour users who have smaller debts making less
-    /// transactions, and those with larget debts making more
-    /// transactions.
-    pub fn balance_transactions(&self) -> Result<Vec<Settlement>, CostingError> {
+    /// An id which is greater than that of every [User] on this tab.
+    /// Users involved in expenses can't be removed, so this id is
+    /// never referred to by an existing expense.
+    pub fn next_user_id(&self) -> UserID {
+        self.users.iter().map(|u| u.id + 1).max().unwrap_or(0)
+    }
+
+    /// An id which is greater than that of every [Expense] on this
+    /// tab, including those in closed periods.
+    pub fn next_expense_id(&self) -> ExpenseID {
+        self.closed_periods
+            .iter()
+            .flat_map(|period| period.expenses.iter())
+            .chain(self.expenses.iter())
+            .map(|e| e.id + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The balances carried forward from the most recently closed
+    /// [TabPeriod](TabPeriod), or `None` if no period has been closed
+    /// on this tab yet.
+    pub fn opening_balances(&self) -> Option<&HashMap<UserID, Commodity>> {
+        self.closed_periods
+            .last()
+            .map(|period| &period.closing_balances)
+    }
+
+    /// The date that the current period of this tab started (the end
+    /// of the most recently closed [TabPeriod](TabPeriod)), or `None`
+    /// if no period has been closed on this tab yet.
+    pub fn current_period_start(&self) -> Option<NaiveDate> {
+        self.closed_periods.last().map(|period| period.end)
+    }
+
+    /// Close the current period of this tab, archiving all the
+    /// expenses which occurred before the `end` date into a new
+    /// [TabPeriod](TabPeriod). The balance of each user at the end of
+    /// the period is carried forward, and used as the opening balance
+    /// when balancing the remaining expenses on this tab.
+    pub fn close_period(&mut self, end: NaiveDate) -> Result<&TabPeriod, CostingError> {
+        let start = self.current_period_start();
+
+        if let Some(start) = start {
+            if end <= start {
+                return Err(CostingError::PeriodAlreadyClosed(end, self.id));
+            }
+        }
+
+        let (period_expenses, expenses): (Vec<Expense>, Vec<Expense>) = self
+            .expenses
+            .iter()
+            .cloned()
+            .partition(|expense| expense.date < end);
+
+        let closing_balances =
+            self.expenses_user_balances(&period_expenses, self.closed_periods.last())?;
+
+        self.expenses = expenses;
+        self.closed_periods.push(TabPeriod::new(
+            start,
+            end,
+            period_expenses,
+            closing_balances,
+        ));
+
+        Ok(self.closed_periods.last().unwrap())
+    }
+
+    /// Calculate the balance of each user on this tab as of the
+    /// specified date, including the balances carried forward from
+    /// closed periods. A positive amount is owed to the user, and a
+    /// negative amount is owed by the user.
+    pub fn user_balances(
+        &self,
+        as_of: NaiveDate,
+    ) -> Result<HashMap<UserID, Commodity>, CostingError> {
+        let (expenses, opening_period) = self.expenses_as_of(as_of);
+        self.expenses_user_balances(&expenses, opening_period)
+    }
+
+    /// Select the expenses which occurred on or before the `as_of`
+    /// date (within the period which contains that date), and the
+    /// closed [TabPeriod](TabPeriod) which provides the opening
+    /// balances for that period.
+    fn expenses_as_of(&self, as_of: NaiveDate) -> (Vec<Expense>, Option<&TabPeriod>) {
+        let (expenses, opening_period) =
+            match self.closed_periods.iter().position(|p| as_of < p.end) {
+                Some(i) => {
+                    let opening_period = if i > 0 {
+                        self.closed_periods.get(i - 1)
+                    } else {
+                        None
+                    };
+                    (&self.closed_periods[i].expenses, opening_period)
+                }
+                None => (&self.expenses, self.closed_periods.last()),
+            };
+
+        let expenses = expenses
+            .iter()
+            .filter(|expense| expense.date <= as_of)
+            .cloned()
+            .collect();
+
+        (expenses, opening_period)
+    }
+
+    fn expenses_user_balances(
+        &self,
+        expenses: &[Expense],
+        opening_period: Option<&TabPeriod>,
+    ) -> Result<HashMap<UserID, Commodity>, CostingError> {
+        let states = self.balance_states(expenses, opening_period)?;
+
+        states
+            .user_differences
+            .values()
+            .map(|state| {
+                let user = self.get_user_with_account(&state.account.id)?;
+                Ok((user.id, state.amount))
+            })
+            .collect()
+    }
+
+    /// Execute the actual transactions (including the opening
+    /// balances carried forward from the `opening_period`), and the
+    /// transactions where `expenses` are fairly shared, and calculate
+    /// the difference between the resulting states of the user
+    /// accounts.
+    fn balance_states(
+        &self,
+        expenses: &[Expense],
+        opening_period: Option<&TabPeriod>,
+    ) -> Result<BalanceStates, CostingError> {
         let zero = Commodity::zero(self.working_currency);
 
-        let mut actual_transactions: Vec<Rc<ActionTypeValue>> =
-            Vec::with_capacity(self.expenses.len());
-        let mut shared_transactions: Vec<Rc<ActionTypeValue>> =
-            Vec::with_capacity(self.expenses.len());
+        let mut actual_transactions: Vec<Rc<ActionTypeValue>> = Vec::with_capacity(expenses.len());
+        let mut shared_transactions: Vec<Rc<ActionTypeValue>> = Vec::with_capacity(expenses.len());
 
         let mut accounts: HashMap<AccountID, Rc<Account>> = HashMap::new();
 
-        for expense in &self.expenses {
+        for expense in expenses {
             actual_transactions.push(Rc::new(expense.get_actual_transaction(self)?.into()));
             shared_transactions.push(Rc::new(expense.get_shared_transaction(self)?.into()));
 
@@ -261,24 +526,43 @@ impl Tab {
             accounts.insert(account.id, account.clone());
         }
 
-        let expense_accounts: Vec<Rc<Account>> = accounts.iter().map(|(_, v)| v.clone()).collect();
+        // the balances carried forward from the previous period are
+        // treated as though each user paid their balance into an
+        // opening balances account.
+        if let Some(period) = opening_period {
+            let account = Rc::new(Tab::new_opening_balances_account(self.working_currency));
+
+            for (user_id, balance) in &period.closing_balances {
+                let transaction = Transaction::new(
+                    Some("Opening Balance"),
+                    period.end,
+                    vec![
+                        TransactionElement::new(
+                            self.get_user_account(user_id)?.id,
+                            Some(balance.neg()),
+                            None,
+                        ),
+                        TransactionElement::new(account.id, None, None),
+                    ],
+                );
+                actual_transactions.push(Rc::new(transaction.into()));
+            }
+
+            accounts.insert(account.id, account);
+        }
+
+        let non_user_accounts: Vec<Rc<Account>> = accounts.values().cloned().collect();
 
         let actual_program = Program::new(actual_transactions.clone());
 
         for user in &self.users {
             let account = self.get_user_account(&user.id)?;
-            match accounts.insert(account.id, account.clone()) {
-                Some(account) => {
-                    panic!(format!(
-                        "there is a duplicate account with id: {}",
-                        account.id
-                    ));
-                }
-                None => {}
+            if let Some(account) = accounts.insert(account.id, account.clone()) {
+                panic!("there is a duplicate account with id: {}", account.id);
             }
         }
 
-        let accounts_vec: Vec<Rc<Account>> = accounts.into_iter().map(|(_, v)| v).collect();
+        let accounts_vec: Vec<Rc<Account>> = accounts.into_values().collect();
         let mut actual_program_state = ProgramState::new(&accounts_vec, AccountStatus::Open);
 
         actual_program_state.execute_program(&actual_program)?;
@@ -290,21 +574,21 @@ impl Tab {
         let mut shared_program_state = ProgramState::new(&accounts_vec, AccountStatus::Open);
         shared_program_state.execute_program(&shared_program)?;
 
-        let account_states_from = &mut actual_program_state.account_states;
-        let account_states_to = &mut shared_program_state.account_states;
+        let account_states_from = actual_program_state.account_states;
+        let account_states_to = shared_program_state.account_states;
 
         let from_sum_with_expenses =
-            sum_account_states(account_states_from, self.working_currency, None)?;
+            sum_account_states(&account_states_from, self.working_currency, None)?;
         assert!(from_sum_with_expenses.eq_approx(zero, Commodity::default_epsilon()));
         let to_sum_with_expenses =
-            sum_account_states(account_states_to, self.working_currency, None)?;
+            sum_account_states(&account_states_to, self.working_currency, None)?;
         assert!(to_sum_with_expenses.eq_approx(zero, Commodity::default_epsilon()));
 
         let mut account_states_from_without_expenses = account_states_from.clone();
         let mut account_states_to_without_expenses = account_states_to.clone();
 
-        // remove the expense accounts from the states
-        for account in &expense_accounts {
+        // remove the expense and opening balance accounts from the states
+        for account in &non_user_accounts {
             account_states_from_without_expenses.remove(&account.id);
             account_states_to_without_expenses.remove(&account.id);
         }
@@ -319,6 +603,39 @@ impl Tab {
 
         assert!(differences_sum.eq_approx(zero, Commodity::default_epsilon()));
 
+        Ok(BalanceStates {
+            accounts: accounts_vec,
+            actual_transactions,
+            shared_states: account_states_to,
+            user_differences: account_differences,
+        })
+    }
+
+    /// Produce a set of transactions, that, when applied to the
+    /// result of the actual transactions generated by this Tab's
+    /// expenses, will ensure that each user has fairly shared each
+    /// expense that they have participated in.
+    ///
+    /// The aim here, is to produce a minimal set of transactions,
+    /// which favour users who have smaller debts making less
+    /// transactions, and those with larget debts making more
+    /// transactions.
+    ///
+    /// Only the expenses which occurred on or before the `as_of`
+    /// date are considered, and the balancing transactions are dated
+    /// `as_of`, so the result is the same regardless of when it is
+    /// calculated.
+    pub fn balance_transactions(&self, as_of: NaiveDate) -> Result<Vec<Settlement>, CostingError> {
+        let zero = Commodity::zero(self.working_currency);
+        let (expenses, opening_period) = self.expenses_as_of(as_of);
+
+        let BalanceStates {
+            accounts: accounts_vec,
+            actual_transactions,
+            shared_states: account_states_to,
+            user_differences: account_differences,
+        } = self.balance_states(&expenses, opening_period)?;
+
         let mut negative_differences: Vec<AccountState> =
             Vec::with_capacity(account_differences.len());
         let mut positive_differences: Vec<AccountState> =
@@ -326,7 +643,7 @@ impl Tab {
 
         // create two lists of account state differences associated with those users
         // one list of negative, and one list of positive
-        for (_, state) in &account_differences {
+        for state in account_differences.values() {
             if state.amount.lt(&zero)? {
                 negative_differences.push(state.clone());
             } else if state.amount.gt(&zero)? {
@@ -354,9 +671,6 @@ impl Tab {
             // differences (the accounts which are owed)
             let negated_negative_state_amount = negative_difference_state.amount.neg();
 
-            // cache today's date
-            let today = Local::today().naive_local();
-
             // find continue on to find the first state which is
             // bigger or equal to the selected state if found, create
             // a transaction to cancel out the selected state's debt,
@@ -366,7 +680,7 @@ impl Tab {
 
                 if positive_difference_state.amount >= negated_negative_state_amount {
                     let mut transactions = balance_entire_negative_into_positive(
-                        today,
+                        as_of,
                         negative_difference_state,
                         positive_difference_state,
                         &zero,
@@ -392,7 +706,7 @@ impl Tab {
                     if positive_difference_state.amount <= negated_negative_state_amount {
                         balancing_transactions.push(Transaction::new_simple(
                             Some("balancing"),
-                            today,
+                            as_of,
                             negative_difference_state.account.id,
                             positive_difference_state.account.id,
                             positive_difference_state.amount,
@@ -405,7 +719,7 @@ impl Tab {
                         positive_difference_state.amount = zero;
                     } else {
                         let mut transactions = balance_entire_negative_into_positive(
-                            today,
+                            as_of,
                             negative_difference_state,
                             positive_difference_state,
                             &zero,
@@ -423,8 +737,9 @@ impl Tab {
                 }
             }
 
-            // remove positive differences with a now zero amount
-            for i in &to_remove_positive {
+            // remove positive differences with a now zero amount (in
+            // reverse, so the remaining indices stay valid)
+            for i in to_remove_positive.iter().rev() {
                 positive_differences.remove(*i);
             }
 
@@ -452,14 +767,14 @@ impl Tab {
         let actual_balanced_states = &actual_balanced_transactions_states.account_states;
 
         let actual_balanced_sum =
-            sum_account_states(&actual_balanced_states, self.working_currency, None)?;
+            sum_account_states(actual_balanced_states, self.working_currency, None)?;
         assert!(actual_balanced_sum.eq_approx(zero, Commodity::default_epsilon()));
 
         // dbg!(&account_states_to);
         // dbg!(&actual_balanced_states);
 
         assert_eq!(account_states_to.len(), actual_balanced_states.len());
-        for (id, to_state) in account_states_to {
+        for (id, to_state) in &account_states_to {
             let balanced_state = actual_balanced_states.get(id).unwrap();
             to_state.eq_approx(balanced_state, Commodity::default_epsilon());
         }
@@ -469,7 +784,7 @@ impl Tab {
             .map(|transaction: &Transaction| {
                 assert_eq!(2, transaction.elements.len());
 
-                let element0: &TransactionElement = transaction.elements.get(0).unwrap();
+                let element0: &TransactionElement = transaction.elements.first().unwrap();
                 let element1: &TransactionElement = transaction.elements.get(1).unwrap();
 
                 let (sender_element, receiver_element) = if element0.amount.is_none() {
@@ -506,7 +821,7 @@ impl Tab {
             .users
             .iter()
             .find(|(_, v)| v.id == *account_id)
-            .map(|(k, _)| self.user(k).map(|u| u.clone()))
+            .map(|(k, _)| self.user(k).cloned())
             .unwrap()
     }
 }
@@ -517,7 +832,7 @@ impl DatabaseValueRead<TabID, ()> for Tab {
         path: P,
         database: &dyn KeyValueDB,
         db_store: &S,
-    ) -> Option<Self>
+    ) -> Result<Option<Self>, DatabaseError>
     where
         S: KeyValueDBStore,
         P: Into<Option<&'a str>>,
@@ -527,16 +842,19 @@ impl DatabaseValueRead<TabID, ()> for Tab {
             None => format!("{}", id),
         };
 
-        let tab_data: Option<TabData> = database
-            .get_deserialize(db_store, key)
-            .expect("unable to read tab from database");
+        let tab_data: Option<TabData> = database.get_deserialize_versioned(db_store, key)?;
 
-        tab_data.map(|td| td.into())
+        Ok(tab_data.map(|td| td.into()))
     }
 }
 
 impl DatabaseValueWrite<TabID> for Tab {
-    fn write_to_db<'a, T, S, P>(&self, path: P, transaction: &mut T, db_store: &S)
+    fn write_to_db<'a, T, S, P>(
+        &self,
+        path: P,
+        transaction: &mut T,
+        db_store: &S,
+    ) -> Result<(), DatabaseError>
     where
         T: DBTransactionSerde,
         S: KeyValueDBStore,
@@ -547,7 +865,31 @@ impl DatabaseValueWrite<TabID> for Tab {
             None => self.id.to_string(),
         };
 
-        transaction.put_serialize(db_store, key, TabData::from_tab(self));
+        transaction.put_serialize_versioned(db_store, key, &TabData::from_tab(self))
+    }
+}
+
+impl DatabaseValueDelete<TabID, ()> for Tab {
+    /// Delete the tab, whether it was stored as a single record, or
+    /// as granular records by [TabStorage](crate::db::tabs::TabStorage).
+    fn delete_from_db<'a, S, P>(
+        id: &TabID,
+        path: P,
+        _database: &dyn KeyValueDB,
+        transaction: &mut DBTransaction,
+        db_store: &S,
+    ) -> Result<(), DatabaseError>
+    where
+        S: KeyValueDBStore,
+        P: Into<Option<&'a str>>,
+    {
+        let key = match path.into() {
+            Some(path) => format!("{}/{}", path, id),
+            None => id.to_string(),
+        };
+
+        delete_with_children(transaction, db_store, &key);
+        Ok(())
     }
 }
 
@@ -586,7 +928,7 @@ fn account_state_difference(
             Some(state) => state,
             None => {
                 return Err(CostingError::Accounting(
-                    AccountingError::MissingAccountState(from_id.clone()),
+                    AccountingError::MissingAccountState(*from_id),
                 ))
             }
         };
@@ -594,7 +936,7 @@ fn account_state_difference(
         let difference_amount = to_state
             .amount
             .sub(&from_state.amount)
-            .map_err(|e| AccountingError::Commodity(e))?;
+            .map_err(AccountingError::Commodity)?;
 
         let difference_state = AccountState::new(
             to_state.account.clone(),
@@ -602,7 +944,7 @@ fn account_state_difference(
             AccountStatus::Open,
         );
 
-        result.insert(from_id.clone(), difference_state);
+        result.insert(*from_id, difference_state);
     }
 
     Ok(result)
@@ -631,5 +973,5 @@ fn balance_entire_negative_into_positive(
         .add(&negative_difference_state.amount)?;
     negative_difference_state.amount = *zero;
 
-    return Ok(transactions);
+    Ok(transactions)
 }
@@ -1,3 +1,4 @@
+use crate::db::{DatabaseValueSchema, Migrations, SchemaVersion};
 use serde::{Deserialize, Serialize};
 
 pub type UserID = i32;
@@ -19,11 +20,19 @@ impl User {
         User {
             id,
             name: String::from(name),
-            email: email.map(|e| String::from(e)),
+            email: email.map(String::from),
         }
     }
 }
 
+impl DatabaseValueSchema for User {
+    const SCHEMA_VERSION: SchemaVersion = 0;
+
+    fn migrations() -> Migrations {
+        Migrations::new()
+    }
+}
+
 impl PartialEq for User {
     fn eq(&self, other: &User) -> bool {
         self.id == other.id
@@ -1,9 +1,11 @@
 use crate::state::middleware::localize::LocalizeStore;
 use crate::{
-    state::{CosterEvent, StateCallback, StateStoreRef},
+    state::{CosterAction, CosterEvent, StateCallback, StateStoreRef},
     AppRoute,
 };
 
+use costing::actions::TabUserActionType;
+use costing::TabID;
 use tr::tr;
 use yew::MouseEvent;
 use yew::{html, Component, ComponentLink, Html, Properties, ShouldRender};
@@ -21,7 +23,10 @@ pub enum Msg {
     NewCostingTab,
     LanguageChanged,
     TabsChanged,
-    TestGraphQL,
+    DismissRejectedTabActions,
+    TrashTab(TabID),
+    RestoreTab(TabID),
+    DeleteTab(TabID),
 }
 
 #[derive(Clone, Properties, PartialEq)]
@@ -40,9 +45,13 @@ impl Component for CostingTabList {
 
         let tabs_changed_callback = link.callback(|(_store, _event)| Msg::TabsChanged).into();
 
-        props
-            .state_store
-            .subscribe_event(&tabs_changed_callback, CosterEvent::TabsChanged);
+        props.state_store.subscribe_events(
+            &tabs_changed_callback,
+            vec![
+                CosterEvent::TabsChanged,
+                CosterEvent::RejectedTabActionsChanged,
+            ],
+        );
 
         CostingTabList {
             props,
@@ -60,8 +69,31 @@ impl Component for CostingTabList {
             }
             Msg::LanguageChanged => true,
             Msg::TabsChanged => true,
-            Msg::TestGraphQL => {
-                crate::graphql::addtest::add_test();
+            Msg::DismissRejectedTabActions => {
+                self.props
+                    .state_store
+                    .dispatch(CosterAction::DismissRejectedTabActions);
+                false
+            }
+            Msg::TrashTab(tab_id) => {
+                self.props.state_store.dispatch(CosterAction::TrashTab {
+                    tab_id,
+                    write_to_database: true,
+                });
+                false
+            }
+            Msg::RestoreTab(tab_id) => {
+                self.props.state_store.dispatch(CosterAction::RestoreTab {
+                    tab_id,
+                    write_to_database: true,
+                });
+                false
+            }
+            Msg::DeleteTab(tab_id) => {
+                self.props.state_store.dispatch(CosterAction::DeleteTab {
+                    tab_id,
+                    write_to_database: true,
+                });
                 false
             }
         }
@@ -79,18 +111,96 @@ impl Component for CostingTabList {
     fn view(&self) -> Html {
         let state = self.props.state_store.state();
         let new_tab_handler = self.link.callback(|_msg: MouseEvent| Msg::NewCostingTab);
-        let test_graphql_handler = self.link.callback(|_msg: MouseEvent| Msg::TestGraphQL);
 
         let tabs_html_iter = state.tabs.iter().map(|tab| {
+            let tab_id = tab.id;
+            let trash_handler = self
+                .link
+                .callback(move |_msg: MouseEvent| Msg::TrashTab(tab_id));
+            html! {
+                <tr>
+                    <td>{ &tab.name }</td>
+                    <td>
+                        <button class="button is-small" onclick = trash_handler>{ tr!("Move to Trash") }</button>
+                    </td>
+                </tr>
+            }
+        });
+
+        let trashed_tabs_html_iter = state.trashed_tabs.iter().map(|tab| {
+            let tab_id = tab.id;
+            let restore_handler = self
+                .link
+                .callback(move |_msg: MouseEvent| Msg::RestoreTab(tab_id));
+            let delete_handler = self
+                .link
+                .callback(move |_msg: MouseEvent| Msg::DeleteTab(tab_id));
             html! {
                 <tr>
                     <td>{ &tab.name }</td>
+                    <td>
+                        <div class="buttons">
+                            <button class="button is-small" onclick = restore_handler>{ tr!("Restore") }</button>
+                            <button class="button is-small is-danger" onclick = delete_handler>{ tr!("Delete Permanently") }</button>
+                        </div>
+                    </td>
                 </tr>
             }
         });
 
+        let trash_html = if state.trashed_tabs.is_empty() {
+            html! {}
+        } else {
+            html! {
+                <>
+                    <h4 class="title is-4">{ tr!("Trash") }</h4>
+                    <table class="table is-striped is-fullwidth">
+                        <thead>
+                            <tr>
+                                <th>{ tr!("Tab Name") }</th>
+                                <th></th>
+                            </tr>
+                        </thead>
+                        <tbody>
+                            { for trashed_tabs_html_iter }
+                        </tbody>
+                    </table>
+                </>
+            }
+        };
+
+        let rejected_html_iter = state.rejected_tab_actions.iter().map(|rejected| {
+            let tab_name = state
+                .tabs
+                .iter()
+                .chain(state.trashed_tabs.iter())
+                .find(|tab| tab.id == rejected.tab_id)
+                .map_or_else(String::new, |tab| tab.name.clone());
+            html! {
+                <li>{ format!("{}: {} ({})", tab_name, describe_action(&rejected.action), rejected.message) }</li>
+            }
+        });
+
+        let rejected_html = if state.rejected_tab_actions.is_empty() {
+            html! {}
+        } else {
+            let dismiss_handler = self
+                .link
+                .callback(|_msg: MouseEvent| Msg::DismissRejectedTabActions);
+            html! {
+                <div class="notification is-warning">
+                    <button class="delete" onclick = dismiss_handler></button>
+                    <p>{ tr!("These changes couldn't be saved, because the tabs were changed on another device:") }</p>
+                    <ul>
+                        { for rejected_html_iter }
+                    </ul>
+                </div>
+            }
+        };
+
         html! {
             <>
+                { rejected_html }
                 <nav class="level">
                     <div class="level-left">
                         <div class="level-item">
@@ -107,14 +217,33 @@ impl Component for CostingTabList {
                     <thead>
                         <tr>
                             <th>{ tr!("Tab Name") }</th>
+                            <th></th>
                         </tr>
                     </thead>
                     <tbody>
                         { for tabs_html_iter }
                     </tbody>
                 </table>
-                <button class="button is-success" onclick = test_graphql_handler>{ "Test GraphQL" }</button>
+                { trash_html }
             </>
         }
     }
 }
+
+/// A short description of an `action`, which is shown to the user.
+fn describe_action(action: &TabUserActionType) -> String {
+    match action {
+        TabUserActionType::AddExpense(action) => {
+            tr!("Add the expense \"{0}\"", action.expense.description)
+        }
+        TabUserActionType::EditExpense(action) => {
+            tr!("Edit the expense \"{0}\"", action.expense.description)
+        }
+        TabUserActionType::RemoveExpense(_) => tr!("Remove an expense"),
+        TabUserActionType::AddUser(action) => tr!("Add {0}", action.user_to_add.name),
+        TabUserActionType::RemoveUser(_) => tr!("Remove a user"),
+        TabUserActionType::ChangeTabName(action) => tr!("Rename to \"{0}\"", action.name),
+        TabUserActionType::ClosePeriod(action) => tr!("Close the period ending {0}", action.end),
+        TabUserActionType::RecordSettlement(_) => tr!("Record a settlement"),
+    }
+}
@@ -0,0 +1,227 @@
+use crate::graphql::{
+    invite::{fetch_invite, invite::InviteInvite, redeem_invite},
+    session::store_session_token,
+};
+use crate::{
+    state::{middleware::localize::LocalizeStore, StateCallback, StateStoreRef},
+    AppRoute,
+};
+
+use log::error;
+use switch_router_middleware::RouteStore;
+use tr::tr;
+use wasm_bindgen_futures::spawn_local;
+use yew::{html, Component, ComponentLink, Html, InputData, Properties, ShouldRender};
+
+/// A page which shows the details of an invite to a tab (opened
+/// using the link to the invite), and lets the user join the tab.
+pub struct JoinTab {
+    props: Props,
+    link: ComponentLink<Self>,
+    /// The details of the invite, once they have been loaded.
+    invite: Option<Result<InviteInvite, String>>,
+    name: String,
+    joining: bool,
+    /// The error which occurred while joining the tab.
+    error: Option<String>,
+    _language_changed_callback: StateCallback,
+}
+
+#[derive(Clone)]
+pub enum Msg {
+    InviteLoaded(Result<InviteInvite, String>),
+    UpdateName(String),
+    Join,
+    Joined(Result<(), String>),
+    Cancel,
+    LanguageChanged,
+}
+
+#[derive(Clone, Properties, PartialEq)]
+pub struct Props {
+    pub state_store: StateStoreRef,
+    /// The token of the invite.
+    pub token: String,
+}
+
+impl JoinTab {
+    fn load_invite(&self) {
+        let link = self.link.clone();
+        let token = self.props.token.clone();
+        spawn_local(async move {
+            let invite = fetch_invite(token).await.map_err(|err| err.to_string());
+            link.send_message(Msg::InviteLoaded(invite));
+        });
+    }
+}
+
+impl Component for JoinTab {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Props, link: ComponentLink<Self>) -> Self {
+        let callback = props
+            .state_store
+            .subscribe_language_changed(&link, Msg::LanguageChanged);
+
+        let join_tab = JoinTab {
+            props,
+            link,
+            invite: None,
+            name: String::new(),
+            joining: false,
+            error: None,
+            _language_changed_callback: callback,
+        };
+        join_tab.load_invite();
+        join_tab
+    }
+
+    fn update(&mut self, msg: Msg) -> ShouldRender {
+        match msg {
+            Msg::InviteLoaded(invite) => {
+                if let Err(err) = &invite {
+                    error!("Unable to load the invite: {}", err);
+                }
+                self.invite = Some(invite);
+                true
+            }
+            Msg::UpdateName(name) => {
+                self.name = name;
+                true
+            }
+            Msg::Join => {
+                let name = self.name.trim().to_string();
+                if name.is_empty() || self.joining {
+                    return false;
+                }
+
+                self.joining = true;
+                self.error = None;
+                let link = self.link.clone();
+                let token = self.props.token.clone();
+                spawn_local(async move {
+                    let result = redeem_invite(token, name)
+                        .await
+                        .map(|redemption| {
+                            if let Some(session_token) = &redemption.session_token {
+                                store_session_token(session_token);
+                            }
+                        })
+                        .map_err(|err| err.to_string());
+                    link.send_message(Msg::Joined(result));
+                });
+                true
+            }
+            Msg::Joined(result) => {
+                self.joining = false;
+                match result {
+                    Ok(()) => {
+                        self.props.state_store.change_route(AppRoute::Index);
+                    }
+                    Err(err) => {
+                        error!("Unable to join the tab: {}", err);
+                        self.error = Some(err);
+                    }
+                }
+                true
+            }
+            Msg::Cancel => {
+                self.props.state_store.change_route(AppRoute::Index);
+                true
+            }
+            Msg::LanguageChanged => true,
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        if self.props != props {
+            let token_changed = self.props.token != props.token;
+            self.props = props;
+            if token_changed {
+                self.invite = None;
+                self.load_invite();
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn view(&self) -> Html {
+        let invite = match &self.invite {
+            None => return html! { <progress class="progress is-small is-primary"/> },
+            Some(Err(_)) => {
+                return html! {
+                    <div class="notification is-warning">
+                        { tr!("This invite has expired, or has already been used.") }
+                    </div>
+                }
+            }
+            Some(Ok(invite)) => invite,
+        };
+
+        let onclick_cancel = self.link.callback(|_| Msg::Cancel);
+        let onclick_join = self.link.callback(|_| Msg::Join);
+        let oninput_name = self.link.callback(|e: InputData| Msg::UpdateName(e.value));
+
+        let invited_by = match &invite.invited_by {
+            Some(invited_by) => html! {
+                <p class="subtitle is-5">{ tr!("{0} has invited you to share costs", invited_by) }</p>
+            },
+            None => html! {},
+        };
+
+        let error = match &self.error {
+            Some(error) => html! { <p class="help is-danger">{ error }</p> },
+            None => html! {},
+        };
+
+        let your_name_label = tr!("Your Name");
+
+        html! {
+            <>
+                <nav class="level">
+                    <div class="level-left">
+                        <div class="level-item">
+                            <h3 class="title is-3">{ tr!("Join {0}", invite.tab_name) }</h3>
+                        </div>
+                    </div>
+                </nav>
+                { invited_by }
+
+                <div class="card">
+                    <div class="field">
+                        <label class="label">{ your_name_label.clone() }</label>
+                        <div class="control">
+                            <input
+                                class="input"
+                                type="text"
+                                placeholder=your_name_label
+                                value=self.name.clone()
+                                oninput=oninput_name/>
+                        </div>
+                        { error }
+                    </div>
+                    <div class="field is-grouped">
+                        <div class="control">
+                            <button
+                                class="button is-link"
+                                onclick=onclick_join
+                                disabled=self.name.trim().is_empty() || self.joining>
+                                { tr!("Join Tab") }
+                            </button>
+                        </div>
+                        <div class="control">
+                            <button
+                                class="button is-link is-light"
+                                onclick=onclick_cancel>
+                                { tr!("Cancel") }
+                            </button>
+                        </div>
+                    </div>
+                </div>
+            </>
+        }
+    }
+}
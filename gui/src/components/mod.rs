@@ -1,5 +1,6 @@
 pub mod costing_tab;
 pub mod costing_tab_list;
+pub mod join_tab;
 pub mod navbar;
 pub mod new_costing_tab;
 pub mod pages;
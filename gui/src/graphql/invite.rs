@@ -0,0 +1,44 @@
+//! Queries used to join a tab using an invite.
+
+use super::{api_client, response_data};
+use graphql_client::GraphQLQuery;
+
+type UUID = uuid::Uuid;
+type DateTime = chrono::DateTime<chrono::Utc>;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema.graphql",
+    query_path = "src/graphql/invite.graphql",
+    response_derives = "Debug, Clone, PartialEq"
+)]
+pub struct Invite;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema.graphql",
+    query_path = "src/graphql/invite.graphql",
+    response_derives = "Debug, Clone, PartialEq"
+)]
+pub struct RedeemInvite;
+
+/// The details of the invite with the specified `token`.
+pub async fn fetch_invite(token: String) -> Result<invite::InviteInvite, anyhow::Error> {
+    let variables = invite::Variables { token };
+    let response = api_client().call(Invite, variables).await?;
+    Ok(response_data(response)?.invite)
+}
+
+/// Join the tab which the invite with the specified `token` is to,
+/// as a new user with the specified `name`.
+pub async fn redeem_invite(
+    token: String,
+    name: String,
+) -> Result<redeem_invite::RedeemInviteRedeemInvite, anyhow::Error> {
+    let variables = redeem_invite::Variables {
+        token,
+        user: redeem_invite::UserInput { name, email: None },
+    };
+    let response = api_client().call(RedeemInvite, variables).await?;
+    Ok(response_data(response)?.redeem_invite)
+}
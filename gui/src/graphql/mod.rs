@@ -1,4 +1,35 @@
-pub mod addtest;
 mod client;
+pub mod invite;
+pub mod session;
 
 pub use client::*;
+
+use anyhow::anyhow;
+use graphql_client::Response;
+
+/// The path of the server's GraphQL API.
+pub const API_ENDPOINT: &str = "/api";
+
+/// A [Client] for the server's GraphQL API, which authenticates
+/// requests with the stored session token (if there is one).
+pub fn api_client() -> Client {
+    let mut client = Client::new(API_ENDPOINT);
+    if let Some(token) = session::session_token() {
+        client.add_header("Authorization", &format!("Bearer {}", token));
+    }
+    client
+}
+
+/// The data of a `response`, or an error containing the messages of
+/// the errors it contains.
+pub fn response_data<T>(response: Response<T>) -> Result<T, anyhow::Error> {
+    match (response.data, response.errors) {
+        (Some(data), None) => Ok(data),
+        (_, Some(errors)) => Err(anyhow!(errors
+            .iter()
+            .map(|error| error.message.clone())
+            .collect::<Vec<_>>()
+            .join(", "))),
+        (None, None) => Err(anyhow!("the response contains no data")),
+    }
+}
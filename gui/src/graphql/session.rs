@@ -0,0 +1,30 @@
+//! The token of the session which authenticates requests to the
+//! server's API, for sessions which aren't stored in a cookie (such
+//! as the guest account created when an invite is redeemed).
+
+use log::error;
+
+/// The key in the browser's local storage used to store the token.
+const SESSION_TOKEN_KEY: &str = "coster_session";
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok().flatten()
+}
+
+/// The stored session token, if there is one.
+pub fn session_token() -> Option<String> {
+    local_storage()?.get_item(SESSION_TOKEN_KEY).ok().flatten()
+}
+
+/// Store the session `token`, so that it is sent with subsequent
+/// requests.
+pub fn store_session_token(token: &str) {
+    match local_storage() {
+        Some(storage) => {
+            if storage.set_item(SESSION_TOKEN_KEY, token).is_err() {
+                error!("Unable to store the session token");
+            }
+        }
+        None => error!("Local storage is not available to store the session token"),
+    }
+}
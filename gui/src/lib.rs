@@ -2,10 +2,12 @@
 
 mod components;
 mod graphql;
+mod rest;
 mod state;
 
 use components::costing_tab::CostingTab;
 use components::costing_tab_list::CostingTabList;
+use components::join_tab::JoinTab;
 use components::new_costing_tab::NewCostingTab;
 use components::pages::{centered, Page};
 use switch_router::{SwitchRoute, SwitchRouteService, WebRouteService};
@@ -24,6 +26,7 @@ use state::{
     middleware::{
         db::DatabaseMiddleware,
         localize::LocalizeMiddleware,
+        sync::SyncMiddleware,
     },
     AppRoute, CosterAction, CosterEvent, CosterReducer, CosterState, RouteType, StateStoreRef,
 };
@@ -119,7 +122,12 @@ impl Component for Model {
                     let database_middleware = DatabaseMiddleware::new(database);
 
                     state_store_clone.add_middleware(database_middleware);
-                    state_store_clone.dispatch(CosterAction::LoadDatabase)
+                    state_store_clone.dispatch(CosterAction::LoadDatabase);
+
+                    // the tabs are synchronised with the server once
+                    // they have been loaded from the database
+                    let sync_middleware = SyncMiddleware::new(state_store_clone.clone());
+                    state_store_clone.add_middleware(sync_middleware);
                 }
                 Err(error) => error!("Error opening database: {}", error),
             }
@@ -181,6 +189,9 @@ impl Component for Model {
             RouteType::Valid(AppRoute::About) => {
                 self.page(html! { <h1 class="title is-1">{ tr!("About Coster") }</h1> })
             }
+            RouteType::Valid(AppRoute::JoinTab(token)) => self.page(centered(
+                html! {<JoinTab state_store=self.state_store.clone() token=token.clone()/>},
+            )),
             RouteType::Valid(AppRoute::Index) => {
                 if state.route.path() == "/" {
                     self.page(centered(
@@ -0,0 +1,143 @@
+//! Requests to the server's JSON REST API, for operations which use
+//! the serde representations of the [costing] types, such as
+//! synchronising tabs.
+
+use crate::graphql::session::session_token;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Request, RequestInit, Response};
+
+/// The path which the REST API is served under.
+pub const REST_ENDPOINT: &str = "/api/v1";
+
+/// An error which occurs while making a request to the REST API.
+#[derive(Debug, Error)]
+pub enum RestError {
+    /// The request could not be sent, probably because the browser is
+    /// offline.
+    #[error("unable to reach the server: {0}")]
+    Network(String),
+    /// The server responded with an error.
+    #[error("{message} ({code})")]
+    Response {
+        status: u16,
+        code: String,
+        message: String,
+    },
+    #[error("the request body could not be serialized: {0}")]
+    InvalidRequest(String),
+    #[error("the response from the server is invalid: {0}")]
+    InvalidResponse(String),
+}
+
+impl RestError {
+    /// Whether the request may succeed if it is retried later.
+    pub fn is_temporary(&self) -> bool {
+        match self {
+            RestError::Network(_) => true,
+            RestError::Response { status, .. } => *status >= 500,
+            RestError::InvalidRequest(_) | RestError::InvalidResponse(_) => false,
+        }
+    }
+}
+
+/// The description of an error returned by the server.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+fn js_error(value: JsValue) -> String {
+    js_sys::Error::from(value).message().into()
+}
+
+/// Send a `GET` request for the resource at `path` (relative to the
+/// [REST_ENDPOINT]).
+pub async fn get<T: DeserializeOwned>(path: &str) -> Result<T, RestError> {
+    send("GET", path, None).await
+}
+
+/// Send a `POST` request with the JSON `body` to the resource at
+/// `path` (relative to the [REST_ENDPOINT]).
+pub async fn post<B: Serialize, T: DeserializeOwned>(path: &str, body: &B) -> Result<T, RestError> {
+    let body = serde_json::to_string(body)
+        .map_err(|error| RestError::InvalidRequest(error.to_string()))?;
+    send("POST", path, Some(body)).await
+}
+
+/// Send a `DELETE` request for the resource at `path` (relative to
+/// the [REST_ENDPOINT]).
+pub async fn delete(path: &str) -> Result<(), RestError> {
+    send("DELETE", path, None).await
+}
+
+/// Send a request, authenticated with the stored session token (if
+/// there is one), and parse the JSON body of the response.
+async fn send<T: DeserializeOwned>(
+    method: &str,
+    path: &str,
+    body: Option<String>,
+) -> Result<T, RestError> {
+    let window =
+        web_sys::window().ok_or_else(|| RestError::Network("there is no window".to_string()))?;
+
+    let mut request_init = RequestInit::new();
+    request_init.method(method);
+    if let Some(body) = &body {
+        request_init.body(Some(&JsValue::from_str(body)));
+    }
+    let request =
+        Request::new_with_str_and_init(&format!("{}{}", REST_ENDPOINT, path), &request_init)
+            .map_err(|error| RestError::Network(js_error(error)))?;
+
+    let headers = request.headers();
+    let mut set_header = |name: &str, value: &str| {
+        headers
+            .set(name, value)
+            .map_err(|error| RestError::Network(js_error(error)))
+    };
+    set_header("Accept", "application/json")?;
+    if body.is_some() {
+        set_header("Content-Type", "application/json")?;
+    }
+    if let Some(token) = session_token() {
+        set_header("Authorization", &format!("Bearer {}", token))?;
+    }
+
+    let response: Response = JsFuture::from(window.fetch_with_request(&request))
+        .await
+        .map_err(|error| RestError::Network(js_error(error)))?
+        .dyn_into()
+        .map_err(|_| RestError::InvalidResponse("not a Response".to_string()))?;
+    let text = JsFuture::from(
+        response
+            .text()
+            .map_err(|error| RestError::InvalidResponse(js_error(error)))?,
+    )
+    .await
+    .map_err(|error| RestError::Network(js_error(error)))?
+    .as_string()
+    .unwrap_or_default();
+
+    if response.ok() {
+        // e.g. a 204 No Content response
+        let text = if text.is_empty() { "null" } else { &text };
+        serde_json::from_str(text).map_err(|error| RestError::InvalidResponse(error.to_string()))
+    } else {
+        let error_response: ErrorResponse = serde_json::from_str(&text)
+            .map_err(|error| RestError::InvalidResponse(error.to_string()))?;
+        Err(RestError::Response {
+            status: response.status(),
+            code: error_response.error.code,
+            message: error_response.error.message,
+        })
+    }
+}
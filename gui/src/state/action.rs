@@ -1,12 +1,13 @@
 use super::{
     middleware::{
         localize::{ChangeSelectedLanguage, LocalizeAction},
+        sync::{RejectedTabAction, SyncAction, TrashChange},
     },
     RouteType,
 };
 use switch_router_middleware::{IsRouteAction, RouteAction};
 use commodity::CommodityType;
-use costing::Tab;
+use costing::{actions::TabUserActionType, Tab, TabID};
 use serde::Serialize;
 use std::{fmt::Display, rc::Rc};
 
@@ -32,6 +33,47 @@ pub enum CosterAction {
         tabs: Vec<Rc<Tab>>,
         write_to_database: bool,
     },
+    /// Load the tabs which are in the trash (replacing any currently
+    /// loaded trashed tabs).
+    LoadTrashedTabs {
+        tabs: Vec<Rc<Tab>>,
+    },
+    /// Move a tab to the trash, from where it can be restored.
+    TrashTab {
+        tab_id: TabID,
+        write_to_database: bool,
+    },
+    /// Restore a tab from the trash.
+    RestoreTab {
+        tab_id: TabID,
+        write_to_database: bool,
+    },
+    /// Permanently delete a tab, whether or not it is in the trash.
+    DeleteTab {
+        tab_id: TabID,
+        write_to_database: bool,
+    },
+    /// Perform an action on a tab, and record it in the tab's
+    /// `user_actions`, so that it can be synchronised with the
+    /// server.
+    PerformTabAction {
+        tab_id: TabID,
+        action: TabUserActionType,
+        write_to_database: bool,
+    },
+    /// Replace a tab (which may be in the trash) with a newer copy
+    /// of it, such as one received from the server.
+    UpdateTab {
+        tab: Rc<Tab>,
+        write_to_database: bool,
+    },
+    /// Record actions which the server rejected while synchronising
+    /// the tabs, so that they can be shown to the user.
+    RejectTabActions {
+        actions: Vec<RejectedTabAction>,
+    },
+    /// Dismiss the rejected actions which are being shown to the user.
+    DismissRejectedTabActions,
 }
 
 impl Display for CosterAction {
@@ -65,6 +107,38 @@ impl Display for CosterAction {
                 tabs.len(),
                 write_to_database
             ),
+            CosterAction::LoadTrashedTabs { tabs } => {
+                write!(f, "LoadTrashedTabs({} tabs)", tabs.len())
+            }
+            CosterAction::TrashTab {
+                tab_id,
+                write_to_database,
+            } => write!(f, "TrashTab({}, write: {:?})", tab_id, write_to_database),
+            CosterAction::RestoreTab {
+                tab_id,
+                write_to_database,
+            } => write!(f, "RestoreTab({}, write: {:?})", tab_id, write_to_database),
+            CosterAction::DeleteTab {
+                tab_id,
+                write_to_database,
+            } => write!(f, "DeleteTab({}, write: {:?})", tab_id, write_to_database),
+            CosterAction::PerformTabAction {
+                tab_id,
+                action,
+                write_to_database,
+            } => write!(
+                f,
+                "PerformTabAction({}, {:?}, write: {:?})",
+                tab_id, action, write_to_database
+            ),
+            CosterAction::UpdateTab {
+                tab,
+                write_to_database,
+            } => write!(f, "UpdateTab({}, write: {:?})", tab.id, write_to_database),
+            CosterAction::RejectTabActions { actions } => {
+                write!(f, "RejectTabActions({} actions)", actions.len())
+            }
+            CosterAction::DismissRejectedTabActions => write!(f, "DismissRejectedTabActions"),
         }
     }
 }
@@ -101,3 +175,35 @@ impl From<ChangeLastSelectedCurrency> for CosterAction {
         CosterAction::ChangeLastSelectedCurrency(action)
     }
 }
+
+impl SyncAction for CosterAction {
+    fn get_perform_tab_action(&self) -> Option<(&TabID, &TabUserActionType)> {
+        match self {
+            CosterAction::PerformTabAction { tab_id, action, .. } => Some((tab_id, action)),
+            _ => None,
+        }
+    }
+    fn get_trash_change(&self) -> Option<(&TabID, TrashChange)> {
+        match self {
+            CosterAction::TrashTab { tab_id, .. } => Some((tab_id, TrashChange::Trash)),
+            CosterAction::RestoreTab { tab_id, .. } => Some((tab_id, TrashChange::Restore)),
+            CosterAction::DeleteTab { tab_id, .. } => Some((tab_id, TrashChange::Delete)),
+            _ => None,
+        }
+    }
+    fn create_tab(tab: Rc<Tab>) -> Self {
+        CosterAction::CreateTab {
+            tab,
+            write_to_database: true,
+        }
+    }
+    fn update_tab(tab: Rc<Tab>) -> Self {
+        CosterAction::UpdateTab {
+            tab,
+            write_to_database: true,
+        }
+    }
+    fn reject_tab_actions(actions: Vec<RejectedTabAction>) -> Self {
+        CosterAction::RejectTabActions { actions }
+    }
+}
@@ -1,4 +1,5 @@
-use costing::db::KeyValueDBStore;
+use costing::db::{tabs::TabStorage, DatabaseError, KeyValueDBStore};
+use kvdb::{DBTransaction, KeyValueDB};
 
 #[derive(Debug)]
 pub enum CosterClientDBStore {
@@ -26,3 +27,25 @@ impl KeyValueDBStore for CosterClientDBStore {
         2
     }
 }
+
+/// The [TabStorage] used to store [costing::Tab]s in the
+/// [CosterClientDBStore::Tabs] store.
+pub fn tab_storage() -> TabStorage<'static, CosterClientDBStore> {
+    TabStorage::new("tabs", &CosterClientDBStore::Tabs)
+}
+
+/// Populate a [DBTransaction] using `f`, and write it to the
+/// `database`, logging any error which occurs (described by
+/// `description`) rather than panicking.
+pub fn write_transaction<F>(database: &dyn KeyValueDB, description: &str, f: F)
+where
+    F: FnOnce(&mut DBTransaction) -> Result<(), DatabaseError>,
+{
+    let mut transaction = database.transaction();
+    let result =
+        f(&mut transaction).and_then(|_| database.write(transaction).map_err(DatabaseError::from));
+
+    if let Err(error) = result {
+        log::error!("Unable to {}: {}", description, error);
+    }
+}
@@ -14,6 +14,7 @@ pub enum CosterEvent {
     RouteChanged,
     LastSelectedCurrencyChanged,
     TabsChanged,
+    RejectedTabActionsChanged,
     None,
 }
 
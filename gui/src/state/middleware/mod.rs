@@ -1,2 +1,3 @@
 pub mod db;
-pub mod localize;
\ No newline at end of file
+pub mod localize;
+pub mod sync;
\ No newline at end of file
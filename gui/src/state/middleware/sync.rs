@@ -0,0 +1,610 @@
+//! Synchronises the locally stored tabs with the server.
+//!
+//! Actions performed on a tab are queued (in the browser's local
+//! storage, so that the queue survives while the browser is offline
+//! or closed), and pushed to the server's `tabs/{id}/sync` endpoint
+//! along with the tab's cursor. The server merges them, and responds
+//! with the merged tab, which replaces the local copy. Any actions
+//! which were queued while the request was in progress are performed
+//! again on the merged tab. Tabs which were created locally are
+//! pushed in their entirety the first time they are synchronised,
+//! and tabs which the account is a member of on the server are
+//! pulled into the local state.
+//!
+//! Each push of queued actions is sent with an id, which is sent
+//! again if the push is retried, so that the server doesn't merge the
+//! actions twice. The server may reassign the ids of expenses and
+//! users added while offline, and these are also replaced in the
+//! actions which are still queued. Actions which the server rejects
+//! are kept in the state, so that they can be shown to the user.
+//!
+//! Moving a tab to the trash, restoring it, and deleting it are also
+//! queued, and sent to the server's REST endpoints after the tab's
+//! actions have been pushed. Only the latest of these changes is
+//! kept, and one which the server rejects (for example because
+//! another client already deleted the tab) is dropped. A deleted tab
+//! keeps its state in the queue until the server has deleted it too,
+//! so that it isn't pulled again in the meantime.
+//!
+//! Failed synchronisations are retried with an increasing delay, and
+//! all tabs are synchronised periodically to pull changes made by
+//! other clients.
+
+use crate::rest::{self, ErrorBody, RestError};
+use costing::actions::{IdRemapping, TabUserAction, TabUserActionType};
+use costing::{Tab, TabData, TabID};
+use log::{debug, error, warn};
+use reactive_state::{
+    middleware::{Middleware, ReduceMiddlewareResult},
+    Store, StoreEvent, StoreRef,
+};
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, collections::HashMap, hash::Hash, rc::Rc, time::Duration};
+use uuid::Uuid;
+use wasm_bindgen_futures::spawn_local;
+use yew::{
+    services::{interval::IntervalTask, timeout::TimeoutTask, IntervalService, TimeoutService},
+    Callback,
+};
+
+/// The key in the browser's local storage used to store the
+/// [SyncQueue].
+const SYNC_QUEUE_KEY: &str = "coster_sync";
+
+/// How long to wait after a tab is modified before synchronising it,
+/// so that several actions performed together are pushed at once.
+const SYNC_DELAY: Duration = Duration::from_millis(500);
+
+/// How often all the tabs are synchronised.
+const SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The delay before the first retry of a failed synchronisation,
+/// which doubles after each failure up to [MAX_RETRY_DELAY].
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(120);
+
+/// The synchronisation state of a tab.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct TabSync {
+    /// The number of the tab's actions which have been received from
+    /// the server, or `None` if the tab was created locally and
+    /// hasn't been pushed yet.
+    cursor: Option<usize>,
+    /// Actions which were performed locally, and haven't been merged
+    /// by the server yet.
+    pending: Vec<TabUserActionType>,
+    /// The push of the first `pending` actions, if it has been sent
+    /// and not acknowledged by the server yet.
+    #[serde(default)]
+    push: Option<Push>,
+    /// A change to whether the tab is in the trash, which hasn't been
+    /// sent to the server yet.
+    #[serde(default)]
+    trash: Option<TrashChange>,
+}
+
+/// A change to whether a tab is in the trash, which is sent to the
+/// server when the tab is synchronised.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TrashChange {
+    /// Move the tab to the trash.
+    Trash,
+    /// Restore the tab from the trash.
+    Restore,
+    /// Permanently delete the tab.
+    Delete,
+}
+
+/// A push of the pending actions of a tab, which is sent again with
+/// the same `id` until the server acknowledges it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Push {
+    id: Uuid,
+    /// The number of pending actions which were pushed.
+    actions: usize,
+}
+
+/// The synchronisation state of all the tabs which have been stored
+/// locally. Tabs which were deleted locally keep their state until
+/// the server has deleted them, so that they aren't pulled from the
+/// server again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SyncQueue {
+    tabs: HashMap<TabID, TabSync>,
+}
+
+impl SyncQueue {
+    fn local_storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok().flatten()
+    }
+
+    fn load() -> Self {
+        let stored = Self::local_storage()
+            .and_then(|storage| storage.get_item(SYNC_QUEUE_KEY).ok().flatten());
+        match stored.map(|json| serde_json::from_str(&json)) {
+            Some(Ok(queue)) => queue,
+            Some(Err(err)) => {
+                error!("Unable to read the sync queue: {}", err);
+                SyncQueue::default()
+            }
+            None => SyncQueue::default(),
+        }
+    }
+
+    fn store(&self) {
+        let result = serde_json::to_string(self)
+            .map_err(|err| err.to_string())
+            .and_then(|json| match Self::local_storage() {
+                Some(storage) => storage
+                    .set_item(SYNC_QUEUE_KEY, &json)
+                    .map_err(|_| "unable to write to local storage".to_string()),
+                None => Err("local storage is not available".to_string()),
+            });
+        if let Err(err) = result {
+            error!("Unable to store the sync queue: {}", err);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SyncRequest<'a> {
+    push_id: Option<Uuid>,
+    cursor: usize,
+    actions: &'a [TabUserActionType],
+    tab: Option<TabData>,
+}
+
+#[derive(Deserialize)]
+struct RejectedAction {
+    index: usize,
+    error: ErrorBody,
+}
+
+#[derive(Deserialize)]
+struct SyncResponse {
+    cursor: usize,
+    rejected: Vec<RejectedAction>,
+    #[serde(default)]
+    remapped: IdRemapping,
+    tab: TabData,
+}
+
+/// An action performed locally which the server couldn't merge into
+/// the tab, for example because another client removed the expense
+/// which it edits.
+#[derive(Debug, Clone, Serialize)]
+pub struct RejectedTabAction {
+    pub tab_id: TabID,
+    pub action: TabUserActionType,
+    /// The description of the reason the action was rejected.
+    pub message: String,
+}
+
+/// An action which can be synchronised by the [SyncMiddleware].
+pub trait SyncAction {
+    /// The tab id and the action, if this performs an action on a
+    /// tab.
+    fn get_perform_tab_action(&self) -> Option<(&TabID, &TabUserActionType)>;
+    /// The tab id and the change, if this moves a tab to the trash,
+    /// restores it, or deletes it.
+    fn get_trash_change(&self) -> Option<(&TabID, TrashChange)>;
+    /// Add a tab which was pulled from the server.
+    fn create_tab(tab: Rc<Tab>) -> Self;
+    /// Replace a tab with the copy received from the server.
+    fn update_tab(tab: Rc<Tab>) -> Self;
+    /// Record actions which were rejected by the server, so that they
+    /// can be shown to the user.
+    fn reject_tab_actions(actions: Vec<RejectedTabAction>) -> Self;
+}
+
+/// State containing the tabs which are synchronised by the
+/// [SyncMiddleware].
+pub trait SyncState {
+    /// All the tabs which are stored locally, including those in the
+    /// trash.
+    fn local_tabs(&self) -> Vec<Rc<Tab>>;
+}
+
+fn local_tab<State: SyncState>(state: &State, tab_id: &TabID) -> Option<Rc<Tab>> {
+    state.local_tabs().into_iter().find(|tab| tab.id == *tab_id)
+}
+
+/// Whether a synchronisation is in progress or scheduled.
+struct Schedule {
+    syncing: bool,
+    /// Whether to synchronise again once the current synchronisation
+    /// has finished, because a tab was modified in the meantime.
+    again: bool,
+    retry_delay: Duration,
+    timeout: Option<TimeoutTask>,
+}
+
+struct Syncer<State, Action, Event, Effect> {
+    store: StoreRef<State, Action, Event, Effect>,
+    queue: RefCell<SyncQueue>,
+    schedule: RefCell<Schedule>,
+}
+
+impl<State, Action, Event, Effect> Syncer<State, Action, Event, Effect>
+where
+    State: SyncState + 'static,
+    Action: SyncAction + 'static,
+    Event: StoreEvent + Clone + Hash + Eq + 'static,
+    Effect: 'static,
+{
+    /// Synchronise all the tabs after `delay`, unless a
+    /// synchronisation is already scheduled.
+    fn schedule(self: &Rc<Self>, delay: Duration) {
+        let mut schedule = self.schedule.borrow_mut();
+        if schedule.syncing {
+            schedule.again = true;
+            return;
+        }
+        if schedule.timeout.is_some() {
+            return;
+        }
+
+        let syncer = self.clone();
+        let callback = Callback::from(move |_| {
+            syncer.schedule.borrow_mut().timeout = None;
+            spawn_local(syncer.clone().sync());
+        });
+        schedule.timeout = Some(TimeoutService::spawn(delay, callback));
+    }
+
+    async fn sync(self: Rc<Self>) {
+        {
+            let mut schedule = self.schedule.borrow_mut();
+            if schedule.syncing {
+                schedule.again = true;
+                return;
+            }
+            schedule.syncing = true;
+            schedule.again = false;
+        }
+
+        let result = self.sync_tabs().await;
+
+        let (again, retry_delay) = {
+            let mut schedule = self.schedule.borrow_mut();
+            schedule.syncing = false;
+            match &result {
+                Ok(()) => schedule.retry_delay = MIN_RETRY_DELAY,
+                Err(_) => {
+                    schedule.retry_delay = (schedule.retry_delay * 2).min(MAX_RETRY_DELAY);
+                }
+            }
+            (schedule.again, schedule.retry_delay)
+        };
+
+        match result {
+            Ok(()) if again => self.schedule(SYNC_DELAY),
+            Ok(()) => {}
+            Err(err) if err.is_temporary() => {
+                warn!(
+                    "Unable to synchronise the tabs, retrying in {:?}: {}",
+                    retry_delay, err
+                );
+                self.schedule(retry_delay);
+            }
+            // not retried until the next periodic synchronisation,
+            // for example because there is no account logged in
+            Err(err) => debug!("Unable to synchronise the tabs: {}", err),
+        }
+    }
+
+    /// Pull the tabs which only exist on the server, and synchronise
+    /// each of the local tabs.
+    async fn sync_tabs(&self) -> Result<(), RestError> {
+        let remote_tabs: Vec<TabData> = rest::get("/tabs").await?;
+        for tab_data in remote_tabs {
+            if self.queue.borrow().tabs.contains_key(&tab_data.id) {
+                continue;
+            }
+            let tab = Tab::from(tab_data);
+            self.queue.borrow_mut().tabs.insert(
+                tab.id,
+                TabSync {
+                    cursor: Some(tab.user_actions.len()),
+                    pending: Vec::new(),
+                    push: None,
+                    trash: None,
+                },
+            );
+            self.queue.borrow().store();
+            self.store.dispatch(Action::create_tab(Rc::new(tab)));
+        }
+
+        for tab in self.store.state().local_tabs() {
+            match self.sync_tab(&tab).await {
+                Ok(()) => {}
+                Err(err) if err.is_temporary() => return Err(err),
+                Err(err) => error!("Unable to synchronise tab {}: {}", tab.id, err),
+            }
+        }
+
+        // deleted tabs are no longer stored locally, so they are only
+        // in the queue
+        let deleted: Vec<TabID> = self
+            .queue
+            .borrow()
+            .tabs
+            .iter()
+            .filter(|(_, tab_sync)| tab_sync.trash == Some(TrashChange::Delete))
+            .map(|(tab_id, _)| *tab_id)
+            .collect();
+        for tab_id in deleted {
+            self.sync_trash(&tab_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Send the queued [TrashChange] of the tab with the specified
+    /// `tab_id` to the server, if there is one.
+    async fn sync_trash(&self, tab_id: &TabID) -> Result<(), RestError> {
+        let (change, pushed) = match self.queue.borrow().tabs.get(tab_id) {
+            Some(TabSync {
+                trash: Some(change),
+                cursor,
+                ..
+            }) => (*change, cursor.is_some()),
+            _ => return Ok(()),
+        };
+
+        // a tab which was never pushed only needs to be changed
+        // locally, and is pushed to the server (if it isn't deleted)
+        // once it has a user
+        if !pushed && change != TrashChange::Delete {
+            return Ok(());
+        }
+
+        let result = if !pushed {
+            Ok(())
+        } else {
+            match change {
+                TrashChange::Trash => rest::delete(&format!("/tabs/{}", tab_id)).await,
+                TrashChange::Restore => {
+                    rest::post::<_, TabData>(&format!("/tabs/{}/restore", tab_id), &())
+                        .await
+                        .map(|_| ())
+                }
+                TrashChange::Delete => rest::delete(&format!("/trash/{}", tab_id)).await,
+            }
+        };
+        match result {
+            Ok(()) => {}
+            Err(err) if err.is_temporary() => return Err(err),
+            Err(err) => warn!(
+                "The server rejected {:?} on tab {}: {}",
+                change, tab_id, err
+            ),
+        }
+
+        // the change is only removed if another one wasn't queued
+        // during the request
+        let mut queue = self.queue.borrow_mut();
+        match queue.tabs.get(tab_id).and_then(|tab_sync| tab_sync.trash) {
+            Some(TrashChange::Delete) if change == TrashChange::Delete => {
+                queue.tabs.remove(tab_id);
+            }
+            Some(queued) if queued == change => {
+                queue.tabs.entry(*tab_id).or_default().trash = None;
+            }
+            _ => {}
+        }
+        queue.store();
+        Ok(())
+    }
+
+    async fn sync_tab(&self, tab: &Tab) -> Result<(), RestError> {
+        let tab_sync = self
+            .queue
+            .borrow()
+            .tabs
+            .get(&tab.id)
+            .cloned()
+            .unwrap_or_default();
+
+        // a new tab is pushed in its entirety (including its pending
+        // actions), once it has a user which the account can be
+        // linked to
+        let (request, sent) = match tab_sync.cursor {
+            Some(cursor) => {
+                // a push which wasn't acknowledged is retried with the
+                // same id and actions
+                let push = match (&tab_sync.push, tab_sync.pending.is_empty()) {
+                    (Some(push), _) => Some(push.clone()),
+                    (None, true) => None,
+                    (None, false) => {
+                        let push = Push {
+                            id: Uuid::new_v4(),
+                            actions: tab_sync.pending.len(),
+                        };
+                        let mut queue = self.queue.borrow_mut();
+                        queue.tabs.entry(tab.id).or_default().push = Some(push.clone());
+                        queue.store();
+                        Some(push)
+                    }
+                };
+                let sent = push.as_ref().map_or(0, |push| push.actions);
+                (
+                    SyncRequest {
+                        push_id: push.map(|push| push.id),
+                        cursor,
+                        actions: &tab_sync.pending[..sent],
+                        tab: None,
+                    },
+                    sent,
+                )
+            }
+            None if tab.users.is_empty() => return Ok(()),
+            None => (
+                SyncRequest {
+                    push_id: None,
+                    cursor: 0,
+                    actions: &[],
+                    tab: Some(TabData::from_tab(tab)),
+                },
+                tab_sync.pending.len(),
+            ),
+        };
+
+        let response: SyncResponse =
+            rest::post(&format!("/tabs/{}/sync", tab.id), &request).await?;
+        let rejected: Vec<RejectedTabAction> = response
+            .rejected
+            .iter()
+            .filter_map(|rejected| {
+                let action = request.actions.get(rejected.index)?;
+                warn!(
+                    "The server rejected {:?} on tab {}: {}",
+                    action, tab.id, rejected.error.message
+                );
+                Some(RejectedTabAction {
+                    tab_id: tab.id,
+                    action: action.clone(),
+                    message: rejected.error.message.clone(),
+                })
+            })
+            .collect();
+
+        // perform the actions which were queued during the request
+        // on the merged tab, using the ids which the server
+        // reassigned
+        let mut merged = Tab::from(response.tab);
+        let mut remapped = response.remapped;
+        let pending: Vec<TabUserActionType> = {
+            let mut queue = self.queue.borrow_mut();
+            let tab_sync = queue.tabs.entry(tab.id).or_default();
+            tab_sync.cursor = Some(response.cursor);
+            tab_sync.push = None;
+            tab_sync.pending.drain(..sent);
+            tab_sync.pending.drain(..).collect()
+        };
+        let mut remapped_pending = Vec::with_capacity(pending.len());
+        for action in pending {
+            let mut remapping = remapped.clone();
+            let action = remapping.remap(&merged, action);
+            match action.perform(&mut merged) {
+                Ok(()) => {
+                    merged.user_actions.push(action.clone());
+                    remapped = remapping;
+                }
+                Err(err) => error!(
+                    "Unable to perform {:?} on the synchronised tab {}: {}",
+                    action, tab.id, err
+                ),
+            }
+            remapped_pending.push(action);
+        }
+        {
+            let mut queue = self.queue.borrow_mut();
+            queue.tabs.entry(tab.id).or_default().pending = remapped_pending;
+            queue.store();
+        }
+
+        if !rejected.is_empty() {
+            self.store.dispatch(Action::reject_tab_actions(rejected));
+        }
+
+        if local_tab(&*self.store.state(), &tab.id).is_some() {
+            self.store.dispatch(Action::update_tab(Rc::new(merged)));
+        }
+
+        self.sync_trash(&tab.id).await
+    }
+}
+
+/// Middleware which synchronises the tabs in the state with the
+/// server, see the [sync](self) module.
+pub struct SyncMiddleware<State, Action, Event, Effect> {
+    syncer: Rc<Syncer<State, Action, Event, Effect>>,
+    _interval: IntervalTask,
+}
+
+impl<State, Action, Event, Effect> SyncMiddleware<State, Action, Event, Effect>
+where
+    State: SyncState + 'static,
+    Action: SyncAction + 'static,
+    Event: StoreEvent + Clone + Hash + Eq + 'static,
+    Effect: 'static,
+{
+    /// Create a new middleware which synchronises the tabs in the
+    /// `store`, starting with an initial synchronisation.
+    pub fn new(store: StoreRef<State, Action, Event, Effect>) -> Self {
+        let syncer = Rc::new(Syncer {
+            store,
+            queue: RefCell::new(SyncQueue::load()),
+            schedule: RefCell::new(Schedule {
+                syncing: false,
+                again: false,
+                retry_delay: MIN_RETRY_DELAY,
+                timeout: None,
+            }),
+        });
+
+        let interval_syncer = syncer.clone();
+        let interval = IntervalService::spawn(
+            SYNC_INTERVAL,
+            Callback::from(move |_| interval_syncer.schedule(Duration::from_secs(0))),
+        );
+        syncer.schedule(SYNC_DELAY);
+
+        Self {
+            syncer,
+            _interval: interval,
+        }
+    }
+}
+
+impl<State, Action, Event, Effect> Middleware<State, Action, Event, Effect>
+    for SyncMiddleware<State, Action, Event, Effect>
+where
+    State: SyncState + 'static,
+    Action: SyncAction + 'static,
+    Event: StoreEvent + Clone + Hash + Eq + 'static,
+    Effect: 'static,
+{
+    fn on_reduce(
+        &self,
+        store: &Store<State, Action, Event, Effect>,
+        action: Option<&Action>,
+        reduce: reactive_state::middleware::ReduceFn<State, Action, Event, Effect>,
+    ) -> ReduceMiddlewareResult<Event, Effect> {
+        let tab_action = action.and_then(|action| action.get_perform_tab_action());
+        let actions_before = tab_action
+            .and_then(|(tab_id, _)| local_tab(&*store.state(), tab_id))
+            .map(|tab| tab.user_actions.len());
+
+        let result = reduce(store, action);
+
+        // the latest change replaces any which hasn't been sent yet
+        if let Some((tab_id, change)) = action.and_then(|action| action.get_trash_change()) {
+            let mut queue = self.syncer.queue.borrow_mut();
+            queue.tabs.entry(*tab_id).or_default().trash = Some(change);
+            queue.store();
+            drop(queue);
+            self.syncer.schedule(SYNC_DELAY);
+        }
+
+        // only queue actions which the reducer was able to perform
+        if let (Some((tab_id, tab_action)), Some(actions_before)) = (tab_action, actions_before) {
+            let actions_after =
+                local_tab(&*store.state(), tab_id).map(|tab| tab.user_actions.len());
+            if actions_after > Some(actions_before) {
+                let mut queue = self.syncer.queue.borrow_mut();
+                queue
+                    .tabs
+                    .entry(*tab_id)
+                    .or_default()
+                    .pending
+                    .push(tab_action.clone());
+                queue.store();
+                drop(queue);
+                self.syncer.schedule(SYNC_DELAY);
+            }
+        }
+
+        result
+    }
+}
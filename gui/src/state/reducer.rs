@@ -1,14 +1,16 @@
 use super::{
-    db::CosterClientDBStore,
+    db::{tab_storage, write_transaction, CosterClientDBStore},
     middleware::{db::DatabaseEffect, localize::LocalizeStore},
     ChangeLastSelectedCurrency, CosterAction, CosterEffect, CosterEvent, CosterState,
 };
 use switch_router_middleware::RouteAction;
+use chrono::Utc;
 use commodity::CommodityType;
-use costing::db::{DBTransactionSerde, DatabaseValueRead, DatabaseValueWriteID, KeyValueDBSerde};
+use costing::actions::TabUserAction;
+use costing::db::{DBTransactionSerde, DatabaseError, KeyValueDBSerde};
 use costing::Tab;
 use std::rc::Rc;
-use reactive_state::{Reducer, ReducerResult, Store};
+use reactive_state::{Reducer, ReducerResult};
 
 pub struct CosterReducer;
 
@@ -31,15 +33,13 @@ impl Reducer<CosterState, CosterAction, CosterEvent, CosterEffect> for CosterRed
                     let effect_language = action.selected_language.clone();
                     let effect =
                         DatabaseEffect::new("write selected_language", move |_store, database| {
-                            let mut transaction = database.transaction();
-                            transaction.put_serialize(
-                                &CosterClientDBStore::General,
-                                "selected_language",
-                                &effect_language,
-                            );
-                            database
-                                .write(transaction)
-                                .expect("there was a problem executing a database transaction");
+                            write_transaction(database, "write selected_language", |transaction| {
+                                transaction.put_serialize(
+                                    &CosterClientDBStore::General,
+                                    "selected_language",
+                                    &effect_language,
+                                )
+                            });
                         });
 
                     effects.push(effect.into());
@@ -67,15 +67,17 @@ impl Reducer<CosterState, CosterAction, CosterEvent, CosterEffect> for CosterRed
                     let effect = DatabaseEffect::new(
                         "write last_selected_currency",
                         move |_store, database| {
-                            let mut transaction = database.transaction();
-                            transaction.put_serialize(
-                                &CosterClientDBStore::General,
-                                "last_selected_currency",
-                                &effect_currency,
+                            write_transaction(
+                                database,
+                                "write last_selected_currency",
+                                |transaction| {
+                                    transaction.put_serialize(
+                                        &CosterClientDBStore::General,
+                                        "last_selected_currency",
+                                        &effect_currency,
+                                    )
+                                },
                             );
-                            database
-                                .write(transaction)
-                                .expect("there was a problem executing a database transaction");
                         },
                     );
 
@@ -95,30 +97,11 @@ impl Reducer<CosterState, CosterAction, CosterEvent, CosterEffect> for CosterRed
 
                 if *write_to_database {
                     let effect_tab = tab.clone();
-                    let effect = DatabaseEffect::new(
-                        "write tabs, and add new tab",
-                        move |store: &Store<
-                            CosterState,
-                            CosterAction,
-                            CosterEvent,
-                            CosterEffect,
-                        >,
-                              database| {
-                            let mut transaction = database.transaction();
-                            let tabs = &store.state().tabs;
-
-                            tabs.write_to_db_id(
-                                &"tabs".to_string(),
-                                None,
-                                &mut transaction,
-                                &CosterClientDBStore::Tabs,
-                            );
-
-                            database
-                                .write(transaction)
-                                .expect("there was a problem executing a database transaction");
-                        },
-                    );
+                    let effect = DatabaseEffect::new("add new tab", move |_store, database| {
+                        write_transaction(database, "add new tab", |transaction| {
+                            tab_storage().add_tab(database, transaction, &effect_tab)
+                        });
+                    });
 
                     effects.push(effect.into());
                 }
@@ -128,35 +111,95 @@ impl Reducer<CosterState, CosterAction, CosterEvent, CosterEffect> for CosterRed
             CosterAction::LoadDatabase => {
                 let effect = DatabaseEffect::new("load database", move |store, database| {
                     log::debug!("DatabaseEffect load database");
-                    let selected_language_option: Option<Option<unic_langid::LanguageIdentifier>> =
-                        database
-                            .get_deserialize(&CosterClientDBStore::General, "selected_language")
-                            .expect("unable to read from database");
-                    if let Some(selected_language) = selected_language_option {
-                        store.change_selected_language(selected_language, false);
+                    let selected_language_result: Result<
+                        Option<Option<unic_langid::LanguageIdentifier>>,
+                        DatabaseError,
+                    > = database
+                        .get_deserialize(&CosterClientDBStore::General, "selected_language");
+                    match selected_language_result {
+                        Ok(Some(selected_language)) => {
+                            store.change_selected_language(selected_language, false);
+                        }
+                        Ok(None) => {}
+                        Err(error) => {
+                            log::error!(
+                                "Unable to read \"selected_language\" from database: {}",
+                                error
+                            )
+                        }
                     }
-                    let last_selected_currency_option: Option<Option<CommodityType>> = database
-                        .get_deserialize(&CosterClientDBStore::General, "last_selected_currency")
-                        .expect("unable to read \"last_selected_currency\" from database");
-                    if let Some(last_selected_currency) = last_selected_currency_option {
-                        store.dispatch(ChangeLastSelectedCurrency {
-                            last_selected_currency,
-                            write_to_database: false,
-                        });
+
+                    let last_selected_currency_result: Result<
+                        Option<Option<CommodityType>>,
+                        DatabaseError,
+                    > = database
+                        .get_deserialize(&CosterClientDBStore::General, "last_selected_currency");
+                    match last_selected_currency_result {
+                        Ok(Some(last_selected_currency)) => {
+                            store.dispatch(ChangeLastSelectedCurrency {
+                                last_selected_currency,
+                                write_to_database: false,
+                            });
+                        }
+                        Ok(None) => {}
+                        Err(error) => log::error!(
+                            "Unable to read \"last_selected_currency\" from database: {}",
+                            error
+                        ),
                     }
 
-                    let tabs_option = Vec::<Rc<Tab>>::read_from_db(
-                        &"tabs".to_string(),
-                        None,
-                        database,
-                        &CosterClientDBStore::Tabs,
-                    );
+                    let storage = tab_storage();
+                    match storage.read_tabs(database) {
+                        Ok(tabs_results) => {
+                            // skip any tabs which could not be read
+                            let tabs: Vec<Rc<Tab>> = tabs_results
+                                .filter_map(|tab_result| match tab_result {
+                                    Ok(tab) => Some(Rc::new(tab)),
+                                    Err(error) => {
+                                        log::error!("Unable to read tab from database: {}", error);
+                                        None
+                                    }
+                                })
+                                .collect();
 
-                    if let Some(tabs) = tabs_option {
-                        store.dispatch(CosterAction::LoadTabs {
-                            tabs,
-                            write_to_database: false,
-                        });
+                            store.dispatch(CosterAction::LoadTabs {
+                                tabs,
+                                write_to_database: false,
+                            });
+                        }
+                        Err(error) => log::error!("Unable to read tabs from database: {}", error),
+                    }
+
+                    match storage.read_trashed_tabs(database) {
+                        Ok(trashed) => {
+                            let tabs: Vec<Rc<Tab>> = trashed
+                                .iter()
+                                .filter_map(|trashed_tab| {
+                                    match storage.read_tab(database, &trashed_tab.id) {
+                                        Ok(Some(tab)) => Some(Rc::new(tab)),
+                                        Ok(None) => {
+                                            log::error!(
+                                                "Trashed tab {} is missing from the database",
+                                                trashed_tab.id
+                                            );
+                                            None
+                                        }
+                                        Err(error) => {
+                                            log::error!(
+                                                "Unable to read trashed tab from database: {}",
+                                                error
+                                            );
+                                            None
+                                        }
+                                    }
+                                })
+                                .collect();
+
+                            store.dispatch(CosterAction::LoadTrashedTabs { tabs });
+                        }
+                        Err(error) => {
+                            log::error!("Unable to read trashed tabs from database: {}", error)
+                        }
                     }
                 });
 
@@ -171,17 +214,10 @@ impl Reducer<CosterState, CosterAction, CosterEvent, CosterEffect> for CosterRed
                     let tabs_effect = tabs.clone();
                     let effect = DatabaseEffect::new(
                         "write all tabs to database",
-                        move |store, database| {
-                            let mut transaction = database.transaction();
-                            tabs_effect.write_to_db_id(
-                                &"tabs".to_string(),
-                                None,
-                                &mut transaction,
-                                &CosterClientDBStore::Tabs,
-                            );
-                            database
-                                .write(transaction)
-                                .expect("unable to write tabs to database");
+                        move |_store, database| {
+                            write_transaction(database, "write all tabs", |transaction| {
+                                tab_storage().write_tabs(database, transaction, &tabs_effect)
+                            });
                         },
                     );
 
@@ -191,6 +227,203 @@ impl Reducer<CosterState, CosterAction, CosterEvent, CosterEffect> for CosterRed
                 events.push(CosterEvent::TabsChanged);
                 Rc::new(prev_state.change_tabs(tabs.clone()))
             }
+            CosterAction::LoadTrashedTabs { tabs } => {
+                events.push(CosterEvent::TabsChanged);
+                Rc::new(prev_state.change_trashed_tabs(tabs.clone()))
+            }
+            CosterAction::TrashTab {
+                tab_id,
+                write_to_database,
+            } => {
+                let mut tabs = prev_state.tabs.clone();
+                let mut trashed_tabs = prev_state.trashed_tabs.clone();
+
+                if let Some(index) = tabs.iter().position(|tab| tab.id == *tab_id) {
+                    trashed_tabs.push(tabs.remove(index));
+                    events.push(CosterEvent::TabsChanged);
+                }
+
+                if *write_to_database {
+                    let effect_tab_id = *tab_id;
+                    let effect = DatabaseEffect::new("trash tab", move |_store, database| {
+                        write_transaction(database, "trash tab", |transaction| {
+                            tab_storage().trash_tab(
+                                database,
+                                transaction,
+                                &effect_tab_id,
+                                Utc::now(),
+                            )
+                        });
+                    });
+
+                    effects.push(effect.into());
+                }
+
+                Rc::new(prev_state.change_tabs_and_trashed_tabs(tabs, trashed_tabs))
+            }
+            CosterAction::RestoreTab {
+                tab_id,
+                write_to_database,
+            } => {
+                let mut tabs = prev_state.tabs.clone();
+                let mut trashed_tabs = prev_state.trashed_tabs.clone();
+
+                if let Some(index) = trashed_tabs.iter().position(|tab| tab.id == *tab_id) {
+                    tabs.push(trashed_tabs.remove(index));
+                    events.push(CosterEvent::TabsChanged);
+                }
+
+                if *write_to_database {
+                    let effect_tab_id = *tab_id;
+                    let effect = DatabaseEffect::new("restore tab", move |_store, database| {
+                        write_transaction(database, "restore tab", |transaction| {
+                            tab_storage().restore_tab(database, transaction, &effect_tab_id)
+                        });
+                    });
+
+                    effects.push(effect.into());
+                }
+
+                Rc::new(prev_state.change_tabs_and_trashed_tabs(tabs, trashed_tabs))
+            }
+            CosterAction::DeleteTab {
+                tab_id,
+                write_to_database,
+            } => {
+                let mut tabs = prev_state.tabs.clone();
+                let mut trashed_tabs = prev_state.trashed_tabs.clone();
+                tabs.retain(|tab| tab.id != *tab_id);
+                trashed_tabs.retain(|tab| tab.id != *tab_id);
+                events.push(CosterEvent::TabsChanged);
+
+                if *write_to_database {
+                    let effect_tab_id = *tab_id;
+                    let effect = DatabaseEffect::new("delete tab", move |_store, database| {
+                        write_transaction(database, "delete tab", |transaction| {
+                            tab_storage().delete_tab(database, transaction, &effect_tab_id)
+                        });
+                    });
+
+                    effects.push(effect.into());
+                }
+
+                Rc::new(prev_state.change_tabs_and_trashed_tabs(tabs, trashed_tabs))
+            }
+            CosterAction::PerformTabAction {
+                tab_id,
+                action,
+                write_to_database,
+            } => {
+                let mut tabs = prev_state.tabs.clone();
+
+                match tabs.iter().position(|tab| tab.id == *tab_id) {
+                    Some(index) => {
+                        let previous = tabs[index].clone();
+                        let mut tab: Tab = (*previous).clone();
+                        match action.perform(&mut tab) {
+                            Ok(()) => {
+                                tab.user_actions.push(action.clone());
+                                let tab = Rc::new(tab);
+                                tabs[index] = tab.clone();
+                                events.push(CosterEvent::TabsChanged);
+
+                                // only the action, and the records
+                                // which it touched, are written
+                                if *write_to_database {
+                                    let effect = DatabaseEffect::new(
+                                        "write tab action",
+                                        move |_store, database| {
+                                            write_transaction(
+                                                database,
+                                                "write tab action",
+                                                |transaction| {
+                                                    tab_storage().write_tab_changes(
+                                                        database,
+                                                        transaction,
+                                                        &previous,
+                                                        &tab,
+                                                    )
+                                                },
+                                            );
+                                        },
+                                    );
+
+                                    effects.push(effect.into());
+                                }
+
+                                Rc::new(prev_state.change_tabs(tabs))
+                            }
+                            Err(error) => {
+                                log::error!(
+                                    "Unable to perform {:?} on tab {}: {}",
+                                    action,
+                                    tab_id,
+                                    error
+                                );
+                                prev_state.clone()
+                            }
+                        }
+                    }
+                    None => {
+                        log::error!("Unable to perform an action on missing tab {}", tab_id);
+                        prev_state.clone()
+                    }
+                }
+            }
+            CosterAction::UpdateTab {
+                tab,
+                write_to_database,
+            } => {
+                let replace = |tabs: &Vec<Rc<Tab>>| -> Vec<Rc<Tab>> {
+                    tabs.iter()
+                        .map(|existing| {
+                            if existing.id == tab.id {
+                                tab.clone()
+                            } else {
+                                existing.clone()
+                            }
+                        })
+                        .collect()
+                };
+                let previous = prev_state
+                    .tabs
+                    .iter()
+                    .chain(prev_state.trashed_tabs.iter())
+                    .find(|existing| existing.id == tab.id)
+                    .cloned();
+                let tabs = replace(&prev_state.tabs);
+                let trashed_tabs = replace(&prev_state.trashed_tabs);
+                events.push(CosterEvent::TabsChanged);
+
+                if *write_to_database {
+                    let effect_tab = tab.clone();
+                    let effect = DatabaseEffect::new("update tab", move |_store, database| {
+                        write_transaction(database, "update tab", |transaction| match &previous {
+                            Some(previous) => tab_storage().write_tab_changes(
+                                database,
+                                transaction,
+                                previous,
+                                &effect_tab,
+                            ),
+                            None => tab_storage().write_tab(transaction, &effect_tab),
+                        });
+                    });
+
+                    effects.push(effect.into());
+                }
+
+                Rc::new(prev_state.change_tabs_and_trashed_tabs(tabs, trashed_tabs))
+            }
+            CosterAction::RejectTabActions { actions } => {
+                let mut rejected_tab_actions = prev_state.rejected_tab_actions.clone();
+                rejected_tab_actions.extend(actions.iter().cloned());
+                events.push(CosterEvent::RejectedTabActionsChanged);
+                Rc::new(prev_state.change_rejected_tab_actions(rejected_tab_actions))
+            }
+            CosterAction::DismissRejectedTabActions => {
+                events.push(CosterEvent::RejectedTabActionsChanged);
+                Rc::new(prev_state.change_rejected_tab_actions(Vec::new()))
+            }
         };
 
         ReducerResult {
@@ -17,6 +17,10 @@ pub enum AppRoute {
     /// Matches the `/about` route.
     #[to = "/about"]
     About,
+    /// Matches the `/join/{token}` route, which redeems the invite
+    /// with the specified token.
+    #[to = "/join/{token}"]
+    JoinTab(String),
     /// Matches the `/` route.
     #[to = "/"]
     Index, // Order is important here, the index needs to be last.
@@ -29,6 +33,7 @@ impl ToString for AppRoute {
             AppRoute::NewCostingTab => "/new".to_string(),
             AppRoute::Help => "/help".to_string(),
             AppRoute::About => "/about".to_string(),
+            AppRoute::JoinTab(token) => format!("/join/{}", token),
             AppRoute::Index => "/".to_string(),
         }
     }
@@ -76,6 +81,7 @@ impl Debug for AppRoute {
             AppRoute::NewCostingTab => "NewCostingTab",
             AppRoute::Help => "Help",
             AppRoute::About => "About",
+            AppRoute::JoinTab(_) => "JoinTab",
             AppRoute::Index => "Index",
         };
         write!(f, "{}: \"{}\"", route_name, self.to_string())
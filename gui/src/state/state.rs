@@ -1,5 +1,8 @@
 use super::{
-    middleware::localize::LocalizeState,
+    middleware::{
+        localize::LocalizeState,
+        sync::{RejectedTabAction, SyncState},
+    },
     AppRoute, CosterAction, CosterEffect, CosterEvent, RouteType,
 };
 use switch_router_middleware::RouteState;
@@ -20,6 +23,11 @@ pub struct CosterState {
     pub route: RouteType,
     pub last_selected_currency: Option<CommodityType>,
     pub tabs: Vec<Rc<Tab>>,
+    /// Tabs which have been moved to the trash, and can be restored.
+    pub trashed_tabs: Vec<Rc<Tab>>,
+    /// Actions which the server rejected while synchronising the
+    /// tabs, which haven't been dismissed by the user yet.
+    pub rejected_tab_actions: Vec<RejectedTabAction>,
 }
 
 impl Default for CosterState {
@@ -29,6 +37,8 @@ impl Default for CosterState {
             route: RouteType::Valid(AppRoute::Index),
             last_selected_currency: None,
             tabs: Vec::new(),
+            trashed_tabs: Vec::new(),
+            rejected_tab_actions: Vec::new(),
         }
     }
 }
@@ -41,6 +51,8 @@ impl CosterState {
             route,
             last_selected_currency: self.last_selected_currency.clone(),
             tabs: self.tabs.clone(),
+            trashed_tabs: self.trashed_tabs.clone(),
+            rejected_tab_actions: self.rejected_tab_actions.clone(),
         }
     }
 
@@ -50,6 +62,8 @@ impl CosterState {
             route: self.route.clone(),
             last_selected_currency: self.last_selected_currency.clone(),
             tabs: self.tabs.clone(),
+            trashed_tabs: self.trashed_tabs.clone(),
+            rejected_tab_actions: self.rejected_tab_actions.clone(),
         }
     }
 
@@ -62,6 +76,8 @@ impl CosterState {
             route: self.route.clone(),
             last_selected_currency,
             tabs: self.tabs.clone(),
+            trashed_tabs: self.trashed_tabs.clone(),
+            rejected_tab_actions: self.rejected_tab_actions.clone(),
         }
     }
 
@@ -71,6 +87,48 @@ impl CosterState {
             route: self.route.clone(),
             last_selected_currency: self.last_selected_currency.clone(),
             tabs,
+            trashed_tabs: self.trashed_tabs.clone(),
+            rejected_tab_actions: self.rejected_tab_actions.clone(),
+        }
+    }
+
+    pub fn change_trashed_tabs(&self, trashed_tabs: Vec<Rc<Tab>>) -> Self {
+        Self {
+            selected_language: self.selected_language.clone(),
+            route: self.route.clone(),
+            last_selected_currency: self.last_selected_currency.clone(),
+            tabs: self.tabs.clone(),
+            trashed_tabs,
+            rejected_tab_actions: self.rejected_tab_actions.clone(),
+        }
+    }
+
+    pub fn change_tabs_and_trashed_tabs(
+        &self,
+        tabs: Vec<Rc<Tab>>,
+        trashed_tabs: Vec<Rc<Tab>>,
+    ) -> Self {
+        Self {
+            selected_language: self.selected_language.clone(),
+            route: self.route.clone(),
+            last_selected_currency: self.last_selected_currency.clone(),
+            tabs,
+            trashed_tabs,
+            rejected_tab_actions: self.rejected_tab_actions.clone(),
+        }
+    }
+
+    pub fn change_rejected_tab_actions(
+        &self,
+        rejected_tab_actions: Vec<RejectedTabAction>,
+    ) -> Self {
+        Self {
+            selected_language: self.selected_language.clone(),
+            route: self.route.clone(),
+            last_selected_currency: self.last_selected_currency.clone(),
+            tabs: self.tabs.clone(),
+            trashed_tabs: self.trashed_tabs.clone(),
+            rejected_tab_actions,
         }
     }
 }
@@ -86,3 +144,13 @@ impl LocalizeState for CosterState {
         &self.selected_language
     }
 }
+
+impl SyncState for CosterState {
+    fn local_tabs(&self) -> Vec<Rc<Tab>> {
+        self.tabs
+            .iter()
+            .chain(self.trashed_tabs.iter())
+            .cloned()
+            .collect()
+    }
+}
@@ -0,0 +1,381 @@
+//! Accounts which people log in to the server with, and the sessions
+//! which remember them between requests. An account is linked to one
+//! [costing::User] on each of the tabs that it is a member of, and
+//! can only access those tabs.
+//!
+//! People who join a tab using an [Invite](crate::invites::Invite)
+//! without logging in are given a guest account, which has no email
+//! address or password, and can only be used for as long as its
+//! session lasts.
+
+use crate::error::ErrorCode;
+use chrono::{DateTime, Duration, Utc};
+use costing::{TabID, UserID};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+use warp::{
+    http::{Method, StatusCode},
+    Filter, Rejection,
+};
+
+pub type AccountID = Uuid;
+
+/// The name of the cookie which stores the session token in a
+/// browser.
+pub const SESSION_COOKIE: &str = "coster_session";
+
+/// The minimum number of characters in a password.
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// How long a session lasts after logging in.
+pub fn session_duration() -> Duration {
+    Duration::days(30)
+}
+
+/// An error which occurs while authenticating a request, or because
+/// an account is not allowed to perform an operation.
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("you need to log in to perform this operation")]
+    NotAuthenticated,
+    #[error("the session has expired or has been logged out")]
+    InvalidSession,
+    #[error("the email address or password is incorrect")]
+    InvalidCredentials,
+    #[error("{0:?} is not a valid email address")]
+    InvalidEmail(String),
+    #[error("the password needs to be at least {0} characters long")]
+    PasswordTooShort(usize),
+    #[error("an account with the email address {0:?} already exists")]
+    EmailAlreadyRegistered(String),
+    #[error("there is no account with the email address {0:?}")]
+    AccountNotFound(String),
+    #[error("the account is not a member of the tab with id {0}")]
+    NotTabMember(TabID),
+    #[error("the account is already a member of the tab with id {0}")]
+    AlreadyTabMember(TabID),
+    #[error("the user with id {1} on the tab with id {0} is already linked to an account")]
+    UserAlreadyLinked(TabID, UserID),
+    #[error(
+        "the action needs to be performed as the user with id {0}, which is linked to the account"
+    )]
+    WrongActingUser(UserID),
+    #[error("unable to hash the password")]
+    PasswordHash(#[from] argon2::Error),
+}
+
+impl ErrorCode for AuthError {
+    fn code(&self) -> &'static str {
+        match self {
+            AuthError::NotAuthenticated => "NOT_AUTHENTICATED",
+            AuthError::InvalidSession => "INVALID_SESSION",
+            AuthError::InvalidCredentials => "INVALID_CREDENTIALS",
+            AuthError::InvalidEmail(_) => "INVALID_EMAIL",
+            AuthError::PasswordTooShort(_) => "PASSWORD_TOO_SHORT",
+            AuthError::EmailAlreadyRegistered(_) => "EMAIL_ALREADY_REGISTERED",
+            AuthError::AccountNotFound(_) => "ACCOUNT_NOT_FOUND",
+            AuthError::NotTabMember(_) | AuthError::WrongActingUser(_) => "FORBIDDEN",
+            AuthError::AlreadyTabMember(_) => "ALREADY_TAB_MEMBER",
+            AuthError::UserAlreadyLinked(_, _) => "USER_ALREADY_LINKED",
+            AuthError::PasswordHash(_) => "INTERNAL",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            AuthError::NotAuthenticated
+            | AuthError::InvalidSession
+            | AuthError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            AuthError::InvalidEmail(_) | AuthError::PasswordTooShort(_) => StatusCode::BAD_REQUEST,
+            AuthError::EmailAlreadyRegistered(_)
+            | AuthError::AlreadyTabMember(_)
+            | AuthError::UserAlreadyLinked(_, _) => StatusCode::CONFLICT,
+            AuthError::AccountNotFound(_) => StatusCode::NOT_FOUND,
+            AuthError::NotTabMember(_) | AuthError::WrongActingUser(_) => StatusCode::FORBIDDEN,
+            AuthError::PasswordHash(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Normalize an email address so that it can be compared with
+/// others, and check that it looks valid.
+pub fn normalize_email(email: &str) -> Result<String, AuthError> {
+    let email = email.trim().to_lowercase();
+    match email.find('@') {
+        Some(at) if at > 0 && at < email.len() - 1 && !email.contains(char::is_whitespace) => {
+            Ok(email)
+        }
+        _ => Err(AuthError::InvalidEmail(email)),
+    }
+}
+
+/// The hash of a password which isn't used by any account, with the
+/// same parameters as the hashes created by [hash_password].
+const DUMMY_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=4096,t=3,p=1$pYiRo+I70TcwUsplDF1h7A$1e3RaVo+7gZRsx6T9N+df5o0Akr3BFY1KnorpDjJlAg";
+
+/// Check `password` against a dummy hash, which takes as long as
+/// checking the password of an account, and always fails. This is
+/// used when there is no password to check, so that the time taken
+/// to log in doesn't reveal which email addresses are registered.
+pub fn verify_dummy_password(password: &str) -> Result<bool, AuthError> {
+    argon2::verify_encoded(DUMMY_PASSWORD_HASH, password.as_bytes())?;
+    Ok(false)
+}
+
+fn hash_password(password: &str) -> Result<String, AuthError> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(AuthError::PasswordTooShort(MIN_PASSWORD_LENGTH));
+    }
+
+    let salt: [u8; 16] = rand::thread_rng().gen();
+    let config = argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        ..argon2::Config::default()
+    };
+    Ok(argon2::hash_encoded(password.as_bytes(), &salt, &config)?)
+}
+
+/// A random token which is practically impossible to guess, encoded
+/// so that it can be used in a URL.
+pub(crate) fn random_token() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// An account which can log in to the server.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Account {
+    pub id: AccountID,
+    /// The name displayed for the account.
+    pub name: String,
+    /// The (normalized) email address used to log in, or `None` for a
+    /// guest account.
+    pub email: Option<String>,
+    /// The password, hashed using Argon2id and encoded along with its
+    /// parameters, or `None` for a guest account.
+    pub password_hash: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Account {
+    /// Create a new account, hashing its `password`.
+    pub fn new(
+        name: &str,
+        email: &str,
+        password: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<Self, AuthError> {
+        Ok(Account {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            email: Some(normalize_email(email)?),
+            password_hash: Some(hash_password(password)?),
+            created_at,
+        })
+    }
+
+    /// Create a new guest account, which can't be logged in to.
+    pub fn guest(name: &str, created_at: DateTime<Utc>) -> Self {
+        Account {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            email: None,
+            password_hash: None,
+            created_at,
+        }
+    }
+
+    /// Whether this is a guest account.
+    pub fn is_guest(&self) -> bool {
+        self.email.is_none()
+    }
+
+    /// Check whether `password` is the password of this account. The
+    /// password of a guest account is never correct.
+    pub fn verify_password(&self, password: &str) -> Result<bool, AuthError> {
+        match &self.password_hash {
+            Some(password_hash) => Ok(argon2::verify_encoded(password_hash, password.as_bytes())?),
+            None => verify_dummy_password(password),
+        }
+    }
+}
+
+/// A logged in session of an [Account], identified by a random
+/// `token` which is sent with each request.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Session {
+    pub token: String,
+    pub account_id: AccountID,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Session {
+    /// Create a new session for the account with the specified id,
+    /// with a new random token.
+    pub fn new(account_id: AccountID, created_at: DateTime<Utc>) -> Self {
+        Session {
+            token: random_token(),
+            account_id,
+            created_at,
+            expires_at: created_at + session_duration(),
+        }
+    }
+
+    /// Whether this session has expired as of `now`.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        now >= self.expires_at
+    }
+}
+
+/// The value of a `Set-Cookie` header which stores the token of the
+/// `session` in the [SESSION_COOKIE], or removes the cookie if
+/// `session` is `None`. If `secure` is set (because the server is
+/// served over HTTPS), browsers only send the cookie over HTTPS.
+pub fn session_cookie(session: Option<&Session>, secure: bool) -> String {
+    let (token, max_age) = match session {
+        Some(session) => (
+            session.token.as_str(),
+            (session.expires_at - session.created_at).num_seconds(),
+        ),
+        None => ("", 0),
+    };
+    format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        SESSION_COOKIE,
+        token,
+        max_age,
+        if secure { "; Secure" } else { "" }
+    )
+}
+
+/// Extracts the session token sent with a request, either as a
+/// `Bearer` token in the `Authorization` header (for scripts), or in
+/// the [SESSION_COOKIE] (for browsers).
+pub fn session_token() -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::cookie::optional(SESSION_COOKIE))
+        .map(|authorization: Option<String>, cookie: Option<String>| {
+            authorization
+                .and_then(|authorization| {
+                    authorization
+                        .strip_prefix("Bearer ")
+                        .map(|token| token.trim().to_string())
+                })
+                .or(cookie)
+                .filter(|token| !token.is_empty())
+        })
+}
+
+/// Like [session_token], except that the [SESSION_COOKIE] is only used
+/// for `POST` requests with a JSON body. The cookie is `SameSite=Lax`,
+/// so browsers also send it with links and forms from other sites,
+/// which must not be able to act as the account. A `Bearer` token can
+/// be used with any request.
+pub fn json_session_token() -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::method()
+        .and(warp::header::optional::<String>("content-type"))
+        .and(session_token())
+        .and(warp::header::optional::<String>("authorization"))
+        .map(
+            |method: Method,
+             content_type: Option<String>,
+             token: Option<String>,
+             authorization: Option<String>| {
+                let is_json = content_type.is_some_and(|content_type| {
+                    content_type
+                        .to_ascii_lowercase()
+                        .starts_with("application/json")
+                });
+                let is_bearer =
+                    authorization.is_some_and(|authorization| authorization.starts_with("Bearer "));
+                token.filter(|_| is_bearer || (method == Method::POST && is_json))
+            },
+        )
+}
+
+/// The link between an [Account], and the user which it acts as on a
+/// tab.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TabMember {
+    pub tab_id: TabID,
+    pub user_id: UserID,
+    pub account_id: AccountID,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{
+        normalize_email, session_cookie, verify_dummy_password, Account, AuthError, Session,
+    };
+    use chrono::{Duration, Utc};
+    use uuid::Uuid;
+
+    /// Create an account with the specified `name`, and an email
+    /// address derived from it. It has no password (so that tests run
+    /// quickly), and can't be used to log in.
+    pub(crate) fn create_test_account(name: &str) -> Account {
+        Account {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            email: Some(format!("{}@example.com", name.to_lowercase())),
+            password_hash: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn passwords_are_hashed() {
+        let account =
+            Account::new("Alice", " Alice@Example.com", "correct horse", Utc::now()).unwrap();
+        assert_eq!(Some("alice@example.com"), account.email.as_deref());
+        assert!(!account
+            .password_hash
+            .as_ref()
+            .unwrap()
+            .contains("correct horse"));
+        assert!(account.verify_password("correct horse").unwrap());
+        assert!(!account.verify_password("battery staple").unwrap());
+
+        let guest = Account::guest("Carol", Utc::now());
+        assert!(guest.is_guest());
+        assert!(!guest.verify_password("").unwrap());
+        assert!(!verify_dummy_password("correct horse").unwrap());
+
+        assert!(matches!(
+            Account::new("Alice", "alice@example.com", "short", Utc::now()),
+            Err(AuthError::PasswordTooShort(_))
+        ));
+    }
+
+    #[test]
+    fn email_addresses_are_validated() {
+        assert!(normalize_email("bob@example.com").is_ok());
+        for email in &["bob", "@example.com", "bob@", "bob smith@example.com"] {
+            assert!(matches!(
+                normalize_email(email),
+                Err(AuthError::InvalidEmail(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn sessions_expire() {
+        let now = Utc::now();
+        let session = Session::new(Uuid::new_v4(), now);
+        assert_ne!(session.token, Session::new(session.account_id, now).token);
+        assert!(!session.is_expired(now + Duration::days(1)));
+        assert!(session.is_expired(now + Duration::days(31)));
+    }
+
+    #[test]
+    fn session_cookies_are_secure_over_https() {
+        let session = Session::new(Uuid::new_v4(), Utc::now());
+        assert!(!session_cookie(Some(&session), false).contains("Secure"));
+        assert!(session_cookie(Some(&session), true).ends_with("; Secure"));
+        assert!(session_cookie(None, true).ends_with("; Secure"));
+    }
+}
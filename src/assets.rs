@@ -0,0 +1,554 @@
+//! The static files in `public/` (the GUI's wasm bundle, styles and
+//! icons), which are embedded in the binary and served under `/dist`.
+//!
+//! Each file is prepared when the server starts (or the first time it
+//! is requested, if that's sooner): it is hashed to create its `ETag`,
+//! and compressed with brotli and gzip, which are kept in memory so
+//! that they are only compressed once. A file which is requested
+//! before it has been compressed is only compressed with the encoding
+//! which the request accepts. Files whose names contain a hash of
+//! their content (e.g. `gui-3f2a9c1b7d.js`) can be cached by browsers
+//! forever, and the others need to be revalidated using their `ETag`.
+
+use crate::web::get_or_head;
+use brotli::CompressorWriter;
+use flate2::{write::GzEncoder, Compression};
+use log::{debug, error};
+use ring::digest::{digest, SHA256};
+use rust_embed::RustEmbed;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, OnceLock};
+use warp::filters::BoxedFilter;
+use warp::http::header::{self, HeaderValue};
+use warp::http::{Method, StatusCode};
+use warp::hyper::body::{Body, Bytes};
+use warp::path::Tail;
+use warp::reply::Response;
+use warp::Filter;
+
+#[derive(RustEmbed)]
+#[folder = "public/"]
+struct Asset;
+
+/// The `Cache-Control` header of files whose names contain a hash of
+/// their content, which never change.
+pub const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// The `Cache-Control` header of the other files, which browsers can
+/// store, but need to check are still current using their `ETag`.
+pub const REVALIDATE_CACHE_CONTROL: &str = "no-cache";
+
+/// Files smaller than this aren't worth compressing.
+const MIN_COMPRESSED_SIZE: usize = 1024;
+
+/// The minimum number of hexadecimal digits in the part of a file
+/// name which is a hash of its content.
+const MIN_NAME_HASH_LENGTH: usize = 8;
+
+/// The quality used to compress files with brotli, from 0 to 11.
+/// Each file is only compressed once, so the best compression is used.
+const BROTLI_QUALITY: u32 = 11;
+
+/// The base 2 logarithm of the brotli window size.
+const BROTLI_WINDOW: u32 = 22;
+
+/// An encoding which a file can be sent with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    /// The value of the `Content-Encoding` header, or `None` for
+    /// [Encoding::Identity].
+    fn content_encoding(self) -> Option<&'static str> {
+        match self {
+            Encoding::Identity => None,
+            Encoding::Gzip => Some("gzip"),
+            Encoding::Brotli => Some("br"),
+        }
+    }
+
+    /// The encoding most preferred in the value of an
+    /// `Accept-Encoding` header, e.g. `gzip, deflate, br`. Brotli is
+    /// preferred over gzip when the client accepts both equally,
+    /// because it compresses better.
+    pub fn negotiate(accept_encoding: Option<&str>) -> Encoding {
+        // the qualities of brotli, gzip and any other encoding (`*`),
+        // which are 0 (not acceptable) unless they are listed
+        let (mut brotli, mut gzip, mut any) = (None, None, None);
+        for preference in accept_encoding.unwrap_or_default().split(',') {
+            let mut parts = preference.split(';');
+            let name = parts.next().unwrap_or_default().trim();
+            let quality: f32 = match parts
+                .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+                .next()
+            {
+                Some(quality) => quality.trim().parse().unwrap_or(0.0),
+                None => 1.0,
+            };
+            if name == "*" {
+                any = Some(quality);
+            } else if name.eq_ignore_ascii_case("br") {
+                brotli = Some(quality);
+            } else if name.eq_ignore_ascii_case("gzip") {
+                gzip = Some(quality);
+            }
+        }
+
+        let brotli = brotli.or(any).unwrap_or(0.0);
+        let gzip = gzip.or(any).unwrap_or(0.0);
+        if brotli > 0.0 && brotli >= gzip {
+            Encoding::Brotli
+        } else if gzip > 0.0 {
+            Encoding::Gzip
+        } else {
+            Encoding::Identity
+        }
+    }
+}
+
+/// Whether the name of the file at `path` contains a hash of its
+/// content, e.g. `gui.3f2a9c1b.js` or `gui-3f2a9c1b_bg.wasm`.
+fn is_hashed(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let stem = match name.rfind('.') {
+        Some(extension) => &name[..extension],
+        None => name,
+    };
+    stem.split(['.', '-', '_']).any(|part| {
+        part.len() >= MIN_NAME_HASH_LENGTH && part.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+/// Whether files with the `mime` type are made smaller by
+/// compressing them, unlike images and fonts which are already
+/// compressed.
+fn is_compressible(mime: &mime_guess::Mime) -> bool {
+    mime.type_() == mime_guess::mime::TEXT
+        || ["javascript", "json", "wasm", "xml", "svg+xml", "x-icon"]
+            .contains(&mime.subtype().as_str())
+        || mime.essence_str() == "image/vnd.microsoft.icon"
+}
+
+fn gzip(content: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(content)?;
+    encoder.finish()
+}
+
+fn brotli(content: &[u8]) -> io::Result<Vec<u8>> {
+    let mut compressed = Vec::new();
+    {
+        let mut writer =
+            CompressorWriter::new(&mut compressed, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+        writer.write_all(content)?;
+    }
+    Ok(compressed)
+}
+
+/// A static file which is ready to be served.
+#[derive(Debug)]
+struct PreparedFile {
+    path: String,
+    content: Bytes,
+    /// Whether the content is worth compressing.
+    compressible: bool,
+    /// The content compressed with gzip, once it has been compressed,
+    /// which is `None` if it isn't any smaller.
+    gzip: OnceLock<Option<Bytes>>,
+    /// The content compressed with brotli, once it has been
+    /// compressed, which is `None` if it isn't any smaller.
+    brotli: OnceLock<Option<Bytes>>,
+    /// A hash of the content, which the `ETag`s are created from.
+    hash: String,
+    content_type: HeaderValue,
+    cache_control: &'static str,
+}
+
+impl PreparedFile {
+    /// Prepare the file at `path`, with the specified `content`, which
+    /// is compressed later, by [PreparedFile::compressed].
+    fn new(path: &str, content: Bytes) -> Self {
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+        let hash = digest(&SHA256, &content);
+
+        PreparedFile {
+            path: path.to_string(),
+            compressible: content.len() >= MIN_COMPRESSED_SIZE && is_compressible(&mime),
+            gzip: OnceLock::new(),
+            brotli: OnceLock::new(),
+            hash: base64::encode_config(&hash.as_ref()[..16], base64::URL_SAFE_NO_PAD),
+            content_type: HeaderValue::from_str(mime.as_ref())
+                .expect("mime types are valid header values"),
+            cache_control: if is_hashed(path) {
+                IMMUTABLE_CACHE_CONTROL
+            } else {
+                REVALIDATE_CACHE_CONTROL
+            },
+            content,
+        }
+    }
+
+    /// Where the content compressed with the `encoding` is kept, or
+    /// `None` if the content isn't compressed with it.
+    fn compressed_cell(&self, encoding: Encoding) -> Option<&OnceLock<Option<Bytes>>> {
+        match encoding {
+            _ if !self.compressible => None,
+            Encoding::Identity => None,
+            Encoding::Gzip => Some(&self.gzip),
+            Encoding::Brotli => Some(&self.brotli),
+        }
+    }
+
+    /// Whether the content can be sent with the `encoding` without
+    /// compressing it first.
+    fn is_ready(&self, encoding: Encoding) -> bool {
+        self.compressed_cell(encoding)
+            .is_none_or(|cell| cell.get().is_some())
+    }
+
+    /// The content compressed with the `encoding`, if it is smaller.
+    /// The content is compressed the first time this is called for the
+    /// encoding, and calls for the same encoding in the meantime wait
+    /// for it. Large files are slow to compress, so this shouldn't be
+    /// called on the runtime's threads unless it [is
+    /// ready](PreparedFile::is_ready).
+    fn compressed(&self, encoding: Encoding) -> Option<&Bytes> {
+        let cell = self.compressed_cell(encoding)?;
+        cell.get_or_init(|| {
+            let (name, compressed) = match encoding {
+                Encoding::Gzip => ("gzip", gzip(&self.content)),
+                _ => ("brotli", brotli(&self.content)),
+            };
+            match compressed {
+                // it isn't worth sending if it isn't any smaller
+                Ok(compressed) if compressed.len() < self.content.len() => {
+                    Some(Bytes::from(compressed))
+                }
+                Ok(_) => None,
+                Err(error) => {
+                    error!("Unable to compress {} with {}: {}", self.path, name, error);
+                    None
+                }
+            }
+        })
+        .as_ref()
+    }
+
+    /// The content to send to a client which prefers the `encoding`,
+    /// and the encoding it is actually sent with.
+    fn variant(&self, encoding: Encoding) -> (Encoding, &Bytes) {
+        match self.compressed(encoding) {
+            Some(compressed) => (encoding, compressed),
+            None => (Encoding::Identity, &self.content),
+        }
+    }
+
+    /// The `ETag` of the content sent with the `encoding`, which is
+    /// different for each encoding, because the bytes are different.
+    fn etag(&self, encoding: Encoding) -> String {
+        match encoding.content_encoding() {
+            Some(name) => format!("\"{}.{}\"", self.hash, name),
+            None => format!("\"{}\"", self.hash),
+        }
+    }
+
+    /// Whether the value of an `If-None-Match` header matches any of
+    /// the `ETag`s of this file, so that the client's cached copy is
+    /// current.
+    fn matches(&self, if_none_match: &str) -> bool {
+        if_none_match.split(',').any(|tag| {
+            let tag = tag.trim();
+            let tag = tag.strip_prefix("W/").unwrap_or(tag);
+            tag == "*"
+                || [Encoding::Identity, Encoding::Gzip, Encoding::Brotli]
+                    .iter()
+                    .any(|encoding| tag == self.etag(*encoding))
+        })
+    }
+
+    /// The response to a request for this file, which accepts the
+    /// `encoding` (which needs to be [ready](PreparedFile::is_ready)
+    /// to avoid blocking). If the client's cached copy matches, the response
+    /// has no content, and `HEAD` requests get the headers which
+    /// would have been sent with the content.
+    fn response(&self, encoding: Encoding, if_none_match: Option<&str>, head: bool) -> Response {
+        let (encoding, content) = self.variant(encoding);
+        let mut response = Response::new(Body::empty());
+        let headers = response.headers_mut();
+        headers.insert(header::CONTENT_TYPE, self.content_type.clone());
+        headers.insert(
+            header::ETAG,
+            HeaderValue::from_str(&self.etag(encoding)).expect("the hash is a valid header value"),
+        );
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(self.cache_control),
+        );
+        if self.compressible {
+            headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+        }
+
+        if if_none_match.is_some_and(|tags| self.matches(tags)) {
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            return response;
+        }
+
+        if let Some(name) = encoding.content_encoding() {
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(name));
+        }
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(content.len()));
+        if !head {
+            *response.body_mut() = Body::from(content.clone());
+        }
+        response
+    }
+}
+
+/// The static files which have been prepared to be served.
+#[derive(Clone, Default)]
+pub struct StaticFiles(Arc<Mutex<HashMap<String, Arc<PreparedFile>>>>);
+
+impl StaticFiles {
+    /// Prepare every file, and compress it with each encoding, so that
+    /// requests don't have to wait for them to be compressed. The
+    /// files are prepared one at a time, on a thread where blocking is
+    /// allowed.
+    pub async fn prepare_all(&self) {
+        for path in Asset::iter() {
+            let file = match self.get(&path).await {
+                Some(file) => file,
+                None => continue,
+            };
+            let _ = tokio::task::spawn_blocking(move || {
+                file.compressed(Encoding::Brotli);
+                file.compressed(Encoding::Gzip);
+            })
+            .await;
+        }
+        debug!(target: "coster::dist", "Prepared the static files");
+    }
+
+    /// The prepared file at `path` within `public/`, or `None` if it
+    /// doesn't exist.
+    async fn get(&self, path: &str) -> Option<Arc<PreparedFile>> {
+        let content = Asset::get(path)?;
+        if let Some(prepared) = self.0.lock().unwrap().get(path) {
+            // in debug builds the files are read from the disk, so
+            // they can change while the server is running
+            if !cfg!(debug_assertions) || prepared.content[..] == content[..] {
+                return Some(prepared.clone());
+            }
+        }
+
+        let content = match content {
+            Cow::Borrowed(content) => Bytes::from_static(content),
+            Cow::Owned(content) => Bytes::from(content),
+        };
+        debug!(target: "coster::dist", "Preparing static file: {}", path);
+        let prepared_path = path.to_string();
+        let prepared = tokio::task::spawn_blocking(move || {
+            Arc::new(PreparedFile::new(&prepared_path, content))
+        })
+        .await
+        .ok()?;
+
+        let mut files = self.0.lock().unwrap();
+        match files.get(path) {
+            // the file was prepared by another request in the meantime,
+            // which is kept so that it is only compressed once
+            Some(existing) if existing.content == prepared.content => Some(existing.clone()),
+            _ => {
+                files.insert(path.to_string(), prepared.clone());
+                Some(prepared)
+            }
+        }
+    }
+}
+
+/// For any path within the path `/dist`, serve the embedded static
+/// `files`.
+pub fn routes(files: StaticFiles) -> BoxedFilter<(Response,)> {
+    warp::path("dist")
+        .and(get_or_head())
+        .and(warp::path::tail())
+        .and(warp::method())
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(warp::any().map(move || files.clone()))
+        .and_then(
+            |path: Tail,
+             method: Method,
+             accept_encoding: Option<String>,
+             if_none_match: Option<String>,
+             files: StaticFiles| async move {
+                debug!(target: "coster::dist", "Serving a request for static file in dist/{:?}", path);
+                let file = files
+                    .get(path.as_str())
+                    .await
+                    .ok_or_else(warp::reject::not_found)?;
+                let encoding = Encoding::negotiate(accept_encoding.as_deref());
+                if !file.is_ready(encoding) {
+                    let file = file.clone();
+                    let _ = tokio::task::spawn_blocking(move || file.compressed(encoding).is_some())
+                        .await;
+                }
+                Ok::<_, warp::Rejection>(file.response(
+                    encoding,
+                    if_none_match.as_deref(),
+                    method == Method::HEAD,
+                ))
+            },
+        )
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_hashed, routes, Asset, Encoding, StaticFiles, REVALIDATE_CACHE_CONTROL};
+    use std::io::Read;
+
+    #[test]
+    fn negotiate_encoding() {
+        assert_eq!(Encoding::Identity, Encoding::negotiate(None));
+        assert_eq!(Encoding::Identity, Encoding::negotiate(Some("deflate")));
+        assert_eq!(Encoding::Gzip, Encoding::negotiate(Some("gzip, deflate")));
+        assert_eq!(
+            Encoding::Brotli,
+            Encoding::negotiate(Some("gzip, deflate, br"))
+        );
+        assert_eq!(
+            Encoding::Gzip,
+            Encoding::negotiate(Some("br;q=0.5, gzip;q=0.8"))
+        );
+        assert_eq!(Encoding::Gzip, Encoding::negotiate(Some("br;q=0, *")));
+        assert_eq!(Encoding::Identity, Encoding::negotiate(Some("*;q=0")));
+    }
+
+    #[test]
+    fn hashed_file_names() {
+        assert!(is_hashed("js/gui.3f2a9c1b.js"));
+        assert!(is_hashed("js/gui-3f2a9c1b7d6e5f40_bg.wasm"));
+        assert!(!is_hashed("js/gui/gui_bg.wasm"));
+        assert!(!is_hashed("css/bulma/bulma.min.css"));
+        assert!(!is_hashed("css/webfonts/fa-solid-900.woff2"));
+        assert!(!is_hashed("deadbeef01/style.css"));
+    }
+
+    #[tokio::test]
+    async fn serve_static_files() {
+        let files = StaticFiles::default();
+        let routes = routes(files.clone());
+        let path = "/dist/css/bulma/bulma.min.css";
+        let original = Asset::get("css/bulma/bulma.min.css").unwrap();
+
+        let response = warp::test::request().path(path).reply(&routes).await;
+        assert_eq!(200, response.status());
+        assert_eq!("text/css", response.headers()["content-type"]);
+        assert_eq!(
+            REVALIDATE_CACHE_CONTROL,
+            response.headers()["cache-control"]
+        );
+        assert_eq!("accept-encoding", response.headers()["vary"]);
+        assert!(response.headers().get("content-encoding").is_none());
+        assert_eq!(original[..], response.body()[..]);
+        let etag = response.headers()["etag"].clone();
+
+        let response = warp::test::request()
+            .path(path)
+            .header("accept-encoding", "gzip, deflate, br")
+            .reply(&routes)
+            .await;
+        assert_eq!("br", response.headers()["content-encoding"]);
+        assert!(response.body().len() < original.len());
+        let mut decompressed = Vec::new();
+        brotli::Decompressor::new(&response.body()[..], 4096)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(original[..], decompressed[..]);
+        assert_ne!(etag, response.headers()["etag"]);
+
+        // only the encoding which was requested has been compressed
+        let file = files.get("css/bulma/bulma.min.css").await.unwrap();
+        assert!(file.brotli.get().is_some());
+        assert!(file.gzip.get().is_none());
+
+        let response = warp::test::request()
+            .path(path)
+            .header("accept-encoding", "gzip")
+            .reply(&routes)
+            .await;
+        assert_eq!("gzip", response.headers()["content-encoding"]);
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(&response.body()[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(original[..], decompressed[..]);
+
+        // the cached copy is current, whichever encoding it was sent
+        // with
+        let response = warp::test::request()
+            .path(path)
+            .header("accept-encoding", "gzip")
+            .header("if-none-match", etag.to_str().unwrap())
+            .reply(&routes)
+            .await;
+        assert_eq!(304, response.status());
+        assert!(response.body().is_empty());
+        let response = warp::test::request()
+            .path(path)
+            .header("if-none-match", "\"outdated\"")
+            .reply(&routes)
+            .await;
+        assert_eq!(200, response.status());
+
+        let response = warp::test::request()
+            .method("HEAD")
+            .path(path)
+            .reply(&routes)
+            .await;
+        assert_eq!(200, response.status());
+        assert_eq!(
+            original.len().to_string(),
+            response.headers()["content-length"]
+        );
+        assert!(response.body().is_empty());
+
+        // fonts are already compressed
+        let response = warp::test::request()
+            .path("/dist/css/webfonts/fa-solid-900.woff2")
+            .header("accept-encoding", "br")
+            .reply(&routes)
+            .await;
+        assert_eq!(200, response.status());
+        assert!(response.headers().get("content-encoding").is_none());
+        assert!(response.headers().get("vary").is_none());
+
+        let response = warp::test::request()
+            .path("/dist/missing.js")
+            .reply(&routes)
+            .await;
+        assert_eq!(404, response.status());
+        let response = warp::test::request()
+            .method("POST")
+            .path(path)
+            .reply(&routes)
+            .await;
+        assert_eq!(405, response.status());
+    }
+
+    #[tokio::test]
+    async fn prepare_all_static_files() {
+        let files = StaticFiles::default();
+        files.prepare_all().await;
+        assert_eq!(Asset::iter().count(), files.0.lock().unwrap().len());
+        let file = files.get("css/bulma/bulma.min.css").await.unwrap();
+        assert!(file.is_ready(Encoding::Brotli));
+        assert!(file.is_ready(Encoding::Gzip));
+    }
+}
@@ -0,0 +1,754 @@
+//! The configuration of the server, which is read from (in order of
+//! increasing precedence) a TOML configuration file, environment
+//! variables, and command line arguments. The settings are described
+//! in `coster.example.toml`, at the root of the repository.
+
+use crate::repository::{RepositoryBackend, DEFAULT_DATA_DIR};
+use clap::{App, AppSettings, Arg, ArgMatches};
+use log::LevelFilter;
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::fmt::{self, Display};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+/// The port which the server listens on if none is configured.
+pub const DEFAULT_PORT: u16 = 8000;
+
+/// The size (in bytes) of the largest request body which is accepted
+/// if no limit is configured.
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 2 * 1024 * 1024;
+
+/// The number of requests which each client can make per minute if
+/// no rate limit is configured.
+pub const DEFAULT_RATE_LIMIT: u32 = 600;
+
+/// How long the server waits for requests in progress to complete
+/// when it is stopped, if no timeout is configured.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An error in the configuration of the server.
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    /// The command line arguments are invalid, or help or version
+    /// information was requested, which is displayed by
+    /// [clap::Error::exit].
+    #[error(transparent)]
+    Arguments(#[from] clap::Error),
+    #[error("unable to read the configuration file {0:?}")]
+    ReadFile(PathBuf, #[source] std::io::Error),
+    #[error("unable to parse the configuration file {0:?}")]
+    ParseFile(PathBuf, #[source] toml::de::Error),
+    #[error("invalid value {value:?} for {name}: {reason}")]
+    InvalidValue {
+        /// The name of the setting, as it was specified.
+        name: String,
+        value: String,
+        reason: String,
+    },
+    #[error("the TLS certificate and key need to be specified together")]
+    IncompleteTls,
+    #[error("redirecting HTTP to HTTPS requires a TLS certificate and key")]
+    RedirectWithoutTls,
+    #[error("the {0} file {1:?} does not exist")]
+    MissingFile(&'static str, PathBuf),
+    #[error("the data directory {0:?} is not a directory")]
+    InvalidDataDir(PathBuf),
+}
+
+/// The paths of the certificate and private key used to serve HTTPS.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    /// A PEM encoded certificate chain.
+    pub certificate: PathBuf,
+    /// A PEM encoded private key.
+    pub key: PathBuf,
+    /// Serve HTTP on this port, redirecting every request to HTTPS.
+    pub redirect_port: Option<u16>,
+}
+
+/// The validated configuration of the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// The address of the interface which the server listens on.
+    pub bind_address: IpAddr,
+    pub port: u16,
+    /// The directory which the database is stored in.
+    pub data_dir: PathBuf,
+    pub backend: RepositoryBackend,
+    /// The level of the messages which are logged, which can be
+    /// refined using the `RUST_LOG` environment variable.
+    pub log_level: LevelFilter,
+    /// The origins (e.g. `https://coster.example.com`) which are
+    /// allowed to make cross origin requests to the API, or `*` for
+    /// any origin.
+    pub allowed_origins: Vec<String>,
+    /// The size (in bytes) of the largest request body which is
+    /// accepted.
+    pub max_body_size: u64,
+    /// The number of requests which each client (identified by its IP
+    /// address) can make per minute, or `0` for no limit.
+    pub rate_limit: u32,
+    /// The addresses of the reverse proxies in front of the server,
+    /// whose `X-Forwarded-For` and `Forwarded` headers are trusted to
+    /// identify the client.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// How long the server waits for the requests and background jobs
+    /// in progress to complete when it is stopped.
+    pub shutdown_timeout: Duration,
+    /// Serve HTTPS using this certificate, rather than HTTP.
+    pub tls: Option<TlsConfig>,
+}
+
+impl Config {
+    /// The address which the server listens on.
+    pub fn address(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+
+    /// Create the configuration from the `settings`, using the
+    /// defaults for those which weren't specified.
+    fn from_settings(settings: Settings) -> Result<Self, ConfigError> {
+        let data_dir = settings
+            .data_dir
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_DATA_DIR));
+        if data_dir.exists() && !data_dir.is_dir() {
+            return Err(ConfigError::InvalidDataDir(data_dir));
+        }
+
+        let port = settings.port.unwrap_or(DEFAULT_PORT);
+
+        let tls = match (settings.tls.certificate, settings.tls.key) {
+            (Some(certificate), Some(key)) => Some(TlsConfig {
+                certificate: existing_file("TLS certificate", certificate)?,
+                key: existing_file("TLS key", key)?,
+                redirect_port: settings.tls.redirect_port,
+            }),
+            (None, None) if settings.tls.redirect_port.is_some() => {
+                return Err(ConfigError::RedirectWithoutTls)
+            }
+            (None, None) => None,
+            _ => return Err(ConfigError::IncompleteTls),
+        };
+        if let Some(redirect_port) = settings.tls.redirect_port {
+            if redirect_port == port {
+                return Err(ConfigError::InvalidValue {
+                    name: "redirect_port".to_string(),
+                    value: redirect_port.to_string(),
+                    reason: "the port is already used to serve HTTPS".to_string(),
+                });
+            }
+        }
+
+        let allowed_origins = settings.allowed_origins.unwrap_or_default();
+        for origin in &allowed_origins {
+            validate_origin(origin).map_err(|reason| ConfigError::InvalidValue {
+                name: "allowed_origins".to_string(),
+                value: origin.clone(),
+                reason,
+            })?;
+        }
+
+        Ok(Config {
+            bind_address: settings
+                .bind_address
+                .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            port,
+            data_dir,
+            backend: parse_setting("backend", settings.backend)?
+                .unwrap_or(RepositoryBackend::RocksDB),
+            log_level: parse_setting("log_level", settings.log_level)?.unwrap_or(LevelFilter::Info),
+            allowed_origins,
+            max_body_size: settings.max_body_size.unwrap_or(DEFAULT_MAX_BODY_SIZE),
+            rate_limit: settings.rate_limit.unwrap_or(DEFAULT_RATE_LIMIT),
+            trusted_proxies: settings.trusted_proxies.unwrap_or_default(),
+            shutdown_timeout: settings
+                .shutdown_timeout
+                .map_or(DEFAULT_SHUTDOWN_TIMEOUT, Duration::from_secs),
+            tls,
+        })
+    }
+}
+
+impl Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "address: {}", self.address())?;
+        writeln!(f, "data directory: {:?}", self.data_dir)?;
+        writeln!(f, "storage backend: {:?}", self.backend)?;
+        writeln!(f, "log level: {}", self.log_level)?;
+        writeln!(f, "allowed origins: {:?}", self.allowed_origins)?;
+        writeln!(f, "maximum body size: {} bytes", self.max_body_size)?;
+        match self.rate_limit {
+            0 => writeln!(f, "rate limit: disabled")?,
+            limit => writeln!(f, "rate limit: {} requests per minute", limit)?,
+        }
+        writeln!(f, "trusted proxies: {:?}", self.trusted_proxies)?;
+        writeln!(
+            f,
+            "shutdown timeout: {} seconds",
+            self.shutdown_timeout.as_secs()
+        )?;
+        match &self.tls {
+            Some(tls) => {
+                write!(
+                    f,
+                    "TLS: certificate {:?}, key {:?}",
+                    tls.certificate, tls.key
+                )?;
+                match tls.redirect_port {
+                    Some(port) => write!(f, "\nHTTP redirect port: {}", port),
+                    None => write!(f, "\nHTTP redirect: disabled"),
+                }
+            }
+            None => write!(f, "TLS: disabled"),
+        }
+    }
+}
+
+fn existing_file(description: &'static str, path: PathBuf) -> Result<PathBuf, ConfigError> {
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err(ConfigError::MissingFile(description, path))
+    }
+}
+
+/// Check that an `origin` is `*`, or consists of only an `http` or
+/// `https` scheme, a host, and an optional port.
+fn validate_origin(origin: &str) -> Result<(), String> {
+    if origin == "*" {
+        return Ok(());
+    }
+
+    let host = origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        .ok_or_else(|| "the scheme needs to be http or https".to_string())?;
+    if host.is_empty() {
+        return Err("the host is missing".to_string());
+    }
+    if host.contains(&['/', '?', '#'][..]) {
+        return Err("an origin can't contain a path".to_string());
+    }
+    Ok(())
+}
+
+/// Parse the `value` of the setting with the specified `name`, if
+/// there is one.
+fn parse_setting<T>(name: &str, value: Option<String>) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value.map(|value| parse_value(name, value)).transpose()
+}
+
+/// Parse each of the `values` of the list setting with the specified
+/// `name`.
+fn parse_list<'a, T, I>(name: &str, values: I) -> Result<Vec<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+    I: IntoIterator<Item = &'a str>,
+{
+    values
+        .into_iter()
+        .map(|value| parse_value(name, value.to_string()))
+        .collect()
+}
+
+fn parse_value<T>(name: &str, value: String) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|error: T::Err| ConfigError::InvalidValue {
+            name: name.to_string(),
+            reason: error.to_string(),
+            value,
+        })
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct TlsSettings {
+    certificate: Option<PathBuf>,
+    key: Option<PathBuf>,
+    redirect_port: Option<u16>,
+}
+
+/// The settings specified by one of the sources of the
+/// configuration, which are all optional.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    bind_address: Option<IpAddr>,
+    port: Option<u16>,
+    data_dir: Option<PathBuf>,
+    backend: Option<String>,
+    log_level: Option<String>,
+    allowed_origins: Option<Vec<String>>,
+    max_body_size: Option<u64>,
+    rate_limit: Option<u32>,
+    trusted_proxies: Option<Vec<IpAddr>>,
+    /// In seconds.
+    shutdown_timeout: Option<u64>,
+    #[serde(default)]
+    tls: TlsSettings,
+}
+
+impl Settings {
+    /// Read the settings from a TOML configuration file.
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|error| ConfigError::ReadFile(path.to_path_buf(), error))?;
+        toml::from_str(&contents).map_err(|error| ConfigError::ParseFile(path.to_path_buf(), error))
+    }
+
+    /// Read the settings from the environment variables returned by
+    /// `var`.
+    fn from_env<F>(var: F) -> Result<Self, ConfigError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        Ok(Settings {
+            bind_address: parse_setting("COSTER_BIND_ADDRESS", var("COSTER_BIND_ADDRESS"))?,
+            port: parse_setting("COSTER_PORT", var("COSTER_PORT"))?,
+            data_dir: var("COSTER_DATA_DIR").map(PathBuf::from),
+            backend: var("COSTER_DB_BACKEND"),
+            log_level: var("COSTER_LOG_LEVEL"),
+            allowed_origins: var("COSTER_ALLOWED_ORIGINS").map(|origins| {
+                origins
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|origin| !origin.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }),
+            max_body_size: parse_setting("COSTER_MAX_BODY_SIZE", var("COSTER_MAX_BODY_SIZE"))?,
+            rate_limit: parse_setting("COSTER_RATE_LIMIT", var("COSTER_RATE_LIMIT"))?,
+            trusted_proxies: var("COSTER_TRUSTED_PROXIES")
+                .map(|proxies| {
+                    let proxies = proxies
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|proxy| !proxy.is_empty());
+                    parse_list("COSTER_TRUSTED_PROXIES", proxies)
+                })
+                .transpose()?,
+            shutdown_timeout: parse_setting(
+                "COSTER_SHUTDOWN_TIMEOUT",
+                var("COSTER_SHUTDOWN_TIMEOUT"),
+            )?,
+            tls: TlsSettings {
+                certificate: var("COSTER_TLS_CERTIFICATE").map(PathBuf::from),
+                key: var("COSTER_TLS_KEY").map(PathBuf::from),
+                redirect_port: parse_setting(
+                    "COSTER_TLS_REDIRECT_PORT",
+                    var("COSTER_TLS_REDIRECT_PORT"),
+                )?,
+            },
+        })
+    }
+
+    /// Read the settings from the command line arguments.
+    fn from_args(matches: &ArgMatches) -> Result<Self, ConfigError> {
+        let value = |name: &str| matches.value_of(name).map(str::to_string);
+        Ok(Settings {
+            bind_address: parse_setting("--bind-address", value("bind-address"))?,
+            port: parse_setting("--port", value("port"))?,
+            data_dir: matches.value_of_os("data-dir").map(PathBuf::from),
+            backend: value("backend"),
+            log_level: value("log-level"),
+            allowed_origins: matches
+                .values_of("allowed-origin")
+                .map(|origins| origins.map(str::to_string).collect()),
+            max_body_size: parse_setting("--max-body-size", value("max-body-size"))?,
+            rate_limit: parse_setting("--rate-limit", value("rate-limit"))?,
+            trusted_proxies: matches
+                .values_of("trusted-proxy")
+                .map(|proxies| parse_list("--trusted-proxy", proxies))
+                .transpose()?,
+            shutdown_timeout: parse_setting("--shutdown-timeout", value("shutdown-timeout"))?,
+            tls: TlsSettings {
+                certificate: matches.value_of_os("tls-certificate").map(PathBuf::from),
+                key: matches.value_of_os("tls-key").map(PathBuf::from),
+                redirect_port: parse_setting("--tls-redirect-port", value("tls-redirect-port"))?,
+            },
+        })
+    }
+
+    /// Override these settings with those specified in `other`.
+    fn merge(self, other: Settings) -> Settings {
+        Settings {
+            bind_address: other.bind_address.or(self.bind_address),
+            port: other.port.or(self.port),
+            data_dir: other.data_dir.or(self.data_dir),
+            backend: other.backend.or(self.backend),
+            log_level: other.log_level.or(self.log_level),
+            allowed_origins: other.allowed_origins.or(self.allowed_origins),
+            max_body_size: other.max_body_size.or(self.max_body_size),
+            rate_limit: other.rate_limit.or(self.rate_limit),
+            trusted_proxies: other.trusted_proxies.or(self.trusted_proxies),
+            shutdown_timeout: other.shutdown_timeout.or(self.shutdown_timeout),
+            tls: TlsSettings {
+                certificate: other.tls.certificate.or(self.tls.certificate),
+                key: other.tls.key.or(self.tls.key),
+                redirect_port: other.tls.redirect_port.or(self.tls.redirect_port),
+            },
+        }
+    }
+}
+
+/// The command line interface of the server.
+fn app() -> App<'static, 'static> {
+    App::new("coster")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("A server for sharing costs between the users of a tab")
+        .setting(AppSettings::ColoredHelp)
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .short("c")
+                .value_name("FILE")
+                .help("A TOML configuration file, also specified by COSTER_CONFIG"),
+        )
+        .arg(
+            Arg::with_name("check-config").long("check-config").help(
+                "Check that the configuration is valid, and exit without starting the server",
+            ),
+        )
+        .arg(
+            Arg::with_name("bind-address")
+                .long("bind-address")
+                .value_name("ADDRESS")
+                .help("The IP address to listen on [default: 0.0.0.0]"),
+        )
+        .arg(
+            Arg::with_name("port")
+                .long("port")
+                .short("p")
+                .value_name("PORT")
+                .help("The port to listen on [default: 8000]"),
+        )
+        .arg(
+            Arg::with_name("data-dir")
+                .long("data-dir")
+                .value_name("DIR")
+                .help("The directory to store the database in [default: data]"),
+        )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend")
+                .value_name("BACKEND")
+                .help("The database to store the data in, rocksdb or sqlite [default: rocksdb]"),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .value_name("LEVEL")
+                .help("off, error, warn, info, debug or trace [default: info]"),
+        )
+        .arg(
+            Arg::with_name("allowed-origin")
+                .long("allowed-origin")
+                .value_name("ORIGIN")
+                .multiple(true)
+                .number_of_values(1)
+                .help("An origin which is allowed to make cross origin requests, or * for any"),
+        )
+        .arg(
+            Arg::with_name("max-body-size")
+                .long("max-body-size")
+                .value_name("BYTES")
+                .help("The size of the largest request body which is accepted [default: 2097152]"),
+        )
+        .arg(
+            Arg::with_name("rate-limit")
+                .long("rate-limit")
+                .value_name("REQUESTS")
+                .help("The number of requests per minute allowed for each client, or 0 for no limit [default: 600]"),
+        )
+        .arg(
+            Arg::with_name("trusted-proxy")
+                .long("trusted-proxy")
+                .value_name("ADDRESS")
+                .multiple(true)
+                .number_of_values(1)
+                .help("The IP address of a reverse proxy whose X-Forwarded-For header identifies the client"),
+        )
+        .arg(
+            Arg::with_name("shutdown-timeout")
+                .long("shutdown-timeout")
+                .value_name("SECONDS")
+                .help("How long to wait for requests in progress to complete when stopped [default: 30]"),
+        )
+        .arg(
+            Arg::with_name("tls-certificate")
+                .long("tls-certificate")
+                .value_name("FILE")
+                .help("A PEM encoded certificate chain, used to serve HTTPS"),
+        )
+        .arg(
+            Arg::with_name("tls-key")
+                .long("tls-key")
+                .value_name("FILE")
+                .help("The PEM encoded private key of the TLS certificate"),
+        )
+        .arg(
+            Arg::with_name("tls-redirect-port")
+                .long("tls-redirect-port")
+                .value_name("PORT")
+                .help("Serve HTTP on this port, redirecting every request to HTTPS"),
+        )
+}
+
+/// The options which the server was started with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub config: Config,
+    /// Only check the configuration, rather than starting the server.
+    pub check_config: bool,
+}
+
+impl Options {
+    /// Load the options from the command line `args` (including the
+    /// name of the executable), the environment variables returned
+    /// by `var`, and the configuration file specified by either of
+    /// them (if there is one).
+    pub fn load<I, T, F>(args: I, var: F) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+        F: Fn(&str) -> Option<String>,
+    {
+        let matches = app().get_matches_from_safe(args)?;
+
+        let config_file = matches
+            .value_of_os("config")
+            .map(PathBuf::from)
+            .or_else(|| var("COSTER_CONFIG").map(PathBuf::from));
+        let file_settings = match config_file {
+            Some(path) => Settings::from_file(&path)?,
+            None => Settings::default(),
+        };
+
+        let settings = file_settings
+            .merge(Settings::from_env(var)?)
+            .merge(Settings::from_args(&matches)?);
+
+        Ok(Options {
+            config: Config::from_settings(settings)?,
+            check_config: matches.is_present("check-config"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ConfigError, Options, TlsConfig, DEFAULT_MAX_BODY_SIZE, DEFAULT_PORT, DEFAULT_RATE_LIMIT,
+        DEFAULT_SHUTDOWN_TIMEOUT,
+    };
+    use crate::repository::RepositoryBackend;
+    use log::LevelFilter;
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::net::IpAddr;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn load(args: &[&str], env: &[(&str, &str)]) -> Result<Options, ConfigError> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let args = std::iter::once("coster").chain(args.iter().cloned());
+        Options::load(args, |name| env.get(name).cloned())
+    }
+
+    #[test]
+    fn defaults() {
+        let options = load(&[], &[]).unwrap();
+        assert!(!options.check_config);
+        assert_eq!("0.0.0.0:8000", options.config.address().to_string());
+        assert_eq!(DEFAULT_PORT, options.config.port);
+        assert_eq!(RepositoryBackend::RocksDB, options.config.backend);
+        assert_eq!(LevelFilter::Info, options.config.log_level);
+        assert!(options.config.allowed_origins.is_empty());
+        assert_eq!(DEFAULT_MAX_BODY_SIZE, options.config.max_body_size);
+        assert_eq!(DEFAULT_RATE_LIMIT, options.config.rate_limit);
+        assert!(options.config.trusted_proxies.is_empty());
+        assert_eq!(DEFAULT_SHUTDOWN_TIMEOUT, options.config.shutdown_timeout);
+        assert_eq!(None, options.config.tls);
+    }
+
+    #[test]
+    fn sources_take_precedence() {
+        let mut file = tempfile();
+        writeln!(
+            file.1,
+            "port = 9000\nbackend = \"sqlite\"\nlog_level = \"warn\"\nallowed_origins = [\"https://coster.example.com\"]"
+        )
+        .unwrap();
+        let path = file.0.to_str().unwrap();
+
+        let options = load(
+            &["--config", path, "--port", "9002", "--check-config"],
+            &[("COSTER_PORT", "9001"), ("COSTER_LOG_LEVEL", "debug")],
+        )
+        .unwrap();
+        assert!(options.check_config);
+        assert_eq!(9002, options.config.port);
+        assert_eq!(RepositoryBackend::Sqlite, options.config.backend);
+        assert_eq!(LevelFilter::Debug, options.config.log_level);
+        assert_eq!(
+            vec!["https://coster.example.com".to_string()],
+            options.config.allowed_origins
+        );
+
+        let options = load(
+            &[
+                "--allowed-origin",
+                "http://localhost:8080",
+                "--rate-limit",
+                "0",
+                "--trusted-proxy",
+                "10.0.0.1",
+            ],
+            &[
+                ("COSTER_CONFIG", path),
+                ("COSTER_TRUSTED_PROXIES", "127.0.0.1, ::1"),
+                ("COSTER_ALLOWED_ORIGINS", "*, https://a.example.com"),
+                ("COSTER_MAX_BODY_SIZE", "1024"),
+                ("COSTER_SHUTDOWN_TIMEOUT", "5"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(9000, options.config.port);
+        assert_eq!(1024, options.config.max_body_size);
+        assert_eq!(0, options.config.rate_limit);
+        assert_eq!(
+            vec!["10.0.0.1".parse::<IpAddr>().unwrap()],
+            options.config.trusted_proxies
+        );
+        assert_eq!(Duration::from_secs(5), options.config.shutdown_timeout);
+        assert_eq!(
+            vec!["http://localhost:8080".to_string()],
+            options.config.allowed_origins
+        );
+
+        std::fs::remove_file(&file.0).unwrap();
+    }
+
+    #[test]
+    fn tls() {
+        let certificate = concat!(env!("CARGO_MANIFEST_DIR"), "/test-data/localhost.cert.pem");
+        let key = concat!(env!("CARGO_MANIFEST_DIR"), "/test-data/localhost.key.pem");
+        let options = load(
+            &["--tls-certificate", certificate, "--port", "8443"],
+            &[
+                ("COSTER_TLS_KEY", key),
+                ("COSTER_TLS_REDIRECT_PORT", "8080"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            Some(TlsConfig {
+                certificate: PathBuf::from(certificate),
+                key: PathBuf::from(key),
+                redirect_port: Some(8080),
+            }),
+            options.config.tls
+        );
+    }
+
+    #[test]
+    fn example_config_file_is_valid() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/coster.example.toml");
+        let options = load(&["--config", path], &[]).unwrap();
+        assert_eq!("127.0.0.1:8000", options.config.address().to_string());
+    }
+
+    #[test]
+    fn invalid_configurations() {
+        assert!(matches!(
+            load(&["--port", "http"], &[]),
+            Err(ConfigError::InvalidValue { name, .. }) if name == "--port"
+        ));
+        assert!(matches!(
+            load(&[], &[("COSTER_BIND_ADDRESS", "localhost")]),
+            Err(ConfigError::InvalidValue { name, .. }) if name == "COSTER_BIND_ADDRESS"
+        ));
+        assert!(matches!(
+            load(&[], &[("COSTER_TRUSTED_PROXIES", "127.0.0.1,proxy")]),
+            Err(ConfigError::InvalidValue { name, value, .. })
+                if name == "COSTER_TRUSTED_PROXIES" && value == "proxy"
+        ));
+        assert!(matches!(
+            load(&["--backend", "postgres"], &[]),
+            Err(ConfigError::InvalidValue { name, .. }) if name == "backend"
+        ));
+        assert!(matches!(
+            load(&["--allowed-origin", "https://coster.example.com/app"], &[]),
+            Err(ConfigError::InvalidValue { name, .. }) if name == "allowed_origins"
+        ));
+        assert!(matches!(
+            load(&["--tls-certificate", "cert.pem"], &[]),
+            Err(ConfigError::IncompleteTls)
+        ));
+        assert!(matches!(
+            load(
+                &[
+                    "--tls-certificate",
+                    "missing.pem",
+                    "--tls-key",
+                    "missing.pem"
+                ],
+                &[]
+            ),
+            Err(ConfigError::MissingFile(_, _))
+        ));
+        let certificate = concat!(env!("CARGO_MANIFEST_DIR"), "/test-data/localhost.cert.pem");
+        let key = concat!(env!("CARGO_MANIFEST_DIR"), "/test-data/localhost.key.pem");
+        assert!(matches!(
+            load(&["--tls-redirect-port", "8080"], &[]),
+            Err(ConfigError::RedirectWithoutTls)
+        ));
+        assert!(matches!(
+            load(
+                &["--tls-certificate", certificate, "--tls-key", key],
+                &[("COSTER_TLS_REDIRECT_PORT", "8000")]
+            ),
+            Err(ConfigError::InvalidValue { name, .. }) if name == "redirect_port"
+        ));
+        assert!(matches!(
+            load(&["--config", "missing.toml"], &[]),
+            Err(ConfigError::ReadFile(_, _))
+        ));
+        assert!(matches!(
+            load(&["--unknown"], &[]),
+            Err(ConfigError::Arguments(_))
+        ));
+
+        let mut file = tempfile();
+        writeln!(file.1, "prot = 9000").unwrap();
+        assert!(matches!(
+            load(&["--config", file.0.to_str().unwrap()], &[]),
+            Err(ConfigError::ParseFile(_, _))
+        ));
+        std::fs::remove_file(&file.0).unwrap();
+    }
+
+    /// Create a new uniquely named file in the temporary directory.
+    fn tempfile() -> (PathBuf, std::fs::File) {
+        let path = std::env::temp_dir().join(format!("coster-{}.toml", uuid::Uuid::new_v4()));
+        let file = std::fs::File::create(&path).unwrap();
+        (path, file)
+    }
+}
@@ -0,0 +1,161 @@
+//! Errors which are returned to the clients of the server's APIs,
+//! identified by a machine readable code.
+
+use crate::repository::RepositoryError;
+use crate::request_id::RequestId;
+use commodity::CommodityError;
+use costing::CostingError;
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use thiserror::Error;
+use warp::http::StatusCode;
+
+/// An error which can be returned by the API, with a `code` which
+/// clients can use to identify it.
+pub trait ErrorCode: Error {
+    /// A short upper case identifier for the type of this error.
+    fn code(&self) -> &'static str;
+
+    /// The HTTP status which best describes this error.
+    fn status(&self) -> StatusCode;
+}
+
+impl ErrorCode for CostingError {
+    fn code(&self) -> &'static str {
+        match self {
+            CostingError::Accounting(_) => "ACCOUNTING",
+            CostingError::Currency(_) => "CURRENCY",
+            CostingError::Database(_) => "DATABASE",
+            CostingError::UserAlreadyExistsOnTab(_, _) => "USER_ALREADY_EXISTS",
+            CostingError::UserDoesNotExistOnTab(_, _) => "USER_NOT_FOUND",
+            CostingError::UserHasExpensesOnTab(_, _) => "USER_HAS_EXPENSES",
+            CostingError::UserAccountDoesNotExistOnTab(_, _) => "USER_ACCOUNT_NOT_FOUND",
+            CostingError::ExpenseAlreadyExistsOnTab(_, _) => "EXPENSE_ALREADY_EXISTS",
+            CostingError::ExpenseDoesNotExistOnTab(_, _) => "EXPENSE_NOT_FOUND",
+            CostingError::NoExpenseCategoryAccountOnTab(_, _) => "EXPENSE_CATEGORY_NOT_FOUND",
+            CostingError::PeriodAlreadyClosed(_, _) => "PERIOD_ALREADY_CLOSED",
+            CostingError::ExpenseInClosedPeriod(_, _) => "EXPENSE_IN_CLOSED_PERIOD",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            CostingError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            CostingError::UserDoesNotExistOnTab(_, _)
+            | CostingError::ExpenseDoesNotExistOnTab(_, _) => StatusCode::NOT_FOUND,
+            CostingError::UserAlreadyExistsOnTab(_, _)
+            | CostingError::UserHasExpensesOnTab(_, _)
+            | CostingError::ExpenseAlreadyExistsOnTab(_, _) => StatusCode::CONFLICT,
+            _ => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+impl ErrorCode for RepositoryError {
+    fn code(&self) -> &'static str {
+        match self {
+            RepositoryError::TabNotFound(_) => "TAB_NOT_FOUND",
+            _ => "DATABASE",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            RepositoryError::TabNotFound(_) => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// An invalid argument supplied to the API.
+#[derive(Error, Debug)]
+pub enum InputError {
+    #[error("the amount {0:?} is not a valid decimal number")]
+    InvalidAmount(String, #[source] rust_decimal::Error),
+    #[error("the currency {0:?} is not a valid ISO 4217 currency code")]
+    InvalidCurrency(String, #[source] CommodityError),
+}
+
+impl ErrorCode for InputError {
+    fn code(&self) -> &'static str {
+        match self {
+            InputError::InvalidAmount(_, _) => "INVALID_AMOUNT",
+            InputError::InvalidCurrency(_, _) => "INVALID_CURRENCY",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+/// The description of an error which is sent to clients.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ErrorBody {
+    /// The [ErrorCode::code] of the error.
+    pub code: String,
+    /// A human readable description of the error.
+    pub message: String,
+    /// The messages of the errors which caused this error, starting
+    /// with the most direct cause. These are only sent for client
+    /// errors, because the causes of server errors can describe its
+    /// internals, and are logged instead.
+    pub causes: Vec<String>,
+}
+
+impl ErrorBody {
+    pub fn new<E: ErrorCode + ?Sized>(error: &E) -> Self {
+        ErrorBody {
+            code: error.code().to_string(),
+            message: error.to_string(),
+            causes: if error.status().is_server_error() {
+                Vec::new()
+            } else {
+                causes(error)
+            },
+        }
+    }
+}
+
+/// The messages of the errors which caused the `error`, starting with
+/// the most direct cause.
+fn causes<E: Error + ?Sized>(error: &E) -> Vec<String> {
+    let mut causes = Vec::new();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        causes.push(cause.to_string());
+        source = cause.source();
+    }
+    causes
+}
+
+/// Log an `error` which is returned to a client, along with the id of
+/// the request which caused it. Server errors are logged as errors,
+/// and client errors at the debug level.
+pub fn log_error<E: ErrorCode + ?Sized>(error: &E, body: &ErrorBody) {
+    let request_id = RequestId::current();
+    let request_id = request_id.as_ref().map_or("-", RequestId::as_str);
+    if error.status().is_server_error() {
+        error!(
+            target: "coster::error",
+            "request_id={} status={} code={} message={:?} causes={:?} error={:?}",
+            request_id,
+            error.status().as_u16(),
+            body.code,
+            body.message,
+            causes(error),
+            error
+        );
+    } else {
+        debug!(
+            target: "coster::error",
+            "request_id={} status={} code={} message={:?} causes={:?}",
+            request_id,
+            error.status().as_u16(),
+            body.code,
+            body.message,
+            body.causes
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! An in-process broadcast of the changes made to tabs, which is used
+//! to push live updates to the clients subscribed to a tab.
+
+use costing::actions::TabUserActionType;
+use costing::TabID;
+use futures::{future, Stream, StreamExt};
+use log::warn;
+use tokio::sync::broadcast::{self, RecvError};
+
+/// A [TabUserActionType] which has been performed on a tab, and
+/// stored in the repository.
+#[derive(Debug, Clone)]
+pub struct TabEvent {
+    /// The id of the tab that the action was performed on.
+    pub tab_id: TabID,
+    /// The action that was performed.
+    pub action: TabUserActionType,
+}
+
+/// Broadcasts [TabEvent]s to subscribers. Clones share the same
+/// channel, so a clone can be given to each part of the server which
+/// publishes or subscribes to events.
+#[derive(Debug, Clone)]
+pub struct TabEvents {
+    sender: broadcast::Sender<TabEvent>,
+}
+
+impl TabEvents {
+    /// The number of events which are buffered for each subscriber.
+    /// A subscriber which falls further behind than this will miss
+    /// events.
+    pub const CAPACITY: usize = 64;
+
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(Self::CAPACITY);
+        TabEvents { sender }
+    }
+
+    /// Send the `event` to all the current subscribers.
+    pub fn publish(&self, event: TabEvent) {
+        // an error only means that there are no subscribers
+        let _ = self.sender.send(event);
+    }
+
+    /// A stream of the events published for the tab with the
+    /// specified `tab_id` from now on.
+    pub fn subscribe(&self, tab_id: TabID) -> impl Stream<Item = TabEvent> {
+        self.sender
+            .subscribe()
+            .into_stream()
+            .filter_map(move |event| {
+                future::ready(match event {
+                    Ok(event) if event.tab_id == tab_id => Some(event),
+                    Ok(_) => None,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!(
+                            "Subscriber to tab {} missed {} events by lagging behind",
+                            tab_id, missed
+                        );
+                        None
+                    }
+                    Err(RecvError::Closed) => None,
+                })
+            })
+    }
+}
+
+impl Default for TabEvents {
+    fn default() -> Self {
+        TabEvents::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TabEvent, TabEvents};
+    use costing::actions::{ChangeTabName, TabUserActionType};
+    use futures::StreamExt;
+    use uuid::Uuid;
+
+    fn create_event(tab_id: Uuid, name: &str) -> TabEvent {
+        TabEvent {
+            tab_id,
+            action: TabUserActionType::ChangeTabName(ChangeTabName::new(1, name)),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribe_to_tab() {
+        let events = TabEvents::new();
+        let tab_id = Uuid::new_v4();
+        let mut subscription = events.subscribe(tab_id).boxed();
+
+        // events for other tabs are filtered out
+        events
+            .clone()
+            .publish(create_event(Uuid::new_v4(), "Other"));
+        events.publish(create_event(tab_id, "Holiday"));
+
+        let event = subscription.next().await.unwrap();
+        assert_eq!(tab_id, event.tab_id);
+        match event.action {
+            TabUserActionType::ChangeTabName(action) => assert_eq!("Holiday", action.name),
+            action => panic!("unexpected action {:?}", action),
+        }
+    }
+}
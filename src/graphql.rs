@@ -0,0 +1,1503 @@
+//! The GraphQL API of the server. The types in this module are
+//! snapshots of the corresponding [costing] types, which can be sent
+//! between threads while a query is being resolved.
+//!
+//! The [Account] which is logged in needs to be added to the data of
+//! each query, and each subscription connection. Tabs can only be
+//! accessed by their members, and modifications are performed on
+//! behalf of the tab's user which the account is linked to. Invites
+//! can be read and redeemed without logging in.
+
+use crate::accounts::{json_session_token, Account, AuthError};
+use crate::error::{log_error, ErrorBody, ErrorCode, InputError};
+use crate::metrics::Metrics;
+use crate::request_id::RequestId;
+use crate::service::{parse_currency, ServiceError, TabService};
+use async_graphql::{
+    Context, FieldError, FieldResult, InputObject, Object, OutputJson, QueryBuilder, Schema,
+    SimpleObject, Subscription,
+};
+use async_graphql_warp::GQLResponse;
+use chrono::{DateTime, NaiveDate, Utc};
+use commodity::{Commodity, CommodityTypeID};
+use costing::actions::{
+    AddExpense, AddUser, ChangeTabName, ClosePeriod, EditExpense, RecordSettlement, RemoveExpense,
+    RemoveUser, TabUserAction, TabUserActionType,
+};
+use costing::{ExpenseID, TabID, TabPeriod, UserID};
+use futures::{Stream, StreamExt};
+use log::error;
+use rust_decimal::Decimal;
+use serde_json::json;
+use std::{convert::Infallible, rc::Rc, str::FromStr, sync::Arc, time::Instant};
+use uuid::Uuid;
+use warp::{filters::BoxedFilter, Filter, Reply};
+
+/// The route which executes GraphQL queries and mutations sent over
+/// HTTP, as the account which is logged in (if any). Queries can also
+/// be sent in the query string of a GET request, but the session
+/// cookie is only accepted with JSON POST requests, so that other
+/// sites can't perform mutations using it (see [json_session_token]).
+pub fn routes(
+    schema: Schema<Query, Mutation, Subscription>,
+    service: TabService,
+    metrics: Metrics,
+    max_body_size: u64,
+) -> BoxedFilter<(impl Reply,)> {
+    let body = warp::get()
+        .or(warp::body::content_length_limit(max_body_size))
+        .unify();
+    body.and(async_graphql_warp::graphql(schema))
+        .and(json_session_token())
+        .and(warp::any().map(move || service.clone()))
+        .and(warp::any().map(move || metrics.clone()))
+        .and_then(
+            |(schema, builder): (_, QueryBuilder),
+             token: Option<String>,
+             service: TabService,
+             metrics: Metrics| async move {
+                // requests with an invalid session are treated as
+                // unauthenticated
+                let account = match token {
+                    Some(token) => {
+                        service
+                            .blocking(move |service| service.authenticate(&token).ok())
+                            .await
+                    }
+                    None => None,
+                };
+                let builder = match account {
+                    Some(account) => builder.data(account),
+                    None => builder,
+                };
+                let query_source = builder.query_source().to_string();
+                let start = Instant::now();
+                let resp = builder.execute(&schema).await;
+                metrics.record_graphql(&query_source, start.elapsed());
+                Ok::<_, Infallible>(GQLResponse::from(resp))
+            },
+        )
+        .boxed()
+}
+
+/// Convert an `error` into a [FieldError], with its `code`, the
+/// messages of the errors which caused it (for client errors), and
+/// the id of the request, in the extensions. The error is logged
+/// along with the request id.
+pub fn field_error<E: ErrorCode>(error: E) -> FieldError {
+    let body = ErrorBody::new(&error);
+    log_error(&error, &body);
+    let mut extensions = json!({
+        "code": body.code,
+        "causes": body.causes,
+    });
+    if let Some(request_id) = RequestId::current() {
+        extensions["request_id"] = json!(request_id.as_str());
+    }
+    FieldError(body.message, Some(extensions))
+}
+
+/// The [TabService] used by the resolvers, which needs to be added to
+/// the schema's data.
+fn service<'a>(ctx: &'a Context<'_>) -> FieldResult<&'a TabService> {
+    ctx.data::<TabService>()
+}
+
+/// The [Account] which is logged in, which the resolvers act on
+/// behalf of.
+fn account<'a>(ctx: &'a Context<'_>) -> FieldResult<&'a Account> {
+    ctx.data_opt::<Account>()
+        .ok_or_else(|| field_error(AuthError::NotAuthenticated))
+}
+
+/// Call `f` with the [TabService] on a thread where blocking is
+/// allowed, using [TabService::blocking].
+async fn blocking<T, F>(ctx: &Context<'_>, f: F) -> FieldResult<T>
+where
+    F: FnOnce(&TabService) -> Result<T, ServiceError> + Send + 'static,
+    T: Send + 'static,
+{
+    service(ctx)?.blocking(f).await.map_err(field_error)
+}
+
+/// Call `f` with the [TabService] and the [Account] which is logged
+/// in, on a thread where blocking is allowed.
+async fn blocking_as_account<T, F>(ctx: &Context<'_>, f: F) -> FieldResult<T>
+where
+    F: FnOnce(&TabService, &Account) -> Result<T, ServiceError> + Send + 'static,
+    T: Send + 'static,
+{
+    let account = account(ctx)?.clone();
+    blocking(ctx, move |service| f(service, &account)).await
+}
+
+/// An amount of a currency. The value is a decimal number encoded as
+/// a string (without trailing zeros), so that no precision is lost.
+#[SimpleObject(desc = "An amount of a currency")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Amount {
+    #[field(desc = "The decimal value, encoded as a string")]
+    pub value: String,
+    #[field(desc = "The currency code, e.g. AUD")]
+    pub currency: String,
+}
+
+impl From<&Commodity> for Amount {
+    fn from(commodity: &Commodity) -> Self {
+        Amount {
+            value: commodity.value.normalize().to_string(),
+            currency: commodity.type_id.to_string(),
+        }
+    }
+}
+
+#[InputObject(desc = "An amount of a currency")]
+#[derive(Clone, Debug)]
+pub struct AmountInput {
+    #[field(desc = "The decimal value, encoded as a string")]
+    pub value: String,
+    #[field(desc = "The currency code, e.g. AUD")]
+    pub currency: String,
+}
+
+impl AmountInput {
+    fn to_commodity(&self) -> Result<Commodity, InputError> {
+        let value = Decimal::from_str(&self.value)
+            .map_err(|error| InputError::InvalidAmount(self.value.clone(), error))?;
+        Ok(Commodity::new(value, parse_currency(&self.currency)?))
+    }
+}
+
+#[SimpleObject(desc = "A user involved with a tab")]
+#[derive(Clone, Debug)]
+pub struct User {
+    #[field(desc = "The id of the user, unique within the tab")]
+    pub id: i32,
+    #[field(desc = "The name of the user")]
+    pub name: String,
+    #[field(desc = "The email address of the user")]
+    pub email: Option<String>,
+}
+
+impl From<&costing::User> for User {
+    fn from(user: &costing::User) -> Self {
+        User {
+            id: user.id,
+            name: user.name.clone(),
+            email: user.email.clone(),
+        }
+    }
+}
+
+#[InputObject(desc = "A user to add to a tab")]
+#[derive(Clone, Debug)]
+pub struct UserInput {
+    #[field(desc = "The name of the user")]
+    pub name: String,
+    #[field(desc = "The email address of the user")]
+    pub email: Option<String>,
+}
+
+impl UserInput {
+    fn to_user(&self, id: UserID) -> costing::User {
+        costing::User::new(id, &self.name, self.email.as_deref())
+    }
+}
+
+#[SimpleObject(desc = "An expense paid by a user, and shared by users of a tab")]
+#[derive(Clone, Debug)]
+pub struct Expense {
+    #[field(desc = "The id of the expense, unique within the tab")]
+    pub id: i32,
+    #[field(desc = "The description of the expense")]
+    pub description: String,
+    #[field(desc = "The category that the expense is attributed to")]
+    pub category: String,
+    #[field(desc = "The date the expense was incurred")]
+    pub date: NaiveDate,
+    #[field(desc = "The id of the user who paid the expense")]
+    pub paid_by: i32,
+    #[field(desc = "The ids of the users sharing the expense")]
+    pub shared_by: Vec<i32>,
+    #[field(desc = "The amount that was paid")]
+    pub amount: Amount,
+}
+
+impl From<&costing::Expense> for Expense {
+    fn from(expense: &costing::Expense) -> Self {
+        Expense {
+            id: expense.id,
+            description: expense.description.clone(),
+            category: expense.category.clone(),
+            date: expense.date,
+            paid_by: expense.paid_by,
+            shared_by: expense.shared_by.clone(),
+            amount: (&expense.amount).into(),
+        }
+    }
+}
+
+#[InputObject(desc = "The details of an expense")]
+#[derive(Clone, Debug)]
+pub struct ExpenseInput {
+    #[field(desc = "The description of the expense")]
+    pub description: String,
+    #[field(desc = "The category that the expense is attributed to")]
+    pub category: String,
+    #[field(desc = "The date the expense was incurred")]
+    pub date: NaiveDate,
+    #[field(desc = "The id of the user who paid the expense")]
+    pub paid_by: i32,
+    #[field(desc = "The ids of the users sharing the expense")]
+    pub shared_by: Vec<i32>,
+    #[field(desc = "The amount that was paid")]
+    pub amount: AmountInput,
+}
+
+impl ExpenseInput {
+    fn to_expense(&self, id: ExpenseID) -> Result<costing::Expense, InputError> {
+        Ok(costing::Expense::new(
+            id,
+            &self.description,
+            &self.category,
+            self.date,
+            self.paid_by,
+            self.shared_by.clone(),
+            self.amount.to_commodity()?,
+            None,
+        ))
+    }
+}
+
+#[SimpleObject(desc = "A payment which settles a debt between two users")]
+#[derive(Clone, Debug)]
+pub struct Settlement {
+    #[field(desc = "The id of the user who owes the money")]
+    pub sender: i32,
+    #[field(desc = "The id of the user who is owed the money")]
+    pub receiver: i32,
+    #[field(desc = "The amount to be paid")]
+    pub amount: Amount,
+}
+
+impl From<&costing::Settlement> for Settlement {
+    fn from(settlement: &costing::Settlement) -> Self {
+        Settlement {
+            sender: settlement.sender,
+            receiver: settlement.receiver,
+            amount: (&settlement.amount).into(),
+        }
+    }
+}
+
+#[InputObject(desc = "A payment which was made to settle a debt between two users")]
+#[derive(Clone, Debug)]
+pub struct SettlementInput {
+    #[field(desc = "The id of the user who sent the money")]
+    pub sender: i32,
+    #[field(desc = "The id of the user who received the money")]
+    pub receiver: i32,
+    #[field(desc = "The amount that was paid")]
+    pub amount: AmountInput,
+    #[field(desc = "The date the payment was made")]
+    pub date: NaiveDate,
+}
+
+impl SettlementInput {
+    fn to_settlement(&self) -> Result<costing::Settlement, InputError> {
+        Ok(costing::Settlement::new(
+            self.sender,
+            self.receiver,
+            self.amount.to_commodity()?,
+        ))
+    }
+}
+
+#[SimpleObject(desc = "The balance of a user on a tab")]
+#[derive(Clone, Debug)]
+pub struct Balance {
+    #[field(desc = "The id of the user")]
+    pub user_id: i32,
+    #[field(
+        desc = "The balance, positive if the user is owed money, or negative if they owe money"
+    )]
+    pub amount: Amount,
+}
+
+/// A tab which costs are shared on. Balances and settlements are
+/// calculated when they are requested, from the same snapshot of the
+/// tab as its other fields.
+#[derive(Clone, Debug)]
+pub struct Tab {
+    pub id: TabID,
+    pub name: String,
+    pub working_currency: String,
+    pub users: Vec<User>,
+    pub expenses: Vec<Expense>,
+    costing: Arc<CostingTab>,
+}
+
+/// The parts of a [costing::Tab] which its balances are calculated
+/// from. A [costing::Tab] can't be sent between threads, because its
+/// users are shared using [Rc], so it is rebuilt from these when it
+/// is needed.
+#[derive(Debug)]
+struct CostingTab {
+    id: TabID,
+    name: String,
+    working_currency: CommodityTypeID,
+    users: Vec<costing::User>,
+    expenses: Vec<costing::Expense>,
+    closed_periods: Vec<TabPeriod>,
+}
+
+impl CostingTab {
+    fn to_tab(&self) -> costing::Tab {
+        let mut tab = costing::Tab::new(
+            self.id,
+            &self.name,
+            self.working_currency,
+            self.users.iter().cloned().map(Rc::new).collect(),
+            self.expenses.clone(),
+        );
+        tab.closed_periods = self.closed_periods.clone();
+        tab
+    }
+}
+
+impl From<&costing::Tab> for Tab {
+    fn from(tab: &costing::Tab) -> Self {
+        Tab {
+            id: tab.id,
+            name: tab.name.clone(),
+            working_currency: tab.working_currency.to_string(),
+            users: tab.users.iter().map(|user| user.as_ref().into()).collect(),
+            expenses: tab.expenses.iter().map(Expense::from).collect(),
+            costing: Arc::new(CostingTab {
+                id: tab.id,
+                name: tab.name.clone(),
+                working_currency: tab.working_currency,
+                users: tab.users.iter().map(|user| (**user).clone()).collect(),
+                expenses: tab.expenses.clone(),
+                closed_periods: tab.closed_periods.clone(),
+            }),
+        }
+    }
+}
+
+impl Tab {
+    /// Rebuild the [costing::Tab] that this is a snapshot of, and
+    /// apply `f` to it.
+    fn with_costing_tab<T, F, E>(&self, f: F) -> FieldResult<T>
+    where
+        F: FnOnce(&costing::Tab) -> Result<T, E>,
+        E: ErrorCode,
+    {
+        f(&self.costing.to_tab()).map_err(field_error)
+    }
+}
+
+fn as_of_or_today(as_of: Option<NaiveDate>) -> NaiveDate {
+    as_of.unwrap_or_else(|| Utc::today().naive_utc())
+}
+
+#[Object(desc = "A tab which costs are shared on")]
+impl Tab {
+    #[field(desc = "The id of the tab")]
+    async fn id(&self) -> TabID {
+        self.id
+    }
+
+    #[field(desc = "The name of the tab")]
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[field(desc = "The currency code that balances are calculated in")]
+    async fn working_currency(&self) -> &str {
+        &self.working_currency
+    }
+
+    #[field(desc = "The users involved with the tab")]
+    async fn users(&self) -> &[User] {
+        &self.users
+    }
+
+    #[field(desc = "The expenses in the current period of the tab")]
+    async fn expenses(&self) -> &[Expense] {
+        &self.expenses
+    }
+
+    #[field(desc = "The balance of each user")]
+    async fn balances(
+        &self,
+        #[arg(desc = "The date to calculate the balances as of, defaults to today")] as_of: Option<
+            NaiveDate,
+        >,
+    ) -> FieldResult<Vec<Balance>> {
+        let mut balances: Vec<Balance> = self.with_costing_tab(|tab| {
+            tab.user_balances(as_of_or_today(as_of)).map(|balances| {
+                balances
+                    .iter()
+                    .map(|(user_id, amount)| Balance {
+                        user_id: *user_id,
+                        amount: amount.into(),
+                    })
+                    .collect()
+            })
+        })?;
+        balances.sort_by_key(|balance| balance.user_id);
+        Ok(balances)
+    }
+
+    #[field(desc = "The payments required to settle the balances of the users")]
+    async fn settlements(
+        &self,
+        #[arg(desc = "The date to calculate the settlements as of, defaults to today")]
+        as_of: Option<NaiveDate>,
+    ) -> FieldResult<Vec<Settlement>> {
+        self.with_costing_tab(|tab| {
+            tab.balance_transactions(as_of_or_today(as_of))
+                .map(|settlements| settlements.iter().map(Settlement::from).collect())
+        })
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    #[field(desc = "All the tabs that you are a member of, not including those in the trash")]
+    async fn tabs(&self, ctx: &Context<'_>) -> FieldResult<Vec<Tab>> {
+        blocking_as_account(ctx, |service, account| {
+            let tabs = service.tabs(account)?;
+            Ok(tabs.iter().map(Tab::from).collect())
+        })
+        .await
+    }
+
+    #[field(desc = "The tab with the specified id")]
+    async fn tab(&self, ctx: &Context<'_>, id: TabID) -> FieldResult<Option<Tab>> {
+        blocking_as_account(ctx, move |service, account| {
+            let tab = service.find_tab(account, &id)?;
+            Ok(tab.as_ref().map(Tab::from))
+        })
+        .await
+    }
+
+    #[field(
+        desc = "The details of the invite with the specified token, if it can still be redeemed"
+    )]
+    async fn invite(&self, ctx: &Context<'_>, token: String) -> FieldResult<InvitePreview> {
+        blocking(ctx, move |service| {
+            let (invite, tab) = service.invite(&token)?;
+            Ok(InvitePreview {
+                tab_id: tab.id,
+                tab_name: tab.name.clone(),
+                invited_by: tab
+                    .user(&invite.created_by)
+                    .ok()
+                    .map(|user| user.name.clone()),
+                expires_at: invite.expires_at,
+            })
+        })
+        .await
+    }
+}
+
+#[SimpleObject(desc = "An invite to join a tab")]
+#[derive(Clone, Debug)]
+pub struct Invite {
+    #[field(desc = "The token which identifies the invite")]
+    pub token: String,
+    #[field(desc = "The id of the tab which the invite is to")]
+    pub tab_id: TabID,
+    #[field(desc = "The id of the user who created the invite")]
+    pub created_by: i32,
+    #[field(desc = "When the invite was created")]
+    pub created_at: DateTime<Utc>,
+    #[field(desc = "When the invite can no longer be redeemed")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[field(desc = "Whether the invite can only be redeemed once")]
+    pub single_use: bool,
+    #[field(desc = "The path of the page which redeems the invite")]
+    pub path: String,
+}
+
+impl From<&crate::invites::Invite> for Invite {
+    fn from(invite: &crate::invites::Invite) -> Self {
+        Invite {
+            token: invite.token.clone(),
+            tab_id: invite.tab_id,
+            created_by: invite.created_by,
+            created_at: invite.created_at,
+            expires_at: invite.expires_at,
+            single_use: invite.single_use,
+            path: invite.path(),
+        }
+    }
+}
+
+#[SimpleObject(
+    desc = "A link to the read-only summary of a tab, which anyone with the link can read"
+)]
+#[derive(Clone, Debug)]
+pub struct ShareLink {
+    #[field(desc = "The token which identifies the link")]
+    pub token: String,
+    #[field(desc = "The id of the tab which the link is to")]
+    pub tab_id: TabID,
+    #[field(desc = "The id of the user who created the link")]
+    pub created_by: i32,
+    #[field(desc = "When the link was created")]
+    pub created_at: DateTime<Utc>,
+    #[field(desc = "The path of the page which shows the summary of the tab")]
+    pub path: String,
+}
+
+impl From<&crate::shares::ShareLink> for ShareLink {
+    fn from(link: &crate::shares::ShareLink) -> Self {
+        ShareLink {
+            token: link.token.clone(),
+            tab_id: link.tab_id,
+            created_by: link.created_by,
+            created_at: link.created_at,
+            path: link.path(),
+        }
+    }
+}
+
+#[SimpleObject(desc = "The details of an invite which anyone with its token can read")]
+#[derive(Clone, Debug)]
+pub struct InvitePreview {
+    #[field(desc = "The id of the tab which the invite is to")]
+    pub tab_id: TabID,
+    #[field(desc = "The name of the tab which the invite is to")]
+    pub tab_name: String,
+    #[field(desc = "The name of the user who created the invite")]
+    pub invited_by: Option<String>,
+    #[field(desc = "When the invite can no longer be redeemed")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[SimpleObject(desc = "The result of redeeming an invite")]
+#[derive(Clone, Debug)]
+pub struct InviteRedemption {
+    #[field(desc = "The tab, including the new user")]
+    pub tab: Tab,
+    #[field(desc = "The id of the new user, which you are linked to")]
+    pub user_id: i32,
+    #[field(
+        desc = "The session token of the guest account which was created if you weren't logged in"
+    )]
+    pub session_token: Option<String>,
+}
+
+#[SimpleObject(desc = "An action performed by a user to modify a tab")]
+#[derive(Clone, Debug)]
+pub struct TabAction {
+    #[field(desc = "The type of the action, e.g. AddExpense")]
+    pub kind: String,
+    #[field(desc = "The id of the user who performed the action")]
+    pub user_id: i32,
+    #[field(desc = "When the action was performed")]
+    pub datetime: DateTime<Utc>,
+    #[field(desc = "The details of the action, in the same format that it is stored in")]
+    pub details: OutputJson<serde_json::Value>,
+}
+
+impl From<&TabUserActionType> for TabAction {
+    fn from(action: &TabUserActionType) -> Self {
+        let metadata = action.metadata();
+        // the action is serialized as an object with its kind as the
+        // only key.
+        let (kind, details) = match serde_json::to_value(action) {
+            Ok(serde_json::Value::Object(map)) => map.into_iter().next(),
+            _ => None,
+        }
+        .unwrap_or_default();
+
+        TabAction {
+            kind,
+            user_id: metadata.user_id,
+            datetime: metadata.datetime,
+            details: OutputJson(details),
+        }
+    }
+}
+
+#[SimpleObject(desc = "An action which has been performed on a tab")]
+#[derive(Clone, Debug)]
+pub struct TabUpdate {
+    #[field(desc = "The action which was performed")]
+    pub action: TabAction,
+    #[field(desc = "The tab, including any later changes")]
+    pub tab: Tab,
+}
+
+/// Modifies the tabs in the repository. Each tab modification is
+/// performed as a [TabUserAction] on behalf of the tab's user which
+/// the account is linked to, and recorded in the tab's
+/// `user_actions`.
+pub struct Mutation;
+
+impl Mutation {
+    /// Perform the action created by `f` on the tab with the
+    /// specified `id`, using [TabService::perform].
+    async fn perform<F>(&self, ctx: &Context<'_>, id: TabID, f: F) -> FieldResult<Tab>
+    where
+        F: FnOnce(&costing::Tab, UserID) -> Result<TabUserActionType, ServiceError>
+            + Send
+            + 'static,
+    {
+        blocking_as_account(ctx, move |service, account| {
+            let tab = service.perform(account, &id, f)?;
+            Ok(Tab::from(&tab))
+        })
+        .await
+    }
+}
+
+#[Object]
+impl Mutation {
+    #[field(desc = "Create a new tab, with the specified users. You are linked to the first user")]
+    async fn create_tab(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        #[arg(desc = "The currency code that balances are calculated in")] working_currency: String,
+        users: Vec<UserInput>,
+    ) -> FieldResult<Tab> {
+        let working_currency = parse_currency(&working_currency).map_err(field_error)?;
+        let users: Vec<costing::User> = users
+            .iter()
+            .zip(0..)
+            .map(|(user, id)| user.to_user(id))
+            .collect();
+
+        blocking_as_account(ctx, move |service, account| {
+            let users = users.into_iter().map(Rc::new).collect();
+            let tab = costing::Tab::new(Uuid::new_v4(), name, working_currency, users, vec![]);
+            service.add_tab(account, &tab, 0)?;
+            Ok(Tab::from(&tab))
+        })
+        .await
+    }
+
+    #[field(desc = "Change the name of a tab")]
+    async fn rename_tab(&self, ctx: &Context<'_>, tab_id: TabID, name: String) -> FieldResult<Tab> {
+        self.perform(ctx, tab_id, move |_, acting_user_id| {
+            Ok(TabUserActionType::ChangeTabName(ChangeTabName::new(
+                acting_user_id,
+                &name,
+            )))
+        })
+        .await
+    }
+
+    #[field(desc = "Add a new user to a tab")]
+    async fn add_user(
+        &self,
+        ctx: &Context<'_>,
+        tab_id: TabID,
+        user: UserInput,
+    ) -> FieldResult<Tab> {
+        self.perform(ctx, tab_id, move |tab, acting_user_id| {
+            Ok(TabUserActionType::AddUser(AddUser::new(
+                acting_user_id,
+                user.to_user(tab.next_user_id()),
+            )))
+        })
+        .await
+    }
+
+    #[field(desc = "Remove a user from a tab")]
+    async fn remove_user(
+        &self,
+        ctx: &Context<'_>,
+        tab_id: TabID,
+        user_id: i32,
+    ) -> FieldResult<Tab> {
+        self.perform(ctx, tab_id, move |_, acting_user_id| {
+            Ok(TabUserActionType::RemoveUser(RemoveUser::new(
+                acting_user_id,
+                user_id,
+            )))
+        })
+        .await
+    }
+
+    #[field(desc = "Add a new expense to a tab")]
+    async fn add_expense(
+        &self,
+        ctx: &Context<'_>,
+        tab_id: TabID,
+        expense: ExpenseInput,
+    ) -> FieldResult<Tab> {
+        self.perform(ctx, tab_id, move |tab, acting_user_id| {
+            Ok(TabUserActionType::AddExpense(AddExpense::new(
+                acting_user_id,
+                expense.to_expense(tab.next_expense_id())?,
+            )))
+        })
+        .await
+    }
+
+    #[field(desc = "Replace the details of an existing expense on a tab")]
+    async fn edit_expense(
+        &self,
+        ctx: &Context<'_>,
+        tab_id: TabID,
+        expense_id: i32,
+        expense: ExpenseInput,
+    ) -> FieldResult<Tab> {
+        self.perform(ctx, tab_id, move |_, acting_user_id| {
+            Ok(TabUserActionType::EditExpense(EditExpense::new(
+                acting_user_id,
+                expense.to_expense(expense_id)?,
+            )))
+        })
+        .await
+    }
+
+    #[field(desc = "Remove an expense from a tab")]
+    async fn remove_expense(
+        &self,
+        ctx: &Context<'_>,
+        tab_id: TabID,
+        expense_id: i32,
+    ) -> FieldResult<Tab> {
+        self.perform(ctx, tab_id, move |_, acting_user_id| {
+            Ok(TabUserActionType::RemoveExpense(RemoveExpense::new(
+                acting_user_id,
+                expense_id,
+            )))
+        })
+        .await
+    }
+
+    #[field(desc = "Record a payment between two users, which is added to the tab as an expense")]
+    async fn record_settlement(
+        &self,
+        ctx: &Context<'_>,
+        tab_id: TabID,
+        settlement: SettlementInput,
+    ) -> FieldResult<Tab> {
+        self.perform(ctx, tab_id, move |tab, acting_user_id| {
+            Ok(TabUserActionType::RecordSettlement(RecordSettlement::new(
+                acting_user_id,
+                tab.next_expense_id(),
+                settlement.date,
+                settlement.to_settlement()?,
+            )))
+        })
+        .await
+    }
+
+    #[field(
+        desc = "Close the period of a tab before the specified date, archiving its expenses and carrying the balances forward"
+    )]
+    async fn close_period(
+        &self,
+        ctx: &Context<'_>,
+        tab_id: TabID,
+        #[arg(desc = "Expenses which occurred before this date are archived")] end: NaiveDate,
+    ) -> FieldResult<Tab> {
+        self.perform(ctx, tab_id, move |_, acting_user_id| {
+            Ok(TabUserActionType::ClosePeriod(ClosePeriod::new(
+                acting_user_id,
+                end,
+            )))
+        })
+        .await
+    }
+
+    #[field(desc = "Create an invite to a tab")]
+    async fn create_invite(
+        &self,
+        ctx: &Context<'_>,
+        tab_id: TabID,
+        #[arg(desc = "When the invite can no longer be redeemed, by default it doesn't expire")]
+        expires_at: Option<DateTime<Utc>>,
+        #[arg(desc = "Whether the invite can only be redeemed once, defaults to false")]
+        single_use: Option<bool>,
+    ) -> FieldResult<Invite> {
+        blocking_as_account(ctx, move |service, account| {
+            let invite =
+                service.create_invite(account, &tab_id, expires_at, single_use.unwrap_or(false))?;
+            Ok(Invite::from(&invite))
+        })
+        .await
+    }
+
+    #[field(desc = "Create a link to the read-only summary of a tab")]
+    async fn create_share_link(&self, ctx: &Context<'_>, tab_id: TabID) -> FieldResult<ShareLink> {
+        blocking_as_account(ctx, move |service, account| {
+            let link = service.create_share_link(account, &tab_id)?;
+            Ok(ShareLink::from(&link))
+        })
+        .await
+    }
+
+    #[field(
+        desc = "Join a tab by adding a new user to it, which you are linked to. If you aren't logged in, a guest account is created"
+    )]
+    async fn redeem_invite(
+        &self,
+        ctx: &Context<'_>,
+        token: String,
+        user: UserInput,
+    ) -> FieldResult<InviteRedemption> {
+        let account = ctx.data_opt::<Account>().cloned();
+        blocking(ctx, move |service| {
+            let redemption = service.redeem_invite(
+                account.as_ref(),
+                &token,
+                &user.name,
+                user.email.as_deref(),
+            )?;
+            Ok(InviteRedemption {
+                tab: Tab::from(&redemption.tab),
+                user_id: redemption.member.user_id,
+                session_token: redemption.session.map(|session| session.token),
+            })
+        })
+        .await
+    }
+
+    #[field(desc = "Move a tab to the trash, from which it can be restored")]
+    async fn trash_tab(&self, ctx: &Context<'_>, tab_id: TabID) -> FieldResult<TabID> {
+        blocking_as_account(ctx, move |service, account| {
+            service.trash_tab(account, &tab_id, Utc::now())
+        })
+        .await?;
+        Ok(tab_id)
+    }
+
+    #[field(desc = "Restore a tab from the trash")]
+    async fn restore_tab(&self, ctx: &Context<'_>, tab_id: TabID) -> FieldResult<Tab> {
+        blocking_as_account(ctx, move |service, account| {
+            let tab = service.restore_tab(account, &tab_id)?;
+            Ok(Tab::from(&tab))
+        })
+        .await
+    }
+
+    #[field(desc = "Permanently delete a tab")]
+    async fn delete_tab(&self, ctx: &Context<'_>, tab_id: TabID) -> FieldResult<TabID> {
+        blocking_as_account(ctx, move |service, account| {
+            service.delete_tab(account, &tab_id)
+        })
+        .await?;
+        Ok(tab_id)
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    #[field(desc = "The actions performed on a tab, from when the subscription starts")]
+    async fn tab_updates(
+        &self,
+        ctx: &Context<'_>,
+        tab_id: TabID,
+    ) -> FieldResult<impl Stream<Item = TabUpdate>> {
+        blocking_as_account(ctx, move |service, account| {
+            service.tab(account, &tab_id).map(|_| ())
+        })
+        .await?;
+
+        let service = service(ctx)?.clone();
+        let account = account(ctx)?.clone();
+        Ok(service.events().subscribe(tab_id).filter_map(move |event| {
+            let account = account.clone();
+            let service = service.clone();
+            async move {
+                service
+                    .blocking(move |service| {
+                        match service.find_tab(&account, &event.tab_id) {
+                            Ok(Some(tab)) => Some(TabUpdate {
+                                action: (&event.action).into(),
+                                tab: Tab::from(&tab),
+                            }),
+                            // the tab has since been deleted
+                            Ok(None) => None,
+                            // the account has since been removed from the tab
+                            Err(ServiceError::Auth(_)) => None,
+                            Err(err) => {
+                                error!(
+                                    "Unable to read tab {} for a subscriber: {}",
+                                    event.tab_id, err
+                                );
+                                None
+                            }
+                        }
+                    })
+                    .await
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{field_error, routes, Mutation, Query, Subscription};
+    use crate::accounts::{Account, SESSION_COOKIE};
+    use crate::config::DEFAULT_MAX_BODY_SIZE;
+    use crate::metrics::Metrics;
+    use crate::repository::RepositoryError;
+    use crate::service::tests::create_test_service;
+    use async_graphql::{Data, Error, QueryBuilder, QueryResponse, Schema};
+    use async_graphql::{QueryError, Result};
+    use costing::CostingError;
+    use futures::StreamExt;
+    use serde_json::{json, Value};
+    use std::io;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use uuid::Uuid;
+    use warp::test::request;
+
+    const TEST_TAB_ID: &str = "936da01f-9abd-4d9d-80c7-02af85c822a8";
+
+    type TestSchema = Schema<Query, Mutation, Subscription>;
+
+    /// Create a schema using the service created by
+    /// [create_test_service], and return it along with the accounts
+    /// of Alice (a member of the test tab) and Bob (not a member).
+    fn create_schema() -> (TestSchema, Account, Account) {
+        let (service, alice, bob) = create_test_service();
+        let schema = Schema::build(Query, Mutation, Subscription)
+            .data(service)
+            .finish();
+        (schema, alice, bob)
+    }
+
+    /// The data for a subscription made by the `account`.
+    fn account_data(account: &Account) -> Arc<Data> {
+        let mut data = Data::default();
+        data.insert(account.clone());
+        Arc::new(data)
+    }
+
+    /// Execute the `query` as the `account`.
+    async fn execute(schema: &TestSchema, account: &Account, query: &str) -> Result<QueryResponse> {
+        QueryBuilder::new(query)
+            .data(account.clone())
+            .execute(schema)
+            .await
+    }
+
+    /// Execute the `query` as the `account`, and return the code of
+    /// the field error which it is expected to fail with.
+    async fn error_code(schema: &TestSchema, account: &Account, query: &str) -> Value {
+        match execute(schema, account, query).await {
+            Err(Error::Query {
+                err:
+                    QueryError::FieldError {
+                        extended_error: Some(extensions),
+                        ..
+                    },
+                ..
+            }) => extensions["code"].clone(),
+            other => panic!("expected a field error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn query_tab_balances() {
+        let (schema, alice, _) = create_schema();
+        let response = execute(&schema, &alice,
+                r#"{
+                    tabs {
+                        name
+                        users { id name }
+                        expenses { id amount { value currency } }
+                        balances(asOf: "2020-06-01") { userId amount { value } }
+                        settlements(asOf: "2020-06-01") { sender receiver amount { value currency } }
+                    }
+                }"#,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            json!({
+                "tabs": [{
+                    "name": "Road Trip",
+                    "users": [{ "id": 1, "name": "User 1" }, { "id": 2, "name": "User 2" }],
+                    "expenses": [{ "id": 1, "amount": { "value": "100", "currency": "AUD" } }],
+                    "balances": [
+                        { "userId": 1, "amount": { "value": "50" } },
+                        { "userId": 2, "amount": { "value": "-50" } },
+                    ],
+                    "settlements": [
+                        { "sender": 2, "receiver": 1, "amount": { "value": "50", "currency": "AUD" } },
+                    ],
+                }]
+            }),
+            response.data
+        );
+    }
+
+    #[tokio::test]
+    async fn query_missing_tab() {
+        let (schema, alice, _) = create_schema();
+        let query = format!(r#"{{ tab(id: "{}") {{ name }} }}"#, Uuid::nil());
+        let response = execute(&schema, &alice, &query).await.unwrap();
+        assert_eq!(json!({ "tab": null }), response.data);
+    }
+
+    #[tokio::test]
+    async fn mutate_tab() {
+        let (schema, alice, _) = create_schema();
+        let mutation = format!(
+            r#"mutation {{
+                renameTab(tabId: "{id}", name: "Holiday") {{ name }}
+                addUser(tabId: "{id}", user: {{ name: "User 3" }}) {{
+                    users {{ id }}
+                }}
+                addExpense(
+                    tabId: "{id}",
+                    expense: {{
+                        description: "Lunch",
+                        category: "Food",
+                        date: "2020-05-02",
+                        paidBy: 2,
+                        sharedBy: [1, 2, 3],
+                        amount: {{ value: "30.00", currency: "AUD" }}
+                    }}
+                ) {{
+                    expenses {{ id category }}
+                }}
+                editExpense(
+                    tabId: "{id}",
+                    expenseId: 2,
+                    expense: {{
+                        description: "Lunch",
+                        category: "Food",
+                        date: "2020-05-02",
+                        paidBy: 2,
+                        sharedBy: [1, 2],
+                        amount: {{ value: "30.00", currency: "AUD" }}
+                    }}
+                ) {{
+                    expenses {{ id sharedBy }}
+                }}
+                removeUser(tabId: "{id}", userId: 3) {{ users {{ id }} }}
+                recordSettlement(
+                    tabId: "{id}",
+                    settlement: {{
+                        sender: 2,
+                        receiver: 1,
+                        amount: {{ value: "35", currency: "AUD" }},
+                        date: "2020-05-03"
+                    }}
+                ) {{
+                    expenses {{ id category }}
+                    balances(asOf: "2020-06-01") {{ userId amount {{ value }} }}
+                }}
+                closePeriod(tabId: "{id}", end: "2020-05-03") {{
+                    expenses {{ id }}
+                    balances(asOf: "2020-06-01") {{ userId amount {{ value }} }}
+                }}
+            }}"#,
+            id = TEST_TAB_ID
+        );
+        let response = execute(&schema, &alice, &mutation).await.unwrap();
+
+        assert_eq!(
+            json!({
+                "renameTab": { "name": "Holiday" },
+                "addUser": { "users": [{ "id": 1 }, { "id": 2 }, { "id": 3 }] },
+                "addExpense": {
+                    "expenses": [
+                        { "id": 1, "category": "Transport" },
+                        { "id": 2, "category": "Food" },
+                    ]
+                },
+                "editExpense": {
+                    "expenses": [{ "id": 1, "sharedBy": [1, 2] }, { "id": 2, "sharedBy": [1, 2] }]
+                },
+                "removeUser": { "users": [{ "id": 1 }, { "id": 2 }] },
+                "recordSettlement": {
+                    "expenses": [
+                        { "id": 1, "category": "Transport" },
+                        { "id": 2, "category": "Food" },
+                        { "id": 3, "category": "Settlement" },
+                    ],
+                    "balances": [
+                        { "userId": 1, "amount": { "value": "0" } },
+                        { "userId": 2, "amount": { "value": "0" } },
+                    ],
+                },
+                "closePeriod": {
+                    "expenses": [{ "id": 3 }],
+                    "balances": [
+                        { "userId": 1, "amount": { "value": "0" } },
+                        { "userId": 2, "amount": { "value": "0" } },
+                    ],
+                },
+            }),
+            response.data
+        );
+
+        let query = format!(r#"{{ tab(id: "{}") {{ name }} }}"#, TEST_TAB_ID);
+        let response = execute(&schema, &alice, &query).await.unwrap();
+        assert_eq!(json!({ "tab": { "name": "Holiday" } }), response.data);
+    }
+
+    #[tokio::test]
+    async fn create_and_delete_tab() {
+        let (schema, alice, _) = create_schema();
+        let response = execute(
+            &schema,
+            &alice,
+            r#"mutation {
+                    createTab(
+                        name: "Flat",
+                        workingCurrency: "NZD",
+                        users: [{ name: "User 0", email: "user0@test.com" }, { name: "User 1" }]
+                    ) { id users { id name email } }
+                }"#,
+        )
+        .await
+        .unwrap();
+
+        let id = response.data["createTab"]["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            json!([
+                { "id": 0, "name": "User 0", "email": "user0@test.com" },
+                { "id": 1, "name": "User 1", "email": null },
+            ]),
+            response.data["createTab"]["users"]
+        );
+
+        let mutation = format!(r#"mutation {{ trashTab(tabId: "{}") }}"#, id);
+        execute(&schema, &alice, &mutation).await.unwrap();
+        let response = execute(&schema, &alice, "{ tabs { name } }").await.unwrap();
+        assert_eq!(json!({ "tabs": [{ "name": "Road Trip" }] }), response.data);
+
+        let mutation = format!(r#"mutation {{ restoreTab(tabId: "{}") {{ name }} }}"#, id);
+        let response = execute(&schema, &alice, &mutation).await.unwrap();
+        assert_eq!(json!({ "restoreTab": { "name": "Flat" } }), response.data);
+
+        let mutation = format!(r#"mutation {{ deleteTab(tabId: "{}") }}"#, id);
+        execute(&schema, &alice, &mutation).await.unwrap();
+        let query = format!(r#"{{ tab(id: "{}") {{ name }} }}"#, id);
+        let response = execute(&schema, &alice, &query).await.unwrap();
+        assert_eq!(json!({ "tab": null }), response.data);
+    }
+
+    #[tokio::test]
+    async fn mutation_errors() {
+        let (schema, alice, bob) = create_schema();
+
+        let mutation = format!(
+            r#"mutation {{ renameTab(tabId: "{}", name: "Holiday") {{ name }} }}"#,
+            Uuid::nil()
+        );
+        assert_eq!(
+            json!("TAB_NOT_FOUND"),
+            error_code(&schema, &alice, &mutation).await
+        );
+
+        let mutation = format!(
+            r#"mutation {{ renameTab(tabId: "{}", name: "Holiday") {{ name }} }}"#,
+            TEST_TAB_ID
+        );
+        assert_eq!(
+            json!("FORBIDDEN"),
+            error_code(&schema, &bob, &mutation).await
+        );
+        match schema.execute(&mutation).await {
+            Err(Error::Query {
+                err:
+                    QueryError::FieldError {
+                        extended_error: Some(extensions),
+                        ..
+                    },
+                ..
+            }) => assert_eq!(json!("NOT_AUTHENTICATED"), extensions["code"]),
+            other => panic!("expected a field error, got {:?}", other),
+        }
+
+        let mutation = format!(
+            r#"mutation {{ removeExpense(tabId: "{}", expenseId: 5) {{ name }} }}"#,
+            TEST_TAB_ID
+        );
+        assert_eq!(
+            json!("EXPENSE_NOT_FOUND"),
+            error_code(&schema, &alice, &mutation).await
+        );
+
+        let mutation = format!(
+            r#"mutation {{
+                recordSettlement(
+                    tabId: "{}",
+                    settlement: {{
+                        sender: 2,
+                        receiver: 1,
+                        amount: {{ value: "35", currency: "XYZ" }},
+                        date: "2020-05-03"
+                    }}
+                ) {{ name }}
+            }}"#,
+            TEST_TAB_ID
+        );
+        assert_eq!(
+            json!("INVALID_CURRENCY"),
+            error_code(&schema, &alice, &mutation).await
+        );
+
+        // the failed mutations left the tab unchanged
+        let query = format!(
+            r#"{{ tab(id: "{}") {{ name expenses {{ id }} }} }}"#,
+            TEST_TAB_ID
+        );
+        let response = execute(&schema, &alice, &query).await.unwrap();
+        assert_eq!(
+            json!({ "tab": { "name": "Road Trip", "expenses": [{ "id": 1 }] } }),
+            response.data
+        );
+    }
+
+    #[tokio::test]
+    async fn create_share_link() {
+        let (schema, alice, _) = create_schema();
+        let mutation = format!(
+            r#"mutation {{ createShareLink(tabId: "{}") {{ token createdBy path }} }}"#,
+            TEST_TAB_ID
+        );
+        let response = execute(&schema, &alice, &mutation).await.unwrap();
+        let link = &response.data["createShareLink"];
+        assert_eq!(json!(1), link["createdBy"]);
+        assert_eq!(
+            json!(format!("/shared/{}", link["token"].as_str().unwrap())),
+            link["path"]
+        );
+    }
+
+    #[tokio::test]
+    async fn join_tab_with_invite() {
+        let (schema, alice, _) = create_schema();
+        let mutation = format!(
+            r#"mutation {{ createInvite(tabId: "{}", singleUse: true) {{ token createdBy singleUse }} }}"#,
+            TEST_TAB_ID
+        );
+        let response = execute(&schema, &alice, &mutation).await.unwrap();
+        let invite = &response.data["createInvite"];
+        assert_eq!(json!(1), invite["createdBy"]);
+        let token = invite["token"].as_str().unwrap();
+
+        // invites can be read and redeemed without logging in
+        let query = format!(
+            r#"{{ invite(token: "{}") {{ tabName invitedBy }} }}"#,
+            token
+        );
+        let response = schema.execute(&query).await.unwrap();
+        assert_eq!(
+            json!({ "invite": { "tabName": "Road Trip", "invitedBy": "User 1" } }),
+            response.data
+        );
+
+        let mutation = format!(
+            r#"mutation {{
+                redeemInvite(token: "{}", user: {{ name: "Carol" }}) {{
+                    userId
+                    sessionToken
+                    tab {{ users {{ name }} }}
+                }}
+            }}"#,
+            token
+        );
+        let response = schema.execute(&mutation).await.unwrap();
+        let redemption = &response.data["redeemInvite"];
+        assert_eq!(json!(3), redemption["userId"]);
+        assert!(redemption["sessionToken"].is_string());
+        assert_eq!(json!("Carol"), redemption["tab"]["users"][2]["name"]);
+
+        match schema.execute(&mutation).await {
+            Err(Error::Query {
+                err:
+                    QueryError::FieldError {
+                        extended_error: Some(extensions),
+                        ..
+                    },
+                ..
+            }) => assert_eq!(json!("INVITE_NOT_FOUND"), extensions["code"]),
+            other => panic!("expected a field error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribe_to_tab_updates() {
+        let (schema, alice, _) = create_schema();
+        let subscription = format!(
+            r#"subscription {{
+                tabUpdates(tabId: "{}") {{
+                    action {{ kind userId details }}
+                    tab {{ name balances(asOf: "2020-06-01") {{ userId amount {{ value }} }} }}
+                }}
+            }}"#,
+            TEST_TAB_ID
+        );
+        let mut stream = schema
+            .create_subscription_stream(
+                &subscription,
+                None,
+                Default::default(),
+                Some(account_data(&alice)),
+            )
+            .await
+            .unwrap();
+
+        let mutation = format!(
+            r#"mutation {{
+                recordSettlement(
+                    tabId: "{}",
+                    settlement: {{
+                        sender: 2,
+                        receiver: 1,
+                        amount: {{ value: "50", currency: "AUD" }},
+                        date: "2020-05-03"
+                    }}
+                ) {{ name }}
+            }}"#,
+            TEST_TAB_ID
+        );
+        execute(&schema, &alice, &mutation).await.unwrap();
+
+        let update = stream.next().await.unwrap().unwrap();
+        assert_eq!(
+            json!("RecordSettlement"),
+            update["tabUpdates"]["action"]["kind"]
+        );
+        assert_eq!(json!(1), update["tabUpdates"]["action"]["userId"]);
+        assert_eq!(
+            json!("2020-05-03"),
+            update["tabUpdates"]["action"]["details"]["date"]
+        );
+        assert_eq!(
+            json!({
+                "name": "Road Trip",
+                "balances": [
+                    { "userId": 1, "amount": { "value": "0" } },
+                    { "userId": 2, "amount": { "value": "0" } },
+                ],
+            }),
+            update["tabUpdates"]["tab"]
+        );
+    }
+
+    #[tokio::test]
+    async fn subscribe_to_missing_tab() {
+        let (schema, alice, bob) = create_schema();
+        let subscription = format!(
+            r#"subscription {{ tabUpdates(tabId: "{}") {{ action {{ kind }} }} }}"#,
+            Uuid::nil()
+        );
+        assert!(schema
+            .create_subscription_stream(
+                &subscription,
+                None,
+                Default::default(),
+                Some(account_data(&alice))
+            )
+            .await
+            .is_err());
+
+        // only members of the tab can subscribe to it
+        let subscription = format!(
+            r#"subscription {{ tabUpdates(tabId: "{}") {{ action {{ kind }} }} }}"#,
+            TEST_TAB_ID
+        );
+        assert!(schema
+            .create_subscription_stream(
+                &subscription,
+                None,
+                Default::default(),
+                Some(account_data(&bob))
+            )
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn cookie_only_accepted_with_json_posts() {
+        let (service, _, _) = create_test_service();
+        let schema = Schema::build(Query, Mutation, Subscription)
+            .data(service.clone())
+            .finish();
+        let routes = routes(schema, service, Metrics::new(), DEFAULT_MAX_BODY_SIZE);
+        let cookie = format!("{}=alice", SESSION_COOKIE);
+        let mutation = format!(
+            r#"mutation {{ renameTab(tabId: "{}", name: "Holiday") {{ name }} }}"#,
+            TEST_TAB_ID
+        );
+        let query_string: String = mutation
+            .chars()
+            .map(|c| match c {
+                ' ' => "%20".to_string(),
+                '"' => "%22".to_string(),
+                '{' => "%7B".to_string(),
+                '}' => "%7D".to_string(),
+                c => c.to_string(),
+            })
+            .collect();
+
+        // e.g. a link from another site, which the browser sends the
+        // cookie with
+        let response = request()
+            .method("GET")
+            .path(&format!("/?query={}", query_string))
+            .header("cookie", &cookie)
+            .reply(&routes)
+            .await;
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            json!("NOT_AUTHENTICATED"),
+            body["errors"][0]["extensions"]["code"]
+        );
+
+        let response = request()
+            .method("POST")
+            .path("/")
+            .header("cookie", &cookie)
+            .json(&json!({ "query": mutation }))
+            .reply(&routes)
+            .await;
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(json!({ "renameTab": { "name": "Holiday" } }), body["data"]);
+
+        // scripts can use a bearer token with any request
+        let response = request()
+            .method("GET")
+            .path(&format!("/?query={}", query_string))
+            .header("authorization", "Bearer alice")
+            .reply(&routes)
+            .await;
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(json!({ "renameTab": { "name": "Holiday" } }), body["data"]);
+    }
+
+    #[test]
+    fn costing_error_code() {
+        let error = field_error(CostingError::UserDoesNotExistOnTab(3, Uuid::nil()));
+        assert_eq!(
+            Some(json!({ "code": "USER_NOT_FOUND", "causes": [] })),
+            error.1
+        );
+
+        // the causes of server errors are only logged
+        let error = field_error(RepositoryError::Open(
+            PathBuf::from("data"),
+            io::Error::other("/home/coster/data is locked"),
+        ));
+        assert_eq!(Some(json!({ "code": "DATABASE", "causes": [] })), error.1);
+    }
+}
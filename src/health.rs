@@ -0,0 +1,156 @@
+//! Routes for process supervisors and monitoring: `/healthz` reports
+//! that the server is running, `/readyz` that it is able to use its
+//! storage, and `/metrics` reports the [Metrics] in the Prometheus
+//! text format.
+
+use crate::error::ErrorCode;
+use crate::metrics::{route, Gauge, Metrics, CONTENT_TYPE as METRICS_CONTENT_TYPE};
+use crate::rest::error_response;
+use crate::service::{ServiceError, TabService};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use warp::filters::BoxedFilter;
+use warp::http::{header, StatusCode};
+use warp::reply::{self, Response};
+use warp::{Filter, Rejection, Reply};
+
+#[derive(Error, Debug)]
+pub enum HealthError {
+    #[error("the storage is unavailable: {0}")]
+    StorageUnavailable(#[source] ServiceError),
+}
+
+impl ErrorCode for HealthError {
+    fn code(&self) -> &'static str {
+        match self {
+            HealthError::StorageUnavailable(_) => "STORAGE_UNAVAILABLE",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            HealthError::StorageUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+/// The routes reporting the health of the server, whose storage is
+/// in the `data_dir`.
+pub fn routes(
+    service: TabService,
+    metrics: Metrics,
+    data_dir: PathBuf,
+) -> BoxedFilter<(Response,)> {
+    let healthz = warp::path!("healthz")
+        .and(warp::get())
+        .map(|| reply::with_status("ok", StatusCode::OK).into_response());
+
+    let ready_service = service.clone();
+    let readyz = warp::path!("readyz").and(warp::get()).and_then(move || {
+        let service = ready_service.clone();
+        async move {
+            let response = match service.blocking(|service| service.tab_count()).await {
+                Ok(_) => reply::with_status("ok", StatusCode::OK).into_response(),
+                Err(error) => error_response(&HealthError::StorageUnavailable(error)),
+            };
+            Ok::<_, Rejection>(response)
+        }
+    });
+
+    let metrics_route = warp::path!("metrics").and(warp::get()).and_then(move || {
+        let service = service.clone();
+        let metrics = metrics.clone();
+        let data_dir = data_dir.clone();
+        async move {
+            let gauges = service
+                .blocking(move |service| {
+                    let mut gauges = Vec::new();
+                    // the gauges are omitted if they can't be measured,
+                    // rather than failing to report the other metrics
+                    if let Ok(count) = service.tab_count() {
+                        gauges.push(Gauge {
+                            name: "coster_tabs",
+                            help: "The number of tabs stored, not including those in the trash.",
+                            value: count as f64,
+                        });
+                    }
+                    if let Ok(size) = storage_size(&data_dir) {
+                        gauges.push(Gauge {
+                            name: "coster_storage_bytes",
+                            help: "The size of the files in the data directory.",
+                            value: size as f64,
+                        });
+                    }
+                    gauges
+                })
+                .await;
+            let response = reply::with_header(
+                metrics.encode(&gauges),
+                header::CONTENT_TYPE,
+                METRICS_CONTENT_TYPE,
+            )
+            .into_response();
+            Ok::<_, Rejection>(response)
+        }
+    });
+
+    route("healthz", healthz)
+        .or(route("readyz", readyz))
+        .unify()
+        .or(route("metrics", metrics_route))
+        .unify()
+        .boxed()
+}
+
+/// The total size of the files within the `path`.
+fn storage_size(path: &Path) -> io::Result<u64> {
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        size += storage_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{routes, storage_size};
+    use crate::events::TabEvents;
+    use crate::metrics::Metrics;
+    use crate::repository::KeyValueDBRepository;
+    use crate::service::TabService;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn health_routes() {
+        let data_dir = std::env::temp_dir().join(format!("coster-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(data_dir.join("db")).unwrap();
+        std::fs::write(data_dir.join("db").join("data"), [0u8; 100]).unwrap();
+        assert_eq!(100, storage_size(&data_dir).unwrap());
+
+        let repository = KeyValueDBRepository::in_memory();
+        let service = TabService::new(Arc::new(repository), TabEvents::new());
+        let routes = routes(service, Metrics::new(), data_dir.clone());
+
+        let response = warp::test::request().path("/healthz").reply(&routes).await;
+        assert_eq!(200, response.status());
+        let response = warp::test::request().path("/readyz").reply(&routes).await;
+        assert_eq!(200, response.status());
+
+        let response = warp::test::request().path("/metrics").reply(&routes).await;
+        assert_eq!(200, response.status());
+        assert_eq!(
+            "text/plain; version=0.0.4",
+            response.headers()["content-type"]
+        );
+        let body = String::from_utf8_lossy(response.body());
+        assert!(body.contains("\ncoster_tabs 0\n"), "{}", body);
+        assert!(body.contains("\ncoster_storage_bytes 100\n"), "{}", body);
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
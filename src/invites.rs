@@ -0,0 +1,136 @@
+//! Invites which share a tab with other people. The members of a tab
+//! can create an invite, and send the link to it to the people they
+//! want to share the tab with. Redeeming an invite adds a new user to
+//! the tab, which the account redeeming it is linked to.
+
+use crate::accounts::{random_token, Account, Session, TabMember};
+use crate::error::ErrorCode;
+use chrono::{DateTime, Utc};
+use costing::{Tab, TabID, UserID};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use warp::http::StatusCode;
+
+/// An error which occurs while creating or redeeming an [Invite].
+#[derive(Error, Debug)]
+pub enum InviteError {
+    #[error("the invite does not exist, or has already been used")]
+    NotFound,
+    #[error("the invite expired at {0}")]
+    Expired(DateTime<Utc>),
+    #[error("the invite would have already expired at {0}")]
+    InvalidExpiry(DateTime<Utc>),
+}
+
+impl ErrorCode for InviteError {
+    fn code(&self) -> &'static str {
+        match self {
+            InviteError::NotFound => "INVITE_NOT_FOUND",
+            InviteError::Expired(_) => "INVITE_EXPIRED",
+            InviteError::InvalidExpiry(_) => "INVALID_EXPIRY",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            InviteError::NotFound => StatusCode::NOT_FOUND,
+            InviteError::Expired(_) => StatusCode::GONE,
+            InviteError::InvalidExpiry(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// An invite to join a tab, identified by a random `token` which is
+/// part of the link to it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Invite {
+    pub token: String,
+    pub tab_id: TabID,
+    /// The id of the user who created the invite.
+    pub created_by: UserID,
+    pub created_at: DateTime<Utc>,
+    /// When the invite can no longer be redeemed, or `None` if it
+    /// doesn't expire.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Whether the invite is deleted after it has been redeemed once.
+    pub single_use: bool,
+}
+
+impl Invite {
+    /// Create a new invite to the tab with the specified `tab_id`,
+    /// with a new random token.
+    pub fn new(
+        tab_id: TabID,
+        created_by: UserID,
+        created_at: DateTime<Utc>,
+        expires_at: Option<DateTime<Utc>>,
+        single_use: bool,
+    ) -> Result<Self, InviteError> {
+        if let Some(expires_at) = expires_at {
+            if expires_at <= created_at {
+                return Err(InviteError::InvalidExpiry(expires_at));
+            }
+        }
+
+        Ok(Invite {
+            token: random_token(),
+            tab_id,
+            created_by,
+            created_at,
+            expires_at,
+            single_use,
+        })
+    }
+
+    /// Whether this invite has expired as of `now`.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        match self.expires_at {
+            Some(expires_at) => now >= expires_at,
+            None => false,
+        }
+    }
+
+    /// The path of the page in the GUI which redeems this invite.
+    pub fn path(&self) -> String {
+        format!("/join/{}", self.token)
+    }
+}
+
+/// The result of redeeming an [Invite].
+pub struct Redemption {
+    /// The account which redeemed the invite.
+    pub account: Account,
+    /// The session of the guest account which was created to redeem
+    /// the invite, or `None` if it was redeemed by an existing
+    /// account.
+    pub session: Option<Session>,
+    /// The link between the account and its new user on the tab.
+    pub member: TabMember,
+    /// The tab, including the new user.
+    pub tab: Tab,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Invite, InviteError};
+    use chrono::{Duration, Utc};
+    use uuid::Uuid;
+
+    #[test]
+    fn invites_expire() {
+        let now = Utc::now();
+        let invite =
+            Invite::new(Uuid::nil(), 1, now, Some(now + Duration::days(7)), false).unwrap();
+        assert!(!invite.is_expired(now + Duration::days(1)));
+        assert!(invite.is_expired(now + Duration::days(7)));
+        assert_eq!(format!("/join/{}", invite.token), invite.path());
+
+        let invite = Invite::new(Uuid::nil(), 1, now, None, true).unwrap();
+        assert!(!invite.is_expired(now + Duration::days(365)));
+
+        assert!(matches!(
+            Invite::new(Uuid::nil(), 1, now, Some(now - Duration::days(1)), false),
+            Err(InviteError::InvalidExpiry(_))
+        ));
+    }
+}
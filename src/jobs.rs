@@ -0,0 +1,299 @@
+//! Jobs which the server runs in the background on a schedule, to
+//! maintain the data it stores, such as deleting expired invites,
+//! emptying the trash and compacting the storage.
+
+use crate::repository::RepositoryBackend;
+use crate::service::{ServiceError, TabService};
+use crate::shutdown::ShutdownSignal;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use log::{debug, error, info};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How often expired invites are deleted.
+pub const EXPIRE_INVITES_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often the trash is emptied.
+pub const EMPTY_TRASH_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long tabs are kept in the trash, before they are permanently
+/// deleted.
+pub fn trash_retention() -> ChronoDuration {
+    ChronoDuration::days(30)
+}
+
+/// How often the storage is compacted.
+pub const COMPACT_STORAGE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A job which is run periodically by a [JobRunner].
+pub trait Job: Send + Sync {
+    /// A short name for the job, which is used in the logs.
+    fn name(&self) -> &'static str;
+
+    /// How long to wait between runs of the job. The job is first run
+    /// one interval after the server starts.
+    fn interval(&self) -> Duration;
+
+    /// Run the job at the time `now`. Jobs are run on a thread which
+    /// is allowed to block, so they can use the [TabService] directly.
+    fn run(&self, now: DateTime<Utc>) -> Result<(), ServiceError>;
+}
+
+/// Deletes the invites which have expired, so that they don't
+/// accumulate in the storage.
+pub struct ExpireInvites(pub TabService);
+
+impl Job for ExpireInvites {
+    fn name(&self) -> &'static str {
+        "expire_invites"
+    }
+
+    fn interval(&self) -> Duration {
+        EXPIRE_INVITES_INTERVAL
+    }
+
+    fn run(&self, now: DateTime<Utc>) -> Result<(), ServiceError> {
+        let deleted = self.0.delete_expired_invites(now)?;
+        if deleted > 0 {
+            info!("Deleted {} expired invites", deleted);
+        }
+        Ok(())
+    }
+}
+
+/// Permanently deletes the tabs which have been in the trash for
+/// longer than the [trash_retention] period.
+pub struct EmptyTrash(pub TabService);
+
+impl Job for EmptyTrash {
+    fn name(&self) -> &'static str {
+        "empty_trash"
+    }
+
+    fn interval(&self) -> Duration {
+        EMPTY_TRASH_INTERVAL
+    }
+
+    fn run(&self, now: DateTime<Utc>) -> Result<(), ServiceError> {
+        let deleted = self.0.empty_trash(now - trash_retention())?;
+        if deleted > 0 {
+            info!("Deleted {} tabs from the trash", deleted);
+        }
+        Ok(())
+    }
+}
+
+/// Reclaims the space used by deleted data, see
+/// [TabService::compact_storage]. This is only run for the SQLite
+/// backend: RocksDB compacts its files in the background, and
+/// `kvdb-rocksdb` doesn't provide a way to compact them on demand.
+pub struct CompactStorage(pub TabService);
+
+impl Job for CompactStorage {
+    fn name(&self) -> &'static str {
+        "compact_storage"
+    }
+
+    fn interval(&self) -> Duration {
+        COMPACT_STORAGE_INTERVAL
+    }
+
+    fn run(&self, _now: DateTime<Utc>) -> Result<(), ServiceError> {
+        self.0.compact_storage()
+    }
+}
+
+/// Runs [Job]s periodically, one at a time, until the server shuts
+/// down.
+#[derive(Default)]
+pub struct JobRunner {
+    jobs: Vec<Arc<dyn Job>>,
+}
+
+impl JobRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The jobs which maintain the data stored by the `service`, in
+    /// a repository using the `backend`.
+    pub fn maintenance(service: &TabService, backend: RepositoryBackend) -> Self {
+        let runner = Self::new()
+            .job(ExpireInvites(service.clone()))
+            .job(EmptyTrash(service.clone()));
+        match backend {
+            RepositoryBackend::Sqlite => runner.job(CompactStorage(service.clone())),
+            RepositoryBackend::RocksDB => runner,
+        }
+    }
+
+    /// Add a `job` to be run.
+    pub fn job<J: Job + 'static>(mut self, job: J) -> Self {
+        self.jobs.push(Arc::new(job));
+        self
+    }
+
+    /// Run the jobs when they are due, until shutting down is
+    /// requested by the `shutdown` signal. A job which is running
+    /// when shutting down is requested is completed first.
+    pub async fn run(self, mut shutdown: ShutdownSignal) {
+        let start = Instant::now();
+        let mut due: Vec<Instant> = self.jobs.iter().map(|job| start + job.interval()).collect();
+
+        loop {
+            let next = due
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, due)| **due)
+                .map(|(index, due)| (index, *due));
+            let index = match next {
+                Some((index, due)) => {
+                    tokio::select! {
+                        _ = tokio::time::delay_until(due) => index,
+                        _ = shutdown.requested() => return,
+                    }
+                }
+                None => return,
+            };
+
+            let job = self.jobs[index].clone();
+            debug!("Running the {} job", job.name());
+            let name = job.name();
+            match tokio::task::spawn_blocking(move || job.run(Utc::now())).await {
+                Ok(Ok(())) => debug!("Completed the {} job", name),
+                Ok(Err(error)) => error!("The {} job failed: {}", name, error),
+                Err(error) => error!("The {} job panicked: {}", name, error),
+            }
+            due[index] = Instant::now() + self.jobs[index].interval();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{trash_retention, EmptyTrash, ExpireInvites, Job, JobRunner};
+    use crate::events::TabEvents;
+    use crate::invites::Invite;
+    use crate::repository::{
+        tests::create_test_tab, InviteRepository, KeyValueDBRepository, RepositoryBackend,
+        TabRepository,
+    };
+    use crate::service::{ServiceError, TabService};
+    use crate::shutdown::Shutdown;
+    use chrono::{DateTime, Duration as ChronoDuration, Utc};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    struct Count(Arc<AtomicUsize>, Duration);
+
+    impl Job for Count {
+        fn name(&self) -> &'static str {
+            "count"
+        }
+
+        fn interval(&self) -> Duration {
+            self.1
+        }
+
+        fn run(&self, _now: DateTime<Utc>) -> Result<(), ServiceError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn run_jobs() {
+        let frequent = Arc::new(AtomicUsize::new(0));
+        let infrequent = Arc::new(AtomicUsize::new(0));
+        let shutdown = Shutdown::new();
+        let runner = tokio::spawn(
+            JobRunner::new()
+                .job(Count(frequent.clone(), Duration::from_millis(10)))
+                .job(Count(infrequent.clone(), Duration::from_secs(60)))
+                .run(shutdown.signal()),
+        );
+
+        tokio::time::delay_for(Duration::from_millis(200)).await;
+        assert!(shutdown.shutdown(Duration::from_secs(5)).await);
+        runner.await.unwrap();
+        assert!(frequent.load(Ordering::SeqCst) >= 2);
+        assert_eq!(0, infrequent.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn maintenance_jobs() {
+        let service = TabService::new(
+            Arc::new(KeyValueDBRepository::in_memory()),
+            TabEvents::new(),
+        );
+        let names = |backend| -> Vec<&'static str> {
+            JobRunner::maintenance(&service, backend)
+                .jobs
+                .iter()
+                .map(|job| job.name())
+                .collect()
+        };
+        assert_eq!(
+            vec!["expire_invites", "empty_trash"],
+            names(RepositoryBackend::RocksDB)
+        );
+        assert_eq!(
+            vec!["expire_invites", "empty_trash", "compact_storage"],
+            names(RepositoryBackend::Sqlite)
+        );
+    }
+
+    #[test]
+    fn expire_invites() {
+        let repository = Arc::new(KeyValueDBRepository::in_memory());
+        let tab = create_test_tab();
+        repository.add_tab(&tab).unwrap();
+        let now = Utc::now();
+        let expired = Invite::new(
+            tab.id,
+            1,
+            now - ChronoDuration::days(2),
+            Some(now - ChronoDuration::days(1)),
+            false,
+        )
+        .unwrap();
+        let valid =
+            Invite::new(tab.id, 1, now, Some(now + ChronoDuration::days(1)), false).unwrap();
+        repository.add_invite(&expired).unwrap();
+        repository.add_invite(&valid).unwrap();
+
+        let service = TabService::new(repository.clone(), TabEvents::new());
+        ExpireInvites(service).run(now).unwrap();
+        assert_eq!(vec![valid], repository.tab_invites(&tab.id).unwrap());
+    }
+
+    #[test]
+    fn empty_trash() {
+        let repository = Arc::new(KeyValueDBRepository::in_memory());
+        let expired = create_test_tab();
+        let mut recent = create_test_tab();
+        recent.id = Uuid::new_v4();
+        repository.add_tab(&expired).unwrap();
+        repository.add_tab(&recent).unwrap();
+        let now = Utc::now();
+        repository
+            .trash_tab(
+                &expired.id,
+                now - trash_retention() - ChronoDuration::days(1),
+            )
+            .unwrap();
+        repository
+            .trash_tab(&recent.id, now - ChronoDuration::days(1))
+            .unwrap();
+
+        let service = TabService::new(repository.clone(), TabEvents::new());
+        EmptyTrash(service).run(now).unwrap();
+        assert!(repository.tab(&expired.id).unwrap().is_none());
+        assert!(repository.tab(&recent.id).unwrap().is_some());
+    }
+}
@@ -1,2 +1,22 @@
+pub mod accounts;
+pub mod assets;
+pub mod config;
 pub mod desktop;
+pub mod error;
+pub mod events;
+pub mod graphql;
+pub mod health;
+pub mod invites;
+pub mod jobs;
+pub mod metrics;
+pub mod repository;
+pub mod request_id;
+pub mod rest;
+pub mod security;
+pub mod server;
+pub mod service;
+pub mod shares;
+pub mod shutdown;
+pub mod sync;
+pub mod tls;
 pub mod web;
@@ -1,124 +1,232 @@
 use async_graphql::{
     http::{playground_source, GraphQLPlaygroundConfig},
-    EmptyMutation, EmptySubscription, Object, QueryBuilder, Schema,
+    Data, Schema,
 };
-use async_graphql_warp::{BadRequest, GQLResponse};
-use log::{debug, info};
-use mime_guess;
-use rust_embed::RustEmbed;
-use std::convert::Infallible;
-use warp::{
-    filters::BoxedFilter, http, http::header::HeaderValue, hyper::StatusCode, path::Tail, reply,
-    Filter, Rejection, Reply,
-};
-
-#[derive(RustEmbed)]
-#[folder = "public/"]
-struct Asset;
+use async_graphql_warp::BadRequest;
+use coster::assets::{self, StaticFiles};
+use coster::config::{ConfigError, Options};
+use coster::events::TabEvents;
+use coster::graphql::{self, field_error, Mutation, Query, Subscription};
+use coster::health;
+use coster::jobs::JobRunner;
+use coster::metrics::{route, Metrics};
+use coster::repository::open_repository;
+use coster::rest::{self, RequestError};
+use coster::security::{Security, PLAYGROUND_CONTENT_SECURITY_POLICY};
+use coster::server;
+use coster::service::TabService;
+use coster::shutdown::{self, Shutdown};
+use coster::tls;
+use coster::web::{self, Language, Pages};
+use log::{error, info, warn};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use warp::{filters::BoxedFilter, http, Filter, Rejection, Reply};
 
-struct Query;
+#[tokio::main]
+async fn main() {
+    let options = match Options::load(std::env::args_os(), |name| std::env::var(name).ok()) {
+        Ok(options) => options,
+        Err(ConfigError::Arguments(error)) => error.exit(),
+        Err(error) => exit_with_error("Invalid configuration", &error),
+    };
+    let config = options.config;
+
+    // the certificate is loaded before checking the configuration, so
+    // that --check-config also reports problems with it
+    let tls_acceptor = match &config.tls {
+        Some(tls_config) => match tls::Acceptor::new(tls_config.clone()) {
+            Ok(acceptor) => Some(Arc::new(acceptor)),
+            Err(error) => exit_with_error("Invalid TLS configuration", &error),
+        },
+        None => None,
+    };
 
-#[Object]
-impl Query {
-    #[field(desc = "Returns the sum of a and b")]
-    async fn add(&self, a: i32, b: i32) -> i32 {
-        a + b
+    if options.check_config {
+        println!("The configuration is valid:\n{}", config);
+        return;
     }
-}
 
-#[tokio::main]
-async fn main() {
-    pretty_env_logger::init();
+    // RUST_LOG can be used to refine the configured log level
+    let mut logger = pretty_env_logger::formatted_builder();
+    logger.filter_level(config.log_level);
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        logger.parse_filters(&filters);
+    }
+    logger.init();
 
-    let localhost: [u8; 4] = [0, 0, 0, 0];
-    let port: u16 = 8000;
-    let addr = (localhost, port);
+    info!(
+        "Opening {:?} database in {:?}",
+        config.backend, config.data_dir
+    );
+    let repository = match open_repository(config.backend, &config.data_dir) {
+        Ok(repository) => repository,
+        Err(error) => {
+            error!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let service = TabService::new(repository, TabEvents::new());
+
+    let pages = match Pages::load() {
+        Ok(pages) => pages,
+        Err(error) => exit_with_error("Invalid templates", &error),
+    };
+    // the inline scripts are the same in every language
+    let security = match pages.app_shell(Language::DEFAULT) {
+        Ok(app_shell) => Security::new(&config).allow_inline_scripts(&app_shell),
+        Err(error) => exit_with_error("Invalid templates", &error),
+    };
+    let metrics = Metrics::new();
+    let static_files = StaticFiles::default();
+    let routes = metrics.instrument(
+        security.protect(
+            health::routes(service.clone(), metrics.clone(), config.data_dir.clone())
+                .or(api(
+                    service.clone(),
+                    metrics.clone(),
+                    config.max_body_size,
+                    config.tls.is_some(),
+                ))
+                .or(route("static", assets::routes(static_files.clone())))
+                .or(web::routes(service.clone(), pages)),
+        ),
+    );
 
-    let routes = api()
-        .or(static_files_handler())
-        .or(index_static_file_redirect());
+    let listener = match TcpListener::bind(config.address()).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            error!("Unable to listen on {}: {}", config.address(), error);
+            std::process::exit(1);
+        }
+    };
+
+    let shutdown = Shutdown::new();
+    tokio::spawn(JobRunner::maintenance(&service, config.backend).run(shutdown.signal()));
+    // compressing the static files can take a few seconds, so it
+    // happens in the background
+    tokio::spawn(async move { static_files.prepare_all().await });
+
+    match tls_acceptor {
+        Some(acceptor) => {
+            #[cfg(unix)]
+            tokio::spawn(tls::reload_on_hangup(acceptor.clone()));
+
+            if let Some(redirect_port) = config.tls.as_ref().and_then(|tls| tls.redirect_port) {
+                let redirect_address = SocketAddr::new(config.bind_address, redirect_port);
+                let mut redirect_shutdown = shutdown.signal();
+                match warp::serve(tls::redirect_to_https(config.port))
+                    .try_bind_with_graceful_shutdown(redirect_address, async move {
+                        redirect_shutdown.requested().await
+                    }) {
+                    Ok((_, redirect_server)) => {
+                        info!("Redirecting http://{} to HTTPS", redirect_address);
+                        tokio::spawn(redirect_server);
+                    }
+                    Err(error) => {
+                        error!("Unable to listen on {}: {}", redirect_address, error);
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            info!("Serving on https://{}", config.address());
+            tokio::spawn(tls::serve(routes, listener, acceptor, shutdown.signal()));
+        }
+        None => {
+            info!("Serving on http://{}", config.address());
+            tokio::spawn(server::serve(routes, listener, shutdown.signal()));
+        }
+    }
 
-    println!("Serving on http://0.0.0.0:8000");
-    warp::serve(routes).run(addr).await;
+    shutdown::stop_requested().await;
+    info!(
+        "Stopping, waiting up to {} seconds for the requests in progress to complete",
+        config.shutdown_timeout.as_secs()
+    );
+    if !shutdown.shutdown(config.shutdown_timeout).await {
+        warn!("Stopping before the requests in progress completed");
+    }
+    if let Err(error) = service.flush_storage() {
+        error!("Unable to flush the database: {}", error);
+        std::process::exit(1);
+    }
+    info!("Stopped");
 }
 
-pub fn api() -> BoxedFilter<(impl Reply,)> {
-    let log = warp::log("api");
+/// Print the `error` and its causes to stderr, and exit the process.
+fn exit_with_error(message: &str, error: &dyn std::error::Error) -> ! {
+    eprintln!("{}: {}", message, error);
+    let mut source = error.source();
+    while let Some(cause) = source {
+        eprintln!("  caused by: {}", cause);
+        source = cause.source();
+    }
+    std::process::exit(1);
+}
 
-    let schema = Schema::build(Query, EmptyMutation, EmptySubscription).finish();
+pub fn api(
+    service: TabService,
+    metrics: Metrics,
+    max_body_size: u64,
+    secure_cookies: bool,
+) -> BoxedFilter<(impl Reply,)> {
+    let rest = route(
+        "rest",
+        rest::routes(service.clone(), max_body_size, secure_cookies),
+    );
 
-    let graphql_post = async_graphql_warp::graphql(schema).and_then(
-        |(schema, builder): (_, QueryBuilder)| async move {
-            let resp = builder.execute(&schema).await;
-            Ok::<_, Infallible>(GQLResponse::from(resp))
-        },
+    let schema = Schema::build(Query, Mutation, Subscription)
+        .data(service.clone())
+        .finish();
+
+    // subscriptions are authenticated using the session token in the
+    // payload of the connection_init message, e.g. {"token": "..."}
+    let subscription_service = service.clone();
+    let graphql_subscription = warp::path("ws").and(
+        async_graphql_warp::graphql_subscription_with_data(schema.clone(), move |payload| {
+            let mut data = Data::default();
+            if let Some(token) = payload.get("token").and_then(|token| token.as_str()) {
+                let account = subscription_service
+                    .authenticate(token)
+                    .map_err(field_error)?;
+                data.insert(account);
+            }
+            Ok(data)
+        }),
     );
 
+    let graphql_post = graphql::routes(schema, service, metrics, max_body_size);
+
     let graphql_playground = warp::path::end().and(warp::get()).map(|| {
         http::Response::builder()
             .header("content-type", "text/html")
-            .body(playground_source(GraphQLPlaygroundConfig::new("/api/")))
+            .header(
+                "content-security-policy",
+                PLAYGROUND_CONTENT_SECURITY_POLICY,
+            )
+            .body(playground_source(
+                GraphQLPlaygroundConfig::new("/api/").subscription_endpoint("/api/ws"),
+            ))
     });
 
     // let log = warp::log("coster::api");
     warp::path("api")
-        .and(
-            graphql_playground
-                .or(graphql_post)
-                .recover(|err: Rejection| async move {
-                    if let Some(BadRequest(err)) = err.find() {
-                        return Ok::<_, Infallible>(warp::reply::with_status(
-                            err.to_string(),
-                            StatusCode::BAD_REQUEST,
-                        ));
+        .and(route(
+            "api",
+            rest.or(route("graphql_ws", graphql_subscription))
+                .or(route("graphql_playground", graphql_playground))
+                .or(route("graphql", graphql_post))
+                .recover(|rejection: Rejection| async move {
+                    if let Some(BadRequest(error)) = rejection.find() {
+                        return Ok(rest::error_response(&RequestError::InvalidGraphQLRequest(
+                            error.to_string(),
+                        )));
                     }
-
-                    Ok(warp::reply::with_status(
-                        "INTERNAL_SERVER_ERROR".to_string(),
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                    ))
+                    rest::handle_rejection(rejection).await
                 }),
-        )
-        .with(log)
-        .boxed()
-}
-
-/// For any path within the path `/dist`, serve the embedded static
-/// files.
-fn static_files_handler() -> BoxedFilter<(impl Reply,)> {
-    // let log = warp::log("coster::dist");
-    warp::path("dist")
-        .and(warp::get())
-        .and(warp::path::tail())
-        .and_then(|path: Tail| async move {
-            debug!(target: "coster::dist", "Serving a request for static file in dist/{:?}", path);
-            serve_impl(path.as_str())
-        })
-        // .with(log)
-        .boxed()
-}
-
-/// For any path not already matched, return the index.html, so the app will bootstrap itself
-/// regardless of whatever the frontend-specific path is.
-fn index_static_file_redirect() -> BoxedFilter<(impl Reply,)> {
-    warp::path::tail()
-        .and_then(|tail| async move {
-            debug!(target: "coster::index-redirect", "Serving index page for path: {:?}", tail);
-            serve_impl("index.html")
-        })
+        ))
         .boxed()
 }
-
-fn serve_impl(path: &str) -> Result<impl Reply, Rejection> {
-    debug!(target: "coster::static-files", "Attempting to serve static file: {}", path);
-    let asset = Asset::get(path).ok_or_else(warp::reject::not_found)?;
-    debug!(target: "coster::static-files", "Found static file: {}", path);
-    let mime = mime_guess::from_path(path).first_or_octet_stream();
-
-    let mut res = reply::Response::new(asset.into());
-    res.headers_mut().insert(
-        "content-type",
-        HeaderValue::from_str(mime.as_ref()).unwrap(),
-    );
-    Ok(res)
-}
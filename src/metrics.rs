@@ -0,0 +1,353 @@
+//! Metrics describing the requests handled by the server, which are
+//! exposed at `/metrics` in the Prometheus text format.
+//!
+//! Requests are counted by the route which handled them, which is
+//! identified by wrapping the route's filter with [route]. Requests
+//! which weren't handled by a route (e.g. because they were refused
+//! by [Security](crate::security::Security)) are counted as
+//! `unrouted`.
+
+use async_graphql::parser::{
+    parse_query,
+    query::{Definition, OperationDefinition},
+};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::filters::BoxedFilter;
+use warp::http::{Method, StatusCode};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+/// The upper bounds (in seconds) of the buckets which durations are
+/// counted in.
+const DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// The number of distinct GraphQL operations which are recorded.
+/// Operations are named by clients, so further operations are
+/// recorded as `other`, to limit the number of time series.
+const MAX_GRAPHQL_OPERATIONS: usize = 100;
+
+/// The content type of the Prometheus text format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// The name of the route which handled a request, which is stored in
+/// the extensions of its response by [route].
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Route(&'static str);
+
+/// Identify the responses of the `filter` as those of the route with
+/// the `name`, unless they were already identified by a more specific
+/// route within it.
+pub fn route<F, T>(
+    name: &'static str,
+    filter: F,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (T,), Error = Rejection> + Clone,
+    T: Reply,
+{
+    filter.map(move |reply: T| {
+        let mut response = reply.into_response();
+        if response.extensions().get::<Route>().is_none() {
+            response.extensions_mut().insert(Route(name));
+        }
+        response
+    })
+}
+
+/// A histogram of durations.
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    /// The number of durations less than or equal to each of the
+    /// [DURATION_BUCKETS].
+    buckets: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS.iter()) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    /// Write the samples of the histogram with the `name` and
+    /// `labels` (e.g. `route="rest"`).
+    fn write(&self, output: &mut String, name: &str, labels: &str) {
+        for (bucket, bound) in self.buckets.iter().zip(DURATION_BUCKETS.iter()) {
+            writeln!(
+                output,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, bound, bucket
+            )
+            .unwrap();
+        }
+        writeln!(
+            output,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, labels, self.count
+        )
+        .unwrap();
+        writeln!(output, "{}_sum{{{}}} {}", name, labels, self.sum).unwrap();
+        writeln!(output, "{}_count{{{}}} {}", name, labels, self.count).unwrap();
+    }
+}
+
+#[derive(Debug, Default)]
+struct Recorded {
+    /// The number of requests by route, method and status.
+    requests: BTreeMap<(&'static str, String, u16), u64>,
+    request_durations: BTreeMap<&'static str, Histogram>,
+    /// The durations of GraphQL operations by type and name.
+    graphql_operations: BTreeMap<(&'static str, String), Histogram>,
+}
+
+/// A gauge which is measured when the metrics are encoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gauge {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub value: f64,
+}
+
+/// The metrics recorded by the server, which can be cloned to record
+/// them from multiple filters.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics(Arc<Mutex<Recorded>>);
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the requests handled by the `filter`, by the [route]
+    /// which handled them.
+    pub fn instrument<F, T>(&self, filter: F) -> BoxedFilter<(Response,)>
+    where
+        F: Filter<Extract = (T,), Error = Rejection> + Clone + Send + Sync + 'static,
+        T: Reply,
+    {
+        let metrics = self.clone();
+        warp::any()
+            .map(Instant::now)
+            .and(warp::method())
+            .and(filter)
+            .map(move |start: Instant, method: Method, reply: T| {
+                let response = reply.into_response();
+                let route = response
+                    .extensions()
+                    .get::<Route>()
+                    .map_or("unrouted", |route| route.0);
+                metrics.record_request(route, &method, response.status(), start.elapsed());
+                response
+            })
+            .boxed()
+    }
+
+    fn record_request(
+        &self,
+        route: &'static str,
+        method: &Method,
+        status: StatusCode,
+        duration: Duration,
+    ) {
+        let mut recorded = self.0.lock().expect("metrics lock is poisoned");
+        *recorded
+            .requests
+            .entry((route, method.to_string(), status.as_u16()))
+            .or_default() += 1;
+        recorded
+            .request_durations
+            .entry(route)
+            .or_default()
+            .observe(duration);
+    }
+
+    /// Record the `duration` of executing the GraphQL query in the
+    /// `query_source`.
+    pub fn record_graphql(&self, query_source: &str, duration: Duration) {
+        let (operation_type, name) = graphql_operation(query_source);
+        let mut recorded = self.0.lock().expect("metrics lock is poisoned");
+        let known = recorded
+            .graphql_operations
+            .contains_key(&(operation_type, name.clone()));
+        let key = if known || recorded.graphql_operations.len() < MAX_GRAPHQL_OPERATIONS {
+            (operation_type, name)
+        } else {
+            (operation_type, "other".to_string())
+        };
+        recorded
+            .graphql_operations
+            .entry(key)
+            .or_default()
+            .observe(duration);
+    }
+
+    /// Encode the recorded metrics, and the `gauges`, in the
+    /// Prometheus text format.
+    pub fn encode(&self, gauges: &[Gauge]) -> String {
+        let recorded = self.0.lock().expect("metrics lock is poisoned");
+        let mut output = String::new();
+
+        output.push_str("# HELP coster_http_requests_total The number of HTTP requests handled.\n");
+        output.push_str("# TYPE coster_http_requests_total counter\n");
+        for ((route, method, status), count) in &recorded.requests {
+            writeln!(
+                output,
+                "coster_http_requests_total{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                route, method, status, count
+            )
+            .unwrap();
+        }
+
+        output.push_str(
+            "# HELP coster_http_request_duration_seconds The time taken to handle HTTP requests.\n",
+        );
+        output.push_str("# TYPE coster_http_request_duration_seconds histogram\n");
+        for (route, histogram) in &recorded.request_durations {
+            histogram.write(
+                &mut output,
+                "coster_http_request_duration_seconds",
+                &format!("route=\"{}\"", route),
+            );
+        }
+
+        output.push_str(
+            "# HELP coster_graphql_operation_duration_seconds The time taken to execute GraphQL operations.\n",
+        );
+        output.push_str("# TYPE coster_graphql_operation_duration_seconds histogram\n");
+        for ((operation_type, name), histogram) in &recorded.graphql_operations {
+            histogram.write(
+                &mut output,
+                "coster_graphql_operation_duration_seconds",
+                &format!("type=\"{}\",operation=\"{}\"", operation_type, name),
+            );
+        }
+
+        for gauge in gauges {
+            writeln!(output, "# HELP {} {}", gauge.name, gauge.help).unwrap();
+            writeln!(output, "# TYPE {} gauge", gauge.name).unwrap();
+            writeln!(output, "{} {}", gauge.name, gauge.value).unwrap();
+        }
+        output
+    }
+}
+
+/// The type and name of the first operation in a GraphQL query, for
+/// labelling its metrics. Anonymous operations are named `anonymous`.
+fn graphql_operation(query_source: &str) -> (&'static str, String) {
+    let document = match parse_query(query_source) {
+        Ok(document) => document,
+        Err(_) => return ("invalid", "invalid".to_string()),
+    };
+    let operation = document
+        .definitions()
+        .iter()
+        .find_map(|definition| match &definition.node {
+            Definition::Operation(operation) => Some(match &operation.node {
+                OperationDefinition::SelectionSet(_) => ("query", None),
+                OperationDefinition::Query(query) => ("query", query.name.as_ref()),
+                OperationDefinition::Mutation(mutation) => ("mutation", mutation.name.as_ref()),
+                OperationDefinition::Subscription(subscription) => {
+                    ("subscription", subscription.name.as_ref())
+                }
+            }),
+            Definition::Fragment(_) => None,
+        });
+    match operation {
+        Some((operation_type, name)) => (
+            operation_type,
+            name.map_or_else(|| "anonymous".to_string(), |name| name.node.clone()),
+        ),
+        None => ("invalid", "invalid".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{graphql_operation, route, Gauge, Metrics, MAX_GRAPHQL_OPERATIONS};
+    use std::time::Duration;
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn record_requests() {
+        let metrics = Metrics::new();
+        let routes = metrics.instrument(
+            route(
+                "hello",
+                warp::path("hello").and(warp::get()).map(|| "world"),
+            )
+            .or(route("other", warp::post().map(|| "other")))
+            .unify(),
+        );
+        warp::test::request().path("/hello").reply(&routes).await;
+        warp::test::request()
+            .method("POST")
+            .path("/hello")
+            .reply(&routes)
+            .await;
+        warp::test::request().path("/hello").reply(&routes).await;
+
+        let encoded = metrics.encode(&[Gauge {
+            name: "coster_tabs",
+            help: "The number of tabs.",
+            value: 3.0,
+        }]);
+        assert!(encoded.contains(
+            "coster_http_requests_total{route=\"hello\",method=\"GET\",status=\"200\"} 2\n"
+        ));
+        assert!(encoded.contains(
+            "coster_http_requests_total{route=\"other\",method=\"POST\",status=\"200\"} 1\n"
+        ));
+        assert!(encoded.contains("coster_http_request_duration_seconds_count{route=\"hello\"} 2\n"));
+        assert!(encoded.contains(
+            "coster_http_request_duration_seconds_bucket{route=\"hello\",le=\"+Inf\"} 2\n"
+        ));
+        assert!(encoded.contains("# TYPE coster_tabs gauge\ncoster_tabs 3\n"));
+    }
+
+    #[test]
+    fn record_graphql_operations() {
+        assert_eq!(
+            ("query", "Tabs".to_string()),
+            graphql_operation("query Tabs { tabs { id } }")
+        );
+        assert_eq!(
+            ("query", "anonymous".to_string()),
+            graphql_operation("{ tabs { id } }")
+        );
+        assert_eq!(
+            ("mutation", "Rename".to_string()),
+            graphql_operation("fragment F on Tab { id } mutation Rename { a }")
+        );
+        assert_eq!(
+            ("invalid", "invalid".to_string()),
+            graphql_operation("query {")
+        );
+
+        let metrics = Metrics::new();
+        for i in 0..=MAX_GRAPHQL_OPERATIONS {
+            metrics.record_graphql(&format!("query Q{} {{ a }}", i), Duration::from_millis(2));
+        }
+        let encoded = metrics.encode(&[]);
+        assert!(encoded.contains(
+            "coster_graphql_operation_duration_seconds_bucket{type=\"query\",operation=\"Q0\",le=\"0.001\"} 0\n"
+        ));
+        assert!(encoded.contains(
+            "coster_graphql_operation_duration_seconds_bucket{type=\"query\",operation=\"Q0\",le=\"0.0025\"} 1\n"
+        ));
+        assert!(encoded.contains(
+            "coster_graphql_operation_duration_seconds_count{type=\"query\",operation=\"other\"} 1\n"
+        ));
+    }
+}
@@ -0,0 +1,1207 @@
+//! Persistent storage for the server. Data can either be stored
+//! using the [costing::db] traits over a [KeyValueDB] (RocksDB on
+//! disk, or in-memory for testing), or in a relational SQLite
+//! database.
+
+use crate::accounts::{Account, AccountID, Session, TabMember};
+use crate::invites::Invite;
+use crate::shares::ShareLink;
+use crate::sync::SyncPush;
+use chrono::{DateTime, NaiveDate, Utc};
+use costing::db::{
+    tabs::TabStorage, DBTransactionSerde, DatabaseError, DatabaseValueEncoding, KeyValueDBSerde,
+    KeyValueDBStore, MigrationError,
+};
+use costing::{Tab, TabID, UserID};
+use kvdb::{DBTransaction, KeyValueDB};
+use log::error;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+
+pub mod sqlite;
+
+pub use sqlite::SqliteRepository;
+
+/// The default directory (relative to the working directory) used to
+/// store the server's database.
+pub const DEFAULT_DATA_DIR: &str = "data";
+
+/// An error which occurs while accessing a [TabRepository].
+#[derive(Error, Debug)]
+pub enum RepositoryError {
+    #[error("unable to open the database in the directory {0:?}")]
+    Open(PathBuf, #[source] io::Error),
+    #[error("unknown database backend {0:?}, expected \"rocksdb\" or \"sqlite\"")]
+    UnknownBackend(String),
+    #[error("the tab with id {0} was not found")]
+    TabNotFound(TabID),
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("unable to encode/decode a JSON value stored in the database")]
+    Json(#[from] serde_json::Error),
+    #[error("the value {0:?} stored in the database is invalid")]
+    InvalidValue(String),
+    #[error("the value stored in the database could not be migrated to the current schema")]
+    Migration(#[from] MigrationError),
+}
+
+/// The stores (columns) of the server's database.
+#[derive(Debug)]
+pub enum CosterServerDBStore {
+    /// Used for storing general server variables that need to be
+    /// persisted.
+    General,
+    /// Used for storing [costing::Tab]s.
+    Tabs,
+    /// Used for storing [Account]s, their [Session]s, the
+    /// [TabMember]s linking them to tabs, and [Invite]s and
+    /// [ShareLink]s to tabs.
+    Accounts,
+}
+
+impl KeyValueDBStore for CosterServerDBStore {
+    fn name(&self) -> &str {
+        match self {
+            CosterServerDBStore::General => "General",
+            CosterServerDBStore::Tabs => "Tabs",
+            CosterServerDBStore::Accounts => "Accounts",
+        }
+    }
+    fn db_col(&self) -> u32 {
+        match self {
+            CosterServerDBStore::General => 0,
+            CosterServerDBStore::Tabs => 1,
+            CosterServerDBStore::Accounts => 2,
+        }
+    }
+    fn n_db_cols() -> u32 {
+        3
+    }
+    /// Values are written as CBOR, which is more compact than JSON.
+    /// Values written as JSON by earlier versions are still read.
+    fn encoding(&self) -> DatabaseValueEncoding {
+        DatabaseValueEncoding::Cbor
+    }
+}
+
+/// Reads and writes the server's [Tab]s.
+pub trait TabRepository: Send + Sync {
+    /// The ids of all the tabs (not including those in the trash).
+    fn tab_ids(&self) -> Result<Vec<TabID>, RepositoryError>;
+
+    /// Read the tab with the specified id (which may be in the trash).
+    fn tab(&self, tab_id: &TabID) -> Result<Option<Tab>, RepositoryError>;
+
+    /// Read the tab with the specified id, with only the closed
+    /// periods needed to calculate its balances as of the specified
+    /// date. The tab must not be written back, because its other
+    /// closed periods may be missing.
+    fn tab_as_of(&self, tab_id: &TabID, _as_of: NaiveDate) -> Result<Option<Tab>, RepositoryError> {
+        self.tab(tab_id)
+    }
+
+    /// Read all the tabs (not including those in the trash).
+    fn tabs(&self) -> Result<Vec<Tab>, RepositoryError>;
+
+    /// Store a new `tab`.
+    fn add_tab(&self, tab: &Tab) -> Result<(), RepositoryError>;
+
+    /// Replace the stored data of an existing `tab`, writing only what
+    /// has changed.
+    fn update_tab(&self, tab: &Tab) -> Result<(), RepositoryError>;
+
+    /// Move the tab with the specified id to the trash.
+    fn trash_tab(&self, tab_id: &TabID, trashed_at: DateTime<Utc>) -> Result<(), RepositoryError>;
+
+    /// Restore the tab with the specified id from the trash.
+    fn restore_tab(&self, tab_id: &TabID) -> Result<(), RepositoryError>;
+
+    /// Permanently delete the tab with the specified id.
+    fn delete_tab(&self, tab_id: &TabID) -> Result<(), RepositoryError>;
+
+    /// Permanently delete the tabs which were moved to the trash
+    /// before `trashed_before`, returning their ids.
+    fn empty_trash(&self, trashed_before: DateTime<Utc>) -> Result<Vec<TabID>, RepositoryError>;
+}
+
+/// Reads and writes the server's [Account]s, their [Session]s, and
+/// the [TabMember]s which link them to the users of tabs.
+pub trait AccountRepository: Send + Sync {
+    /// Read the account with the specified id.
+    fn account(&self, account_id: &AccountID) -> Result<Option<Account>, RepositoryError>;
+
+    /// Read the account with the specified (normalized) email address.
+    fn account_by_email(&self, email: &str) -> Result<Option<Account>, RepositoryError>;
+
+    /// Store a new `account`. The caller is responsible for checking
+    /// that its email address isn't already used by another account.
+    fn add_account(&self, account: &Account) -> Result<(), RepositoryError>;
+
+    /// Read the session with the specified token.
+    fn session(&self, token: &str) -> Result<Option<Session>, RepositoryError>;
+
+    /// Store a new `session`.
+    fn add_session(&self, session: &Session) -> Result<(), RepositoryError>;
+
+    /// Delete the session with the specified token, if it exists.
+    fn delete_session(&self, token: &str) -> Result<(), RepositoryError>;
+
+    /// Read the members of the tab with the specified id.
+    fn tab_members(&self, tab_id: &TabID) -> Result<Vec<TabMember>, RepositoryError>;
+
+    /// Read the tabs which the account with the specified id is a
+    /// member of.
+    fn account_memberships(
+        &self,
+        account_id: &AccountID,
+    ) -> Result<Vec<TabMember>, RepositoryError>;
+
+    /// Store a new `member`. The caller is responsible for checking
+    /// that neither the user or the account are already linked on the
+    /// tab.
+    fn add_tab_member(&self, member: &TabMember) -> Result<(), RepositoryError>;
+
+    /// Remove the link between the user with the specified id and its
+    /// account, if it exists.
+    fn remove_tab_member(&self, tab_id: &TabID, user_id: UserID) -> Result<(), RepositoryError>;
+
+    /// Remove all the members of the tab with the specified id.
+    fn remove_tab_members(&self, tab_id: &TabID) -> Result<(), RepositoryError>;
+}
+
+/// Reads and writes the [Invite]s to the server's tabs.
+pub trait InviteRepository: Send + Sync {
+    /// Read the invite with the specified token.
+    fn invite(&self, token: &str) -> Result<Option<Invite>, RepositoryError>;
+
+    /// Read the invites to the tab with the specified id.
+    fn tab_invites(&self, tab_id: &TabID) -> Result<Vec<Invite>, RepositoryError>;
+
+    /// Store a new `invite`.
+    fn add_invite(&self, invite: &Invite) -> Result<(), RepositoryError>;
+
+    /// Delete the invite with the specified token, if it exists.
+    fn delete_invite(&self, token: &str) -> Result<(), RepositoryError>;
+
+    /// Delete all the invites to the tab with the specified id.
+    fn delete_tab_invites(&self, tab_id: &TabID) -> Result<(), RepositoryError>;
+
+    /// Delete all the invites which have expired as of `now`,
+    /// returning the number of invites deleted.
+    fn delete_expired_invites(&self, now: DateTime<Utc>) -> Result<usize, RepositoryError>;
+}
+
+/// Reads and writes the [ShareLink]s to the server's tabs.
+pub trait ShareLinkRepository: Send + Sync {
+    /// Read the share link with the specified token.
+    fn share_link(&self, token: &str) -> Result<Option<ShareLink>, RepositoryError>;
+
+    /// Read the share links to the tab with the specified id.
+    fn tab_share_links(&self, tab_id: &TabID) -> Result<Vec<ShareLink>, RepositoryError>;
+
+    /// Store a new share `link`.
+    fn add_share_link(&self, link: &ShareLink) -> Result<(), RepositoryError>;
+
+    /// Delete the share link with the specified token, if it exists.
+    fn delete_share_link(&self, token: &str) -> Result<(), RepositoryError>;
+
+    /// Delete all the share links to the tab with the specified id.
+    fn delete_tab_share_links(&self, tab_id: &TabID) -> Result<(), RepositoryError>;
+}
+
+/// Reads and writes the [SyncPush]es to the server's tabs.
+pub trait SyncRepository: Send + Sync {
+    /// Read the last push of actions to the tab with the specified id
+    /// by the account with the specified id.
+    fn sync_push(
+        &self,
+        tab_id: &TabID,
+        account_id: &AccountID,
+    ) -> Result<Option<SyncPush>, RepositoryError>;
+
+    /// Store a `push`, replacing the previous push to its tab by its
+    /// account.
+    fn set_sync_push(&self, push: &SyncPush) -> Result<(), RepositoryError>;
+
+    /// Delete all the pushes to the tab with the specified id.
+    fn delete_tab_sync_pushes(&self, tab_id: &TabID) -> Result<(), RepositoryError>;
+}
+
+/// Maintains the storage used by a repository.
+pub trait StorageRepository: Send + Sync {
+    /// Ensure that all the data which has been written is stored
+    /// durably, before the server exits.
+    fn flush(&self) -> Result<(), RepositoryError>;
+
+    /// Reclaim the space used by data which has been deleted.
+    fn compact(&self) -> Result<(), RepositoryError>;
+}
+
+/// All the data stored by the server. Implementations use interior
+/// synchronization, so a repository can be shared between requests.
+pub trait Repository:
+    TabRepository
+    + AccountRepository
+    + InviteRepository
+    + ShareLinkRepository
+    + SyncRepository
+    + StorageRepository
+{
+}
+
+impl<T> Repository for T where
+    T: TabRepository
+        + AccountRepository
+        + InviteRepository
+        + ShareLinkRepository
+        + SyncRepository
+        + StorageRepository
+{
+}
+
+/// A [Repository] which can be shared between threads.
+pub type RepositoryRef = Arc<dyn Repository>;
+
+/// The type of database used to store the server's data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RepositoryBackend {
+    /// A [KeyValueDBRepository] stored using RocksDB.
+    RocksDB,
+    /// A [SqliteRepository].
+    Sqlite,
+}
+
+impl FromStr for RepositoryBackend {
+    type Err = RepositoryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rocksdb" => Ok(RepositoryBackend::RocksDB),
+            "sqlite" => Ok(RepositoryBackend::Sqlite),
+            _ => Err(RepositoryError::UnknownBackend(s.to_string())),
+        }
+    }
+}
+
+/// Open (or create) the repository stored in `data_dir` using the
+/// specified `backend`.
+pub fn open_repository<P: AsRef<Path>>(
+    backend: RepositoryBackend,
+    data_dir: P,
+) -> Result<RepositoryRef, RepositoryError> {
+    Ok(match backend {
+        RepositoryBackend::RocksDB => Arc::new(KeyValueDBRepository::open(data_dir)?),
+        RepositoryBackend::Sqlite => Arc::new(SqliteRepository::open_dir(data_dir)?),
+    })
+}
+
+/// A [TabRepository] using the [costing::db] traits over a
+/// [KeyValueDB]. Cloning a [KeyValueDBRepository] is cheap, and the
+/// clones share the same database.
+#[derive(Clone)]
+pub struct KeyValueDBRepository {
+    database: Arc<dyn KeyValueDB>,
+    /// Held while writing, so that concurrent modifications of the
+    /// same records (such as the index of tabs) are not lost.
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl KeyValueDBRepository {
+    /// Create a new [KeyValueDBRepository] using the specified `database`.
+    pub fn new(database: Arc<dyn KeyValueDB>) -> Self {
+        KeyValueDBRepository {
+            database,
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Open (or create) a RocksDB database stored in `data_dir`.
+    pub fn open<P: AsRef<Path>>(data_dir: P) -> Result<Self, RepositoryError> {
+        let data_dir = data_dir.as_ref();
+        let config = kvdb_rocksdb::DatabaseConfig::with_columns(CosterServerDBStore::n_db_cols());
+        let database = kvdb_rocksdb::Database::open(&config, &data_dir.to_string_lossy())
+            .map_err(|error| RepositoryError::Open(data_dir.to_path_buf(), error))?;
+        Ok(Self::new(Arc::new(database)))
+    }
+
+    /// Create a [KeyValueDBRepository] with an empty in-memory
+    /// database, which is not persisted.
+    pub fn in_memory() -> Self {
+        Self::new(Arc::new(kvdb_memorydb::create(
+            CosterServerDBStore::n_db_cols(),
+        )))
+    }
+
+    /// The database used by this repository.
+    pub fn database(&self) -> &dyn KeyValueDB {
+        self.database.as_ref()
+    }
+
+    fn tab_storage(&self) -> TabStorage<'static, CosterServerDBStore> {
+        TabStorage::new("tabs", &CosterServerDBStore::Tabs)
+    }
+
+    /// Populate a [DBTransaction] using `f`, and write it to the
+    /// database. No other writes can occur while `f` is running.
+    pub fn write<T, F>(&self, f: F) -> Result<T, RepositoryError>
+    where
+        F: FnOnce(&dyn KeyValueDB, &mut DBTransaction) -> Result<T, RepositoryError>,
+    {
+        // a panic while holding the lock cannot leave a partially
+        // written transaction, so it is safe to ignore poisoning.
+        let _guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let database = self.database();
+        let mut transaction = database.transaction();
+        let value = f(database, &mut transaction)?;
+        database.write(transaction).map_err(DatabaseError::from)?;
+        Ok(value)
+    }
+}
+
+impl TabRepository for KeyValueDBRepository {
+    fn tab_ids(&self) -> Result<Vec<TabID>, RepositoryError> {
+        Ok(self.tab_storage().read_tab_ids(self.database())?)
+    }
+
+    fn tab(&self, tab_id: &TabID) -> Result<Option<Tab>, RepositoryError> {
+        Ok(self.tab_storage().read_tab(self.database(), tab_id)?)
+    }
+
+    fn tab_as_of(&self, tab_id: &TabID, as_of: NaiveDate) -> Result<Option<Tab>, RepositoryError> {
+        Ok(self
+            .tab_storage()
+            .read_tab_as_of(self.database(), tab_id, as_of)?)
+    }
+
+    fn tabs(&self) -> Result<Vec<Tab>, RepositoryError> {
+        let storage = self.tab_storage();
+        let database = self.database();
+
+        // tabs which can't be read are skipped (and logged), so that
+        // they don't prevent listing the others.
+        let tabs = storage
+            .read_tab_ids(database)?
+            .into_iter()
+            .filter_map(|id| match storage.read_tab(database, &id) {
+                Ok(Some(tab)) => Some(tab),
+                Ok(None) => {
+                    error!("Skipping tab {}: {}", id, RepositoryError::TabNotFound(id));
+                    None
+                }
+                Err(err) => {
+                    error!("Skipping tab {}: {}", id, err);
+                    None
+                }
+            })
+            .collect();
+        Ok(tabs)
+    }
+
+    fn add_tab(&self, tab: &Tab) -> Result<(), RepositoryError> {
+        let storage = self.tab_storage();
+        self.write(|database, transaction| Ok(storage.add_tab(database, transaction, tab)?))
+    }
+
+    fn update_tab(&self, tab: &Tab) -> Result<(), RepositoryError> {
+        let storage = self.tab_storage();
+        self.write(|database, transaction| {
+            // only the records which differ from the stored tab are
+            // written, like the rows in SqliteRepository::update_tab
+            match storage.read_tab(database, &tab.id)? {
+                Some(stored) => storage.write_tab_changes(database, transaction, &stored, tab)?,
+                None => storage.write_tab(transaction, tab)?,
+            }
+            Ok(())
+        })
+    }
+
+    fn trash_tab(&self, tab_id: &TabID, trashed_at: DateTime<Utc>) -> Result<(), RepositoryError> {
+        let storage = self.tab_storage();
+        self.write(|database, transaction| {
+            if !storage.read_tab_ids(database)?.contains(tab_id) {
+                return Err(RepositoryError::TabNotFound(*tab_id));
+            }
+
+            Ok(storage.trash_tab(database, transaction, tab_id, trashed_at)?)
+        })
+    }
+
+    fn restore_tab(&self, tab_id: &TabID) -> Result<(), RepositoryError> {
+        let storage = self.tab_storage();
+        self.write(|database, transaction| {
+            let trashed = storage.read_trashed_tabs(database)?;
+            if !trashed.iter().any(|trashed_tab| trashed_tab.id == *tab_id) {
+                return Err(RepositoryError::TabNotFound(*tab_id));
+            }
+
+            Ok(storage.restore_tab(database, transaction, tab_id)?)
+        })
+    }
+
+    fn delete_tab(&self, tab_id: &TabID) -> Result<(), RepositoryError> {
+        let storage = self.tab_storage();
+        self.write(|database, transaction| Ok(storage.delete_tab(database, transaction, tab_id)?))
+    }
+
+    fn empty_trash(&self, trashed_before: DateTime<Utc>) -> Result<Vec<TabID>, RepositoryError> {
+        let storage = self.tab_storage();
+        self.write(|database, transaction| {
+            Ok(storage.empty_trash(database, transaction, trashed_before)?)
+        })
+    }
+}
+
+fn account_key(account_id: &AccountID) -> String {
+    format!("accounts/{}", account_id)
+}
+
+fn email_key(email: &str) -> String {
+    format!("emails/{}", email)
+}
+
+fn session_key(token: &str) -> String {
+    format!("sessions/{}", token)
+}
+
+fn members_prefix(tab_id: &TabID) -> String {
+    format!("members/{}/", tab_id)
+}
+
+fn member_key(tab_id: &TabID, user_id: UserID) -> String {
+    format!("{}{}", members_prefix(tab_id), user_id)
+}
+
+fn memberships_prefix(account_id: &AccountID) -> String {
+    format!("memberships/{}/", account_id)
+}
+
+fn membership_key(member: &TabMember) -> String {
+    format!(
+        "{}{}",
+        memberships_prefix(&member.account_id),
+        member.tab_id
+    )
+}
+
+const INVITES_PREFIX: &str = "invites/";
+
+fn invite_key(token: &str) -> String {
+    format!("{}{}", INVITES_PREFIX, token)
+}
+
+fn tab_invites_prefix(tab_id: &TabID) -> String {
+    format!("tab_invites/{}/", tab_id)
+}
+
+fn tab_invite_key(invite: &Invite) -> String {
+    format!("{}{}", tab_invites_prefix(&invite.tab_id), invite.token)
+}
+
+fn share_link_key(token: &str) -> String {
+    format!("share_links/{}", token)
+}
+
+fn tab_share_links_prefix(tab_id: &TabID) -> String {
+    format!("tab_share_links/{}/", tab_id)
+}
+
+fn tab_share_link_key(link: &ShareLink) -> String {
+    format!("{}{}", tab_share_links_prefix(&link.tab_id), link.token)
+}
+
+fn sync_pushes_prefix(tab_id: &TabID) -> String {
+    format!("sync_pushes/{}/", tab_id)
+}
+
+fn sync_push_key(tab_id: &TabID, account_id: &AccountID) -> String {
+    format!("{}{}", sync_pushes_prefix(tab_id), account_id)
+}
+
+/// Read the values of all the keys starting with `prefix` in the
+/// [CosterServerDBStore::Accounts] store of the `database`.
+fn read_prefix<T>(database: &dyn KeyValueDB, prefix: &str) -> Result<Vec<T>, RepositoryError>
+where
+    T: serde::de::DeserializeOwned,
+{
+    database
+        .iter_with_prefix(CosterServerDBStore::Accounts.db_col(), prefix.as_bytes())
+        .map(|(key, value)| {
+            DatabaseValueEncoding::decode(&value).map_err(|error| {
+                DatabaseError::CorruptValue(String::from_utf8_lossy(&key).to_string(), error).into()
+            })
+        })
+        .collect()
+}
+
+impl AccountRepository for KeyValueDBRepository {
+    fn account(&self, account_id: &AccountID) -> Result<Option<Account>, RepositoryError> {
+        Ok(self
+            .database()
+            .get_deserialize(&CosterServerDBStore::Accounts, account_key(account_id))?)
+    }
+
+    fn account_by_email(&self, email: &str) -> Result<Option<Account>, RepositoryError> {
+        let account_id: Option<AccountID> = self
+            .database()
+            .get_deserialize(&CosterServerDBStore::Accounts, email_key(email))?;
+        match account_id {
+            Some(account_id) => self.account(&account_id),
+            None => Ok(None),
+        }
+    }
+
+    fn add_account(&self, account: &Account) -> Result<(), RepositoryError> {
+        let store = CosterServerDBStore::Accounts;
+        self.write(|_database, transaction| {
+            transaction.put_serialize(&store, account_key(&account.id), account)?;
+            if let Some(email) = &account.email {
+                transaction.put_serialize(&store, email_key(email), account.id)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn session(&self, token: &str) -> Result<Option<Session>, RepositoryError> {
+        Ok(self
+            .database()
+            .get_deserialize(&CosterServerDBStore::Accounts, session_key(token))?)
+    }
+
+    fn add_session(&self, session: &Session) -> Result<(), RepositoryError> {
+        self.write(|_database, transaction| {
+            Ok(transaction.put_serialize(
+                &CosterServerDBStore::Accounts,
+                session_key(&session.token),
+                session,
+            )?)
+        })
+    }
+
+    fn delete_session(&self, token: &str) -> Result<(), RepositoryError> {
+        self.write(|_database, transaction| {
+            transaction.delete(
+                CosterServerDBStore::Accounts.db_col(),
+                session_key(token).as_bytes(),
+            );
+            Ok(())
+        })
+    }
+
+    fn tab_members(&self, tab_id: &TabID) -> Result<Vec<TabMember>, RepositoryError> {
+        read_prefix(self.database(), &members_prefix(tab_id))
+    }
+
+    fn account_memberships(
+        &self,
+        account_id: &AccountID,
+    ) -> Result<Vec<TabMember>, RepositoryError> {
+        read_prefix(self.database(), &memberships_prefix(account_id))
+    }
+
+    fn add_tab_member(&self, member: &TabMember) -> Result<(), RepositoryError> {
+        let store = CosterServerDBStore::Accounts;
+        self.write(|_database, transaction| {
+            transaction.put_serialize(
+                &store,
+                member_key(&member.tab_id, member.user_id),
+                member,
+            )?;
+            Ok(transaction.put_serialize(&store, membership_key(member), member)?)
+        })
+    }
+
+    fn remove_tab_member(&self, tab_id: &TabID, user_id: UserID) -> Result<(), RepositoryError> {
+        let col = CosterServerDBStore::Accounts.db_col();
+        self.write(|database, transaction| {
+            let members: Vec<TabMember> = read_prefix(database, &members_prefix(tab_id))?;
+            for member in members.iter().filter(|member| member.user_id == user_id) {
+                transaction.delete(col, member_key(tab_id, user_id).as_bytes());
+                transaction.delete(col, membership_key(member).as_bytes());
+            }
+            Ok(())
+        })
+    }
+
+    fn remove_tab_members(&self, tab_id: &TabID) -> Result<(), RepositoryError> {
+        let col = CosterServerDBStore::Accounts.db_col();
+        self.write(|database, transaction| {
+            let members: Vec<TabMember> = read_prefix(database, &members_prefix(tab_id))?;
+            for member in &members {
+                transaction.delete(col, membership_key(member).as_bytes());
+            }
+            transaction.delete_prefix(col, members_prefix(tab_id).as_bytes());
+            Ok(())
+        })
+    }
+}
+
+impl InviteRepository for KeyValueDBRepository {
+    fn invite(&self, token: &str) -> Result<Option<Invite>, RepositoryError> {
+        Ok(self
+            .database()
+            .get_deserialize(&CosterServerDBStore::Accounts, invite_key(token))?)
+    }
+
+    fn tab_invites(&self, tab_id: &TabID) -> Result<Vec<Invite>, RepositoryError> {
+        read_prefix(self.database(), &tab_invites_prefix(tab_id))
+    }
+
+    fn add_invite(&self, invite: &Invite) -> Result<(), RepositoryError> {
+        let store = CosterServerDBStore::Accounts;
+        self.write(|_database, transaction| {
+            transaction.put_serialize(&store, invite_key(&invite.token), invite)?;
+            Ok(transaction.put_serialize(&store, tab_invite_key(invite), invite)?)
+        })
+    }
+
+    fn delete_invite(&self, token: &str) -> Result<(), RepositoryError> {
+        let store = CosterServerDBStore::Accounts;
+        let col = store.db_col();
+        self.write(|database, transaction| {
+            let invite: Option<Invite> = database.get_deserialize(&store, invite_key(token))?;
+            if let Some(invite) = &invite {
+                transaction.delete(col, invite_key(&invite.token).as_bytes());
+                transaction.delete(col, tab_invite_key(invite).as_bytes());
+            }
+            Ok(())
+        })
+    }
+
+    fn delete_tab_invites(&self, tab_id: &TabID) -> Result<(), RepositoryError> {
+        let col = CosterServerDBStore::Accounts.db_col();
+        self.write(|database, transaction| {
+            let invites: Vec<Invite> = read_prefix(database, &tab_invites_prefix(tab_id))?;
+            for invite in &invites {
+                transaction.delete(col, invite_key(&invite.token).as_bytes());
+            }
+            transaction.delete_prefix(col, tab_invites_prefix(tab_id).as_bytes());
+            Ok(())
+        })
+    }
+
+    fn delete_expired_invites(&self, now: DateTime<Utc>) -> Result<usize, RepositoryError> {
+        let col = CosterServerDBStore::Accounts.db_col();
+        self.write(|database, transaction| {
+            let expired: Vec<Invite> = read_prefix::<Invite>(database, INVITES_PREFIX)?
+                .into_iter()
+                .filter(|invite| invite.is_expired(now))
+                .collect();
+            for invite in &expired {
+                transaction.delete(col, invite_key(&invite.token).as_bytes());
+                transaction.delete(col, tab_invite_key(invite).as_bytes());
+            }
+            Ok(expired.len())
+        })
+    }
+}
+
+impl ShareLinkRepository for KeyValueDBRepository {
+    fn share_link(&self, token: &str) -> Result<Option<ShareLink>, RepositoryError> {
+        Ok(self
+            .database()
+            .get_deserialize(&CosterServerDBStore::Accounts, share_link_key(token))?)
+    }
+
+    fn tab_share_links(&self, tab_id: &TabID) -> Result<Vec<ShareLink>, RepositoryError> {
+        read_prefix(self.database(), &tab_share_links_prefix(tab_id))
+    }
+
+    fn add_share_link(&self, link: &ShareLink) -> Result<(), RepositoryError> {
+        let store = CosterServerDBStore::Accounts;
+        self.write(|_database, transaction| {
+            transaction.put_serialize(&store, share_link_key(&link.token), link)?;
+            Ok(transaction.put_serialize(&store, tab_share_link_key(link), link)?)
+        })
+    }
+
+    fn delete_share_link(&self, token: &str) -> Result<(), RepositoryError> {
+        let store = CosterServerDBStore::Accounts;
+        let col = store.db_col();
+        self.write(|database, transaction| {
+            let link: Option<ShareLink> =
+                database.get_deserialize(&store, share_link_key(token))?;
+            if let Some(link) = &link {
+                transaction.delete(col, share_link_key(&link.token).as_bytes());
+                transaction.delete(col, tab_share_link_key(link).as_bytes());
+            }
+            Ok(())
+        })
+    }
+
+    fn delete_tab_share_links(&self, tab_id: &TabID) -> Result<(), RepositoryError> {
+        let col = CosterServerDBStore::Accounts.db_col();
+        self.write(|database, transaction| {
+            let links: Vec<ShareLink> = read_prefix(database, &tab_share_links_prefix(tab_id))?;
+            for link in &links {
+                transaction.delete(col, share_link_key(&link.token).as_bytes());
+            }
+            transaction.delete_prefix(col, tab_share_links_prefix(tab_id).as_bytes());
+            Ok(())
+        })
+    }
+}
+
+impl SyncRepository for KeyValueDBRepository {
+    fn sync_push(
+        &self,
+        tab_id: &TabID,
+        account_id: &AccountID,
+    ) -> Result<Option<SyncPush>, RepositoryError> {
+        Ok(self.database().get_deserialize(
+            &CosterServerDBStore::Accounts,
+            sync_push_key(tab_id, account_id),
+        )?)
+    }
+
+    fn set_sync_push(&self, push: &SyncPush) -> Result<(), RepositoryError> {
+        let store = CosterServerDBStore::Accounts;
+        self.write(|_database, transaction| {
+            Ok(transaction.put_serialize(
+                &store,
+                sync_push_key(&push.tab_id, &push.account_id),
+                push,
+            )?)
+        })
+    }
+
+    fn delete_tab_sync_pushes(&self, tab_id: &TabID) -> Result<(), RepositoryError> {
+        let col = CosterServerDBStore::Accounts.db_col();
+        self.write(|_database, transaction| {
+            transaction.delete_prefix(col, sync_pushes_prefix(tab_id).as_bytes());
+            Ok(())
+        })
+    }
+}
+
+/// `kvdb-rocksdb` doesn't provide a way to flush or compact the
+/// database on demand, so these do nothing, and the
+/// [CompactStorage](crate::jobs::CompactStorage) job is only run for
+/// SQLite. Each write is appended to RocksDB's write-ahead log before
+/// it completes, so it isn't lost when the server exits, and RocksDB
+/// compacts its files in the background.
+impl StorageRepository for KeyValueDBRepository {
+    fn flush(&self) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+
+    fn compact(&self) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{
+        CosterServerDBStore, KeyValueDBRepository, Repository, RepositoryError, SqliteRepository,
+        TabRepository,
+    };
+    use crate::accounts::{tests::create_test_account, Account, Session, TabMember};
+    use crate::invites::Invite;
+    use crate::shares::ShareLink;
+    use crate::sync::SyncPush;
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use commodity::{Commodity, CommodityType};
+    use costing::db::{DatabaseValueEncoding, KeyValueDBStore};
+    use costing::{Expense, Tab, User};
+    use std::{rc::Rc, str::FromStr};
+    use uuid::Uuid;
+
+    pub(crate) fn create_test_tab() -> Tab {
+        let aud = CommodityType::from_currency_alpha3("AUD").unwrap();
+        let user1 = Rc::new(User::new(1, "User 1", None));
+        let user2 = Rc::new(User::new(2, "User 2", None));
+        let expense = Expense::new(
+            1,
+            "Petrol",
+            "Transport",
+            NaiveDate::from_ymd(2020, 5, 1),
+            1,
+            vec![1, 2],
+            Commodity::from_str("100.0 AUD").unwrap(),
+            None,
+        );
+        Tab::new(
+            Uuid::parse_str("936DA01F9ABD4d9d80C702AF85C822A8").unwrap(),
+            "Road Trip",
+            aud.id,
+            vec![user1, user2],
+            vec![expense],
+        )
+    }
+
+    pub(crate) fn check_add_update_and_delete_tab(repository: &dyn TabRepository) {
+        let mut tab = create_test_tab();
+
+        repository.add_tab(&tab).unwrap();
+        assert_eq!(vec![tab.id], repository.tab_ids().unwrap());
+
+        tab.name = "Holiday".to_string();
+        repository.update_tab(&tab).unwrap();
+        let read_tab = repository.tab(&tab.id).unwrap().unwrap();
+        assert_eq!("Holiday", read_tab.name);
+        assert_eq!(1, read_tab.expenses.len());
+
+        repository
+            .trash_tab(&tab.id, Utc.ymd(2020, 6, 1).and_hms(0, 0, 0))
+            .unwrap();
+        assert!(repository.tabs().unwrap().is_empty());
+        assert!(repository.tab(&tab.id).unwrap().is_some());
+        match repository.trash_tab(&tab.id, Utc.ymd(2020, 6, 2).and_hms(0, 0, 0)) {
+            Err(RepositoryError::TabNotFound(id)) => assert_eq!(tab.id, id),
+            _ => panic!("expected a TabNotFound error"),
+        }
+
+        repository.restore_tab(&tab.id).unwrap();
+        assert_eq!(1, repository.tabs().unwrap().len());
+
+        repository
+            .trash_tab(&tab.id, Utc.ymd(2020, 6, 1).and_hms(0, 0, 0))
+            .unwrap();
+        let emptied = repository
+            .empty_trash(Utc.ymd(2020, 6, 1).and_hms(0, 0, 0))
+            .unwrap();
+        assert!(emptied.is_empty());
+        let emptied = repository
+            .empty_trash(Utc.ymd(2020, 6, 2).and_hms(0, 0, 0))
+            .unwrap();
+        assert_eq!(vec![tab.id], emptied);
+        assert!(repository.tab(&tab.id).unwrap().is_none());
+
+        repository.add_tab(&tab).unwrap();
+        repository.delete_tab(&tab.id).unwrap();
+        assert!(repository.tab_ids().unwrap().is_empty());
+        assert!(repository.tab(&tab.id).unwrap().is_none());
+    }
+
+    pub(crate) fn check_accounts_and_members(repository: &dyn Repository) {
+        let tab = create_test_tab();
+        let account = create_test_account("Alice");
+        repository.add_tab(&tab).unwrap();
+        repository.add_account(&account).unwrap();
+
+        assert_eq!(
+            Some(&account),
+            repository.account(&account.id).unwrap().as_ref()
+        );
+        assert_eq!(
+            Some(&account),
+            repository
+                .account_by_email("alice@example.com")
+                .unwrap()
+                .as_ref()
+        );
+        assert!(repository
+            .account_by_email("bob@example.com")
+            .unwrap()
+            .is_none());
+
+        let session = Session::new(account.id, Utc.ymd(2020, 6, 1).and_hms(0, 0, 0));
+        repository.add_session(&session).unwrap();
+        assert_eq!(
+            Some(&session),
+            repository.session(&session.token).unwrap().as_ref()
+        );
+        repository.delete_session(&session.token).unwrap();
+        assert!(repository.session(&session.token).unwrap().is_none());
+
+        let member = TabMember {
+            tab_id: tab.id,
+            user_id: 2,
+            account_id: account.id,
+        };
+        repository.add_tab_member(&member).unwrap();
+        assert_eq!(vec![member], repository.tab_members(&tab.id).unwrap());
+        assert_eq!(
+            vec![member],
+            repository.account_memberships(&account.id).unwrap()
+        );
+
+        repository.remove_tab_member(&tab.id, 1).unwrap();
+        assert_eq!(1, repository.tab_members(&tab.id).unwrap().len());
+        repository.remove_tab_member(&tab.id, 2).unwrap();
+        assert!(repository.tab_members(&tab.id).unwrap().is_empty());
+        assert!(repository
+            .account_memberships(&account.id)
+            .unwrap()
+            .is_empty());
+
+        repository.add_tab_member(&member).unwrap();
+        repository.remove_tab_members(&tab.id).unwrap();
+        assert!(repository.tab_members(&tab.id).unwrap().is_empty());
+        assert!(repository
+            .account_memberships(&account.id)
+            .unwrap()
+            .is_empty());
+    }
+
+    pub(crate) fn check_invites(repository: &dyn Repository) {
+        let tab = create_test_tab();
+        repository.add_tab(&tab).unwrap();
+
+        // guest accounts have no email address
+        let guest = Account::guest("Carol", Utc.ymd(2020, 6, 1).and_hms(0, 0, 0));
+        repository.add_account(&guest).unwrap();
+        assert_eq!(
+            Some(&guest),
+            repository.account(&guest.id).unwrap().as_ref()
+        );
+        repository
+            .add_account(&Account::guest(
+                "Dave",
+                Utc.ymd(2020, 6, 1).and_hms(0, 0, 0),
+            ))
+            .unwrap();
+
+        let created_at = Utc.ymd(2020, 6, 1).and_hms(0, 0, 0);
+        let invite = Invite::new(tab.id, 1, created_at, None, false).unwrap();
+        let single_use = Invite::new(
+            tab.id,
+            2,
+            created_at,
+            Some(Utc.ymd(2020, 6, 8).and_hms(0, 0, 0)),
+            true,
+        )
+        .unwrap();
+        repository.add_invite(&invite).unwrap();
+        repository.add_invite(&single_use).unwrap();
+
+        assert_eq!(
+            Some(&single_use),
+            repository.invite(&single_use.token).unwrap().as_ref()
+        );
+        assert_eq!(2, repository.tab_invites(&tab.id).unwrap().len());
+
+        repository.delete_invite(&single_use.token).unwrap();
+        assert!(repository.invite(&single_use.token).unwrap().is_none());
+        assert_eq!(
+            vec![invite.clone()],
+            repository.tab_invites(&tab.id).unwrap()
+        );
+
+        repository.delete_tab_invites(&tab.id).unwrap();
+        assert!(repository.invite(&invite.token).unwrap().is_none());
+        assert!(repository.tab_invites(&tab.id).unwrap().is_empty());
+
+        let expiring = Invite::new(
+            tab.id,
+            1,
+            created_at,
+            Some(Utc.ymd(2020, 6, 8).and_hms(0, 0, 0)),
+            false,
+        )
+        .unwrap();
+        repository.add_invite(&invite).unwrap();
+        repository.add_invite(&expiring).unwrap();
+        assert_eq!(
+            0,
+            repository
+                .delete_expired_invites(Utc.ymd(2020, 6, 7).and_hms(23, 59, 59))
+                .unwrap()
+        );
+        assert_eq!(
+            1,
+            repository
+                .delete_expired_invites(Utc.ymd(2020, 6, 8).and_hms(0, 0, 0))
+                .unwrap()
+        );
+        assert!(repository.invite(&expiring.token).unwrap().is_none());
+        assert_eq!(
+            vec![invite.clone()],
+            repository.tab_invites(&tab.id).unwrap()
+        );
+
+        repository.compact().unwrap();
+        repository.flush().unwrap();
+        assert_eq!(
+            Some(&invite),
+            repository.invite(&invite.token).unwrap().as_ref()
+        );
+    }
+
+    pub(crate) fn check_share_links(repository: &dyn Repository) {
+        let tab = create_test_tab();
+        repository.add_tab(&tab).unwrap();
+
+        let created_at = Utc.ymd(2020, 6, 1).and_hms(0, 0, 0);
+        let link = ShareLink::new(tab.id, 1, created_at);
+        let other = ShareLink::new(tab.id, 2, created_at);
+        repository.add_share_link(&link).unwrap();
+        repository.add_share_link(&other).unwrap();
+
+        assert_eq!(
+            Some(&other),
+            repository.share_link(&other.token).unwrap().as_ref()
+        );
+        assert_eq!(2, repository.tab_share_links(&tab.id).unwrap().len());
+
+        repository.delete_share_link(&other.token).unwrap();
+        assert!(repository.share_link(&other.token).unwrap().is_none());
+        assert_eq!(
+            vec![link.clone()],
+            repository.tab_share_links(&tab.id).unwrap()
+        );
+
+        repository.delete_tab_share_links(&tab.id).unwrap();
+        assert!(repository.share_link(&link.token).unwrap().is_none());
+        assert!(repository.tab_share_links(&tab.id).unwrap().is_empty());
+    }
+
+    pub(crate) fn check_sync_pushes(repository: &dyn Repository) {
+        let tab = create_test_tab();
+        let account = create_test_account("Alice");
+        repository.add_tab(&tab).unwrap();
+        repository.add_account(&account).unwrap();
+        assert!(repository
+            .sync_push(&tab.id, &account.id)
+            .unwrap()
+            .is_none());
+
+        let mut push = SyncPush {
+            id: Uuid::new_v4(),
+            tab_id: tab.id,
+            account_id: account.id,
+            rejected: Vec::new(),
+            remapped: Default::default(),
+        };
+        repository.set_sync_push(&push).unwrap();
+        push.id = Uuid::new_v4();
+        push.remapped.expenses.insert(1, 2);
+        repository.set_sync_push(&push).unwrap();
+        assert_eq!(
+            Some(&push),
+            repository.sync_push(&tab.id, &account.id).unwrap().as_ref()
+        );
+
+        repository.delete_tab_sync_pushes(&tab.id).unwrap();
+        assert!(repository
+            .sync_push(&tab.id, &account.id)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn sync_pushes_kvdb() {
+        check_sync_pushes(&KeyValueDBRepository::in_memory());
+    }
+
+    #[test]
+    fn sync_pushes_sqlite() {
+        check_sync_pushes(&SqliteRepository::in_memory().unwrap());
+    }
+
+    #[test]
+    fn share_links_kvdb() {
+        check_share_links(&KeyValueDBRepository::in_memory());
+    }
+
+    #[test]
+    fn share_links_sqlite() {
+        check_share_links(&SqliteRepository::in_memory().unwrap());
+    }
+
+    #[test]
+    fn invites_kvdb() {
+        check_invites(&KeyValueDBRepository::in_memory());
+    }
+
+    #[test]
+    fn invites_sqlite() {
+        check_invites(&SqliteRepository::in_memory().unwrap());
+    }
+
+    #[test]
+    fn accounts_and_members_kvdb() {
+        check_accounts_and_members(&KeyValueDBRepository::in_memory());
+    }
+
+    #[test]
+    fn accounts_and_members_sqlite() {
+        check_accounts_and_members(&SqliteRepository::in_memory().unwrap());
+    }
+
+    #[test]
+    fn add_update_and_delete_tab_kvdb() {
+        check_add_update_and_delete_tab(&KeyValueDBRepository::in_memory());
+    }
+
+    #[test]
+    fn add_update_and_delete_tab_sqlite() {
+        check_add_update_and_delete_tab(&SqliteRepository::in_memory().unwrap());
+    }
+
+    #[test]
+    fn cbor_encoded_tabs() {
+        let repository = KeyValueDBRepository::in_memory();
+        let tab = create_test_tab();
+        repository.add_tab(&tab).unwrap();
+
+        let database = repository.database();
+        let values: Vec<_> = database.iter(CosterServerDBStore::Tabs.db_col()).collect();
+        assert!(!values.is_empty());
+        for (_, value) in &values {
+            assert_eq!(
+                DatabaseValueEncoding::Cbor,
+                DatabaseValueEncoding::detect(value)
+            );
+        }
+
+        let read_tab = repository.tab(&tab.id).unwrap().unwrap();
+        assert_eq!(tab.name, read_tab.name);
+        assert_eq!(tab.working_currency, read_tab.working_currency);
+        assert_eq!(
+            vec![(1, "User 1"), (2, "User 2")],
+            read_tab
+                .users
+                .iter()
+                .map(|user| (user.id, user.name.as_str()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, read_tab.expenses.len());
+        assert_eq!(tab.expenses[0].amount, read_tab.expenses[0].amount);
+        assert_eq!(tab.expenses[0].shared_by, read_tab.expenses[0].shared_by);
+    }
+
+    #[test]
+    fn tabs_skips_corrupt_tab() {
+        let repository = KeyValueDBRepository::in_memory();
+        let corrupt_tab = create_test_tab();
+        let mut tab = create_test_tab();
+        tab.id = Uuid::parse_str("A6F2E6C5B9D24C1B9A4E5B6C7D8E9F01").unwrap();
+        repository.add_tab(&corrupt_tab).unwrap();
+        repository.add_tab(&tab).unwrap();
+
+        let database = repository.database();
+        let mut transaction = database.transaction();
+        transaction.put(
+            CosterServerDBStore::Tabs.db_col(),
+            format!("tabs/{}/header", corrupt_tab.id).as_bytes(),
+            b"{",
+        );
+        database.write(transaction).unwrap();
+
+        let tabs = repository.tabs().unwrap();
+        assert_eq!(
+            vec![tab.id],
+            tabs.iter().map(|tab| tab.id).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn clones_share_database() {
+        let repository = KeyValueDBRepository::in_memory();
+        let tab = create_test_tab();
+
+        repository.clone().add_tab(&tab).unwrap();
+        assert_eq!(vec![tab.id], repository.tab_ids().unwrap());
+    }
+}
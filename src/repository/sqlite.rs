@@ -0,0 +1,1588 @@
+//! A [Repository](super::Repository) which stores tabs in relational tables in a
+//! SQLite database, so that the data can be queried with ad-hoc SQL
+//! reports, and backed up using standard tools.
+//!
+//! The database contains the following tables:
+//!
+//! + `tabs`: one row per tab, `trashed_at` is set for tabs which
+//!   have been moved to the trash.
+//! + `users`: the users of each tab.
+//! + `expenses`: the expenses of each tab. `period_end` is `NULL`
+//!   for expenses in the current period, otherwise it is the
+//!   `end_date` of the closed period which contains the expense.
+//! + `expense_shares`: the users sharing each expense.
+//! + `periods`: the closed periods of each tab.
+//! + `period_balances`: the closing balance of each user at the end
+//!   of each closed period.
+//! + `actions`: the user actions performed on each tab, stored as
+//!   JSON along with the schema version they were written with.
+//! + `accounts`: the accounts which can log in to the server.
+//!   `email` and `password_hash` are `NULL` for guest accounts.
+//! + `sessions`: the logged in sessions of each account.
+//! + `tab_members`: the user that each account is linked to on the
+//!   tabs it is a member of.
+//! + `invites`: the invites to join each tab.
+//! + `share_links`: the links to the read-only summary of each tab.
+//! + `sync_pushes`: the last push of actions to each tab by each
+//!   account, with the rejected actions and reassigned ids stored as
+//!   JSON.
+//!
+//! Amounts are stored as decimal text alongside their currency, to
+//! avoid any loss of precision.
+
+use super::{
+    AccountRepository, InviteRepository, RepositoryError, ShareLinkRepository, StorageRepository,
+    SyncRepository, TabRepository,
+};
+use crate::accounts::{Account, AccountID, Session, TabMember};
+use crate::invites::Invite;
+use crate::shares::ShareLink;
+use crate::sync::SyncPush;
+use chrono::{DateTime, NaiveDate, Utc};
+use commodity::{Commodity, CommodityTypeID};
+use costing::actions::TabUserActionType;
+use costing::db::{DatabaseValueSchema, MigrationError, SchemaVersion};
+use costing::{Expense, ExpenseID, Tab, TabData, TabID, TabPeriod, User, UserID};
+use log::error;
+use rusqlite::{params, Connection, OptionalExtension, Transaction, NO_PARAMS};
+use rust_decimal::Decimal;
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    rc::Rc,
+    str::FromStr,
+    sync::{Mutex, MutexGuard},
+};
+use uuid::Uuid;
+
+/// The version of the table layout, stored in the `user_version` of
+/// the database.
+const SCHEMA_VERSION: SchemaVersion = 5;
+
+/// The statements which upgrade the table layout from the version at
+/// their index to the next version. They are run with foreign key
+/// constraints disabled, so that tables can be rebuilt.
+const MIGRATIONS: [&str; SCHEMA_VERSION as usize] = [
+    CREATE_TABLES,
+    CREATE_ACCOUNT_TABLES,
+    CREATE_INVITE_TABLES,
+    CREATE_SHARE_LINK_TABLES,
+    CREATE_SYNC_PUSH_TABLES,
+];
+
+const CREATE_TABLES: &str = "
+CREATE TABLE tabs (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    working_currency TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    trashed_at TEXT
+);
+
+CREATE TABLE users (
+    tab_id TEXT NOT NULL REFERENCES tabs (id) ON DELETE CASCADE,
+    id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    email TEXT,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (tab_id, id)
+);
+
+CREATE TABLE expenses (
+    row_id INTEGER PRIMARY KEY,
+    tab_id TEXT NOT NULL REFERENCES tabs (id) ON DELETE CASCADE,
+    id INTEGER NOT NULL,
+    description TEXT NOT NULL,
+    category TEXT NOT NULL,
+    date TEXT NOT NULL,
+    paid_by INTEGER NOT NULL,
+    amount TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    exchange_rate TEXT,
+    period_end TEXT
+);
+
+CREATE INDEX expenses_tab_id ON expenses (tab_id, period_end);
+
+CREATE TABLE expense_shares (
+    expense_row_id INTEGER NOT NULL REFERENCES expenses (row_id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (expense_row_id, position)
+);
+
+CREATE TABLE periods (
+    tab_id TEXT NOT NULL REFERENCES tabs (id) ON DELETE CASCADE,
+    start_date TEXT,
+    end_date TEXT NOT NULL,
+    PRIMARY KEY (tab_id, end_date)
+);
+
+CREATE TABLE period_balances (
+    tab_id TEXT NOT NULL,
+    period_end TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    amount TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    PRIMARY KEY (tab_id, period_end, user_id),
+    FOREIGN KEY (tab_id, period_end) REFERENCES periods (tab_id, end_date) ON DELETE CASCADE
+);
+
+CREATE TABLE actions (
+    tab_id TEXT NOT NULL REFERENCES tabs (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    schema_version INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    PRIMARY KEY (tab_id, position)
+);
+";
+
+const CREATE_ACCOUNT_TABLES: &str = "
+CREATE TABLE accounts (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE sessions (
+    token TEXT PRIMARY KEY NOT NULL,
+    account_id TEXT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL
+);
+
+CREATE TABLE tab_members (
+    tab_id TEXT NOT NULL REFERENCES tabs (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL,
+    account_id TEXT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+    PRIMARY KEY (tab_id, user_id),
+    UNIQUE (tab_id, account_id)
+);
+";
+
+/// Guest accounts have no email address or password, so the
+/// `accounts` table is rebuilt to allow them to be `NULL`.
+const CREATE_INVITE_TABLES: &str = "
+CREATE TABLE new_accounts (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    email TEXT UNIQUE,
+    password_hash TEXT,
+    created_at TEXT NOT NULL
+);
+
+INSERT INTO new_accounts (id, name, email, password_hash, created_at)
+SELECT id, name, email, password_hash, created_at FROM accounts;
+
+DROP TABLE accounts;
+ALTER TABLE new_accounts RENAME TO accounts;
+
+CREATE TABLE invites (
+    token TEXT PRIMARY KEY NOT NULL,
+    tab_id TEXT NOT NULL REFERENCES tabs (id) ON DELETE CASCADE,
+    created_by INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT,
+    single_use INTEGER NOT NULL
+);
+
+CREATE INDEX invites_tab_id ON invites (tab_id);
+";
+
+const CREATE_SHARE_LINK_TABLES: &str = "
+CREATE TABLE share_links (
+    token TEXT PRIMARY KEY NOT NULL,
+    tab_id TEXT NOT NULL REFERENCES tabs (id) ON DELETE CASCADE,
+    created_by INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX share_links_tab_id ON share_links (tab_id);
+";
+
+const CREATE_SYNC_PUSH_TABLES: &str = "
+CREATE TABLE sync_pushes (
+    tab_id TEXT NOT NULL REFERENCES tabs (id) ON DELETE CASCADE,
+    account_id TEXT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+    id TEXT NOT NULL,
+    rejected TEXT NOT NULL,
+    remapped TEXT NOT NULL,
+    PRIMARY KEY (tab_id, account_id)
+);
+";
+
+/// A [Repository](super::Repository) stored in a SQLite database.
+pub struct SqliteRepository {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteRepository {
+    /// The name of the database file created by
+    /// [open_dir()](SqliteRepository::open_dir).
+    pub const FILE_NAME: &'static str = "coster.sqlite3";
+
+    /// Open (or create) the SQLite database file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, RepositoryError> {
+        Self::new(Connection::open(path)?)
+    }
+
+    /// Open (or create) the database file named
+    /// [FILE_NAME](SqliteRepository::FILE_NAME) in `data_dir`,
+    /// creating the directory if it doesn't exist.
+    pub fn open_dir<P: AsRef<Path>>(data_dir: P) -> Result<Self, RepositoryError> {
+        let data_dir = data_dir.as_ref();
+        fs::create_dir_all(data_dir)
+            .map_err(|error| RepositoryError::Open(data_dir.to_path_buf(), error))?;
+        Self::open(data_dir.join(Self::FILE_NAME))
+    }
+
+    /// Create a [SqliteRepository] with an empty in-memory database,
+    /// which is not persisted.
+    pub fn in_memory() -> Result<Self, RepositoryError> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    fn new(connection: Connection) -> Result<Self, RepositoryError> {
+        // the constraints can't be changed inside a transaction, and
+        // may be enabled by default
+        connection.execute_batch("PRAGMA foreign_keys = OFF;")?;
+        Self::create_tables(&connection)?;
+        connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+
+        Ok(SqliteRepository {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Create the tables if the database is empty, or upgrade them
+    /// if they were created by an older version, and check that it
+    /// isn't using a newer layout than this version understands.
+    fn create_tables(connection: &Connection) -> Result<(), RepositoryError> {
+        let version: SchemaVersion =
+            connection.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+
+        if version > SCHEMA_VERSION {
+            return Err(MigrationError::NewerVersion(version, SCHEMA_VERSION).into());
+        }
+
+        for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            connection.execute_batch(&format!(
+                "BEGIN; {} PRAGMA user_version = {}; COMMIT;",
+                migration,
+                from + 1
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        // an interrupted transaction is rolled back when it is
+        // dropped, so it is safe to ignore poisoning.
+        self.connection
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Run `f` in a transaction, which is committed if `f` succeeds.
+    fn write<T, F>(&self, f: F) -> Result<T, RepositoryError>
+    where
+        F: FnOnce(&Transaction) -> Result<T, RepositoryError>,
+    {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let value = f(&transaction)?;
+        transaction.commit()?;
+        Ok(value)
+    }
+}
+
+fn invalid_value<E>(value: &str) -> impl FnOnce(E) -> RepositoryError + '_ {
+    move |_| RepositoryError::InvalidValue(value.to_string())
+}
+
+fn parse_tab_id(value: &str) -> Result<TabID, RepositoryError> {
+    Uuid::parse_str(value).map_err(invalid_value(value))
+}
+
+fn parse_account_id(value: &str) -> Result<AccountID, RepositoryError> {
+    Uuid::parse_str(value).map_err(invalid_value(value))
+}
+
+fn parse_currency(value: &str) -> Result<CommodityTypeID, RepositoryError> {
+    CommodityTypeID::from_str(value).map_err(invalid_value(value))
+}
+
+fn parse_commodity(amount: &str, currency: &str) -> Result<Commodity, RepositoryError> {
+    let value = Decimal::from_str(amount).map_err(invalid_value(amount))?;
+    Ok(Commodity::new(value, parse_currency(currency)?))
+}
+
+/// Write a `tab`, only inserting, updating or deleting the rows
+/// which differ from those already stored, so that a small change to
+/// a tab (such as adding an expense) only writes the rows affected by
+/// that change.
+fn write_tab(transaction: &Transaction, tab: &Tab) -> Result<(), RepositoryError> {
+    let tab_id = tab.id.to_string();
+
+    transaction.execute(
+        "INSERT INTO tabs (id, name, working_currency, position)
+         VALUES (?1, ?2, ?3, (SELECT COALESCE(MAX(position), -1) + 1 FROM tabs))
+         ON CONFLICT (id) DO UPDATE
+         SET name = excluded.name, working_currency = excluded.working_currency
+         WHERE name IS NOT excluded.name OR working_currency IS NOT excluded.working_currency",
+        params![tab_id, tab.name, tab.working_currency.to_string()],
+    )?;
+
+    write_users(transaction, &tab_id, &tab.users)?;
+    write_expenses(transaction, &tab_id, tab)?;
+    write_periods(transaction, &tab_id, &tab.closed_periods)?;
+    write_actions(transaction, &tab_id, &tab.user_actions)?;
+
+    Ok(())
+}
+
+fn write_users(
+    transaction: &Transaction,
+    tab_id: &str,
+    users: &[Rc<User>],
+) -> Result<(), RepositoryError> {
+    type Row = (String, Option<String>, i64);
+
+    let mut statement =
+        transaction.prepare("SELECT id, name, email, position FROM users WHERE tab_id = ?1")?;
+    let mut stored = statement
+        .query_map(params![tab_id], |row| {
+            Ok((row.get(0)?, (row.get(1)?, row.get(2)?, row.get(3)?)))
+        })?
+        .collect::<Result<HashMap<UserID, Row>, rusqlite::Error>>()?;
+
+    for (position, user) in users.iter().enumerate() {
+        let row: Row = (user.name.clone(), user.email.clone(), position as i64);
+        if stored.remove(&user.id).as_ref() == Some(&row) {
+            continue;
+        }
+
+        transaction.execute(
+            "INSERT INTO users (tab_id, id, name, email, position) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (tab_id, id) DO UPDATE
+             SET name = excluded.name, email = excluded.email, position = excluded.position",
+            params![tab_id, user.id, row.0, row.1, row.2],
+        )?;
+    }
+
+    for user_id in stored.keys() {
+        transaction.execute(
+            "DELETE FROM users WHERE tab_id = ?1 AND id = ?2",
+            params![tab_id, user_id],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The values stored in the `expenses` and `expense_shares` tables
+/// for an expense.
+#[derive(PartialEq)]
+struct ExpenseValues {
+    description: String,
+    category: String,
+    date: NaiveDate,
+    paid_by: UserID,
+    amount: String,
+    currency: String,
+    exchange_rate: Option<String>,
+    period_end: Option<NaiveDate>,
+    shared_by: Vec<UserID>,
+}
+
+impl ExpenseValues {
+    fn new(expense: &Expense, period_end: Option<NaiveDate>) -> Result<Self, RepositoryError> {
+        let exchange_rate = match &expense.exchange_rate {
+            Some(exchange_rate) => Some(serde_json::to_string(exchange_rate)?),
+            None => None,
+        };
+
+        Ok(ExpenseValues {
+            description: expense.description.clone(),
+            category: expense.category.clone(),
+            date: expense.date,
+            paid_by: expense.paid_by,
+            amount: expense.amount.value.to_string(),
+            currency: expense.amount.type_id.to_string(),
+            exchange_rate,
+            period_end,
+            shared_by: expense.shared_by.clone(),
+        })
+    }
+}
+
+/// Write the expenses of a `tab`, including those in its closed
+/// periods. Expenses are matched to their stored rows by id.
+fn write_expenses(
+    transaction: &Transaction,
+    tab_id: &str,
+    tab: &Tab,
+) -> Result<(), RepositoryError> {
+    let mut stored = read_expense_values(transaction, tab_id)?;
+
+    let expenses = tab.expenses.iter().map(|expense| (expense, None)).chain(
+        tab.closed_periods.iter().flat_map(|period| {
+            period
+                .expenses
+                .iter()
+                .map(move |expense| (expense, Some(period.end)))
+        }),
+    );
+
+    for (expense, period_end) in expenses {
+        let values = ExpenseValues::new(expense, period_end)?;
+
+        match stored.remove(&expense.id) {
+            Some((_, stored_values)) if stored_values == values => {}
+            Some((row_id, _)) => {
+                transaction.execute(
+                    "UPDATE expenses
+                     SET description = ?2, category = ?3, date = ?4, paid_by = ?5, amount = ?6,
+                         currency = ?7, exchange_rate = ?8, period_end = ?9
+                     WHERE row_id = ?1",
+                    params![
+                        row_id,
+                        values.description,
+                        values.category,
+                        values.date,
+                        values.paid_by,
+                        values.amount,
+                        values.currency,
+                        values.exchange_rate,
+                        values.period_end,
+                    ],
+                )?;
+                transaction.execute(
+                    "DELETE FROM expense_shares WHERE expense_row_id = ?1",
+                    params![row_id],
+                )?;
+                write_expense_shares(transaction, row_id, &values.shared_by)?;
+            }
+            None => {
+                transaction.execute(
+                    "INSERT INTO expenses
+                     (tab_id, id, description, category, date, paid_by, amount, currency, exchange_rate, period_end)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                    params![
+                        tab_id,
+                        expense.id,
+                        values.description,
+                        values.category,
+                        values.date,
+                        values.paid_by,
+                        values.amount,
+                        values.currency,
+                        values.exchange_rate,
+                        values.period_end,
+                    ],
+                )?;
+                let row_id = transaction.last_insert_rowid();
+                write_expense_shares(transaction, row_id, &values.shared_by)?;
+            }
+        }
+    }
+
+    // the shares of these are deleted by cascade
+    for (row_id, _) in stored.values() {
+        transaction.execute("DELETE FROM expenses WHERE row_id = ?1", params![row_id])?;
+    }
+
+    Ok(())
+}
+
+/// Read the row id and stored values of each of the expenses of the
+/// tab with the specified id, indexed by the expense id.
+fn read_expense_values(
+    connection: &Connection,
+    tab_id: &str,
+) -> Result<HashMap<ExpenseID, (i64, ExpenseValues)>, RepositoryError> {
+    let mut statement = connection.prepare(
+        "SELECT row_id, id, description, category, date, paid_by, amount, currency, exchange_rate,
+                period_end
+         FROM expenses WHERE tab_id = ?1",
+    )?;
+    let mut expenses = statement
+        .query_map(params![tab_id], |row| {
+            Ok((
+                row.get(1)?,
+                (
+                    row.get(0)?,
+                    ExpenseValues {
+                        description: row.get(2)?,
+                        category: row.get(3)?,
+                        date: row.get(4)?,
+                        paid_by: row.get(5)?,
+                        amount: row.get(6)?,
+                        currency: row.get(7)?,
+                        exchange_rate: row.get(8)?,
+                        period_end: row.get(9)?,
+                        shared_by: Vec::new(),
+                    },
+                ),
+            ))
+        })?
+        .collect::<Result<HashMap<ExpenseID, (i64, ExpenseValues)>, rusqlite::Error>>()?;
+
+    let mut statement = connection.prepare(
+        "SELECT expenses.id, expense_shares.user_id
+         FROM expense_shares JOIN expenses ON expenses.row_id = expense_shares.expense_row_id
+         WHERE expenses.tab_id = ?1
+         ORDER BY expense_shares.expense_row_id, expense_shares.position",
+    )?;
+    let shares = statement
+        .query_map(params![tab_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(ExpenseID, UserID)>, rusqlite::Error>>()?;
+
+    for (expense_id, user_id) in shares {
+        if let Some((_, values)) = expenses.get_mut(&expense_id) {
+            values.shared_by.push(user_id);
+        }
+    }
+
+    Ok(expenses)
+}
+
+fn write_expense_shares(
+    transaction: &Transaction,
+    row_id: i64,
+    shared_by: &[UserID],
+) -> Result<(), RepositoryError> {
+    for (position, user_id) in shared_by.iter().enumerate() {
+        transaction.execute(
+            "INSERT INTO expense_shares (expense_row_id, user_id, position) VALUES (?1, ?2, ?3)",
+            params![row_id, user_id, position as i64],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write the closed `periods` of a tab (without their expenses, which
+/// are written by [write_expenses()]). Periods are matched to their
+/// stored rows by their end date, and are rewritten if their start
+/// date or closing balances have changed.
+fn write_periods(
+    transaction: &Transaction,
+    tab_id: &str,
+    periods: &[TabPeriod],
+) -> Result<(), RepositoryError> {
+    let mut statement =
+        transaction.prepare("SELECT end_date, start_date FROM periods WHERE tab_id = ?1")?;
+    let mut stored = statement
+        .query_map(params![tab_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<HashMap<NaiveDate, Option<NaiveDate>>, rusqlite::Error>>()?;
+
+    for period in periods {
+        if let Some(start) = stored.remove(&period.end) {
+            if start == period.start
+                && read_closing_balances(transaction, tab_id, period.end)?
+                    == period.closing_balances
+            {
+                continue;
+            }
+
+            // the balances are deleted by cascade
+            transaction.execute(
+                "DELETE FROM periods WHERE tab_id = ?1 AND end_date = ?2",
+                params![tab_id, period.end],
+            )?;
+        }
+
+        write_period(transaction, tab_id, period)?;
+    }
+
+    for end in stored.keys() {
+        transaction.execute(
+            "DELETE FROM periods WHERE tab_id = ?1 AND end_date = ?2",
+            params![tab_id, end],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write_period(
+    transaction: &Transaction,
+    tab_id: &str,
+    period: &TabPeriod,
+) -> Result<(), RepositoryError> {
+    transaction.execute(
+        "INSERT INTO periods (tab_id, start_date, end_date) VALUES (?1, ?2, ?3)",
+        params![tab_id, period.start, period.end],
+    )?;
+
+    for (user_id, balance) in &period.closing_balances {
+        transaction.execute(
+            "INSERT INTO period_balances (tab_id, period_end, user_id, amount, currency)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                tab_id,
+                period.end,
+                user_id,
+                balance.value.to_string(),
+                balance.type_id.to_string()
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write the user `actions` of a tab. Actions are only ever appended
+/// to a tab, so usually only the new actions are written.
+fn write_actions(
+    transaction: &Transaction,
+    tab_id: &str,
+    actions: &[TabUserActionType],
+) -> Result<(), RepositoryError> {
+    let mut statement = transaction
+        .prepare("SELECT position, schema_version, action FROM actions WHERE tab_id = ?1")?;
+    let stored = statement
+        .query_map(params![tab_id], |row| {
+            Ok((row.get(0)?, (row.get(1)?, row.get(2)?)))
+        })?
+        .collect::<Result<HashMap<i64, (SchemaVersion, String)>, rusqlite::Error>>()?;
+
+    for (position, action) in actions.iter().enumerate() {
+        let position = position as i64;
+        let row = (
+            TabUserActionType::SCHEMA_VERSION,
+            serde_json::to_string(action)?,
+        );
+        if stored.get(&position) == Some(&row) {
+            continue;
+        }
+
+        transaction.execute(
+            "INSERT INTO actions (tab_id, position, schema_version, action)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (tab_id, position) DO UPDATE
+             SET schema_version = excluded.schema_version, action = excluded.action",
+            params![tab_id, position, row.0, row.1],
+        )?;
+    }
+
+    transaction.execute(
+        "DELETE FROM actions WHERE tab_id = ?1 AND position >= ?2",
+        params![tab_id, actions.len() as i64],
+    )?;
+
+    Ok(())
+}
+
+fn read_tab(connection: &Connection, tab_id: &TabID) -> Result<Option<Tab>, RepositoryError> {
+    let id = tab_id.to_string();
+
+    let header: Option<(String, String)> = connection
+        .query_row(
+            "SELECT name, working_currency FROM tabs WHERE id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    let (name, working_currency) = match header {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+
+    let mut statement = connection
+        .prepare("SELECT id, name, email FROM users WHERE tab_id = ?1 ORDER BY position")?;
+    let users = statement
+        .query_map(params![id], |row| {
+            Ok(Rc::new(User {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                email: row.get(2)?,
+            }))
+        })?
+        .collect::<Result<Vec<Rc<User>>, rusqlite::Error>>()?;
+
+    let expenses = read_expenses(connection, &id, None)?;
+
+    let mut statement = connection
+        .prepare("SELECT start_date, end_date FROM periods WHERE tab_id = ?1 ORDER BY end_date")?;
+    let period_dates = statement
+        .query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(Option<NaiveDate>, NaiveDate)>, rusqlite::Error>>()?;
+
+    let closed_periods = period_dates
+        .into_iter()
+        .map(|(start, end)| {
+            Ok(TabPeriod::new(
+                start,
+                end,
+                read_expenses(connection, &id, Some(end))?,
+                read_closing_balances(connection, &id, end)?,
+            ))
+        })
+        .collect::<Result<Vec<TabPeriod>, RepositoryError>>()?;
+
+    let mut statement = connection.prepare(
+        "SELECT schema_version, action FROM actions WHERE tab_id = ?1 ORDER BY position",
+    )?;
+    let user_actions = statement
+        .query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(SchemaVersion, String)>, rusqlite::Error>>()?
+        .into_iter()
+        .map(|(schema_version, action)| {
+            let value: serde_json::Value = serde_json::from_str(&action)?;
+            Ok(TabUserActionType::from_versioned_json(
+                value,
+                schema_version,
+            )?)
+        })
+        .collect::<Result<Vec<TabUserActionType>, RepositoryError>>()?;
+
+    let tab_data = TabData {
+        id: *tab_id,
+        name,
+        working_currency: parse_currency(&working_currency)?,
+        users,
+        expenses,
+        user_actions,
+        closed_periods,
+    };
+
+    Ok(Some(tab_data.into()))
+}
+
+/// Read the expenses of the tab with the specified id, which are in
+/// the closed period ending on `period_end`, or in the current period
+/// if `period_end` is `None`. The expenses are ordered by their id.
+fn read_expenses(
+    connection: &Connection,
+    tab_id: &str,
+    period_end: Option<NaiveDate>,
+) -> Result<Vec<Expense>, RepositoryError> {
+    struct ExpenseRow {
+        row_id: i64,
+        id: i32,
+        description: String,
+        category: String,
+        date: NaiveDate,
+        paid_by: UserID,
+        amount: String,
+        currency: String,
+        exchange_rate: Option<String>,
+    }
+
+    let mut statement = connection.prepare(
+        "SELECT row_id, id, description, category, date, paid_by, amount, currency, exchange_rate
+         FROM expenses WHERE tab_id = ?1 AND period_end IS ?2 ORDER BY id",
+    )?;
+    let rows = statement
+        .query_map(params![tab_id, period_end], |row| {
+            Ok(ExpenseRow {
+                row_id: row.get(0)?,
+                id: row.get(1)?,
+                description: row.get(2)?,
+                category: row.get(3)?,
+                date: row.get(4)?,
+                paid_by: row.get(5)?,
+                amount: row.get(6)?,
+                currency: row.get(7)?,
+                exchange_rate: row.get(8)?,
+            })
+        })?
+        .collect::<Result<Vec<ExpenseRow>, rusqlite::Error>>()?;
+
+    let mut shares_statement = connection.prepare(
+        "SELECT user_id FROM expense_shares WHERE expense_row_id = ?1 ORDER BY position",
+    )?;
+
+    rows.into_iter()
+        .map(|row| {
+            let shared_by = shares_statement
+                .query_map(params![row.row_id], |share_row| share_row.get(0))?
+                .collect::<Result<Vec<UserID>, rusqlite::Error>>()?;
+
+            let exchange_rate = match &row.exchange_rate {
+                Some(exchange_rate) => Some(serde_json::from_str(exchange_rate)?),
+                None => None,
+            };
+
+            Ok(Expense {
+                id: row.id,
+                description: row.description,
+                category: row.category,
+                date: row.date,
+                paid_by: row.paid_by,
+                shared_by,
+                amount: parse_commodity(&row.amount, &row.currency)?,
+                exchange_rate,
+            })
+        })
+        .collect()
+}
+
+fn read_closing_balances(
+    connection: &Connection,
+    tab_id: &str,
+    period_end: NaiveDate,
+) -> Result<HashMap<UserID, Commodity>, RepositoryError> {
+    let mut statement = connection.prepare(
+        "SELECT user_id, amount, currency FROM period_balances
+         WHERE tab_id = ?1 AND period_end = ?2",
+    )?;
+    let rows = statement
+        .query_map(params![tab_id, period_end], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .collect::<Result<Vec<(UserID, String, String)>, rusqlite::Error>>()?;
+
+    rows.into_iter()
+        .map(|(user_id, amount, currency)| Ok((user_id, parse_commodity(&amount, &currency)?)))
+        .collect()
+}
+
+impl TabRepository for SqliteRepository {
+    fn tab_ids(&self) -> Result<Vec<TabID>, RepositoryError> {
+        let connection = self.connection();
+        let mut statement =
+            connection.prepare("SELECT id FROM tabs WHERE trashed_at IS NULL ORDER BY position")?;
+        let ids = statement
+            .query_map(NO_PARAMS, |row| row.get(0))?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+        ids.iter().map(|id| parse_tab_id(id)).collect()
+    }
+
+    fn tab(&self, tab_id: &TabID) -> Result<Option<Tab>, RepositoryError> {
+        read_tab(&self.connection(), tab_id)
+    }
+
+    fn tabs(&self) -> Result<Vec<Tab>, RepositoryError> {
+        let connection = self.connection();
+        let mut statement =
+            connection.prepare("SELECT id FROM tabs WHERE trashed_at IS NULL ORDER BY position")?;
+        let ids = statement
+            .query_map(NO_PARAMS, |row| row.get(0))?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+        // tabs which can't be read are skipped (and logged), so that
+        // they don't prevent listing the others.
+        let tabs = ids
+            .iter()
+            .filter_map(|id| {
+                let tab = parse_tab_id(id).and_then(|tab_id| {
+                    read_tab(&connection, &tab_id)?.ok_or(RepositoryError::TabNotFound(tab_id))
+                });
+                match tab {
+                    Ok(tab) => Some(tab),
+                    Err(err) => {
+                        error!("Skipping tab {}: {}", id, err);
+                        None
+                    }
+                }
+            })
+            .collect();
+        Ok(tabs)
+    }
+
+    fn add_tab(&self, tab: &Tab) -> Result<(), RepositoryError> {
+        self.write(|transaction| write_tab(transaction, tab))
+    }
+
+    fn update_tab(&self, tab: &Tab) -> Result<(), RepositoryError> {
+        self.write(|transaction| write_tab(transaction, tab))
+    }
+
+    fn trash_tab(&self, tab_id: &TabID, trashed_at: DateTime<Utc>) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            let updated = transaction.execute(
+                "UPDATE tabs SET trashed_at = ?2 WHERE id = ?1 AND trashed_at IS NULL",
+                params![tab_id.to_string(), trashed_at],
+            )?;
+
+            if updated == 0 {
+                return Err(RepositoryError::TabNotFound(*tab_id));
+            }
+            Ok(())
+        })
+    }
+
+    fn restore_tab(&self, tab_id: &TabID) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            // restored tabs are moved to the end, the same as when
+            // they are first added
+            let updated = transaction.execute(
+                "UPDATE tabs
+                 SET trashed_at = NULL, position = (SELECT MAX(position) + 1 FROM tabs)
+                 WHERE id = ?1 AND trashed_at IS NOT NULL",
+                params![tab_id.to_string()],
+            )?;
+
+            if updated == 0 {
+                return Err(RepositoryError::TabNotFound(*tab_id));
+            }
+            Ok(())
+        })
+    }
+
+    fn delete_tab(&self, tab_id: &TabID) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            transaction.execute(
+                "DELETE FROM tabs WHERE id = ?1",
+                params![tab_id.to_string()],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn empty_trash(&self, trashed_before: DateTime<Utc>) -> Result<Vec<TabID>, RepositoryError> {
+        self.write(|transaction| {
+            let ids = {
+                let mut statement = transaction.prepare(
+                    "SELECT id FROM tabs WHERE trashed_at IS NOT NULL AND trashed_at < ?1",
+                )?;
+                let ids = statement
+                    .query_map(params![trashed_before], |row| row.get(0))?
+                    .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+                ids
+            };
+
+            transaction.execute(
+                "DELETE FROM tabs WHERE trashed_at IS NOT NULL AND trashed_at < ?1",
+                params![trashed_before],
+            )?;
+            ids.iter().map(|id| parse_tab_id(id)).collect()
+        })
+    }
+}
+
+fn read_account(
+    connection: &Connection,
+    condition: &str,
+    value: &str,
+) -> Result<Option<Account>, RepositoryError> {
+    type Row = (
+        String,
+        String,
+        Option<String>,
+        Option<String>,
+        DateTime<Utc>,
+    );
+    let row: Option<Row> = connection
+        .query_row(
+            &format!(
+                "SELECT id, name, email, password_hash, created_at FROM accounts WHERE {} = ?1",
+                condition
+            ),
+            params![value],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            },
+        )
+        .optional()?;
+
+    row.map(|(id, name, email, password_hash, created_at)| {
+        Ok(Account {
+            id: parse_account_id(&id)?,
+            name,
+            email,
+            password_hash,
+            created_at,
+        })
+    })
+    .transpose()
+}
+
+fn read_members(
+    connection: &Connection,
+    condition: &str,
+    value: &str,
+) -> Result<Vec<TabMember>, RepositoryError> {
+    let mut statement = connection.prepare(&format!(
+        "SELECT tab_id, user_id, account_id FROM tab_members WHERE {} = ?1 ORDER BY tab_id, user_id",
+        condition
+    ))?;
+    let rows = statement
+        .query_map(params![value], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .collect::<Result<Vec<(String, UserID, String)>, rusqlite::Error>>()?;
+
+    rows.iter()
+        .map(|(tab_id, user_id, account_id)| {
+            Ok(TabMember {
+                tab_id: parse_tab_id(tab_id)?,
+                user_id: *user_id,
+                account_id: parse_account_id(account_id)?,
+            })
+        })
+        .collect()
+}
+
+impl AccountRepository for SqliteRepository {
+    fn account(&self, account_id: &AccountID) -> Result<Option<Account>, RepositoryError> {
+        read_account(&self.connection(), "id", &account_id.to_string())
+    }
+
+    fn account_by_email(&self, email: &str) -> Result<Option<Account>, RepositoryError> {
+        read_account(&self.connection(), "email", email)
+    }
+
+    fn add_account(&self, account: &Account) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            transaction.execute(
+                "INSERT INTO accounts (id, name, email, password_hash, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    account.id.to_string(),
+                    account.name,
+                    account.email,
+                    account.password_hash,
+                    account.created_at
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn session(&self, token: &str) -> Result<Option<Session>, RepositoryError> {
+        let row: Option<(String, DateTime<Utc>, DateTime<Utc>)> = self
+            .connection()
+            .query_row(
+                "SELECT account_id, created_at, expires_at FROM sessions WHERE token = ?1",
+                params![token],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+
+        row.map(|(account_id, created_at, expires_at)| {
+            Ok(Session {
+                token: token.to_string(),
+                account_id: parse_account_id(&account_id)?,
+                created_at,
+                expires_at,
+            })
+        })
+        .transpose()
+    }
+
+    fn add_session(&self, session: &Session) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            transaction.execute(
+                "INSERT INTO sessions (token, account_id, created_at, expires_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    session.token,
+                    session.account_id.to_string(),
+                    session.created_at,
+                    session.expires_at
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn delete_session(&self, token: &str) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            transaction.execute("DELETE FROM sessions WHERE token = ?1", params![token])?;
+            Ok(())
+        })
+    }
+
+    fn tab_members(&self, tab_id: &TabID) -> Result<Vec<TabMember>, RepositoryError> {
+        read_members(&self.connection(), "tab_id", &tab_id.to_string())
+    }
+
+    fn account_memberships(
+        &self,
+        account_id: &AccountID,
+    ) -> Result<Vec<TabMember>, RepositoryError> {
+        read_members(&self.connection(), "account_id", &account_id.to_string())
+    }
+
+    fn add_tab_member(&self, member: &TabMember) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            transaction.execute(
+                "INSERT INTO tab_members (tab_id, user_id, account_id) VALUES (?1, ?2, ?3)",
+                params![
+                    member.tab_id.to_string(),
+                    member.user_id,
+                    member.account_id.to_string()
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn remove_tab_member(&self, tab_id: &TabID, user_id: UserID) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            transaction.execute(
+                "DELETE FROM tab_members WHERE tab_id = ?1 AND user_id = ?2",
+                params![tab_id.to_string(), user_id],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn remove_tab_members(&self, tab_id: &TabID) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            transaction.execute(
+                "DELETE FROM tab_members WHERE tab_id = ?1",
+                params![tab_id.to_string()],
+            )?;
+            Ok(())
+        })
+    }
+}
+
+fn read_invites(
+    connection: &Connection,
+    condition: &str,
+    value: &str,
+) -> Result<Vec<Invite>, RepositoryError> {
+    type Row = (
+        String,
+        String,
+        UserID,
+        DateTime<Utc>,
+        Option<DateTime<Utc>>,
+        bool,
+    );
+    let mut statement = connection.prepare(&format!(
+        "SELECT token, tab_id, created_by, created_at, expires_at, single_use
+         FROM invites WHERE {} = ?1 ORDER BY created_at, token",
+        condition
+    ))?;
+    let rows = statement
+        .query_map(params![value], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+            ))
+        })?
+        .collect::<Result<Vec<Row>, rusqlite::Error>>()?;
+
+    rows.into_iter()
+        .map(
+            |(token, tab_id, created_by, created_at, expires_at, single_use)| {
+                Ok(Invite {
+                    token,
+                    tab_id: parse_tab_id(&tab_id)?,
+                    created_by,
+                    created_at,
+                    expires_at,
+                    single_use,
+                })
+            },
+        )
+        .collect()
+}
+
+impl InviteRepository for SqliteRepository {
+    fn invite(&self, token: &str) -> Result<Option<Invite>, RepositoryError> {
+        Ok(read_invites(&self.connection(), "token", token)?
+            .into_iter()
+            .next())
+    }
+
+    fn tab_invites(&self, tab_id: &TabID) -> Result<Vec<Invite>, RepositoryError> {
+        read_invites(&self.connection(), "tab_id", &tab_id.to_string())
+    }
+
+    fn add_invite(&self, invite: &Invite) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            transaction.execute(
+                "INSERT INTO invites (token, tab_id, created_by, created_at, expires_at, single_use)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    invite.token,
+                    invite.tab_id.to_string(),
+                    invite.created_by,
+                    invite.created_at,
+                    invite.expires_at,
+                    invite.single_use
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn delete_invite(&self, token: &str) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            transaction.execute("DELETE FROM invites WHERE token = ?1", params![token])?;
+            Ok(())
+        })
+    }
+
+    fn delete_tab_invites(&self, tab_id: &TabID) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            transaction.execute(
+                "DELETE FROM invites WHERE tab_id = ?1",
+                params![tab_id.to_string()],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn delete_expired_invites(&self, now: DateTime<Utc>) -> Result<usize, RepositoryError> {
+        // the times are all stored in UTC, so they can be compared as
+        // text
+        self.write(|transaction| {
+            Ok(transaction.execute(
+                "DELETE FROM invites WHERE expires_at IS NOT NULL AND expires_at <= ?1",
+                params![now],
+            )?)
+        })
+    }
+}
+
+fn read_share_links(
+    connection: &Connection,
+    condition: &str,
+    value: &str,
+) -> Result<Vec<ShareLink>, RepositoryError> {
+    let mut statement = connection.prepare(&format!(
+        "SELECT token, tab_id, created_by, created_at
+         FROM share_links WHERE {} = ?1 ORDER BY created_at, token",
+        condition
+    ))?;
+    let rows = statement
+        .query_map(params![value], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?
+        .collect::<Result<Vec<(String, String, UserID, DateTime<Utc>)>, rusqlite::Error>>()?;
+
+    rows.into_iter()
+        .map(|(token, tab_id, created_by, created_at)| {
+            Ok(ShareLink {
+                token,
+                tab_id: parse_tab_id(&tab_id)?,
+                created_by,
+                created_at,
+            })
+        })
+        .collect()
+}
+
+impl ShareLinkRepository for SqliteRepository {
+    fn share_link(&self, token: &str) -> Result<Option<ShareLink>, RepositoryError> {
+        Ok(read_share_links(&self.connection(), "token", token)?
+            .into_iter()
+            .next())
+    }
+
+    fn tab_share_links(&self, tab_id: &TabID) -> Result<Vec<ShareLink>, RepositoryError> {
+        read_share_links(&self.connection(), "tab_id", &tab_id.to_string())
+    }
+
+    fn add_share_link(&self, link: &ShareLink) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            transaction.execute(
+                "INSERT INTO share_links (token, tab_id, created_by, created_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    link.token,
+                    link.tab_id.to_string(),
+                    link.created_by,
+                    link.created_at
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn delete_share_link(&self, token: &str) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            transaction.execute("DELETE FROM share_links WHERE token = ?1", params![token])?;
+            Ok(())
+        })
+    }
+
+    fn delete_tab_share_links(&self, tab_id: &TabID) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            transaction.execute(
+                "DELETE FROM share_links WHERE tab_id = ?1",
+                params![tab_id.to_string()],
+            )?;
+            Ok(())
+        })
+    }
+}
+
+impl SyncRepository for SqliteRepository {
+    fn sync_push(
+        &self,
+        tab_id: &TabID,
+        account_id: &AccountID,
+    ) -> Result<Option<SyncPush>, RepositoryError> {
+        let row: Option<(String, String, String)> = self
+            .connection()
+            .query_row(
+                "SELECT id, rejected, remapped FROM sync_pushes
+                 WHERE tab_id = ?1 AND account_id = ?2",
+                params![tab_id.to_string(), account_id.to_string()],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+
+        row.map(|(id, rejected, remapped)| {
+            Ok(SyncPush {
+                id: Uuid::parse_str(&id).map_err(invalid_value(&id))?,
+                tab_id: *tab_id,
+                account_id: *account_id,
+                rejected: serde_json::from_str(&rejected)?,
+                remapped: serde_json::from_str(&remapped)?,
+            })
+        })
+        .transpose()
+    }
+
+    fn set_sync_push(&self, push: &SyncPush) -> Result<(), RepositoryError> {
+        let rejected = serde_json::to_string(&push.rejected)?;
+        let remapped = serde_json::to_string(&push.remapped)?;
+        self.write(|transaction| {
+            transaction.execute(
+                "INSERT OR REPLACE INTO sync_pushes (tab_id, account_id, id, rejected, remapped)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    push.tab_id.to_string(),
+                    push.account_id.to_string(),
+                    push.id.to_string(),
+                    rejected,
+                    remapped
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn delete_tab_sync_pushes(&self, tab_id: &TabID) -> Result<(), RepositoryError> {
+        self.write(|transaction| {
+            transaction.execute(
+                "DELETE FROM sync_pushes WHERE tab_id = ?1",
+                params![tab_id.to_string()],
+            )?;
+            Ok(())
+        })
+    }
+}
+
+impl StorageRepository for SqliteRepository {
+    fn flush(&self) -> Result<(), RepositoryError> {
+        // committed transactions are already durable, but if the
+        // database has been switched to WAL mode, the log is copied
+        // back into the database file so that it can be backed up on
+        // its own
+        self.connection()
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", NO_PARAMS, |_| Ok(()))?;
+        Ok(())
+    }
+
+    fn compact(&self) -> Result<(), RepositoryError> {
+        self.connection().execute_batch("VACUUM;")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SqliteRepository, CREATE_ACCOUNT_TABLES, CREATE_TABLES, SCHEMA_VERSION};
+    use crate::accounts::Account;
+    use crate::repository::{tests::create_test_tab, AccountRepository, TabRepository};
+    use chrono::{NaiveDate, Utc};
+    use commodity::Commodity;
+    use costing::actions::{AddUser, TabUserActionType};
+    use costing::db::SchemaVersion;
+    use costing::{Expense, User};
+    use rusqlite::Connection;
+    use rusqlite::{params, NO_PARAMS};
+    use std::str::FromStr;
+    use uuid::Uuid;
+
+    #[test]
+    fn round_trip_closed_periods_and_actions() {
+        let repository = SqliteRepository::in_memory().unwrap();
+        let mut tab = create_test_tab();
+        tab.close_period(NaiveDate::from_ymd(2020, 5, 31)).unwrap();
+        tab.user_actions
+            .push(TabUserActionType::AddUser(AddUser::new(
+                1,
+                User::new(3, "User 3", Some("user3@example.com")),
+            )));
+
+        repository.add_tab(&tab).unwrap();
+        let read_tab = repository.tab(&tab.id).unwrap().unwrap();
+
+        assert!(read_tab.expenses.is_empty());
+        assert_eq!(1, read_tab.closed_periods.len());
+        let period = &read_tab.closed_periods[0];
+        assert_eq!(NaiveDate::from_ymd(2020, 5, 31), period.end);
+        assert_eq!(1, period.expenses.len());
+        assert_eq!(vec![1, 2], period.expenses[0].shared_by);
+        assert_eq!(
+            tab.closed_periods[0].closing_balances,
+            period.closing_balances
+        );
+        assert_eq!(1, read_tab.user_actions.len());
+    }
+
+    #[test]
+    fn migrate_from_version_1() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(&format!("{} PRAGMA user_version = 1;", CREATE_TABLES))
+            .unwrap();
+        let repository = SqliteRepository::new(connection).unwrap();
+        repository.add_tab(&create_test_tab()).unwrap();
+
+        let version: SchemaVersion = repository
+            .connection()
+            .query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(SCHEMA_VERSION, version);
+        assert!(repository
+            .account_by_email("alice@example.com")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn migrate_from_version_2() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(&format!(
+                "{} {}
+                 INSERT INTO tabs (id, name, working_currency, position)
+                 VALUES ('{tab}', 'Road Trip', 'AUD', 0);
+                 INSERT INTO accounts (id, name, email, password_hash, created_at)
+                 VALUES ('{account}', 'Alice', 'alice@example.com', 'hash', '2020-06-01T00:00:00Z');
+                 INSERT INTO sessions (token, account_id, created_at, expires_at)
+                 VALUES ('token', '{account}', '2020-06-01T00:00:00Z', '2020-07-01T00:00:00Z');
+                 INSERT INTO tab_members (tab_id, user_id, account_id)
+                 VALUES ('{tab}', 1, '{account}');
+                 PRAGMA user_version = 2;",
+                CREATE_TABLES,
+                CREATE_ACCOUNT_TABLES,
+                tab = create_test_tab().id,
+                account = Uuid::nil(),
+            ))
+            .unwrap();
+        let repository = SqliteRepository::new(connection).unwrap();
+
+        let account = repository
+            .account_by_email("alice@example.com")
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some("hash"), account.password_hash.as_deref());
+        assert!(repository.session("token").unwrap().is_some());
+        assert_eq!(
+            1,
+            repository.tab_members(&create_test_tab().id).unwrap().len()
+        );
+
+        // the constraints referencing the rebuilt table still work
+        repository
+            .add_account(&Account::guest("Bob", Utc::now()))
+            .unwrap();
+        repository.delete_tab(&create_test_tab().id).unwrap();
+        assert!(repository
+            .account_memberships(&Uuid::nil())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn update_tab_writes_changed_rows() {
+        let repository = SqliteRepository::in_memory().unwrap();
+        let mut tab = create_test_tab();
+        repository.add_tab(&tab).unwrap();
+
+        let expense_row_id = |repository: &SqliteRepository, id: i32| -> i64 {
+            repository
+                .connection()
+                .query_row(
+                    "SELECT row_id FROM expenses WHERE id = ?1",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        let total_changes = |repository: &SqliteRepository| -> i64 {
+            repository
+                .connection()
+                .query_row("SELECT total_changes()", NO_PARAMS, |row| row.get(0))
+                .unwrap()
+        };
+        let row_id = expense_row_id(&repository, 1);
+
+        // only the new expense and its share are written
+        tab.expenses.push(Expense::new(
+            2,
+            "Food",
+            "Groceries",
+            NaiveDate::from_ymd(2020, 5, 2),
+            2,
+            vec![1],
+            Commodity::from_str("50.0 AUD").unwrap(),
+            None,
+        ));
+        let changes = total_changes(&repository);
+        repository.update_tab(&tab).unwrap();
+        assert_eq!(2, total_changes(&repository) - changes);
+        assert_eq!(row_id, expense_row_id(&repository, 1));
+
+        tab.expenses[0].shared_by = vec![2];
+        tab.expenses.remove(1);
+        repository.update_tab(&tab).unwrap();
+        assert_eq!(row_id, expense_row_id(&repository, 1));
+
+        let read_tab = repository.tab(&tab.id).unwrap().unwrap();
+        assert_eq!(1, read_tab.expenses.len());
+        assert_eq!(vec![2], read_tab.expenses[0].shared_by);
+
+        // expenses keep their rows when their period is closed
+        tab.close_period(NaiveDate::from_ymd(2020, 5, 31)).unwrap();
+        repository.update_tab(&tab).unwrap();
+        assert_eq!(row_id, expense_row_id(&repository, 1));
+
+        let read_tab = repository.tab(&tab.id).unwrap().unwrap();
+        assert!(read_tab.expenses.is_empty());
+        assert_eq!(1, read_tab.closed_periods.len());
+        assert_eq!(1, read_tab.closed_periods[0].expenses.len());
+        assert_eq!(
+            tab.closed_periods[0].closing_balances,
+            read_tab.closed_periods[0].closing_balances
+        );
+    }
+
+    #[test]
+    fn tables_can_be_queried() {
+        let repository = SqliteRepository::in_memory().unwrap();
+        repository.add_tab(&create_test_tab()).unwrap();
+
+        let connection = repository.connection();
+        let total: String = connection
+            .query_row(
+                "SELECT tabs.name || ': ' || expenses.amount || ' ' || expenses.currency
+                 FROM expenses JOIN tabs ON tabs.id = expenses.tab_id",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!("Road Trip: 100.0 AUD", total);
+    }
+}
@@ -0,0 +1,113 @@
+//! Identifiers for the requests handled by the server, which are
+//! returned to clients in the `X-Request-Id` header and the bodies of
+//! error responses, and included in the server's logs, so that errors
+//! reported by clients can be correlated with the logged causes.
+
+use std::fmt::{self, Display};
+use std::future::Future;
+use uuid::Uuid;
+use warp::http::HeaderValue;
+
+/// The header which contains the id of a request. A client (or a
+/// reverse proxy) may specify the id itself, otherwise one is
+/// generated by the server.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The longest request id which is accepted from a client.
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: RequestId;
+}
+
+/// The id of a request handled by the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Generate a new random id.
+    pub fn generate() -> Self {
+        RequestId(Uuid::new_v4().to_string())
+    }
+
+    /// Use the id specified by a client in the [REQUEST_ID_HEADER],
+    /// if it is a reasonable length and consists of only letters,
+    /// digits and `-`, `_` or `.`.
+    pub fn from_header(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?;
+        let valid = !value.is_empty()
+            && value.len() <= MAX_LENGTH
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+        if valid {
+            Some(RequestId(value.to_string()))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.0).expect("request ids are valid header values")
+    }
+
+    /// The id of the request which is being handled by the current
+    /// task, if it was run using [RequestId::scope].
+    pub fn current() -> Option<RequestId> {
+        REQUEST_ID.try_with(|id| id.clone()).ok()
+    }
+
+    /// Run the `future`, which handles the request with this id.
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        REQUEST_ID.scope(self, future).await
+    }
+
+    /// Call `f`, which handles the request with this id, on a thread
+    /// outside of the runtime (e.g. using
+    /// [spawn_blocking](tokio::task::spawn_blocking)).
+    pub fn scope_blocking<T, F: FnOnce() -> T>(self, f: F) -> T {
+        futures::executor::block_on(self.scope(async move { f() }))
+    }
+}
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestId;
+    use warp::http::HeaderValue;
+
+    #[tokio::test]
+    async fn request_ids() {
+        assert_eq!(None, RequestId::current());
+        let id = RequestId::generate();
+        let current = id.clone().scope(async { RequestId::current() }).await;
+        assert_eq!(Some(id.clone()), current);
+        let blocking_id = id.clone();
+        let current =
+            tokio::task::spawn_blocking(move || blocking_id.scope_blocking(RequestId::current))
+                .await
+                .unwrap();
+        assert_eq!(Some(id), current);
+
+        assert_eq!(
+            Some("proxy-1.a_b"),
+            RequestId::from_header(&HeaderValue::from_static("proxy-1.a_b"))
+                .as_ref()
+                .map(RequestId::as_str)
+        );
+        assert_eq!(
+            None,
+            RequestId::from_header(&HeaderValue::from_static("a b"))
+        );
+        assert_eq!(None, RequestId::from_header(&HeaderValue::from_static("")));
+    }
+}
@@ -0,0 +1,1711 @@
+//! A JSON REST API for the tabs, served under `/api/v1` alongside the
+//! GraphQL API. Request and response bodies use the serde
+//! representations of the [costing] types, and every error is
+//! returned as an [ErrorResponse]. An OpenAPI description of the API
+//! is served at `/api/v1/openapi.json`.
+//!
+//! Apart from creating an account, logging in, and reading or
+//! redeeming an invite, requests need to be authenticated with a
+//! session token, sent either in the
+//! [SESSION_COOKIE](crate::accounts::SESSION_COOKIE), or as a
+//! `Bearer` token in the `Authorization` header. Requests which
+//! modify a tab are performed on behalf of the tab's user which the
+//! account is linked to.
+
+use crate::accounts::{session_cookie, session_token, Account, AuthError, Session, TabMember};
+use crate::error::{log_error, ErrorBody, ErrorCode};
+use crate::invites::{Invite, Redemption};
+use crate::request_id::RequestId;
+use crate::service::{parse_currency, ServiceError, TabService};
+use crate::shares::ShareLink;
+use crate::sync::Synchronisation;
+use chrono::{DateTime, NaiveDate, Utc};
+use costing::actions::{
+    AddExpense, AddUser, EditExpense, IdRemapping, RecordSettlement, RemoveExpense, RemoveUser,
+    TabUserActionType,
+};
+use costing::{Expense, ExpenseID, Settlement, Tab, TabData, TabID, User, UserID};
+use log::error;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+use thiserror::Error;
+use uuid::Uuid;
+use warp::{
+    filters::body::BodyDeserializeError,
+    filters::BoxedFilter,
+    http::{header, StatusCode},
+    reject::{self, Reject},
+    reply::{self, Response},
+    Filter, Rejection, Reply,
+};
+
+mod openapi;
+
+pub use openapi::{openapi, ApiSchema};
+
+/// The body of a request to create a new tab.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewTab {
+    /// The name of the tab.
+    pub name: String,
+    /// The ISO 4217 code of the currency that balances are calculated
+    /// in.
+    pub working_currency: String,
+    /// The users of the tab, which are given ids in order, starting
+    /// from `0`. The account creating the tab is linked to the first
+    /// user.
+    pub users: Vec<NewUser>,
+}
+
+/// The body of a request to add a new user to a tab.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewUser {
+    /// The name of the user.
+    pub name: String,
+    /// The email address of the user.
+    pub email: Option<String>,
+}
+
+impl NewUser {
+    fn to_user(&self, id: UserID) -> User {
+        User::new(id, &self.name, self.email.as_deref())
+    }
+}
+
+/// The body of a request to record a payment between two users.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewSettlement {
+    /// The date the payment was made.
+    pub date: NaiveDate,
+    /// The payment which was made.
+    #[serde(flatten)]
+    pub settlement: Settlement,
+}
+
+/// The body of a request to create a new account.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewAccount {
+    /// The name displayed for the account.
+    pub name: String,
+    /// The email address used to log in.
+    pub email: String,
+    /// The password used to log in.
+    pub password: String,
+}
+
+/// The body of a request to log in.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Credentials {
+    pub email: String,
+    pub password: String,
+}
+
+/// The public details of an [Account].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccountData {
+    pub id: Uuid,
+    pub name: String,
+    /// The email address used to log in, or `None` for a guest
+    /// account.
+    pub email: Option<String>,
+    /// The tabs that the account is a member of.
+    pub memberships: Vec<TabMember>,
+}
+
+/// The body of the response to a request which logs in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionData {
+    /// The token which authenticates requests as the account. It is
+    /// also stored in a cookie.
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub account: AccountData,
+}
+
+/// The body of a request to link an account to one of the users of a
+/// tab, making it a member of the tab.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewMember {
+    /// The id of the user on the tab.
+    pub user_id: UserID,
+    /// The email address of the account.
+    pub email: String,
+}
+
+/// The body of a request to create an invite to a tab.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NewInvite {
+    /// When the invite can no longer be redeemed, or `None` if it
+    /// doesn't expire.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Whether the invite can only be redeemed once.
+    #[serde(default)]
+    pub single_use: bool,
+}
+
+/// An [Invite], along with the path of the page in the GUI which
+/// redeems it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InviteData {
+    #[serde(flatten)]
+    pub invite: Invite,
+    pub path: String,
+}
+
+impl From<Invite> for InviteData {
+    fn from(invite: Invite) -> Self {
+        InviteData {
+            path: invite.path(),
+            invite,
+        }
+    }
+}
+
+/// A [ShareLink], along with the path of the page which shows the
+/// summary of the tab.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ShareLinkData {
+    #[serde(flatten)]
+    pub link: ShareLink,
+    pub path: String,
+}
+
+impl From<ShareLink> for ShareLinkData {
+    fn from(link: ShareLink) -> Self {
+        ShareLinkData {
+            path: link.path(),
+            link,
+        }
+    }
+}
+
+/// The details of an invite which can be read by anyone who has its
+/// token.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InvitePreview {
+    pub tab_id: TabID,
+    /// The name of the tab which the invite is to.
+    pub tab_name: String,
+    /// The name of the user who created the invite.
+    pub invited_by: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// The body of the response to a request which redeems an invite.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RedemptionData {
+    /// The tab, including the new user.
+    pub tab: TabData,
+    /// The link between the account and its new user on the tab.
+    pub member: TabMember,
+    /// The session of the guest account which was created if the
+    /// request was not authenticated. The token is also stored in a
+    /// cookie.
+    pub session: Option<SessionData>,
+}
+
+/// The body of a request to synchronise a tab with a client, see the
+/// [sync](crate::sync) module.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SyncRequest {
+    /// An id chosen by the client for this push of `actions`, which
+    /// it sends again if it retries the push, so that the actions are
+    /// not merged twice.
+    #[serde(default)]
+    pub push_id: Option<Uuid>,
+    /// The number of the tab's actions which the client has already
+    /// received.
+    #[serde(default)]
+    pub cursor: usize,
+    /// The actions which the client has recorded since it last
+    /// synchronised the tab.
+    #[serde(default)]
+    pub actions: Vec<TabUserActionType>,
+    /// The [TabData] of the tab, if it was created by the client and
+    /// hasn't been synchronised before. The account is linked to its
+    /// first user. It is parsed once the request is handled, because
+    /// [TabData] can't be sent between threads.
+    pub tab: Option<serde_json::Value>,
+}
+
+/// An action pushed by a client which couldn't be merged into the
+/// tab.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RejectedAction {
+    /// The index of the action in the request's `actions`.
+    pub index: usize,
+    pub error: ErrorBody,
+}
+
+/// The body of the response to a request which synchronises a tab.
+#[derive(Serialize, Debug, Clone)]
+pub struct SyncResponse {
+    /// The cursor to send the next time the tab is synchronised.
+    pub cursor: usize,
+    /// The actions after the request's cursor, including those which
+    /// were merged from the request.
+    pub actions: Vec<TabUserActionType>,
+    pub rejected: Vec<RejectedAction>,
+    /// The ids of the expenses and users added by the request's
+    /// actions which were reassigned, because they were already used.
+    pub remapped: IdRemapping,
+    /// The tab, which replaces the client's copy of it.
+    pub tab: TabData,
+}
+
+impl From<Synchronisation> for SyncResponse {
+    fn from(sync: Synchronisation) -> Self {
+        SyncResponse {
+            cursor: sync.cursor,
+            actions: sync.actions,
+            rejected: sync
+                .rejected
+                .into_iter()
+                .map(|(index, error)| RejectedAction { index, error })
+                .collect(),
+            remapped: sync.remapped,
+            tab: TabData::from_tab(&sync.tab),
+        }
+    }
+}
+
+/// The body of every response for a request which failed.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+    /// The id of the request, which is also returned in the
+    /// `X-Request-Id` header, and identifies it in the server's logs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// An error caused by a request which could not be routed to one of
+/// the API's operations.
+#[derive(Error, Debug)]
+pub enum RequestError {
+    #[error("the requested resource does not exist")]
+    NotFound,
+    #[error("the request method is not supported by the requested resource")]
+    MethodNotAllowed,
+    #[error("the request body is invalid: {0}")]
+    InvalidBody(String),
+    #[error("the request body must be JSON")]
+    UnsupportedMediaType,
+    #[error("the length of the request body needs to be specified using Content-Length")]
+    LengthRequired,
+    #[error("the request body is larger than the limit")]
+    BodyTooLarge,
+    #[error("the GraphQL request is invalid: {0}")]
+    InvalidGraphQLRequest(String),
+    #[error("the request query string is invalid")]
+    InvalidQuery,
+    #[error("the request could not be handled")]
+    Unhandled,
+}
+
+impl ErrorCode for RequestError {
+    fn code(&self) -> &'static str {
+        match self {
+            RequestError::NotFound => "NOT_FOUND",
+            RequestError::MethodNotAllowed => "METHOD_NOT_ALLOWED",
+            RequestError::InvalidBody(_) => "INVALID_BODY",
+            RequestError::UnsupportedMediaType => "UNSUPPORTED_MEDIA_TYPE",
+            RequestError::LengthRequired => "LENGTH_REQUIRED",
+            RequestError::BodyTooLarge => "BODY_TOO_LARGE",
+            RequestError::InvalidGraphQLRequest(_) => "INVALID_GRAPHQL_REQUEST",
+            RequestError::InvalidQuery => "INVALID_QUERY",
+            RequestError::Unhandled => "INTERNAL",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            RequestError::NotFound => StatusCode::NOT_FOUND,
+            RequestError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            RequestError::InvalidBody(_)
+            | RequestError::InvalidQuery
+            | RequestError::InvalidGraphQLRequest(_) => StatusCode::BAD_REQUEST,
+            RequestError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            RequestError::LengthRequired => StatusCode::LENGTH_REQUIRED,
+            RequestError::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            RequestError::Unhandled => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// A [ServiceError] which caused a filter to reject a request.
+#[derive(Debug)]
+struct ServiceRejection(ServiceError);
+
+impl Reject for ServiceRejection {}
+
+/// The query parameters of a request for the state of a tab at a
+/// given date.
+#[derive(Deserialize, Debug)]
+struct AsOf {
+    /// Defaults to today.
+    as_of: Option<NaiveDate>,
+}
+
+impl AsOf {
+    fn date(&self) -> NaiveDate {
+        self.as_of.unwrap_or_else(|| Utc::today().naive_utc())
+    }
+}
+
+/// A response with the `error` described as an [ErrorResponse], which
+/// is logged along with the id of the request being handled.
+pub fn error_response<E: ErrorCode>(error: &E) -> Response {
+    let body = ErrorBody::new(error);
+    log_error(error, &body);
+
+    reply::with_status(
+        reply::json(&ErrorResponse {
+            error: body,
+            request_id: RequestId::current().map(|id| id.to_string()),
+        }),
+        error.status(),
+    )
+    .into_response()
+}
+
+fn json_response<T: Serialize>(result: Result<T, ServiceError>, status: StatusCode) -> Response {
+    match result {
+        Ok(value) => reply::with_status(reply::json(&value), status).into_response(),
+        Err(error) => error_response(&error),
+    }
+}
+
+fn empty_response(result: Result<(), ServiceError>) -> Response {
+    match result {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(error) => error_response(&error),
+    }
+}
+
+fn account_data(service: &TabService, account: &Account) -> Result<AccountData, ServiceError> {
+    Ok(AccountData {
+        id: account.id,
+        name: account.name.clone(),
+        email: account.email.clone(),
+        memberships: service.memberships(account)?,
+    })
+}
+
+/// A response with the [SessionData] for a new `session`, which also
+/// stores the session's token in a cookie (see [session_cookie]).
+fn session_response(
+    service: &TabService,
+    result: Result<(Account, Session), ServiceError>,
+    secure_cookies: bool,
+) -> Response {
+    let result = result.and_then(|(account, session)| {
+        Ok(SessionData {
+            account: account_data(service, &account)?,
+            token: session.token.clone(),
+            expires_at: session.expires_at,
+        })
+        .map(|data| (data, session))
+    });
+
+    match result {
+        Ok((data, session)) => reply::with_header(
+            reply::with_status(reply::json(&data), StatusCode::CREATED),
+            header::SET_COOKIE,
+            session_cookie(Some(&session), secure_cookies),
+        )
+        .into_response(),
+        Err(error) => error_response(&error),
+    }
+}
+
+fn redemption_response(
+    service: &TabService,
+    result: Result<Redemption, ServiceError>,
+    secure_cookies: bool,
+) -> Response {
+    let result = result.and_then(|redemption| {
+        let session = match &redemption.session {
+            Some(session) => Some(SessionData {
+                account: account_data(service, &redemption.account)?,
+                token: session.token.clone(),
+                expires_at: session.expires_at,
+            }),
+            None => None,
+        };
+        let data = RedemptionData {
+            tab: TabData::from_tab(&redemption.tab),
+            member: redemption.member,
+            session,
+        };
+        Ok((data, redemption.session))
+    });
+
+    match result {
+        Ok((data, Some(session))) => reply::with_header(
+            reply::with_status(reply::json(&data), StatusCode::CREATED),
+            header::SET_COOKIE,
+            session_cookie(Some(&session), secure_cookies),
+        )
+        .into_response(),
+        Ok((data, None)) => {
+            reply::with_status(reply::json(&data), StatusCode::CREATED).into_response()
+        }
+        Err(error) => error_response(&error),
+    }
+}
+
+/// Respond to a request using `f`, which is called with the
+/// [TabService] on a thread where blocking is allowed.
+async fn respond<F>(service: TabService, f: F) -> Result<Response, Rejection>
+where
+    F: FnOnce(&TabService) -> Response + Send + 'static,
+{
+    Ok(service.blocking(f).await)
+}
+
+/// Extracts the JSON request body, which needs to have a
+/// `Content-Length` of at most `max_body_size` bytes, because the
+/// body of an HTTP/2 request can be streamed without one.
+fn json_body<T>(max_body_size: u64) -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+where
+    T: DeserializeOwned + Send,
+{
+    warp::body::content_length_limit(max_body_size).and(warp::body::json())
+}
+
+/// Extracts the account which is logged in to the session that the
+/// request is authenticated with (or `None` if it isn't
+/// authenticated, or the session is invalid), along with the
+/// [TabService].
+fn optional_account(
+    service: TabService,
+) -> impl Filter<Extract = (Option<Account>, TabService), Error = Rejection> + Clone {
+    session_token()
+        .and(warp::any().map(move || service.clone()))
+        .and_then(|token: Option<String>, service: TabService| async move {
+            let account = match token {
+                Some(token) => {
+                    service
+                        .blocking(move |service| service.authenticate(&token).ok())
+                        .await
+                }
+                None => None,
+            };
+            Ok::<_, Rejection>((account, service))
+        })
+        .untuple_one()
+}
+
+/// Extracts the account which is logged in to the session that the
+/// request is authenticated with, along with the [TabService].
+fn authenticated(
+    service: TabService,
+) -> impl Filter<Extract = (Account, TabService), Error = Rejection> + Clone {
+    session_token()
+        .and(warp::any().map(move || service.clone()))
+        .and_then(|token: Option<String>, service: TabService| async move {
+            let account = match token {
+                Some(token) => {
+                    service
+                        .blocking(move |service| service.authenticate(&token))
+                        .await
+                }
+                None => Err(AuthError::NotAuthenticated.into()),
+            };
+            account
+                .map(|account| (account, service))
+                .map_err(|error| reject::custom(ServiceRejection(error)))
+        })
+        .untuple_one()
+}
+
+/// Respond to a request which was rejected by the routes with an
+/// [ErrorResponse] describing why.
+pub async fn handle_rejection(rejection: Rejection) -> Result<Response, Rejection> {
+    if let Some(ServiceRejection(error)) = rejection.find() {
+        return Ok(error_response(error));
+    }
+
+    let error = if rejection.is_not_found() {
+        RequestError::NotFound
+    } else if let Some(error) = rejection.find::<BodyDeserializeError>() {
+        RequestError::InvalidBody(error.to_string())
+    } else if rejection.find::<reject::InvalidQuery>().is_some() {
+        RequestError::InvalidQuery
+    } else if rejection.find::<reject::UnsupportedMediaType>().is_some() {
+        RequestError::UnsupportedMediaType
+    } else if rejection.find::<reject::LengthRequired>().is_some() {
+        RequestError::LengthRequired
+    } else if rejection.find::<reject::PayloadTooLarge>().is_some() {
+        RequestError::BodyTooLarge
+    } else if rejection.find::<reject::MethodNotAllowed>().is_some() {
+        RequestError::MethodNotAllowed
+    } else {
+        let request_id = RequestId::current();
+        error!(
+            "request_id={} unhandled rejection: {:?}",
+            request_id.as_ref().map_or("-", RequestId::as_str),
+            rejection
+        );
+        RequestError::Unhandled
+    };
+
+    Ok(error_response(&error))
+}
+
+/// The routes of the REST API, which need to be mounted under `/api`.
+/// Request bodies can be at most `max_body_size` bytes long. Session
+/// cookies are marked `Secure` if `secure_cookies` is set.
+pub fn routes(
+    service: TabService,
+    max_body_size: u64,
+    secure_cookies: bool,
+) -> BoxedFilter<(Response,)> {
+    let authenticated = authenticated(service.clone());
+    let optional_account = optional_account(service.clone());
+    let service = warp::any().map(move || service.clone());
+
+    let register = warp::path!("accounts")
+        .and(warp::post())
+        .and(json_body(max_body_size))
+        .and(service.clone())
+        .and_then(move |new: NewAccount, service: TabService| {
+            respond(service, move |service| {
+                let session = service.register(&new.name, &new.email, &new.password);
+                session_response(service, session, secure_cookies)
+            })
+        });
+
+    let current_account = warp::path!("accounts" / "current")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and_then(|account: Account, service: TabService| {
+            respond(service, move |service| {
+                json_response(account_data(service, &account), StatusCode::OK)
+            })
+        });
+
+    let login = warp::path!("sessions")
+        .and(warp::post())
+        .and(json_body(max_body_size))
+        .and(service.clone())
+        .and_then(move |credentials: Credentials, service: TabService| {
+            respond(service, move |service| {
+                let session = service.login(&credentials.email, &credentials.password);
+                session_response(service, session, secure_cookies)
+            })
+        });
+
+    let logout = warp::path!("sessions" / "current")
+        .and(warp::delete())
+        .and(session_token())
+        .and(service.clone())
+        .and_then(move |token: Option<String>, service: TabService| {
+            respond(service, move |service| {
+                let result = match token {
+                    Some(token) => service.logout(&token),
+                    None => Ok(()),
+                };
+                match result {
+                    Ok(()) => reply::with_header(
+                        StatusCode::NO_CONTENT,
+                        header::SET_COOKIE,
+                        session_cookie(None, secure_cookies),
+                    )
+                    .into_response(),
+                    Err(error) => error_response(&error),
+                }
+            })
+        });
+
+    let list_tabs = warp::path!("tabs")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and_then(|account: Account, service: TabService| {
+            respond(service, move |service| {
+                let tabs = service
+                    .tabs(&account)
+                    .map(|tabs| tabs.iter().map(TabData::from_tab).collect::<Vec<_>>());
+                json_response(tabs, StatusCode::OK)
+            })
+        });
+
+    let create_tab = warp::path!("tabs")
+        .and(warp::post())
+        .and(authenticated.clone())
+        .and(json_body(max_body_size))
+        .and_then(|account: Account, service: TabService, new_tab: NewTab| {
+            respond(service, move |service| {
+                json_response(create(service, &account, new_tab), StatusCode::CREATED)
+            })
+        });
+
+    let get_tab = warp::path!("tabs" / TabID)
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and_then(|tab_id: TabID, account: Account, service: TabService| {
+            respond(service, move |service| {
+                let tab = service
+                    .tab(&account, &tab_id)
+                    .map(|tab| TabData::from_tab(&tab));
+                json_response(tab, StatusCode::OK)
+            })
+        });
+
+    let trash_tab = warp::path!("tabs" / TabID)
+        .and(warp::delete())
+        .and(authenticated.clone())
+        .and_then(|tab_id: TabID, account: Account, service: TabService| {
+            respond(service, move |service| {
+                empty_response(service.trash_tab(&account, &tab_id, Utc::now()))
+            })
+        });
+
+    let restore_tab = warp::path!("tabs" / TabID / "restore")
+        .and(warp::post())
+        .and(authenticated.clone())
+        .and_then(|tab_id: TabID, account: Account, service: TabService| {
+            respond(service, move |service| {
+                let tab = service
+                    .restore_tab(&account, &tab_id)
+                    .map(|tab| TabData::from_tab(&tab));
+                json_response(tab, StatusCode::OK)
+            })
+        });
+
+    let delete_tab = warp::path!("trash" / TabID)
+        .and(warp::delete())
+        .and(authenticated.clone())
+        .and_then(|tab_id: TabID, account: Account, service: TabService| {
+            respond(service, move |service| {
+                empty_response(service.delete_tab(&account, &tab_id))
+            })
+        });
+
+    let list_members = warp::path!("tabs" / TabID / "members")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and_then(|tab_id: TabID, account: Account, service: TabService| {
+            respond(service, move |service| {
+                json_response(service.tab_members(&account, &tab_id), StatusCode::OK)
+            })
+        });
+
+    let add_member = warp::path!("tabs" / TabID / "members")
+        .and(warp::post())
+        .and(authenticated.clone())
+        .and(json_body(max_body_size))
+        .and_then(
+            |tab_id: TabID, account: Account, service: TabService, new: NewMember| {
+                respond(service, move |service| {
+                    let member = service.link_user(&account, &tab_id, new.user_id, &new.email);
+                    json_response(member, StatusCode::CREATED)
+                })
+            },
+        );
+
+    let list_invites = warp::path!("tabs" / TabID / "invites")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and_then(|tab_id: TabID, account: Account, service: TabService| {
+            respond(service, move |service| {
+                let invites = service.tab_invites(&account, &tab_id).map(|invites| {
+                    invites
+                        .into_iter()
+                        .map(InviteData::from)
+                        .collect::<Vec<_>>()
+                });
+                json_response(invites, StatusCode::OK)
+            })
+        });
+
+    let create_invite = warp::path!("tabs" / TabID / "invites")
+        .and(warp::post())
+        .and(authenticated.clone())
+        .and(json_body(max_body_size))
+        .and_then(
+            |tab_id: TabID, account: Account, service: TabService, new: NewInvite| {
+                respond(service, move |service| {
+                    let invite = service
+                        .create_invite(&account, &tab_id, new.expires_at, new.single_use)
+                        .map(InviteData::from);
+                    json_response(invite, StatusCode::CREATED)
+                })
+            },
+        );
+
+    let revoke_invite = warp::path!("tabs" / TabID / "invites" / String)
+        .and(warp::delete())
+        .and(authenticated.clone())
+        .and_then(
+            |tab_id: TabID, token: String, account: Account, service: TabService| {
+                respond(service, move |service| {
+                    empty_response(service.revoke_invite(&account, &tab_id, &token))
+                })
+            },
+        );
+
+    let list_share_links = warp::path!("tabs" / TabID / "share-links")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and_then(|tab_id: TabID, account: Account, service: TabService| {
+            respond(service, move |service| {
+                let links = service.tab_share_links(&account, &tab_id).map(|links| {
+                    links
+                        .into_iter()
+                        .map(ShareLinkData::from)
+                        .collect::<Vec<_>>()
+                });
+                json_response(links, StatusCode::OK)
+            })
+        });
+
+    let create_share_link = warp::path!("tabs" / TabID / "share-links")
+        .and(warp::post())
+        .and(authenticated.clone())
+        .and_then(|tab_id: TabID, account: Account, service: TabService| {
+            respond(service, move |service| {
+                let link = service
+                    .create_share_link(&account, &tab_id)
+                    .map(ShareLinkData::from);
+                json_response(link, StatusCode::CREATED)
+            })
+        });
+
+    let revoke_share_link = warp::path!("tabs" / TabID / "share-links" / String)
+        .and(warp::delete())
+        .and(authenticated.clone())
+        .and_then(
+            |tab_id: TabID, token: String, account: Account, service: TabService| {
+                respond(service, move |service| {
+                    empty_response(service.revoke_share_link(&account, &tab_id, &token))
+                })
+            },
+        );
+
+    let get_invite = warp::path!("invites" / String)
+        .and(warp::get())
+        .and(service)
+        .and_then(|token: String, service: TabService| {
+            respond(service, move |service| {
+                let preview = service.invite(&token).map(|(invite, tab)| InvitePreview {
+                    tab_id: tab.id,
+                    tab_name: tab.name.clone(),
+                    invited_by: tab
+                        .user(&invite.created_by)
+                        .ok()
+                        .map(|user| user.name.clone()),
+                    expires_at: invite.expires_at,
+                });
+                json_response(preview, StatusCode::OK)
+            })
+        });
+
+    let redeem_invite = warp::path!("invites" / String / "redeem")
+        .and(warp::post())
+        .and(optional_account)
+        .and(json_body(max_body_size))
+        .and_then(
+            move |token: String,
+                  account: Option<Account>,
+                  service: TabService,
+                  new_user: NewUser| {
+                respond(service, move |service| {
+                    let redemption = service.redeem_invite(
+                        account.as_ref(),
+                        &token,
+                        &new_user.name,
+                        new_user.email.as_deref(),
+                    );
+                    redemption_response(service, redemption, secure_cookies)
+                })
+            },
+        );
+
+    let list_users = warp::path!("tabs" / TabID / "users")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and_then(|tab_id: TabID, account: Account, service: TabService| {
+            respond(service, move |service| {
+                json_response(
+                    service.tab(&account, &tab_id).map(|tab| tab.users),
+                    StatusCode::OK,
+                )
+            })
+        });
+
+    let add_user = warp::path!("tabs" / TabID / "users")
+        .and(warp::post())
+        .and(authenticated.clone())
+        .and(json_body(max_body_size))
+        .and_then(
+            |tab_id: TabID, account: Account, service: TabService, new_user: NewUser| {
+                respond(service, move |service| {
+                    let tab = service.perform(&account, &tab_id, |tab, acting_user_id| {
+                        let user = new_user.to_user(tab.next_user_id());
+                        Ok(TabUserActionType::AddUser(AddUser::new(
+                            acting_user_id,
+                            user,
+                        )))
+                    });
+                    json_response(tab.map(last_user), StatusCode::CREATED)
+                })
+            },
+        );
+
+    let remove_user = warp::path!("tabs" / TabID / "users" / UserID)
+        .and(warp::delete())
+        .and(authenticated.clone())
+        .and_then(
+            |tab_id: TabID, user_id: UserID, account: Account, service: TabService| {
+                respond(service, move |service| {
+                    let tab = service.perform(&account, &tab_id, |_, acting_user_id| {
+                        Ok(TabUserActionType::RemoveUser(RemoveUser::new(
+                            acting_user_id,
+                            user_id,
+                        )))
+                    });
+                    empty_response(tab.map(|_| ()))
+                })
+            },
+        );
+
+    let list_expenses = warp::path!("tabs" / TabID / "expenses")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and_then(|tab_id: TabID, account: Account, service: TabService| {
+            respond(service, move |service| {
+                json_response(
+                    service.tab(&account, &tab_id).map(|tab| tab.expenses),
+                    StatusCode::OK,
+                )
+            })
+        });
+
+    let add_expense = warp::path!("tabs" / TabID / "expenses")
+        .and(warp::post())
+        .and(authenticated.clone())
+        .and(json_body(max_body_size))
+        .and_then(
+            |tab_id: TabID, account: Account, service: TabService, expense: Expense| {
+                respond(service, move |service| {
+                    let tab = service.perform(&account, &tab_id, |_, acting_user_id| {
+                        Ok(TabUserActionType::AddExpense(AddExpense::new(
+                            acting_user_id,
+                            expense,
+                        )))
+                    });
+                    json_response(tab.map(last_expense), StatusCode::CREATED)
+                })
+            },
+        );
+
+    let edit_expense = warp::path!("tabs" / TabID / "expenses" / ExpenseID)
+        .and(warp::put())
+        .and(authenticated.clone())
+        .and(json_body(max_body_size))
+        .and_then(
+            |tab_id: TabID,
+             expense_id: ExpenseID,
+             account: Account,
+             service: TabService,
+             mut expense: Expense| {
+                respond(service, move |service| {
+                    // the id in the path takes precedence over the body
+                    expense.id = expense_id;
+                    let tab = service.perform(&account, &tab_id, |_, acting_user_id| {
+                        Ok(TabUserActionType::EditExpense(EditExpense::new(
+                            acting_user_id,
+                            expense,
+                        )))
+                    });
+                    let expense = tab.map(|tab| {
+                        tab.expenses
+                            .into_iter()
+                            .find(|expense| expense.id == expense_id)
+                    });
+                    json_response(expense, StatusCode::OK)
+                })
+            },
+        );
+
+    let remove_expense = warp::path!("tabs" / TabID / "expenses" / ExpenseID)
+        .and(warp::delete())
+        .and(authenticated.clone())
+        .and_then(
+            |tab_id: TabID, expense_id: ExpenseID, account: Account, service: TabService| {
+                respond(service, move |service| {
+                    let tab = service.perform(&account, &tab_id, |_, acting_user_id| {
+                        Ok(TabUserActionType::RemoveExpense(RemoveExpense::new(
+                            acting_user_id,
+                            expense_id,
+                        )))
+                    });
+                    empty_response(tab.map(|_| ()))
+                })
+            },
+        );
+
+    let list_settlements = warp::path!("tabs" / TabID / "settlements")
+        .and(warp::get())
+        .and(warp::query::<AsOf>())
+        .and(authenticated.clone())
+        .and_then(
+            |tab_id: TabID, as_of: AsOf, account: Account, service: TabService| {
+                respond(service, move |service| {
+                    let as_of = as_of.date();
+                    let settlements = service.tab_as_of(&account, &tab_id, as_of).and_then(|tab| {
+                        tab.balance_transactions(as_of).map_err(ServiceError::from)
+                    });
+                    json_response(settlements, StatusCode::OK)
+                })
+            },
+        );
+
+    let record_settlement = warp::path!("tabs" / TabID / "settlements")
+        .and(warp::post())
+        .and(authenticated.clone())
+        .and(json_body(max_body_size))
+        .and_then(
+            |tab_id: TabID, account: Account, service: TabService, new: NewSettlement| {
+                respond(service, move |service| {
+                    let tab = service.perform(&account, &tab_id, |tab, acting_user_id| {
+                        Ok(TabUserActionType::RecordSettlement(RecordSettlement::new(
+                            acting_user_id,
+                            tab.next_expense_id(),
+                            new.date,
+                            new.settlement,
+                        )))
+                    });
+                    json_response(tab.map(last_expense), StatusCode::CREATED)
+                })
+            },
+        );
+
+    let list_actions = warp::path!("tabs" / TabID / "actions")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and_then(|tab_id: TabID, account: Account, service: TabService| {
+            respond(service, move |service| {
+                json_response(
+                    service.tab(&account, &tab_id).map(|tab| tab.user_actions),
+                    StatusCode::OK,
+                )
+            })
+        });
+
+    let perform_action = warp::path!("tabs" / TabID / "actions")
+        .and(warp::post())
+        .and(authenticated.clone())
+        .and(json_body(max_body_size))
+        .and_then(
+            |tab_id: TabID, account: Account, service: TabService, action: TabUserActionType| {
+                respond(service, move |service| {
+                    let tab = service.perform(&account, &tab_id, |_, _| Ok(action));
+                    json_response(tab.map(|tab| TabData::from_tab(&tab)), StatusCode::CREATED)
+                })
+            },
+        );
+
+    let sync_tab = warp::path!("tabs" / TabID / "sync")
+        .and(warp::post())
+        .and(authenticated)
+        .and(json_body(max_body_size))
+        .and_then(
+            |tab_id: TabID, account: Account, service: TabService, request: SyncRequest| {
+                respond(service, move |service| {
+                    let tab = match request
+                        .tab
+                        .map(serde_json::from_value::<TabData>)
+                        .transpose()
+                    {
+                        Ok(tab) => tab.map(Tab::from),
+                        Err(error) => {
+                            return error_response(&RequestError::InvalidBody(error.to_string()))
+                        }
+                    };
+                    let sync = service.sync(
+                        &account,
+                        &tab_id,
+                        request.push_id,
+                        request.cursor,
+                        request.actions,
+                        tab,
+                    );
+                    json_response(sync.map(SyncResponse::from), StatusCode::OK)
+                })
+            },
+        );
+
+    let openapi = warp::path!("openapi.json")
+        .and(warp::get())
+        .map(|| reply::json(&openapi()).into_response());
+
+    // each route is boxed so that the type of the combined filter
+    // stays small enough to compile quickly
+    let routes = vec![
+        register.boxed(),
+        current_account.boxed(),
+        login.boxed(),
+        logout.boxed(),
+        list_tabs.boxed(),
+        create_tab.boxed(),
+        get_tab.boxed(),
+        trash_tab.boxed(),
+        restore_tab.boxed(),
+        delete_tab.boxed(),
+        list_members.boxed(),
+        add_member.boxed(),
+        list_invites.boxed(),
+        create_invite.boxed(),
+        revoke_invite.boxed(),
+        list_share_links.boxed(),
+        create_share_link.boxed(),
+        revoke_share_link.boxed(),
+        get_invite.boxed(),
+        redeem_invite.boxed(),
+        list_users.boxed(),
+        add_user.boxed(),
+        remove_user.boxed(),
+        list_expenses.boxed(),
+        add_expense.boxed(),
+        edit_expense.boxed(),
+        remove_expense.boxed(),
+        list_settlements.boxed(),
+        record_settlement.boxed(),
+        list_actions.boxed(),
+        perform_action.boxed(),
+        sync_tab.boxed(),
+        openapi.boxed(),
+    ]
+    .into_iter()
+    .fold(None, |combined: Option<BoxedFilter<(Response,)>>, route| {
+        Some(match combined {
+            Some(combined) => combined.or(route).unify().boxed(),
+            None => route,
+        })
+    })
+    .expect("there is at least one route");
+
+    warp::path("v1")
+        .and(routes.recover(handle_rejection).unify())
+        .boxed()
+}
+
+fn create(
+    service: &TabService,
+    account: &Account,
+    new_tab: NewTab,
+) -> Result<TabData, ServiceError> {
+    let working_currency = parse_currency(&new_tab.working_currency)?;
+    let users = new_tab
+        .users
+        .iter()
+        .zip(0..)
+        .map(|(user, id)| Rc::new(user.to_user(id)))
+        .collect();
+    let tab = Tab::new(
+        Uuid::new_v4(),
+        new_tab.name,
+        working_currency,
+        users,
+        vec![],
+    );
+    service.add_tab(account, &tab, 0)?;
+    Ok(TabData::from_tab(&tab))
+}
+
+/// The user which was most recently added to the `tab`.
+fn last_user(tab: Tab) -> Option<Rc<User>> {
+    tab.users.last().cloned()
+}
+
+/// The expense which was most recently added to the `tab`.
+fn last_expense(tab: Tab) -> Option<Expense> {
+    tab.expenses.last().cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::routes;
+    use crate::accounts::SESSION_COOKIE;
+    use crate::config::DEFAULT_MAX_BODY_SIZE;
+    use crate::repository::tests::create_test_tab;
+    use crate::request_id::RequestId;
+    use crate::service::tests::create_test_service;
+    use costing::actions::{AddUser, ChangeTabName, TabUserAction, TabUserActionType};
+    use costing::{Tab, TabData, User};
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+    use warp::{filters::BoxedFilter, http::StatusCode, reply::Response, test::request};
+
+    const TEST_TAB_ID: &str = "936da01f-9abd-4d9d-80c7-02af85c822a8";
+
+    fn create_routes() -> BoxedFilter<(Response,)> {
+        routes(create_test_service().0, DEFAULT_MAX_BODY_SIZE, false)
+    }
+
+    /// Send a request with the `method`, `path` and optional JSON
+    /// `body`, authenticated with the session `token` (if there is
+    /// one), and return the status and body of the response.
+    async fn send(
+        routes: &BoxedFilter<(Response,)>,
+        token: Option<&str>,
+        method: &str,
+        path: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut builder = request().method(method).path(path);
+        if let Some(token) = token {
+            builder = builder.header("authorization", format!("Bearer {}", token));
+        }
+        if let Some(body) = body {
+            builder = builder.json(&body);
+        }
+        let response = builder.reply(routes).await;
+        let body = if response.body().is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(response.body()).unwrap()
+        };
+        (response.status(), body)
+    }
+
+    #[tokio::test]
+    async fn create_tab_and_add_expense() {
+        let routes = create_routes();
+        let alice = Some("alice");
+
+        let new_tab = json!({
+            "name": "Flat",
+            "working_currency": "AUD",
+            "users": [{ "name": "Alice", "email": null }, { "name": "Bob", "email": null }],
+        });
+        let (status, tab) = send(&routes, alice, "POST", "/v1/tabs", Some(new_tab)).await;
+        assert_eq!(StatusCode::CREATED, status);
+        assert_eq!("Flat", tab["name"]);
+        let tab_path = format!("/v1/tabs/{}", tab["id"].as_str().unwrap());
+
+        let (_, tabs) = send(&routes, alice, "GET", "/v1/tabs", None).await;
+        assert_eq!(2, tabs.as_array().unwrap().len());
+
+        let (_, members) = send(
+            &routes,
+            alice,
+            "GET",
+            &format!("{}/members", tab_path),
+            None,
+        )
+        .await;
+        assert_eq!(0, members[0]["user_id"]);
+
+        let mut expense =
+            serde_json::to_value(&crate::repository::tests::create_test_tab().expenses[0]).unwrap();
+        expense["id"] = json!(0);
+        expense["paid_by"] = json!(0);
+        expense["shared_by"] = json!([0, 1]);
+        let (status, added) = send(
+            &routes,
+            alice,
+            "POST",
+            &format!("{}/expenses", tab_path),
+            Some(expense.clone()),
+        )
+        .await;
+        assert_eq!(StatusCode::CREATED, status);
+        assert_eq!(expense, added);
+
+        let (status, _) = send(
+            &routes,
+            alice,
+            "DELETE",
+            &format!("{}/expenses/0", tab_path),
+            None,
+        )
+        .await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+
+        let (_, expenses) = send(
+            &routes,
+            alice,
+            "GET",
+            &format!("{}/expenses", tab_path),
+            None,
+        )
+        .await;
+        assert_eq!(json!([]), expenses);
+
+        let (_, actions) = send(
+            &routes,
+            alice,
+            "GET",
+            &format!("{}/actions", tab_path),
+            None,
+        )
+        .await;
+        assert_eq!(2, actions.as_array().unwrap().len());
+        assert_eq!(0, actions[0]["AddExpense"]["metadata"]["user_id"]);
+    }
+
+    #[tokio::test]
+    async fn record_settlement() {
+        let routes = create_routes();
+        let settlements_path = format!("/v1/tabs/{}/settlements", TEST_TAB_ID);
+
+        let (status, settlements) = send(
+            &routes,
+            Some("alice"),
+            "GET",
+            &format!("{}?as_of=2020-06-01", settlements_path),
+            None,
+        )
+        .await;
+        assert_eq!(StatusCode::OK, status);
+        let settlements = settlements.as_array().unwrap().clone();
+        assert_eq!(1, settlements.len());
+        assert_eq!(2, settlements[0]["sender"]);
+        assert_eq!(1, settlements[0]["receiver"]);
+
+        let mut new_settlement = settlements[0].clone();
+        new_settlement["date"] = json!("2020-05-02");
+        let (status, expense) = send(
+            &routes,
+            Some("alice"),
+            "POST",
+            &settlements_path,
+            Some(new_settlement),
+        )
+        .await;
+        assert_eq!(StatusCode::CREATED, status);
+        assert_eq!(2, expense["paid_by"]);
+        assert_eq!(json!([1]), expense["shared_by"]);
+
+        let (_, settlements) = send(
+            &routes,
+            Some("alice"),
+            "GET",
+            &format!("{}?as_of=2020-06-01", settlements_path),
+            None,
+        )
+        .await;
+        assert_eq!(json!([]), settlements);
+    }
+
+    #[tokio::test]
+    async fn accounts_and_sessions() {
+        let routes = create_routes();
+
+        let new_account = json!({
+            "name": "Carol",
+            "email": "carol@example.com",
+            "password": "correct horse",
+        });
+        let response = request()
+            .method("POST")
+            .path("/v1/accounts")
+            .json(&new_account)
+            .reply(&routes)
+            .await;
+        assert_eq!(StatusCode::CREATED, response.status());
+        let cookie = response.headers()["set-cookie"].to_str().unwrap();
+        assert!(cookie.starts_with(&format!("{}=", SESSION_COOKIE)));
+        assert!(cookie.contains("HttpOnly"));
+
+        // the cookie authenticates requests
+        let cookie = cookie.split(';').next().unwrap();
+        let response = request()
+            .path("/v1/accounts/current")
+            .header("cookie", cookie)
+            .reply(&routes)
+            .await;
+        assert_eq!(StatusCode::OK, response.status());
+        let account: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!("carol@example.com", account["email"]);
+        assert!(account.get("password_hash").is_none());
+
+        let credentials = json!({ "email": "carol@example.com", "password": "wrong password" });
+        let (status, body) = send(&routes, None, "POST", "/v1/sessions", Some(credentials)).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, status);
+        assert_eq!("INVALID_CREDENTIALS", body["error"]["code"]);
+
+        let credentials = json!({ "email": "Carol@example.com", "password": "correct horse" });
+        let (status, session) =
+            send(&routes, None, "POST", "/v1/sessions", Some(credentials)).await;
+        assert_eq!(StatusCode::CREATED, status);
+        let token = session["token"].as_str().unwrap();
+
+        let (status, _) = send(&routes, Some(token), "DELETE", "/v1/sessions/current", None).await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+        let (status, body) = send(&routes, Some(token), "GET", "/v1/tabs", None).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, status);
+        assert_eq!("INVALID_SESSION", body["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn tabs_require_membership() {
+        let routes = create_routes();
+        let tab_path = format!("/v1/tabs/{}", TEST_TAB_ID);
+
+        let (status, body) = send(&routes, None, "GET", &tab_path, None).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, status);
+        assert_eq!("NOT_AUTHENTICATED", body["error"]["code"]);
+
+        let (status, body) = send(&routes, Some("bob"), "GET", &tab_path, None).await;
+        assert_eq!(StatusCode::FORBIDDEN, status);
+        assert_eq!("FORBIDDEN", body["error"]["code"]);
+        let (_, tabs) = send(&routes, Some("bob"), "GET", "/v1/tabs", None).await;
+        assert_eq!(json!([]), tabs);
+
+        let new_member = json!({ "user_id": 2, "email": "bob@example.com" });
+        let (status, _) = send(
+            &routes,
+            Some("alice"),
+            "POST",
+            &format!("{}/members", tab_path),
+            Some(new_member),
+        )
+        .await;
+        assert_eq!(StatusCode::CREATED, status);
+        let (status, tab) = send(&routes, Some("bob"), "GET", &tab_path, None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("Road Trip", tab["name"]);
+
+        // actions can only be performed as the linked user
+        let action = json!({
+            "ChangeTabName": {
+                "metadata": { "user_id": 1, "datetime": "2020-06-01T00:00:00Z" },
+                "name": "Holiday",
+            }
+        });
+        let (status, body) = send(
+            &routes,
+            Some("bob"),
+            "POST",
+            &format!("{}/actions", tab_path),
+            Some(action),
+        )
+        .await;
+        assert_eq!(StatusCode::FORBIDDEN, status);
+        assert_eq!("FORBIDDEN", body["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn share_tab_with_invite() {
+        let routes = create_routes();
+        let invites_path = format!("/v1/tabs/{}/invites", TEST_TAB_ID);
+
+        let (status, body) =
+            send(&routes, Some("bob"), "POST", &invites_path, Some(json!({}))).await;
+        assert_eq!(StatusCode::FORBIDDEN, status);
+        assert_eq!("FORBIDDEN", body["error"]["code"]);
+
+        let new_invite = json!({ "expires_at": "2000-01-01T00:00:00Z" });
+        let (status, body) = send(
+            &routes,
+            Some("alice"),
+            "POST",
+            &invites_path,
+            Some(new_invite),
+        )
+        .await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!("INVALID_EXPIRY", body["error"]["code"]);
+
+        let new_invite = json!({ "single_use": true });
+        let (status, invite) = send(
+            &routes,
+            Some("alice"),
+            "POST",
+            &invites_path,
+            Some(new_invite),
+        )
+        .await;
+        assert_eq!(StatusCode::CREATED, status);
+        let token = invite["token"].as_str().unwrap();
+        assert_eq!(format!("/join/{}", token), invite["path"]);
+
+        // anyone with the token can see which tab it is for
+        let invite_path = format!("/v1/invites/{}", token);
+        let (status, preview) = send(&routes, None, "GET", &invite_path, None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("Road Trip", preview["tab_name"]);
+        assert_eq!("User 1", preview["invited_by"]);
+
+        // redeeming it without logging in creates a guest account
+        let response = request()
+            .method("POST")
+            .path(&format!("{}/redeem", invite_path))
+            .json(&json!({ "name": "Carol", "email": null }))
+            .reply(&routes)
+            .await;
+        assert_eq!(StatusCode::CREATED, response.status());
+        assert!(response.headers()["set-cookie"]
+            .to_str()
+            .unwrap()
+            .starts_with(&format!("{}=", SESSION_COOKIE)));
+        let redemption: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(3, redemption["member"]["user_id"]);
+        assert_eq!(Value::Null, redemption["session"]["account"]["email"]);
+        let guest = redemption["session"]["token"].as_str();
+
+        let tab_path = format!("/v1/tabs/{}", TEST_TAB_ID);
+        let (status, tab) = send(&routes, guest, "GET", &tab_path, None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("Carol", tab["users"][2]["name"]);
+
+        let (status, body) = send(&routes, None, "GET", &invite_path, None).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+        assert_eq!("INVITE_NOT_FOUND", body["error"]["code"]);
+
+        // logged in accounts are linked to the new user
+        let (_, invite) = send(
+            &routes,
+            Some("alice"),
+            "POST",
+            &invites_path,
+            Some(json!({})),
+        )
+        .await;
+        let invite_path = format!("/v1/invites/{}", invite["token"].as_str().unwrap());
+        let (status, redemption) = send(
+            &routes,
+            Some("bob"),
+            "POST",
+            &format!("{}/redeem", invite_path),
+            Some(json!({ "name": "Bob", "email": "bob@example.com" })),
+        )
+        .await;
+        assert_eq!(StatusCode::CREATED, status);
+        assert_eq!(Value::Null, redemption["session"]);
+        assert_eq!(4, redemption["member"]["user_id"]);
+
+        let (_, invites) = send(&routes, Some("bob"), "GET", &invites_path, None).await;
+        assert_eq!(1, invites.as_array().unwrap().len());
+        let (status, _) = send(
+            &routes,
+            Some("bob"),
+            "DELETE",
+            &format!("{}/{}", invites_path, invite["token"].as_str().unwrap()),
+            None,
+        )
+        .await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+        let (status, _) = send(&routes, None, "GET", &invite_path, None).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+    }
+
+    #[tokio::test]
+    async fn share_tab_with_link() {
+        let routes = create_routes();
+        let links_path = format!("/v1/tabs/{}/share-links", TEST_TAB_ID);
+
+        let (status, body) = send(&routes, Some("bob"), "POST", &links_path, None).await;
+        assert_eq!(StatusCode::FORBIDDEN, status);
+        assert_eq!("FORBIDDEN", body["error"]["code"]);
+
+        let (status, link) = send(&routes, Some("alice"), "POST", &links_path, None).await;
+        assert_eq!(StatusCode::CREATED, status);
+        let token = link["token"].as_str().unwrap();
+        assert_eq!(format!("/shared/{}", token), link["path"]);
+        assert_eq!(1, link["created_by"]);
+
+        let (status, links) = send(&routes, Some("alice"), "GET", &links_path, None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(vec![link.clone()], *links.as_array().unwrap());
+
+        let link_path = format!("{}/{}", links_path, token);
+        let (status, _) = send(&routes, Some("alice"), "DELETE", &link_path, None).await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+        let (status, body) = send(&routes, Some("alice"), "DELETE", &link_path, None).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+        assert_eq!("SHARE_LINK_NOT_FOUND", body["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn sync_tab() {
+        let routes = create_routes();
+        let sync_path = format!("/v1/tabs/{}/sync", TEST_TAB_ID);
+        let change_name = |user_id, name| {
+            serde_json::to_value(TabUserActionType::ChangeTabName(ChangeTabName::new(
+                user_id, name,
+            )))
+            .unwrap()
+        };
+
+        let (status, sync) = send(
+            &routes,
+            Some("alice"),
+            "POST",
+            &sync_path,
+            Some(json!({
+                "cursor": 0,
+                "actions": [change_name(1, "Holiday"), change_name(2, "Wrong User")],
+            })),
+        )
+        .await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(1, sync["cursor"]);
+        assert_eq!(1, sync["actions"].as_array().unwrap().len());
+        assert_eq!("Holiday", sync["tab"]["name"]);
+        assert_eq!(1, sync["rejected"][0]["index"]);
+        assert_eq!("FORBIDDEN", sync["rejected"][0]["error"]["code"]);
+
+        let (status, sync) = send(
+            &routes,
+            Some("alice"),
+            "POST",
+            &sync_path,
+            Some(json!({ "cursor": 1 })),
+        )
+        .await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(Value::Array(vec![]), sync["actions"]);
+
+        let (status, body) = send(
+            &routes,
+            Some("alice"),
+            "POST",
+            &sync_path,
+            Some(json!({ "cursor": 2 })),
+        )
+        .await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!("INVALID_CURSOR", body["error"]["code"]);
+
+        // tabs created by the client are pushed in their entirety
+        let currency = create_test_tab().working_currency;
+        let mut tab = Tab::new(Uuid::new_v4(), "Dinner", currency, vec![], vec![]);
+        let add_bob = TabUserActionType::AddUser(AddUser::new(0, User::new(0, "Bob", None)));
+        add_bob.perform(&mut tab).unwrap();
+        tab.user_actions.push(add_bob);
+        let sync_path = format!("/v1/tabs/{}/sync", tab.id);
+        let (status, sync) = send(
+            &routes,
+            Some("bob"),
+            "POST",
+            &sync_path,
+            Some(json!({ "tab": TabData::from_tab(&tab) })),
+        )
+        .await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("Dinner", sync["tab"]["name"]);
+
+        let (status, body) = send(
+            &routes,
+            Some("bob"),
+            "POST",
+            &sync_path,
+            Some(json!({ "tab": { "name": "Dinner" } })),
+        )
+        .await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!("INVALID_BODY", body["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let routes = create_routes();
+        let alice = Some("alice");
+        let tab_path = format!("/v1/tabs/{}", TEST_TAB_ID);
+
+        let (status, body) = send(
+            &routes,
+            alice,
+            "GET",
+            "/v1/tabs/00000000-0000-0000-0000-000000000000",
+            None,
+        )
+        .await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+        assert_eq!("TAB_NOT_FOUND", body["error"]["code"]);
+
+        let (status, body) = send(&routes, alice, "GET", "/v1/nothing", None).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+        assert_eq!("NOT_FOUND", body["error"]["code"]);
+        assert_eq!(None, body.get("request_id"));
+
+        let request_id = RequestId::generate();
+        let (_, body) = request_id
+            .clone()
+            .scope(send(&routes, alice, "GET", "/v1/nothing", None))
+            .await;
+        assert_eq!(request_id.as_str(), body["request_id"]);
+
+        let (status, body) = send(&routes, alice, "POST", "/v1/tabs", Some(json!({}))).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!("INVALID_BODY", body["error"]["code"]);
+
+        let new_tab = json!({ "name": "Flat", "working_currency": "XYZ", "users": [] });
+        let (status, body) = send(&routes, alice, "POST", "/v1/tabs", Some(new_tab)).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!("INVALID_CURRENCY", body["error"]["code"]);
+
+        let (status, body) = send(
+            &routes,
+            alice,
+            "DELETE",
+            &format!("{}/expenses/5", tab_path),
+            None,
+        )
+        .await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+        assert_eq!("EXPENSE_NOT_FOUND", body["error"]["code"]);
+
+        let (status, _) = send(&routes, alice, "DELETE", &tab_path, None).await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+        let (_, tabs) = send(&routes, alice, "GET", "/v1/tabs", None).await;
+        assert_eq!(json!([]), tabs);
+    }
+
+    #[tokio::test]
+    async fn restore_and_delete_trashed_tab() {
+        let routes = create_routes();
+        let alice = Some("alice");
+        let tab_path = format!("/v1/tabs/{}", TEST_TAB_ID);
+
+        let (status, _) = send(&routes, alice, "DELETE", &tab_path, None).await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+        let (status, tab) = send(
+            &routes,
+            alice,
+            "POST",
+            &format!("{}/restore", tab_path),
+            None,
+        )
+        .await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(TEST_TAB_ID, tab["id"]);
+        let (_, tabs) = send(&routes, alice, "GET", "/v1/tabs", None).await;
+        assert_eq!(1, tabs.as_array().unwrap().len());
+
+        let trash_path = format!("/v1/trash/{}", TEST_TAB_ID);
+        let (status, _) = send(&routes, Some("bob"), "DELETE", &trash_path, None).await;
+        assert_eq!(StatusCode::FORBIDDEN, status);
+        let (status, _) = send(&routes, alice, "DELETE", &tab_path, None).await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+        let (status, _) = send(&routes, alice, "DELETE", &trash_path, None).await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+        let (status, _) = send(&routes, alice, "GET", &tab_path, None).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+    }
+
+    #[tokio::test]
+    async fn body_limit() {
+        let routes = routes(create_test_service().0, 64, false);
+        let credentials = json!({ "email": "alice@example.com", "password": "a".repeat(64) });
+        let (status, body) = send(&routes, None, "POST", "/v1/sessions", Some(credentials)).await;
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, status);
+        assert_eq!("BODY_TOO_LARGE", body["error"]["code"]);
+
+        // a body streamed without a Content-Length isn't read
+        let response = request()
+            .method("POST")
+            .path("/v1/sessions")
+            .header("content-type", "application/json")
+            .reply(&routes)
+            .await;
+        assert_eq!(StatusCode::LENGTH_REQUIRED, response.status());
+    }
+}
@@ -0,0 +1,967 @@
+//! The OpenAPI description of the REST API. The schema of each type
+//! used in a request or response body is provided by its
+//! [ApiSchema] implementation, which describes the type's serde
+//! representation.
+
+use super::{
+    AccountData, Credentials, ErrorResponse, InviteData, InvitePreview, NewAccount, NewInvite,
+    NewMember, NewSettlement, NewTab, NewUser, RedemptionData, RejectedAction, SessionData,
+    ShareLinkData, SyncRequest, SyncResponse,
+};
+use crate::accounts::{TabMember, SESSION_COOKIE};
+use crate::error::ErrorBody;
+use commodity::{exchange_rate::ExchangeRate, Commodity};
+use costing::actions::{
+    AddExpense, AddUser, ChangeTabName, ClosePeriod, EditExpense, IdRemapping, RecordSettlement,
+    RemoveExpense, RemoveUser, TabUserActionMetadata, TabUserActionType,
+};
+use costing::{Expense, Settlement, TabData, TabPeriod, User};
+use serde_json::{json, Map, Value};
+
+/// A type which is described by a schema in the `components` of the
+/// OpenAPI description.
+pub trait ApiSchema {
+    /// The name of the schema in the `components`.
+    const NAME: &'static str;
+
+    /// The JSON schema of the serde representation of this type.
+    fn schema() -> Value;
+}
+
+/// A reference to the schema of `T`.
+fn reference<T: ApiSchema>() -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", T::NAME) })
+}
+
+fn array_of<T: ApiSchema>() -> Value {
+    json!({ "type": "array", "items": reference::<T>() })
+}
+
+/// The schema of an object with the specified `properties`, all of
+/// which are required unless they are listed in `optional`.
+fn object(properties: Value, optional: &[&str]) -> Value {
+    let required: Vec<&String> = properties
+        .as_object()
+        .expect("properties must be an object")
+        .keys()
+        .filter(|key| !optional.contains(&key.as_str()))
+        .collect();
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+fn string(format: &str) -> Value {
+    json!({ "type": "string", "format": format })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer", "format": "int32" })
+}
+
+fn decimal() -> Value {
+    json!({ "type": "string", "format": "decimal", "example": "10.50" })
+}
+
+fn currency() -> Value {
+    json!({ "type": "string", "description": "An ISO 4217 currency code", "example": "AUD" })
+}
+
+impl ApiSchema for User {
+    const NAME: &'static str = "User";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "id": integer(),
+                "name": { "type": "string" },
+                "email": { "type": "string", "nullable": true },
+            }),
+            &["email"],
+        )
+    }
+}
+
+impl ApiSchema for Commodity {
+    const NAME: &'static str = "Commodity";
+
+    fn schema() -> Value {
+        object(json!({ "value": decimal(), "type_id": currency() }), &[])
+    }
+}
+
+impl ApiSchema for ExchangeRate {
+    const NAME: &'static str = "ExchangeRate";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "date": string("date"),
+                "obtained_datetime": string("date-time"),
+                "base": currency(),
+                "rates": { "type": "object", "additionalProperties": decimal() },
+            }),
+            &["date", "obtained_datetime", "base"],
+        )
+    }
+}
+
+impl ApiSchema for Expense {
+    const NAME: &'static str = "Expense";
+
+    fn schema() -> Value {
+        let mut exchange_rate = reference::<ExchangeRate>();
+        exchange_rate["nullable"] = json!(true);
+        object(
+            json!({
+                "id": integer(),
+                "description": { "type": "string" },
+                "category": { "type": "string" },
+                "date": string("date"),
+                "paid_by": integer(),
+                "shared_by": { "type": "array", "items": integer() },
+                "amount": reference::<Commodity>(),
+                "exchange_rate": exchange_rate,
+            }),
+            &["exchange_rate"],
+        )
+    }
+}
+
+impl ApiSchema for Settlement {
+    const NAME: &'static str = "Settlement";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "sender": integer(),
+                "receiver": integer(),
+                "amount": reference::<Commodity>(),
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for TabPeriod {
+    const NAME: &'static str = "TabPeriod";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "start": string("date"),
+                "end": string("date"),
+                "expenses": array_of::<Expense>(),
+                "closing_balances": {
+                    "type": "object",
+                    "description": "The balance of each user, by user id",
+                    "additionalProperties": reference::<Commodity>(),
+                },
+            }),
+            &["start"],
+        )
+    }
+}
+
+impl ApiSchema for TabUserActionMetadata {
+    const NAME: &'static str = "TabUserActionMetadata";
+
+    fn schema() -> Value {
+        object(
+            json!({ "user_id": integer(), "datetime": string("date-time") }),
+            &[],
+        )
+    }
+}
+
+/// The schema of an action, which has `metadata` along with the
+/// specified `properties`.
+fn action(properties: Value) -> Value {
+    let mut properties = properties;
+    properties["metadata"] = reference::<TabUserActionMetadata>();
+    object(properties, &[])
+}
+
+impl ApiSchema for AddExpense {
+    const NAME: &'static str = "AddExpense";
+
+    fn schema() -> Value {
+        action(json!({ "expense": reference::<Expense>() }))
+    }
+}
+
+impl ApiSchema for EditExpense {
+    const NAME: &'static str = "EditExpense";
+
+    fn schema() -> Value {
+        action(json!({ "expense": reference::<Expense>() }))
+    }
+}
+
+impl ApiSchema for RemoveExpense {
+    const NAME: &'static str = "RemoveExpense";
+
+    fn schema() -> Value {
+        action(json!({ "expense_id": integer() }))
+    }
+}
+
+impl ApiSchema for ChangeTabName {
+    const NAME: &'static str = "ChangeTabName";
+
+    fn schema() -> Value {
+        action(json!({ "name": { "type": "string" } }))
+    }
+}
+
+impl ApiSchema for AddUser {
+    const NAME: &'static str = "AddUser";
+
+    fn schema() -> Value {
+        action(json!({ "user_to_add": reference::<User>() }))
+    }
+}
+
+impl ApiSchema for RemoveUser {
+    const NAME: &'static str = "RemoveUser";
+
+    fn schema() -> Value {
+        action(json!({ "user_id": integer() }))
+    }
+}
+
+impl ApiSchema for ClosePeriod {
+    const NAME: &'static str = "ClosePeriod";
+
+    fn schema() -> Value {
+        action(json!({ "end": string("date") }))
+    }
+}
+
+impl ApiSchema for RecordSettlement {
+    const NAME: &'static str = "RecordSettlement";
+
+    fn schema() -> Value {
+        action(json!({
+            "expense_id": integer(),
+            "date": string("date"),
+            "settlement": reference::<Settlement>(),
+        }))
+    }
+}
+
+/// The schema of a [TabUserActionType] variant, which is an object
+/// with the name of the action as its only property.
+fn action_variant<T: ApiSchema>() -> Value {
+    object(json!({ (T::NAME): reference::<T>() }), &[])
+}
+
+impl ApiSchema for TabUserActionType {
+    const NAME: &'static str = "TabUserActionType";
+
+    fn schema() -> Value {
+        json!({
+            "oneOf": [
+                action_variant::<AddExpense>(),
+                action_variant::<AddUser>(),
+                action_variant::<RemoveExpense>(),
+                action_variant::<EditExpense>(),
+                action_variant::<ChangeTabName>(),
+                action_variant::<RemoveUser>(),
+                action_variant::<ClosePeriod>(),
+                action_variant::<RecordSettlement>(),
+            ]
+        })
+    }
+}
+
+impl ApiSchema for TabData {
+    const NAME: &'static str = "TabData";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "id": string("uuid"),
+                "name": { "type": "string" },
+                "working_currency": currency(),
+                "users": array_of::<User>(),
+                "expenses": array_of::<Expense>(),
+                "user_actions": array_of::<TabUserActionType>(),
+                "closed_periods": array_of::<TabPeriod>(),
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for NewUser {
+    const NAME: &'static str = "NewUser";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "name": { "type": "string" },
+                "email": { "type": "string", "nullable": true },
+            }),
+            &["email"],
+        )
+    }
+}
+
+impl ApiSchema for NewTab {
+    const NAME: &'static str = "NewTab";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "name": { "type": "string" },
+                "working_currency": currency(),
+                "users": array_of::<NewUser>(),
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for NewSettlement {
+    const NAME: &'static str = "NewSettlement";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "date": string("date"),
+                "sender": integer(),
+                "receiver": integer(),
+                "amount": reference::<Commodity>(),
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for NewAccount {
+    const NAME: &'static str = "NewAccount";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "name": { "type": "string" },
+                "email": string("email"),
+                "password": { "type": "string", "format": "password", "minLength": crate::accounts::MIN_PASSWORD_LENGTH },
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for Credentials {
+    const NAME: &'static str = "Credentials";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "email": string("email"),
+                "password": string("password"),
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for TabMember {
+    const NAME: &'static str = "TabMember";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "tab_id": string("uuid"),
+                "user_id": integer(),
+                "account_id": string("uuid"),
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for AccountData {
+    const NAME: &'static str = "AccountData";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "id": string("uuid"),
+                "name": { "type": "string" },
+                "email": { "type": "string", "format": "email", "nullable": true, "description": "null for guest accounts" },
+                "memberships": array_of::<TabMember>(),
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for SessionData {
+    const NAME: &'static str = "SessionData";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "token": { "type": "string" },
+                "expires_at": string("date-time"),
+                "account": reference::<AccountData>(),
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for NewMember {
+    const NAME: &'static str = "NewMember";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "user_id": integer(),
+                "email": string("email"),
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for NewInvite {
+    const NAME: &'static str = "NewInvite";
+
+    fn schema() -> Value {
+        let mut expires_at = string("date-time");
+        expires_at["nullable"] = json!(true);
+        object(
+            json!({
+                "expires_at": expires_at,
+                "single_use": { "type": "boolean", "default": false },
+            }),
+            &["expires_at", "single_use"],
+        )
+    }
+}
+
+impl ApiSchema for InviteData {
+    const NAME: &'static str = "Invite";
+
+    fn schema() -> Value {
+        let mut expires_at = string("date-time");
+        expires_at["nullable"] = json!(true);
+        object(
+            json!({
+                "token": { "type": "string" },
+                "tab_id": string("uuid"),
+                "created_by": integer(),
+                "created_at": string("date-time"),
+                "expires_at": expires_at,
+                "single_use": { "type": "boolean" },
+                "path": { "type": "string", "description": "The path of the page in the GUI which redeems the invite" },
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for ShareLinkData {
+    const NAME: &'static str = "ShareLink";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "token": { "type": "string" },
+                "tab_id": string("uuid"),
+                "created_by": integer(),
+                "created_at": string("date-time"),
+                "path": { "type": "string", "description": "The path of the page which shows the read-only summary of the tab" },
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for InvitePreview {
+    const NAME: &'static str = "InvitePreview";
+
+    fn schema() -> Value {
+        let mut expires_at = string("date-time");
+        expires_at["nullable"] = json!(true);
+        object(
+            json!({
+                "tab_id": string("uuid"),
+                "tab_name": { "type": "string" },
+                "invited_by": { "type": "string", "nullable": true },
+                "expires_at": expires_at,
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for RedemptionData {
+    const NAME: &'static str = "Redemption";
+
+    fn schema() -> Value {
+        let mut session = reference::<SessionData>();
+        session["nullable"] = json!(true);
+        object(
+            json!({
+                "tab": reference::<TabData>(),
+                "member": reference::<TabMember>(),
+                "session": session,
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for SyncRequest {
+    const NAME: &'static str = "SyncRequest";
+
+    fn schema() -> Value {
+        let mut tab = reference::<TabData>();
+        tab["nullable"] = json!(true);
+        tab["description"] =
+            json!("The tab, if it was created by the client and hasn't been synchronised before");
+        object(
+            json!({
+                "push_id": { "type": "string", "format": "uuid", "nullable": true, "description": "An id chosen by the client for this push of actions, which is sent again if the push is retried, so that the actions are not merged twice" },
+                "cursor": { "type": "integer", "format": "int64", "minimum": 0, "default": 0, "description": "The number of the tab's actions which the client has already received" },
+                "actions": array_of::<TabUserActionType>(),
+                "tab": tab,
+            }),
+            &["push_id", "cursor", "actions", "tab"],
+        )
+    }
+}
+
+impl ApiSchema for RejectedAction {
+    const NAME: &'static str = "RejectedAction";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "index": integer(),
+                "error": reference::<ErrorBody>(),
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for IdRemapping {
+    const NAME: &'static str = "IdRemapping";
+
+    fn schema() -> Value {
+        let ids = |description: &str| {
+            json!({
+                "type": "object",
+                "additionalProperties": integer(),
+                "description": description,
+            })
+        };
+        object(
+            json!({
+                "expenses": ids("The reassigned ids of expenses, by the id which was recorded"),
+                "users": ids("The reassigned ids of users, by the id which was recorded"),
+            }),
+            &["expenses", "users"],
+        )
+    }
+}
+
+impl ApiSchema for SyncResponse {
+    const NAME: &'static str = "SyncResponse";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "cursor": { "type": "integer", "format": "int64", "minimum": 0 },
+                "actions": array_of::<TabUserActionType>(),
+                "rejected": array_of::<RejectedAction>(),
+                "remapped": reference::<IdRemapping>(),
+                "tab": reference::<TabData>(),
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for ErrorBody {
+    const NAME: &'static str = "ErrorBody";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "code": { "type": "string", "example": "TAB_NOT_FOUND" },
+                "message": { "type": "string" },
+                "causes": { "type": "array", "items": { "type": "string" } },
+            }),
+            &[],
+        )
+    }
+}
+
+impl ApiSchema for ErrorResponse {
+    const NAME: &'static str = "ErrorResponse";
+
+    fn schema() -> Value {
+        object(
+            json!({
+                "error": reference::<ErrorBody>(),
+                "request_id": { "type": "string", "description": "The id of the request, which identifies it in the server's logs" },
+            }),
+            &["request_id"],
+        )
+    }
+}
+
+/// Adds the schema of `T` to the `schemas`.
+fn add_schema<T: ApiSchema>(schemas: &mut Map<String, Value>) {
+    schemas.insert(T::NAME.to_string(), T::schema());
+}
+
+fn components() -> Value {
+    let mut schemas = Map::new();
+    add_schema::<User>(&mut schemas);
+    add_schema::<Commodity>(&mut schemas);
+    add_schema::<ExchangeRate>(&mut schemas);
+    add_schema::<Expense>(&mut schemas);
+    add_schema::<Settlement>(&mut schemas);
+    add_schema::<TabPeriod>(&mut schemas);
+    add_schema::<TabUserActionMetadata>(&mut schemas);
+    add_schema::<AddExpense>(&mut schemas);
+    add_schema::<EditExpense>(&mut schemas);
+    add_schema::<RemoveExpense>(&mut schemas);
+    add_schema::<ChangeTabName>(&mut schemas);
+    add_schema::<AddUser>(&mut schemas);
+    add_schema::<RemoveUser>(&mut schemas);
+    add_schema::<ClosePeriod>(&mut schemas);
+    add_schema::<RecordSettlement>(&mut schemas);
+    add_schema::<TabUserActionType>(&mut schemas);
+    add_schema::<TabData>(&mut schemas);
+    add_schema::<NewUser>(&mut schemas);
+    add_schema::<NewTab>(&mut schemas);
+    add_schema::<NewSettlement>(&mut schemas);
+    add_schema::<NewAccount>(&mut schemas);
+    add_schema::<Credentials>(&mut schemas);
+    add_schema::<TabMember>(&mut schemas);
+    add_schema::<AccountData>(&mut schemas);
+    add_schema::<SessionData>(&mut schemas);
+    add_schema::<NewMember>(&mut schemas);
+    add_schema::<NewInvite>(&mut schemas);
+    add_schema::<InviteData>(&mut schemas);
+    add_schema::<InvitePreview>(&mut schemas);
+    add_schema::<ShareLinkData>(&mut schemas);
+    add_schema::<RedemptionData>(&mut schemas);
+    add_schema::<SyncRequest>(&mut schemas);
+    add_schema::<RejectedAction>(&mut schemas);
+    add_schema::<IdRemapping>(&mut schemas);
+    add_schema::<SyncResponse>(&mut schemas);
+    add_schema::<ErrorBody>(&mut schemas);
+    add_schema::<ErrorResponse>(&mut schemas);
+
+    json!({
+        "schemas": schemas,
+        "parameters": {
+            "tab_id": path_parameter("tab_id", string("uuid")),
+            "user_id": path_parameter("user_id", integer()),
+            "expense_id": path_parameter("expense_id", integer()),
+            "token": path_parameter("token", json!({ "type": "string" })),
+            "as_of": {
+                "name": "as_of",
+                "in": "query",
+                "required": false,
+                "description": "The date to calculate the state of the tab as of, defaults to today",
+                "schema": string("date"),
+            },
+        },
+        "responses": {
+            "Error": {
+                "description": "The request failed",
+                "headers": {
+                    "X-Request-Id": {
+                        "description": "The id of the request, which may be specified by the client",
+                        "schema": { "type": "string" },
+                    },
+                },
+                "content": { "application/json": { "schema": reference::<ErrorResponse>() } },
+            },
+        },
+        "securitySchemes": {
+            "cookie": { "type": "apiKey", "in": "cookie", "name": SESSION_COOKIE },
+            "bearer": { "type": "http", "scheme": "bearer" },
+        },
+    })
+}
+
+fn path_parameter(name: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "path", "required": true, "schema": schema })
+}
+
+fn parameters(names: &[&str]) -> Value {
+    names
+        .iter()
+        .map(|name| json!({ "$ref": format!("#/components/parameters/{}", name) }))
+        .collect()
+}
+
+fn json_content(schema: Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+/// An operation which responds with `status`, and a body matching
+/// the `response` schema (if there is one).
+fn operation(
+    summary: &str,
+    parameter_names: &[&str],
+    request: Option<Value>,
+    status: u16,
+    response: Option<Value>,
+) -> Value {
+    let mut success = json!({ "description": "The request succeeded" });
+    if let Some(response) = response {
+        success["content"] = json_content(response);
+    }
+
+    let mut operation = json!({
+        "summary": summary,
+        "parameters": parameters(parameter_names),
+        "responses": {
+            status.to_string(): success,
+            "default": { "$ref": "#/components/responses/Error" },
+        },
+    });
+    if let Some(request) = request {
+        operation["requestBody"] = json!({ "required": true, "content": json_content(request) });
+    }
+    operation
+}
+
+/// An `operation` which doesn't need the request to be authenticated.
+fn public(mut operation: Value) -> Value {
+    operation["security"] = json!([]);
+    operation
+}
+
+/// The OpenAPI description of the REST API.
+pub fn openapi() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Coster",
+            "description": "Share costs between the users of a tab",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": "/api/v1" }],
+        "security": [{ "cookie": [] }, { "bearer": [] }],
+        "paths": {
+            "/accounts": {
+                "post": public(operation("Create a new account, and log in to it", &[], Some(reference::<NewAccount>()), 201, Some(reference::<SessionData>()))),
+            },
+            "/accounts/current": {
+                "get": operation("Get the account which is logged in", &[], None, 200, Some(reference::<AccountData>())),
+            },
+            "/sessions": {
+                "post": public(operation("Log in to an account", &[], Some(reference::<Credentials>()), 201, Some(reference::<SessionData>()))),
+            },
+            "/sessions/current": {
+                "delete": public(operation("Log out of the current session", &[], None, 204, None)),
+            },
+            "/tabs": {
+                "get": operation("List the tabs that the account is a member of, not including those in the trash", &[], None, 200, Some(array_of::<TabData>())),
+                "post": operation("Create a new tab, with the account linked to its first user", &[], Some(reference::<NewTab>()), 201, Some(reference::<TabData>())),
+            },
+            "/tabs/{tab_id}": {
+                "get": operation("Get a tab", &["tab_id"], None, 200, Some(reference::<TabData>())),
+                "delete": operation("Move a tab to the trash", &["tab_id"], None, 204, None),
+            },
+            "/tabs/{tab_id}/restore": {
+                "post": operation("Restore a tab from the trash", &["tab_id"], None, 200, Some(reference::<TabData>())),
+            },
+            "/trash/{tab_id}": {
+                "delete": operation("Permanently delete a tab, which can't be undone", &["tab_id"], None, 204, None),
+            },
+            "/tabs/{tab_id}/members": {
+                "get": operation("List the accounts linked to the users of a tab", &["tab_id"], None, 200, Some(array_of::<TabMember>())),
+                "post": operation("Link an account to a user of a tab, making it a member of the tab", &["tab_id"], Some(reference::<NewMember>()), 201, Some(reference::<TabMember>())),
+            },
+            "/tabs/{tab_id}/invites": {
+                "get": operation("List the invites to a tab", &["tab_id"], None, 200, Some(array_of::<InviteData>())),
+                "post": operation("Create an invite to a tab", &["tab_id"], Some(reference::<NewInvite>()), 201, Some(reference::<InviteData>())),
+            },
+            "/tabs/{tab_id}/invites/{token}": {
+                "delete": operation("Revoke an invite to a tab", &["tab_id", "token"], None, 204, None),
+            },
+            "/tabs/{tab_id}/share-links": {
+                "get": operation("List the links to the read-only summary of a tab", &["tab_id"], None, 200, Some(array_of::<ShareLinkData>())),
+                "post": operation("Create a link to the read-only summary of a tab, which anyone with the link can read", &["tab_id"], None, 201, Some(reference::<ShareLinkData>())),
+            },
+            "/tabs/{tab_id}/share-links/{token}": {
+                "delete": operation("Revoke a link to the read-only summary of a tab", &["tab_id", "token"], None, 204, None),
+            },
+            "/invites/{token}": {
+                "get": public(operation("Get the details of an invite which can still be redeemed", &["token"], None, 200, Some(reference::<InvitePreview>()))),
+            },
+            "/invites/{token}/redeem": {
+                "post": public(operation("Join a tab by adding a new user to it, linked to the account. If the request isn't authenticated, a guest account is created and logged in to", &["token"], Some(reference::<NewUser>()), 201, Some(reference::<RedemptionData>()))),
+            },
+            "/tabs/{tab_id}/users": {
+                "get": operation("List the users of a tab", &["tab_id"], None, 200, Some(array_of::<User>())),
+                "post": operation("Add a new user to a tab", &["tab_id"], Some(reference::<NewUser>()), 201, Some(reference::<User>())),
+            },
+            "/tabs/{tab_id}/users/{user_id}": {
+                "delete": operation("Remove a user from a tab", &["tab_id", "user_id"], None, 204, None),
+            },
+            "/tabs/{tab_id}/expenses": {
+                "get": operation("List the expenses in the current period of a tab", &["tab_id"], None, 200, Some(array_of::<Expense>())),
+                "post": operation("Add a new expense to a tab", &["tab_id"], Some(reference::<Expense>()), 201, Some(reference::<Expense>())),
+            },
+            "/tabs/{tab_id}/expenses/{expense_id}": {
+                "put": operation("Replace an expense, the id in the path takes precedence over the body", &["tab_id", "expense_id"], Some(reference::<Expense>()), 200, Some(reference::<Expense>())),
+                "delete": operation("Remove an expense from a tab", &["tab_id", "expense_id"], None, 204, None),
+            },
+            "/tabs/{tab_id}/settlements": {
+                "get": operation("List the payments required to settle the balances of the users", &["tab_id", "as_of"], None, 200, Some(array_of::<Settlement>())),
+                "post": operation("Record a payment between two users, which is added to the tab as an expense", &["tab_id"], Some(reference::<NewSettlement>()), 201, Some(reference::<Expense>())),
+            },
+            "/tabs/{tab_id}/actions": {
+                "get": operation("List the actions performed on a tab", &["tab_id"], None, 200, Some(array_of::<TabUserActionType>())),
+                "post": operation("Perform an action on a tab, the user in its metadata needs to be linked to the account", &["tab_id"], Some(reference::<TabUserActionType>()), 201, Some(reference::<TabData>())),
+            },
+            "/tabs/{tab_id}/sync": {
+                "post": operation("Merge the actions recorded by a client into a tab, and get the actions after the client's cursor", &["tab_id"], Some(reference::<SyncRequest>()), 200, Some(reference::<SyncResponse>())),
+            },
+            "/openapi.json": {
+                "get": public(operation("This description of the API", &[], None, 200, Some(json!({ "type": "object" })))),
+            },
+        },
+        "components": components(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{openapi, ApiSchema};
+    use crate::accounts::TabMember;
+    use crate::invites::Invite;
+    use crate::repository::tests::create_test_tab;
+    use crate::rest::{
+        AccountData, InviteData, InvitePreview, NewInvite, NewSettlement, RedemptionData,
+        SessionData, ShareLinkData, SyncRequest, SyncResponse,
+    };
+    use crate::service::tests::create_test_service;
+    use crate::shares::ShareLink;
+    use chrono::Utc;
+    use costing::actions::{RecordSettlement, TabUserActionType};
+    use costing::{Settlement, TabData};
+    use serde::Serialize;
+    use serde_json::Value;
+    use std::collections::BTreeSet;
+    use uuid::Uuid;
+
+    /// Check that the properties of the schema of `T` match the
+    /// fields of the serde representation of the `example`.
+    fn check_properties<T: ApiSchema + Serialize>(example: &T) {
+        let value = serde_json::to_value(example).unwrap();
+        let fields: BTreeSet<&String> = value.as_object().unwrap().keys().collect();
+        let schema = T::schema();
+        let properties: BTreeSet<&String> =
+            schema["properties"].as_object().unwrap().keys().collect();
+        assert_eq!(fields, properties, "properties of {}", T::NAME);
+    }
+
+    #[test]
+    fn schemas_match_serde_representations() {
+        let tab = create_test_tab();
+        let expense = tab.expenses[0].clone();
+        let settlement = Settlement::new(2, 1, expense.amount);
+        let action = RecordSettlement::new(2, 2, expense.date, settlement.clone());
+
+        check_properties(&TabData::from_tab(&tab));
+        check_properties(tab.users[0].as_ref());
+        check_properties(&expense);
+        check_properties(&expense.amount);
+        check_properties(&settlement);
+        check_properties(&action);
+        check_properties(&action.metadata);
+        check_properties(&NewSettlement {
+            date: expense.date,
+            settlement,
+        });
+
+        let member = TabMember {
+            tab_id: tab.id,
+            user_id: 1,
+            account_id: Uuid::new_v4(),
+        };
+        let account = AccountData {
+            id: member.account_id,
+            name: "Alice".to_string(),
+            email: Some("alice@example.com".to_string()),
+            memberships: vec![member],
+        };
+        let session = SessionData {
+            token: "token".to_string(),
+            expires_at: Utc::now(),
+            account,
+        };
+        check_properties(&member);
+        check_properties(&session.account);
+        check_properties(&session);
+
+        let invite = Invite::new(tab.id, 1, Utc::now(), None, false).unwrap();
+        check_properties(&NewInvite::default());
+        check_properties(&InvitePreview {
+            tab_id: tab.id,
+            tab_name: tab.name.clone(),
+            invited_by: None,
+            expires_at: None,
+        });
+        check_properties(&InviteData::from(invite));
+        check_properties(&ShareLinkData::from(ShareLink::new(tab.id, 1, Utc::now())));
+        check_properties(&RedemptionData {
+            tab: TabData::from_tab(&tab),
+            member,
+            session: Some(session),
+        });
+
+        let (service, alice, _) = create_test_service();
+        check_properties(&SyncRequest::default());
+        let sync = service
+            .sync(&alice, &tab.id, None, 0, vec![], None)
+            .unwrap();
+        check_properties(&sync.remapped);
+        check_properties(&SyncResponse::from(sync));
+
+        let variant = serde_json::to_value(TabUserActionType::RecordSettlement(action)).unwrap();
+        assert!(variant.get(RecordSettlement::NAME).is_some());
+    }
+
+    #[test]
+    fn references_are_defined() {
+        fn check_references(value: &Value, description: &Value) {
+            match value {
+                Value::Object(map) => {
+                    if let Some(Value::String(reference)) = map.get("$ref") {
+                        let pointer = reference.trim_start_matches('#');
+                        assert!(
+                            description.pointer(pointer).is_some(),
+                            "{} is not defined",
+                            reference
+                        );
+                    }
+                    map.values()
+                        .for_each(|value| check_references(value, description));
+                }
+                Value::Array(values) => values
+                    .iter()
+                    .for_each(|value| check_references(value, description)),
+                _ => {}
+            }
+        }
+
+        let description = openapi();
+        check_references(&description, &description);
+    }
+}
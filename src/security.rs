@@ -0,0 +1,790 @@
+//! Protection for the server's routes: the CORS policy for requests
+//! from other origins, the limits on the size of request bodies and
+//! the rate of requests from each client, and the security headers
+//! which are added to every response.
+
+use crate::config::Config;
+use crate::error::ErrorCode;
+use crate::rest::error_response;
+use ring::digest::{digest, SHA256};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use warp::filters::BoxedFilter;
+use warp::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use warp::http::{Method, StatusCode};
+use warp::reject::{self, Reject};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+/// The content security policy for the pages of the GUI, to which the
+/// hashes of the inline scripts in its `index.html` are added. Wasm
+/// modules need `'wasm-unsafe-eval'` to be compiled.
+const CONTENT_SECURITY_POLICY: &str = "default-src 'self'; \
+    script-src 'self' 'wasm-unsafe-eval'{scripts}; \
+    style-src 'self' 'unsafe-inline'; \
+    img-src 'self' data:; \
+    connect-src 'self'; \
+    object-src 'none'; \
+    base-uri 'self'; \
+    form-action 'self'; \
+    frame-ancestors 'none'";
+
+/// The content security policy for the GraphQL playground, which is
+/// loaded from a CDN.
+pub const PLAYGROUND_CONTENT_SECURITY_POLICY: &str = "default-src 'self'; \
+    script-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; \
+    style-src 'self' 'unsafe-inline' https:; \
+    font-src 'self' https: data:; \
+    img-src 'self' https: data:; \
+    connect-src 'self'; \
+    object-src 'none'; \
+    frame-ancestors 'none'";
+
+/// Instructs browsers to only connect to the server using HTTPS for
+/// the next year, which is only sent when the server is serving
+/// HTTPS itself.
+const STRICT_TRANSPORT_SECURITY: &str = "max-age=31536000";
+
+/// The header which reverse proxies add the address of the client to.
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// The headers which clients on allowed origins may send.
+const ALLOWED_HEADERS: &str = "authorization, content-type";
+
+/// The methods which clients on allowed origins may use.
+const ALLOWED_METHODS: &str = "GET, POST, PUT, PATCH, DELETE";
+
+/// The number of seconds for which browsers may cache the response to
+/// a preflight request.
+const PREFLIGHT_MAX_AGE: &str = "86400";
+
+/// A request which was refused to protect the server.
+#[derive(Error, Debug)]
+pub enum SecurityError {
+    #[error("cross origin requests from {0:?} are not allowed")]
+    OriginNotAllowed(String),
+    #[error("the request body of {size} bytes is larger than the limit of {limit} bytes")]
+    BodyTooLarge { size: u64, limit: u64 },
+    #[error("the length of the request body needs to be specified using Content-Length")]
+    LengthRequired,
+    #[error("too many requests were made, retry after {} seconds", retry_seconds(.0))]
+    RateLimited(Duration),
+}
+
+impl ErrorCode for SecurityError {
+    fn code(&self) -> &'static str {
+        match self {
+            SecurityError::OriginNotAllowed(_) => "ORIGIN_NOT_ALLOWED",
+            SecurityError::BodyTooLarge { .. } => "BODY_TOO_LARGE",
+            SecurityError::LengthRequired => "LENGTH_REQUIRED",
+            SecurityError::RateLimited(_) => "RATE_LIMITED",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            SecurityError::OriginNotAllowed(_) => StatusCode::FORBIDDEN,
+            SecurityError::BodyTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            SecurityError::LengthRequired => StatusCode::LENGTH_REQUIRED,
+            SecurityError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+}
+
+/// The number of whole seconds to wait before retrying a request.
+fn retry_seconds(duration: &Duration) -> u64 {
+    let seconds = duration.as_secs();
+    if duration.subsec_nanos() > 0 {
+        seconds + 1
+    } else {
+        seconds
+    }
+}
+
+/// The address of the client which sent a request, which is added
+/// to the request's extensions by [crate::server], because
+/// [warp::addr::remote] is only provided by [warp::serve].
+#[derive(Debug, Clone, Copy)]
+pub struct RemoteAddress(pub SocketAddr);
+
+/// Extracts the address of the client which sent a request, if it
+/// is known.
+pub fn remote_address() -> impl Filter<Extract = (Option<SocketAddr>,), Error = Infallible> + Clone
+{
+    warp::addr::remote()
+        .and(warp::ext::optional::<RemoteAddress>())
+        .map(
+            |remote: Option<SocketAddr>, extension: Option<RemoteAddress>| {
+                remote.or_else(|| extension.map(|RemoteAddress(address)| address))
+            },
+        )
+}
+
+/// The origins which are allowed to make cross origin requests.
+#[derive(Debug, Clone, PartialEq)]
+enum AllowedOrigins {
+    /// Any origin, which isn't allowed to send credentials (cookies).
+    Any,
+    /// The listed origins, which are allowed to send credentials.
+    List(Vec<String>),
+}
+
+impl AllowedOrigins {
+    fn new(origins: &[String]) -> Self {
+        if origins.iter().any(|origin| origin == "*") {
+            AllowedOrigins::Any
+        } else {
+            AllowedOrigins::List(origins.to_vec())
+        }
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        match self {
+            AllowedOrigins::Any => true,
+            AllowedOrigins::List(origins) => origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin)),
+        }
+    }
+}
+
+/// Limits the rate of requests from each client, allowing bursts of
+/// up to a minute's worth of requests.
+#[derive(Debug)]
+struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    clients: HashMap<IpAddr, Bucket>,
+    /// When the buckets which are full were last removed.
+    pruned: Instant,
+}
+
+/// The requests which a client can make, which is refilled at the
+/// configured rate.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    fn new(per_minute: u32) -> Self {
+        RateLimiter {
+            per_minute,
+            buckets: Mutex::new(Buckets {
+                clients: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// Count a request from the `client` at the time `now`, returning
+    /// how long it needs to wait if it has made too many requests.
+    fn acquire(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(self.per_minute);
+        let refill = Duration::from_secs(60);
+        let per_second = capacity / refill.as_secs_f64();
+        let mut buckets = self.buckets.lock().expect("rate limiter lock is poisoned");
+
+        // a bucket which hasn't been used for the refill period is
+        // full, and the same as a missing bucket
+        if now.saturating_duration_since(buckets.pruned) >= refill {
+            buckets
+                .clients
+                .retain(|_, bucket| now.saturating_duration_since(bucket.updated) < refill);
+            buckets.pruned = now;
+        }
+
+        let bucket = buckets.clients.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+}
+
+/// What was determined about a request before it was handled.
+#[derive(Debug, Clone)]
+struct Checked {
+    /// The `Origin` of a cross origin request which is allowed.
+    cross_origin: Option<HeaderValue>,
+    /// Whether this is a CORS preflight request.
+    preflight: bool,
+}
+
+/// A request which was refused by the [Security] checks.
+#[derive(Debug)]
+struct SecurityRejection {
+    security: Arc<Security>,
+    checked: Checked,
+    error: SecurityError,
+}
+
+impl Reject for SecurityRejection {}
+
+/// The protection which is applied to the server's routes, using
+/// [Security::protect].
+#[derive(Debug)]
+pub struct Security {
+    allowed_origins: AllowedOrigins,
+    max_body_size: u64,
+    rate_limiter: Option<RateLimiter>,
+    trusted_proxies: Vec<IpAddr>,
+    /// The hashes of the inline scripts which are allowed by the
+    /// content security policy.
+    script_hashes: Vec<String>,
+    strict_transport_security: bool,
+}
+
+impl Security {
+    pub fn new(config: &Config) -> Self {
+        Security {
+            allowed_origins: AllowedOrigins::new(&config.allowed_origins),
+            max_body_size: config.max_body_size,
+            rate_limiter: match config.rate_limit {
+                0 => None,
+                limit => Some(RateLimiter::new(limit)),
+            },
+            trusted_proxies: config.trusted_proxies.clone(),
+            script_hashes: Vec::new(),
+            strict_transport_security: config.tls.is_some(),
+        }
+    }
+
+    /// Allow the inline `<script>` elements in the `html` page to be
+    /// run by the content security policy.
+    pub fn allow_inline_scripts(mut self, html: &str) -> Self {
+        self.script_hashes.extend(inline_script_hashes(html));
+        self
+    }
+
+    fn content_security_policy(&self) -> String {
+        let scripts: String = self
+            .script_hashes
+            .iter()
+            .map(|hash| format!(" '{}'", hash))
+            .collect();
+        CONTENT_SECURITY_POLICY.replace("{scripts}", &scripts)
+    }
+
+    /// Check the `filter`'s requests before they're handled, and add
+    /// the security and CORS headers to its responses. Requests which
+    /// are refused receive an error response from the REST API.
+    pub fn protect<F, T>(self, filter: F) -> BoxedFilter<(Response,)>
+    where
+        F: Filter<Extract = (T,), Error = Rejection> + Clone + Send + Sync + 'static,
+        T: Reply,
+    {
+        let security = Arc::new(self);
+        let checked = warp::any()
+            .map(move || security.clone())
+            .and(warp::method())
+            .and(warp::header::headers_cloned())
+            .and(remote_address())
+            .and_then(
+                |security: Arc<Security>,
+                 method: Method,
+                 headers: HeaderMap,
+                 remote: Option<SocketAddr>| async move {
+                    let checked = Checked {
+                        cross_origin: cross_origin(&headers)
+                            .filter(|origin| {
+                                security
+                                    .allowed_origins
+                                    .allows(origin.to_str().unwrap_or_default())
+                            })
+                            .cloned(),
+                        preflight: method == Method::OPTIONS
+                            && headers.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD),
+                    };
+                    match security.check(&headers, remote, Instant::now()) {
+                        Ok(()) => Ok((security, checked)),
+                        Err(error) => Err(reject::custom(SecurityRejection {
+                            security,
+                            checked,
+                            error,
+                        })),
+                    }
+                },
+            )
+            .untuple_one();
+
+        // preflight requests are answered here, because the routes
+        // don't handle OPTIONS requests
+        let preflight = warp::options()
+            .and(warp::header::<String>(
+                header::ACCESS_CONTROL_REQUEST_METHOD.as_str(),
+            ))
+            .map(|_| StatusCode::NO_CONTENT.into_response());
+
+        checked
+            .and(
+                preflight
+                    .or(filter.map(|reply: T| reply.into_response()))
+                    .unify(),
+            )
+            .map(
+                |security: Arc<Security>, checked: Checked, response: Response| {
+                    security.add_headers(response, &checked)
+                },
+            )
+            .recover(|rejection: Rejection| async move {
+                match rejection.find::<SecurityRejection>() {
+                    Some(refused) => {
+                        let mut response = error_response(&refused.error);
+                        if let SecurityError::RateLimited(retry_after) = &refused.error {
+                            response.headers_mut().insert(
+                                header::RETRY_AFTER,
+                                HeaderValue::from(retry_seconds(retry_after)),
+                            );
+                        }
+                        Ok(refused.security.add_headers(response, &refused.checked))
+                    }
+                    None => Err(rejection),
+                }
+            })
+            .unify()
+            .boxed()
+    }
+
+    /// Check whether the request with the `headers`, sent by the
+    /// client at the `remote` address, may be handled.
+    fn check(
+        &self,
+        headers: &HeaderMap,
+        remote: Option<SocketAddr>,
+        now: Instant,
+    ) -> Result<(), SecurityError> {
+        if let (Some(rate_limiter), Some(remote)) = (&self.rate_limiter, remote) {
+            rate_limiter
+                .acquire(self.client_address(headers, remote.ip()), now)
+                .map_err(SecurityError::RateLimited)?;
+        }
+
+        if let Some(origin) = cross_origin(headers) {
+            let origin = origin.to_str().unwrap_or_default();
+            if !self.allowed_origins.allows(origin) {
+                return Err(SecurityError::OriginNotAllowed(origin.to_string()));
+            }
+        }
+
+        match headers.get(header::CONTENT_LENGTH) {
+            Some(length) => {
+                let size = length
+                    .to_str()
+                    .ok()
+                    .and_then(|length| length.parse().ok())
+                    .ok_or(SecurityError::LengthRequired)?;
+                if size > self.max_body_size {
+                    return Err(SecurityError::BodyTooLarge {
+                        size,
+                        limit: self.max_body_size,
+                    });
+                }
+            }
+            // the size of a chunked body can't be checked before it
+            // has been read
+            None if headers.contains_key(header::TRANSFER_ENCODING) => {
+                return Err(SecurityError::LengthRequired)
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    /// The address of the client which sent a request with the
+    /// `headers` from the `peer` address. Each trusted proxy appends
+    /// the address which it received the request from to the
+    /// forwarded addresses, so they're followed from the right until
+    /// one which isn't a trusted proxy is found.
+    fn client_address(&self, headers: &HeaderMap, peer: IpAddr) -> IpAddr {
+        let mut client = peer;
+        for forwarded in forwarded_addresses(headers).into_iter().rev() {
+            if !self.trusted_proxies.contains(&client) {
+                break;
+            }
+            match forwarded {
+                Some(address) => client = address,
+                // an obfuscated or invalid address can't be followed
+                None => break,
+            }
+        }
+        client
+    }
+
+    /// Add the security headers (unless the route has set them) and
+    /// the CORS headers to the `response`.
+    fn add_headers(&self, mut response: Response, checked: &Checked) -> Response {
+        let headers = response.headers_mut();
+        let mut set_default = |name: HeaderName, value: HeaderValue| {
+            headers.entry(name).or_insert(value);
+        };
+        set_default(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_str(&self.content_security_policy())
+                .expect("the content security policy is a valid header"),
+        );
+        set_default(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+        set_default(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        );
+        set_default(
+            header::REFERRER_POLICY,
+            HeaderValue::from_static("same-origin"),
+        );
+        if self.strict_transport_security {
+            set_default(
+                header::STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_static(STRICT_TRANSPORT_SECURITY),
+            );
+        }
+
+        if let Some(origin) = &checked.cross_origin {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+            headers.insert(
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                HeaderValue::from_static("retry-after"),
+            );
+            if let AllowedOrigins::List(_) = self.allowed_origins {
+                headers.insert(
+                    header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                    HeaderValue::from_static("true"),
+                );
+            }
+            if checked.preflight {
+                headers.insert(
+                    header::ACCESS_CONTROL_ALLOW_METHODS,
+                    HeaderValue::from_static(ALLOWED_METHODS),
+                );
+                headers.insert(
+                    header::ACCESS_CONTROL_ALLOW_HEADERS,
+                    HeaderValue::from_static(ALLOWED_HEADERS),
+                );
+                headers.insert(
+                    header::ACCESS_CONTROL_MAX_AGE,
+                    HeaderValue::from_static(PREFLIGHT_MAX_AGE),
+                );
+            }
+        }
+        response
+    }
+}
+
+/// The `Origin` of a request, if it was made from a different origin
+/// than the server's (the request's `Host`).
+fn cross_origin(headers: &HeaderMap) -> Option<&HeaderValue> {
+    let origin = headers.get(header::ORIGIN)?;
+    let origin_host = origin
+        .to_str()
+        .ok()
+        .and_then(|origin| origin.split_once("://"))
+        .map(|(_, origin_host)| origin_host);
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok());
+    match (origin_host, host) {
+        (Some(origin_host), Some(host)) if origin_host.eq_ignore_ascii_case(host) => None,
+        _ => Some(origin),
+    }
+}
+
+/// The addresses which a request was forwarded from, in the order
+/// they were added by the proxies, taken from the `X-Forwarded-For`
+/// header, or the `for` parameters of the `Forwarded` header if there
+/// isn't one. Addresses which can't be parsed are `None`.
+fn forwarded_addresses(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let x_forwarded_for = headers.get_all(X_FORWARDED_FOR);
+    if x_forwarded_for.iter().next().is_some() {
+        return x_forwarded_for
+            .iter()
+            .flat_map(|value| match value.to_str() {
+                Ok(value) => value.split(',').map(parse_forwarded_address).collect(),
+                Err(_) => vec![None],
+            })
+            .collect();
+    }
+
+    headers
+        .get_all(header::FORWARDED)
+        .iter()
+        .flat_map(|value| match value.to_str() {
+            Ok(value) => value
+                .split(',')
+                .map(|element| {
+                    element
+                        .split(';')
+                        .filter_map(|pair| pair.split_once('='))
+                        .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                        .and_then(|(_, address)| parse_forwarded_address(address))
+                })
+                .collect(),
+            Err(_) => vec![None],
+        })
+        .collect()
+}
+
+/// Parse a forwarded address, which may be quoted, and include a port
+/// (in which case an IPv6 address is enclosed in brackets).
+fn parse_forwarded_address(address: &str) -> Option<IpAddr> {
+    let address = address.trim().trim_matches('"');
+    address
+        .parse()
+        .or_else(|_| address.parse().map(|address: SocketAddr| address.ip()))
+        .or_else(|_| {
+            address
+                .strip_prefix('[')
+                .and_then(|address| address.strip_suffix(']'))
+                .unwrap_or_default()
+                .parse()
+        })
+        .ok()
+}
+
+/// The hashes (in the form used by a content security policy) of the
+/// contents of the `<script>` elements in the `html` which don't have
+/// a `src`.
+fn inline_script_hashes(html: &str) -> Vec<String> {
+    let mut hashes = Vec::new();
+    let mut remaining = html;
+    while let Some(start) = remaining.find("<script") {
+        let element = &remaining[start..];
+        let content_start = match element.find('>') {
+            Some(end) => end + 1,
+            None => break,
+        };
+        let content_end = match element[content_start..].find("</script>") {
+            Some(length) => content_start + length,
+            None => break,
+        };
+        if !element[..content_start].contains("src=") {
+            let hash = digest(&SHA256, &element.as_bytes()[content_start..content_end]);
+            hashes.push(format!("sha256-{}", base64::encode(hash.as_ref())));
+        }
+        remaining = &element[content_end..];
+    }
+    hashes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{inline_script_hashes, RateLimiter, Security, SecurityError, X_FORWARDED_FOR};
+    use crate::config::Options;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{Duration, Instant};
+    use warp::http::{header, HeaderMap, HeaderValue};
+    use warp::Filter;
+
+    fn test_security(args: &[&str]) -> Security {
+        let args = std::iter::once("coster").chain(args.iter().cloned());
+        let options = Options::load(args, |_| None).unwrap();
+        Security::new(&options.config)
+    }
+
+    #[test]
+    fn rate_limit() {
+        let limiter = RateLimiter::new(2);
+        let client = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let other_client = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let start = Instant::now();
+
+        assert!(limiter.acquire(client, start).is_ok());
+        assert!(limiter.acquire(client, start).is_ok());
+        assert_eq!(Err(Duration::from_secs(30)), limiter.acquire(client, start));
+        assert!(limiter.acquire(other_client, start).is_ok());
+        assert!(limiter
+            .acquire(client, start + Duration::from_secs(30))
+            .is_ok());
+
+        // full buckets are removed
+        assert!(limiter
+            .acquire(client, start + Duration::from_secs(120))
+            .is_ok());
+        assert_eq!(1, limiter.buckets.lock().unwrap().clients.len());
+    }
+
+    #[test]
+    fn client_address() {
+        let security = test_security(&["--trusted-proxy", "10.0.0.1", "--trusted-proxy", "::1"]);
+        let proxy = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let client = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let mut headers = HeaderMap::new();
+        assert_eq!(proxy, security.client_address(&headers, proxy));
+
+        // the client can add its own addresses, which are ignored
+        headers.insert(
+            X_FORWARDED_FOR,
+            HeaderValue::from_static("198.51.100.1, 192.0.2.1"),
+        );
+        assert_eq!(client, security.client_address(&headers, proxy));
+        headers.append(X_FORWARDED_FOR, HeaderValue::from_static("::1"));
+        assert_eq!(client, security.client_address(&headers, proxy));
+
+        // the headers of untrusted peers are ignored
+        assert_eq!(client, security.client_address(&headers, client));
+
+        headers.insert(X_FORWARDED_FOR, HeaderValue::from_static("unknown"));
+        assert_eq!(proxy, security.client_address(&headers, proxy));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::FORWARDED,
+            HeaderValue::from_static(
+                "for=198.51.100.1, for=\"192.0.2.1:4711\";proto=https, For=\"[::1]\"",
+            ),
+        );
+        assert_eq!(client, security.client_address(&headers, proxy));
+    }
+
+    #[test]
+    fn check_requests() {
+        let security = test_security(&["--max-body-size", "10"]);
+        let now = Instant::now();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(10));
+        assert!(security.check(&headers, None, now).is_ok());
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(11));
+        assert!(matches!(
+            security.check(&headers, None, now),
+            Err(SecurityError::BodyTooLarge {
+                size: 11,
+                limit: 10
+            })
+        ));
+        headers.remove(header::CONTENT_LENGTH);
+        headers.insert(
+            header::TRANSFER_ENCODING,
+            HeaderValue::from_static("chunked"),
+        );
+        assert!(matches!(
+            security.check(&headers, None, now),
+            Err(SecurityError::LengthRequired)
+        ));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_static("localhost:8000"));
+        headers.insert(
+            header::ORIGIN,
+            HeaderValue::from_static("http://localhost:8000"),
+        );
+        assert!(security.check(&headers, None, now).is_ok());
+        headers.insert(
+            header::ORIGIN,
+            HeaderValue::from_static("https://evil.example.com"),
+        );
+        assert!(matches!(
+            security.check(&headers, None, now),
+            Err(SecurityError::OriginNotAllowed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn cors() {
+        let routes = test_security(&["--allowed-origin", "https://coster.example.com"])
+            .protect(warp::post().map(|| "posted"));
+
+        let response = warp::test::request()
+            .method("OPTIONS")
+            .header("host", "api.example.com")
+            .header("origin", "https://coster.example.com")
+            .header("access-control-request-method", "POST")
+            .reply(&routes)
+            .await;
+        assert_eq!(204, response.status());
+        assert_eq!(
+            "https://coster.example.com",
+            response.headers()["access-control-allow-origin"]
+        );
+        assert_eq!(
+            "true",
+            response.headers()["access-control-allow-credentials"]
+        );
+        assert!(response
+            .headers()
+            .contains_key("access-control-allow-methods"));
+
+        let response = warp::test::request()
+            .method("POST")
+            .header("host", "api.example.com")
+            .header("origin", "https://evil.example.com")
+            .reply(&routes)
+            .await;
+        assert_eq!(403, response.status());
+        assert!(!response
+            .headers()
+            .contains_key("access-control-allow-origin"));
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!("ORIGIN_NOT_ALLOWED", body["error"]["code"]);
+
+        let routes =
+            test_security(&["--allowed-origin", "*"]).protect(warp::post().map(|| "posted"));
+        let response = warp::test::request()
+            .method("POST")
+            .header("origin", "https://any.example.com")
+            .reply(&routes)
+            .await;
+        assert_eq!(200, response.status());
+        assert_eq!(
+            "https://any.example.com",
+            response.headers()["access-control-allow-origin"]
+        );
+        assert!(!response
+            .headers()
+            .contains_key("access-control-allow-credentials"));
+    }
+
+    #[tokio::test]
+    async fn security_headers() {
+        let html = "<script type=\"module\">run();</script><script src=\"/a.js\"></script>";
+        let routes = test_security(&[])
+            .allow_inline_scripts(html)
+            .protect(warp::path("page").map(|| "page"));
+
+        let response = warp::test::request().path("/page").reply(&routes).await;
+        assert_eq!(200, response.status());
+        assert_eq!("DENY", response.headers()["x-frame-options"]);
+        assert_eq!("nosniff", response.headers()["x-content-type-options"]);
+        assert!(!response.headers().contains_key("strict-transport-security"));
+        let policy = response.headers()["content-security-policy"]
+            .to_str()
+            .unwrap();
+        assert!(policy.contains(&format!("'{}'", inline_script_hashes(html)[0])));
+
+        // other rejections are passed on
+        assert!(warp::test::request()
+            .path("/other")
+            .filter(&routes)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn script_hashes() {
+        let hashes =
+            inline_script_hashes("<script>alert(1)</script><script src=\"a.js\"></script>");
+        // echo -n "alert(1)" | openssl sha256 -binary | base64
+        assert_eq!(
+            vec!["sha256-bhHHL3z2vDgxUt0W3dWQOrprscmda2Y5pLsLg4GF+pI=".to_string()],
+            hashes
+        );
+    }
+}
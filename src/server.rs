@@ -0,0 +1,307 @@
+//! Serving the routes to the connections accepted by the server, over
+//! HTTP or (using [crate::tls]) HTTPS.
+//!
+//! Each request is assigned a [RequestId], which is available to the
+//! routes using [RequestId::current], returned in the response's
+//! [REQUEST_ID_HEADER], and included in the access log (the
+//! `coster::access` target), which leaves out the secret tokens of
+//! invites and share links. The address of the client is available
+//! using [remote_address](crate::security::remote_address).
+//!
+//! Once shutting down is requested using the [ShutdownSignal], the
+//! server stops accepting connections, and each connection is closed
+//! once its current request has been handled. Connections which have
+//! been upgraded (to WebSockets) are not waited for.
+
+use crate::request_id::{RequestId, REQUEST_ID_HEADER};
+use crate::security::RemoteAddress;
+use crate::shutdown::ShutdownSignal;
+use log::{debug, error, info};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use warp::http::{header, HeaderValue, Request};
+use warp::hyper::server::conn::Http;
+use warp::hyper::service::{service_fn, Service};
+use warp::hyper::Body;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+/// How long a connection which isn't handling a request is given to
+/// finish writing its last response when shutting down.
+const IDLE_CONNECTION_GRACE: Duration = Duration::from_secs(1);
+
+/// The path segments which are followed by the secret token of an
+/// invite or share link, e.g. `/join/{token}`.
+const TOKEN_SEGMENTS: &[&str] = &["shared", "join", "invites", "share-links"];
+
+/// Serve the `filter` over HTTP to the connections accepted by the
+/// `listener`, until shutting down is requested by the `shutdown`
+/// signal.
+pub async fn serve<F>(filter: F, mut listener: TcpListener, mut shutdown: ShutdownSignal)
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.requested() => return,
+        };
+        let (stream, remote_address) = match accepted {
+            Ok(connection) => connection,
+            Err(error) => {
+                error!("Unable to accept a connection: {}", error);
+                continue;
+            }
+        };
+        tokio::spawn(serve_connection(
+            filter.clone(),
+            stream,
+            remote_address,
+            shutdown.clone(),
+        ));
+    }
+}
+
+/// Serve the `filter` to a connection from the client at the
+/// `remote_address`, until shutting down is requested by the
+/// `shutdown` signal.
+pub(crate) async fn serve_connection<F, S>(
+    filter: F,
+    stream: S,
+    remote_address: SocketAddr,
+    mut shutdown: ShutdownSignal,
+) where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let in_progress = Arc::new(AtomicUsize::new(0));
+    let requests = in_progress.clone();
+    let service = service_fn(move |request| {
+        let request_guard = InProgress::start(&requests);
+        let response = handle(filter.clone(), request, remote_address);
+        async move {
+            let response = response.await;
+            drop(request_guard);
+            response
+        }
+    });
+    let connection = Http::new()
+        .serve_connection(stream, service)
+        .with_upgrades();
+    tokio::pin!(connection);
+
+    let result = tokio::select! {
+        result = &mut connection => result,
+        _ = shutdown.requested() => {
+            connection.as_mut().graceful_shutdown();
+            // a connection which hasn't received a request yet isn't
+            // closed by graceful_shutdown, so it isn't waited for
+            if in_progress.load(Ordering::SeqCst) == 0 {
+                tokio::time::timeout(IDLE_CONNECTION_GRACE, connection)
+                    .await
+                    .unwrap_or(Ok(()))
+            } else {
+                connection.await
+            }
+        }
+    };
+    if let Err(error) = result {
+        debug!("Error serving {}: {}", remote_address, error);
+    }
+}
+
+/// Counts a request as being in progress on a connection, until it
+/// is dropped.
+struct InProgress(Arc<AtomicUsize>);
+
+impl InProgress {
+    fn start(requests: &Arc<AtomicUsize>) -> Self {
+        requests.fetch_add(1, Ordering::SeqCst);
+        InProgress(requests.clone())
+    }
+}
+
+impl Drop for InProgress {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+async fn handle<F>(
+    filter: F,
+    mut request: Request<Body>,
+    remote_address: SocketAddr,
+) -> Result<Response, Infallible>
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(RequestId::from_header)
+        .unwrap_or_else(RequestId::generate);
+    request
+        .extensions_mut()
+        .insert(RemoteAddress(remote_address));
+    // HTTP/2 requests specify the host in the URI instead
+    if !request.headers().contains_key(header::HOST) {
+        let host = request
+            .uri()
+            .authority()
+            .and_then(|authority| HeaderValue::from_str(authority.as_str()).ok());
+        if let Some(host) = host {
+            request.headers_mut().insert(header::HOST, host);
+        }
+    }
+
+    let start = Instant::now();
+    let method = request.method().clone();
+    let path = redact_tokens(request.uri().path());
+    let version = request.version();
+
+    let mut response = request_id
+        .clone()
+        .scope(async move { warp::service(filter).call(request).await })
+        .await?;
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, request_id.header_value());
+
+    info!(
+        target: "coster::access",
+        "request_id={} remote={} \"{} {} {:?}\" {} {:?}",
+        request_id,
+        remote_address,
+        method,
+        path,
+        version,
+        response.status().as_u16(),
+        start.elapsed()
+    );
+    Ok(response)
+}
+
+/// The `path` with the tokens which follow the [TOKEN_SEGMENTS]
+/// replaced, so that they aren't logged.
+fn redact_tokens(path: &str) -> String {
+    let mut follows_token_segment = false;
+    path.split('/')
+        .map(|segment| {
+            let redacted = if follows_token_segment && !segment.is_empty() {
+                "[redacted]"
+            } else {
+                segment
+            };
+            follows_token_segment = TOKEN_SEGMENTS.contains(&segment);
+            redacted
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{redact_tokens, serve};
+    use crate::request_id::RequestId;
+    use crate::security::remote_address;
+    use crate::shutdown::Shutdown;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use warp::{Filter, Rejection};
+
+    /// Send a request to the server at `address`, returning the head
+    /// and body of the response.
+    async fn send(address: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serve_http() {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let address = listener.local_addr().unwrap();
+        let slow = warp::path("slow").and_then(|| async {
+            tokio::time::delay_for(Duration::from_millis(200)).await;
+            Ok::<_, Rejection>("done".to_string())
+        });
+        let routes = slow.or(remote_address().map(|remote: Option<SocketAddr>| {
+            format!("{} {}", remote.unwrap().ip(), RequestId::current().unwrap())
+        }));
+        let shutdown = Shutdown::new();
+        let server = tokio::spawn(serve(routes, listener, shutdown.signal()));
+
+        let response = send(
+            address,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nX-Request-Id: abc-123\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(
+            response.contains("x-request-id: abc-123\r\n"),
+            "{}",
+            response
+        );
+        assert!(response.ends_with("127.0.0.1 abc-123"), "{}", response);
+
+        let response = send(
+            address,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        let (head, body) = response.split_at(response.find("\r\n\r\n").unwrap());
+        let request_id = body.trim().split(' ').nth(1).unwrap();
+        assert!(
+            head.contains(&format!("x-request-id: {}\r\n", request_id)),
+            "{}",
+            response
+        );
+
+        // an idle connection doesn't delay shutting down, and the
+        // requests in progress are completed
+        let _idle = TcpStream::connect(address).await.unwrap();
+        let slow = tokio::spawn(send(
+            address,
+            "GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        ));
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+        assert!(shutdown.shutdown(Duration::from_secs(5)).await);
+        server.await.unwrap();
+        let response = slow.await.unwrap();
+        assert!(response.ends_with("done"), "{}", response);
+        assert!(TcpStream::connect(address).await.is_err());
+    }
+
+    #[test]
+    fn redact_access_log_tokens() {
+        assert_eq!("/shared/[redacted]", redact_tokens("/shared/abc"));
+        assert_eq!("/join/[redacted]", redact_tokens("/join/abc"));
+        assert_eq!(
+            "/api/v1/invites/[redacted]/redeem",
+            redact_tokens("/api/v1/invites/abc/redeem")
+        );
+        assert_eq!(
+            "/api/v1/tabs/1/share-links/[redacted]",
+            redact_tokens("/api/v1/tabs/1/share-links/abc")
+        );
+        assert_eq!(
+            "/api/v1/tabs/1/invites",
+            redact_tokens("/api/v1/tabs/1/invites")
+        );
+        assert_eq!("/tabs/1", redact_tokens("/tabs/1"));
+    }
+}
@@ -0,0 +1,1305 @@
+//! Operations on the server's tabs and accounts which are shared by
+//! all of its APIs, so that every change to a tab is authorized,
+//! validated, stored and published in the same way.
+
+use crate::accounts::{
+    normalize_email, verify_dummy_password, Account, AuthError, Session, TabMember,
+};
+use crate::error::{ErrorBody, ErrorCode, InputError};
+use crate::events::{TabEvent, TabEvents};
+use crate::invites::{Invite, InviteError, Redemption};
+use crate::repository::{RepositoryError, RepositoryRef};
+use crate::request_id::RequestId;
+use crate::shares::{ShareLink, ShareLinkError};
+use crate::sync::{SyncError, SyncPush, Synchronisation};
+use chrono::{DateTime, NaiveDate, Utc};
+use commodity::{CommodityType, CommodityTypeID};
+use costing::actions::{AddUser, IdRemapping, TabUserAction, TabUserActionType};
+use costing::{CostingError, Tab, TabID, User, UserID};
+use log::error;
+use std::sync::{Arc, Mutex, MutexGuard};
+use thiserror::Error;
+use uuid::Uuid;
+use warp::http::StatusCode;
+
+/// An error which occurs while performing an operation on the tabs.
+#[derive(Error, Debug)]
+pub enum ServiceError {
+    #[error(transparent)]
+    Repository(#[from] RepositoryError),
+    #[error(transparent)]
+    Costing(#[from] CostingError),
+    #[error(transparent)]
+    Input(#[from] InputError),
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+    #[error(transparent)]
+    Invite(#[from] InviteError),
+    #[error(transparent)]
+    ShareLink(#[from] ShareLinkError),
+    #[error(transparent)]
+    Sync(#[from] SyncError),
+}
+
+impl ErrorCode for ServiceError {
+    fn code(&self) -> &'static str {
+        match self {
+            ServiceError::Repository(error) => error.code(),
+            ServiceError::Costing(error) => error.code(),
+            ServiceError::Input(error) => error.code(),
+            ServiceError::Auth(error) => error.code(),
+            ServiceError::Invite(error) => error.code(),
+            ServiceError::ShareLink(error) => error.code(),
+            ServiceError::Sync(error) => error.code(),
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            ServiceError::Repository(error) => error.status(),
+            ServiceError::Costing(error) => error.status(),
+            ServiceError::Input(error) => error.status(),
+            ServiceError::Auth(error) => error.status(),
+            ServiceError::Invite(error) => error.status(),
+            ServiceError::ShareLink(error) => error.status(),
+            ServiceError::Sync(error) => error.status(),
+        }
+    }
+}
+
+/// Parse an ISO 4217 currency code, e.g. `AUD`.
+pub fn parse_currency(currency: &str) -> Result<CommodityTypeID, InputError> {
+    CommodityType::from_currency_alpha3(currency)
+        .map(|commodity_type| commodity_type.id)
+        .map_err(|error| InputError::InvalidCurrency(currency.to_string(), error))
+}
+
+/// Reads and modifies the accounts and tabs stored in a repository.
+/// Cloning a [TabService] is cheap, and the clones share the same
+/// repository and [TabEvents].
+///
+/// Operations on a tab are performed on behalf of an [Account], which
+/// needs to be a member of the tab.
+#[derive(Clone)]
+pub struct TabService {
+    repository: RepositoryRef,
+    events: TabEvents,
+    /// Held while a tab is read, modified and written, so that
+    /// concurrent modifications of the same tab don't lose updates.
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl TabService {
+    pub fn new(repository: RepositoryRef, events: TabEvents) -> Self {
+        TabService {
+            repository,
+            events,
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// The events published when an action is performed on a tab.
+    pub fn events(&self) -> &TabEvents {
+        &self.events
+    }
+
+    /// Call `f` with this service on a thread where blocking is
+    /// allowed. The operations of the service hash passwords, and
+    /// access the repository synchronously, so the APIs perform them
+    /// using this, to avoid blocking the runtime's threads.
+    pub async fn blocking<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&TabService) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let service = self.clone();
+        // errors are logged and returned with the id of the request
+        let request_id = RequestId::current();
+        let call = move || match request_id {
+            Some(request_id) => request_id.scope_blocking(|| f(&service)),
+            None => f(&service),
+        };
+        match tokio::task::spawn_blocking(call).await {
+            Ok(value) => value,
+            // continue panicking, as if `f` had been called directly
+            Err(error) => std::panic::resume_unwind(error.into_panic()),
+        }
+    }
+
+    /// Prevent other modifications until the returned guard is
+    /// dropped. The lock doesn't protect any data of its own, so a
+    /// panic while it was held (e.g. in a background job) doesn't
+    /// prevent later modifications.
+    fn lock_writes(&self) -> MutexGuard<'_, ()> {
+        self.write_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Create a new account, and log in to it.
+    pub fn register(
+        &self,
+        name: &str,
+        email: &str,
+        password: &str,
+    ) -> Result<(Account, Session), ServiceError> {
+        // hashing the password is slow, so it is done before taking
+        // the lock
+        let account = Account::new(name, email, password, Utc::now())?;
+
+        let _guard = self.lock_writes();
+        if let Some(email) = &account.email {
+            if self.repository.account_by_email(email)?.is_some() {
+                return Err(AuthError::EmailAlreadyRegistered(email.clone()).into());
+            }
+        }
+        self.repository.add_account(&account)?;
+
+        let session = Session::new(account.id, account.created_at);
+        self.repository.add_session(&session)?;
+        Ok((account, session))
+    }
+
+    /// Log in to the account with the specified `email` address,
+    /// creating a new session.
+    pub fn login(&self, email: &str, password: &str) -> Result<(Account, Session), ServiceError> {
+        let account = match normalize_email(email) {
+            Ok(email) => self.repository.account_by_email(&email)?,
+            Err(_) => None,
+        };
+
+        // a password is checked even if there is no account, so that
+        // the time taken doesn't reveal whether it exists
+        let verified = match &account {
+            Some(account) => account.verify_password(password)?,
+            None => verify_dummy_password(password)?,
+        };
+        let account = match account {
+            Some(account) if verified => account,
+            _ => return Err(AuthError::InvalidCredentials.into()),
+        };
+
+        let session = Session::new(account.id, Utc::now());
+        self.repository.add_session(&session)?;
+        Ok((account, session))
+    }
+
+    /// End the session with the specified `token`.
+    pub fn logout(&self, token: &str) -> Result<(), ServiceError> {
+        Ok(self.repository.delete_session(token)?)
+    }
+
+    /// The account logged in to the session with the specified
+    /// `token`.
+    pub fn authenticate(&self, token: &str) -> Result<Account, ServiceError> {
+        let session = self
+            .repository
+            .session(token)?
+            .ok_or(AuthError::InvalidSession)?;
+
+        if session.is_expired(Utc::now()) {
+            self.repository.delete_session(token)?;
+            return Err(AuthError::InvalidSession.into());
+        }
+
+        Ok(self
+            .repository
+            .account(&session.account_id)?
+            .ok_or(AuthError::InvalidSession)?)
+    }
+
+    /// The tabs (including those in the trash) which the `account` is
+    /// a member of.
+    pub fn memberships(&self, account: &Account) -> Result<Vec<TabMember>, ServiceError> {
+        Ok(self.repository.account_memberships(&account.id)?)
+    }
+
+    /// The members of the tab with the specified `id`. The `account`
+    /// needs to be a member of the tab.
+    pub fn tab_members(
+        &self,
+        account: &Account,
+        id: &TabID,
+    ) -> Result<Vec<TabMember>, ServiceError> {
+        self.member(account, id)?;
+        Ok(self.repository.tab_members(id)?)
+    }
+
+    /// The membership of the `account` on the tab with the specified
+    /// `id`.
+    fn member(&self, account: &Account, id: &TabID) -> Result<TabMember, ServiceError> {
+        let members = self.repository.tab_members(id)?;
+        match members
+            .into_iter()
+            .find(|member| member.account_id == account.id)
+        {
+            Some(member) => Ok(member),
+            None if self.repository.tab(id)?.is_none() => {
+                Err(RepositoryError::TabNotFound(*id).into())
+            }
+            None => Err(AuthError::NotTabMember(*id).into()),
+        }
+    }
+
+    /// All the tabs which the `account` is a member of, not including
+    /// those in the trash.
+    pub fn tabs(&self, account: &Account) -> Result<Vec<Tab>, ServiceError> {
+        let memberships = self.memberships(account)?;
+        let tabs = self
+            .repository
+            .tab_ids()?
+            .into_iter()
+            .filter(|id| memberships.iter().any(|member| member.tab_id == *id))
+            .filter_map(|id| match self.stored_tab(&id) {
+                Ok(tab) => Some(tab),
+                Err(err) => {
+                    // skipped, so that one tab which can't be read
+                    // doesn't prevent listing the others
+                    error!("Skipping tab {}: {}", id, err);
+                    None
+                }
+            })
+            .collect();
+        Ok(tabs)
+    }
+
+    /// The number of tabs stored, not including those in the trash.
+    /// This also checks that the repository is available.
+    pub fn tab_count(&self) -> Result<usize, ServiceError> {
+        Ok(self.repository.tab_ids()?.len())
+    }
+
+    /// The tab with the specified `id`, or `None` if it doesn't
+    /// exist. The `account` needs to be a member of the tab.
+    pub fn find_tab(&self, account: &Account, id: &TabID) -> Result<Option<Tab>, ServiceError> {
+        match self.repository.tab(id)? {
+            Some(tab) => {
+                self.member(account, id)?;
+                Ok(Some(tab))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// The tab with the specified `id`, which is expected to exist.
+    /// The `account` needs to be a member of the tab.
+    pub fn tab(&self, account: &Account, id: &TabID) -> Result<Tab, ServiceError> {
+        self.member(account, id)?;
+        self.stored_tab(id)
+    }
+
+    fn stored_tab(&self, id: &TabID) -> Result<Tab, ServiceError> {
+        self.repository
+            .tab(id)?
+            .ok_or_else(|| RepositoryError::TabNotFound(*id).into())
+    }
+
+    /// The tab with the specified `id`, with only the closed periods
+    /// needed to calculate its balances as of the specified date, for
+    /// showing (but not modifying) it. The `account` needs to be a
+    /// member of the tab.
+    pub fn tab_as_of(
+        &self,
+        account: &Account,
+        id: &TabID,
+        as_of: NaiveDate,
+    ) -> Result<Tab, ServiceError> {
+        self.member(account, id)?;
+        self.stored_tab_as_of(id, as_of)
+    }
+
+    fn stored_tab_as_of(&self, id: &TabID, as_of: NaiveDate) -> Result<Tab, ServiceError> {
+        self.repository
+            .tab_as_of(id, as_of)?
+            .ok_or_else(|| RepositoryError::TabNotFound(*id).into())
+    }
+
+    /// Store a new `tab`, with the `account` as a member, linked to
+    /// the tab's user with the specified `user_id`.
+    pub fn add_tab(
+        &self,
+        account: &Account,
+        tab: &Tab,
+        user_id: UserID,
+    ) -> Result<(), ServiceError> {
+        tab.user(&user_id)?;
+
+        let _guard = self.lock_writes();
+        self.store_tab(
+            tab,
+            &TabMember {
+                tab_id: tab.id,
+                user_id,
+                account_id: account.id,
+            },
+        )
+    }
+
+    /// Store a new `tab`, and its first `member`. If the member can't
+    /// be stored, the tab is deleted again, so that it isn't left
+    /// without any members. The write lock needs to be held by the
+    /// caller.
+    fn store_tab(&self, tab: &Tab, member: &TabMember) -> Result<(), ServiceError> {
+        self.repository.add_tab(tab)?;
+        if let Err(error) = self.repository.add_tab_member(member) {
+            self.repository.delete_tab(&tab.id)?;
+            return Err(error.into());
+        }
+        Ok(())
+    }
+
+    /// Make the account with the specified `email` address a member
+    /// of the tab with the specified `id`, linked to the tab's user
+    /// with the specified `user_id`. The `account` performing the
+    /// operation needs to be a member of the tab.
+    pub fn link_user(
+        &self,
+        account: &Account,
+        id: &TabID,
+        user_id: UserID,
+        email: &str,
+    ) -> Result<TabMember, ServiceError> {
+        let _guard = self.lock_writes();
+        self.member(account, id)?;
+        self.stored_tab(id)?.user(&user_id)?;
+
+        let email = normalize_email(email)?;
+        let linked_account = self
+            .repository
+            .account_by_email(&email)?
+            .ok_or(AuthError::AccountNotFound(email))?;
+
+        let members = self.repository.tab_members(id)?;
+        if members.iter().any(|member| member.user_id == user_id) {
+            return Err(AuthError::UserAlreadyLinked(*id, user_id).into());
+        }
+        if members
+            .iter()
+            .any(|member| member.account_id == linked_account.id)
+        {
+            return Err(AuthError::AlreadyTabMember(*id).into());
+        }
+
+        let member = TabMember {
+            tab_id: *id,
+            user_id,
+            account_id: linked_account.id,
+        };
+        self.repository.add_tab_member(&member)?;
+        Ok(member)
+    }
+
+    /// Perform the action created by `f` on the tab with the specified
+    /// `id`, on behalf of the `account`. `f` is called with the tab,
+    /// and the id of the user which the `account` is linked to, which
+    /// needs to be the user performing the action. If the action
+    /// succeeds, it is recorded in the tab's `user_actions`, the tab
+    /// is stored, and a [TabEvent] is published. Returns the modified
+    /// tab.
+    pub fn perform<F>(&self, account: &Account, id: &TabID, f: F) -> Result<Tab, ServiceError>
+    where
+        F: FnOnce(&Tab, UserID) -> Result<TabUserActionType, ServiceError>,
+    {
+        let _guard = self.lock_writes();
+
+        let member = self.member(account, id)?;
+        let mut tab = self.stored_tab(id)?;
+        let action = f(&tab, member.user_id)?;
+
+        if action.metadata().user_id != member.user_id {
+            return Err(AuthError::WrongActingUser(member.user_id).into());
+        }
+        tab.user(&member.user_id)?;
+        self.apply(&mut tab, action)?;
+        Ok(tab)
+    }
+
+    /// Perform the `action` on the `tab`, record it in the tab's
+    /// `user_actions`, store the tab, and publish a [TabEvent]. The
+    /// write lock needs to be held by the caller.
+    fn apply(&self, tab: &mut Tab, action: TabUserActionType) -> Result<(), ServiceError> {
+        action.perform(tab)?;
+        tab.user_actions.push(action.clone());
+        self.store_action(tab, action)
+    }
+
+    /// Store the `tab`, after the `action` was performed on it, and
+    /// publish a [TabEvent]. The write lock needs to be held by the
+    /// caller.
+    fn store_action(&self, tab: &Tab, action: TabUserActionType) -> Result<(), ServiceError> {
+        self.repository.update_tab(tab)?;
+        if let TabUserActionType::RemoveUser(remove_user) = &action {
+            self.repository
+                .remove_tab_member(&tab.id, remove_user.user_id)?;
+        }
+        self.events.publish(TabEvent {
+            tab_id: tab.id,
+            action,
+        });
+        Ok(())
+    }
+
+    /// Synchronise the tab with the specified `id` with a client, on
+    /// behalf of the `account`. The `actions` which the client
+    /// recorded after receiving the first `cursor` of the tab's
+    /// `user_actions` are merged into the stored tab, as described in
+    /// the [sync](crate::sync) module. If the tab doesn't exist, it
+    /// is created by performing the `user_actions` of the
+    /// `pushed_tab`, with the `account` linked to its first user. If the `push_id` is the same as that of the
+    /// account's last push to the tab, the push is being retried, and
+    /// the actions are not merged again.
+    pub fn sync(
+        &self,
+        account: &Account,
+        id: &TabID,
+        push_id: Option<Uuid>,
+        cursor: usize,
+        actions: Vec<TabUserActionType>,
+        pushed_tab: Option<Tab>,
+    ) -> Result<Synchronisation, ServiceError> {
+        let _guard = self.lock_writes();
+
+        let mut tab = match (self.repository.tab(id)?, pushed_tab) {
+            (Some(tab), _) => tab,
+            (None, Some(pushed_tab)) => {
+                if pushed_tab.id != *id {
+                    return Err(SyncError::TabIdMismatch(pushed_tab.id).into());
+                }
+                let tab = rebuild_tab(&pushed_tab)?;
+                let user = tab.users.first().ok_or(SyncError::NoUsers(*id))?;
+                self.store_tab(
+                    &tab,
+                    &TabMember {
+                        tab_id: *id,
+                        user_id: user.id,
+                        account_id: account.id,
+                    },
+                )?;
+                tab
+            }
+            (None, None) => return Err(RepositoryError::TabNotFound(*id).into()),
+        };
+
+        let member = self.member(account, id)?;
+        if cursor > tab.user_actions.len() {
+            return Err(SyncError::InvalidCursor {
+                cursor,
+                actions: tab.user_actions.len(),
+            }
+            .into());
+        }
+
+        let last_push = match push_id {
+            Some(_) => self.repository.sync_push(id, &account.id)?,
+            None => None,
+        };
+        if let Some(push) = last_push.filter(|push| Some(push.id) == push_id) {
+            return Ok(Synchronisation {
+                cursor: tab.user_actions.len(),
+                actions: tab.user_actions[cursor..].to_vec(),
+                rejected: push.rejected,
+                remapped: push.remapped,
+                tab,
+            });
+        }
+
+        let mut rejected = Vec::new();
+        let mut remapped = IdRemapping::default();
+        for (index, action) in actions.into_iter().enumerate() {
+            // the ids are only reassigned if the action is merged, so
+            // that they can't be assigned again by a later action
+            let mut remapping = remapped.clone();
+            let action = remapping.remap(&tab, action);
+            match self.merge(&tab, member.user_id, action) {
+                Ok(merged) => {
+                    tab = merged;
+                    remapped = remapping;
+                }
+                Err(ServiceError::Repository(error)) => return Err(error.into()),
+                Err(error) => rejected.push((index, ErrorBody::new(&error))),
+            }
+        }
+
+        if let Some(push_id) = push_id {
+            self.repository.set_sync_push(&SyncPush {
+                id: push_id,
+                tab_id: *id,
+                account_id: account.id,
+                rejected: rejected.clone(),
+                remapped: remapped.clone(),
+            })?;
+        }
+
+        Ok(Synchronisation {
+            cursor: tab.user_actions.len(),
+            actions: tab.user_actions[cursor..].to_vec(),
+            rejected,
+            remapped,
+            tab,
+        })
+    }
+
+    /// Perform an `action` pushed by a client, which is linked to the
+    /// user with the specified `user_id`, on a copy of the `tab`, so
+    /// that the `tab` is left unchanged if the action is rejected.
+    /// The write lock needs to be held by the caller.
+    fn merge(
+        &self,
+        tab: &Tab,
+        user_id: UserID,
+        action: TabUserActionType,
+    ) -> Result<Tab, ServiceError> {
+        if action.metadata().user_id != user_id {
+            return Err(AuthError::WrongActingUser(user_id).into());
+        }
+        tab.user(&user_id)?;
+
+        let mut merged = tab.clone();
+        self.apply(&mut merged, action)?;
+        Ok(merged)
+    }
+
+    /// Create an invite to the tab with the specified `id`, which
+    /// expires at `expires_at` (if specified), and can optionally
+    /// only be redeemed once. The `account` needs to be a member of
+    /// the tab.
+    pub fn create_invite(
+        &self,
+        account: &Account,
+        id: &TabID,
+        expires_at: Option<DateTime<Utc>>,
+        single_use: bool,
+    ) -> Result<Invite, ServiceError> {
+        let member = self.member(account, id)?;
+        let invite = Invite::new(*id, member.user_id, Utc::now(), expires_at, single_use)?;
+        self.repository.add_invite(&invite)?;
+        Ok(invite)
+    }
+
+    /// The invites to the tab with the specified `id`. The `account`
+    /// needs to be a member of the tab.
+    pub fn tab_invites(&self, account: &Account, id: &TabID) -> Result<Vec<Invite>, ServiceError> {
+        self.member(account, id)?;
+        Ok(self.repository.tab_invites(id)?)
+    }
+
+    /// Delete the invite with the specified `token` to the tab with
+    /// the specified `id`, so that it can't be redeemed. The `account`
+    /// needs to be a member of the tab.
+    pub fn revoke_invite(
+        &self,
+        account: &Account,
+        id: &TabID,
+        token: &str,
+    ) -> Result<(), ServiceError> {
+        let _guard = self.lock_writes();
+        self.member(account, id)?;
+        match self.repository.invite(token)? {
+            Some(invite) if invite.tab_id == *id => Ok(self.repository.delete_invite(token)?),
+            _ => Err(InviteError::NotFound.into()),
+        }
+    }
+
+    /// The invite with the specified `token`, along with the tab it
+    /// is an invite to, if it can still be redeemed. Anyone with the
+    /// token can read the invite.
+    pub fn invite(&self, token: &str) -> Result<(Invite, Tab), ServiceError> {
+        let invite = self.valid_invite(token)?;
+        let tab = self.stored_tab(&invite.tab_id)?;
+        Ok((invite, tab))
+    }
+
+    fn valid_invite(&self, token: &str) -> Result<Invite, ServiceError> {
+        let invite = self
+            .repository
+            .invite(token)?
+            .ok_or(InviteError::NotFound)?;
+        match invite.expires_at {
+            Some(expires_at) if invite.is_expired(Utc::now()) => {
+                self.repository.delete_invite(token)?;
+                Err(InviteError::Expired(expires_at).into())
+            }
+            _ => Ok(invite),
+        }
+    }
+
+    /// Redeem the invite with the specified `token`, adding a new user
+    /// with the specified `name` and `email` to the tab (using an
+    /// [AddUser] action performed as the new user), and linking the
+    /// `account` to it. If there is no `account`, a new guest account
+    /// with the same name as the user is created, and logged in to.
+    pub fn redeem_invite(
+        &self,
+        account: Option<&Account>,
+        token: &str,
+        name: &str,
+        email: Option<&str>,
+    ) -> Result<Redemption, ServiceError> {
+        let _guard = self.lock_writes();
+        let invite = self.valid_invite(token)?;
+        let mut tab = self.stored_tab(&invite.tab_id)?;
+
+        if let Some(account) = account {
+            let members = self.repository.tab_members(&tab.id)?;
+            if members.iter().any(|member| member.account_id == account.id) {
+                return Err(AuthError::AlreadyTabMember(tab.id).into());
+            }
+        }
+
+        let user_id = tab.next_user_id();
+        let user = User::new(user_id, name, email);
+        let (account, session) = match account {
+            Some(account) => (account.clone(), None),
+            None => {
+                let guest = Account::guest(name, Utc::now());
+                let session = Session::new(guest.id, guest.created_at);
+                (guest, Some(session))
+            }
+        };
+        let action = TabUserActionType::AddUser(AddUser::new(user_id, user));
+        action.perform(&mut tab)?;
+        tab.user_actions.push(action.clone());
+
+        // the user is only stored once its account is linked to it,
+        // and a single use invite is consumed first, so a failure
+        // can't leave a user which nobody can act as, or an invite
+        // which can be redeemed again
+        if invite.single_use {
+            self.repository.delete_invite(token)?;
+        }
+        if let Some(session) = &session {
+            self.repository.add_account(&account)?;
+            self.repository.add_session(session)?;
+        }
+        let member = TabMember {
+            tab_id: tab.id,
+            user_id,
+            account_id: account.id,
+        };
+        self.repository.add_tab_member(&member)?;
+        if let Err(error) = self.store_action(&tab, action) {
+            self.repository.remove_tab_member(&tab.id, user_id)?;
+            return Err(error);
+        }
+
+        Ok(Redemption {
+            account,
+            session,
+            member,
+            tab,
+        })
+    }
+
+    /// Create a link to the read-only summary of the tab with the
+    /// specified `id`. The `account` needs to be a member of the tab.
+    pub fn create_share_link(
+        &self,
+        account: &Account,
+        id: &TabID,
+    ) -> Result<ShareLink, ServiceError> {
+        let member = self.member(account, id)?;
+        let link = ShareLink::new(*id, member.user_id, Utc::now());
+        self.repository.add_share_link(&link)?;
+        Ok(link)
+    }
+
+    /// The share links to the tab with the specified `id`. The
+    /// `account` needs to be a member of the tab.
+    pub fn tab_share_links(
+        &self,
+        account: &Account,
+        id: &TabID,
+    ) -> Result<Vec<ShareLink>, ServiceError> {
+        self.member(account, id)?;
+        Ok(self.repository.tab_share_links(id)?)
+    }
+
+    /// Delete the share link with the specified `token` to the tab
+    /// with the specified `id`, so that it can no longer be used to
+    /// read the tab. The `account` needs to be a member of the tab.
+    pub fn revoke_share_link(
+        &self,
+        account: &Account,
+        id: &TabID,
+        token: &str,
+    ) -> Result<(), ServiceError> {
+        let _guard = self.lock_writes();
+        self.member(account, id)?;
+        match self.repository.share_link(token)? {
+            Some(link) if link.tab_id == *id => Ok(self.repository.delete_share_link(token)?),
+            _ => Err(ShareLinkError::NotFound.into()),
+        }
+    }
+
+    /// The tab which the share link with the specified `token` is to,
+    /// with only the closed periods needed to calculate its balances
+    /// as of the specified date. Anyone with the token can read the
+    /// tab, unless it is in the trash.
+    pub fn shared_tab(&self, token: &str, as_of: NaiveDate) -> Result<Tab, ServiceError> {
+        let link = self
+            .repository
+            .share_link(token)?
+            .ok_or(ShareLinkError::NotFound)?;
+        if !self.repository.tab_ids()?.contains(&link.tab_id) {
+            return Err(ShareLinkError::NotFound.into());
+        }
+        self.stored_tab_as_of(&link.tab_id, as_of)
+    }
+
+    /// Move the tab with the specified `id` to the trash. The
+    /// `account` needs to be a member of the tab.
+    pub fn trash_tab(
+        &self,
+        account: &Account,
+        id: &TabID,
+        trashed_at: DateTime<Utc>,
+    ) -> Result<(), ServiceError> {
+        let _guard = self.lock_writes();
+        self.member(account, id)?;
+        Ok(self.repository.trash_tab(id, trashed_at)?)
+    }
+
+    /// Restore the tab with the specified `id` from the trash, and
+    /// return it. The `account` needs to be a member of the tab.
+    pub fn restore_tab(&self, account: &Account, id: &TabID) -> Result<Tab, ServiceError> {
+        let _guard = self.lock_writes();
+        self.member(account, id)?;
+        self.repository.restore_tab(id)?;
+        self.stored_tab(id)
+    }
+
+    /// Permanently delete the tab with the specified `id`. The
+    /// `account` needs to be a member of the tab.
+    pub fn delete_tab(&self, account: &Account, id: &TabID) -> Result<(), ServiceError> {
+        let _guard = self.lock_writes();
+        self.member(account, id)?;
+        self.delete_tab_links(id)?;
+        Ok(self.repository.delete_tab(id)?)
+    }
+
+    /// Permanently delete the tabs which were moved to the trash
+    /// before `trashed_before`, returning the number of tabs deleted.
+    pub fn empty_trash(&self, trashed_before: DateTime<Utc>) -> Result<usize, ServiceError> {
+        let _guard = self.lock_writes();
+        let deleted = self.repository.empty_trash(trashed_before)?;
+        for id in &deleted {
+            self.delete_tab_links(id)?;
+        }
+        Ok(deleted.len())
+    }
+
+    /// Delete the members, invites, share links and sync pushes of the
+    /// tab with the specified `id`, before it is deleted.
+    fn delete_tab_links(&self, id: &TabID) -> Result<(), ServiceError> {
+        self.repository.remove_tab_members(id)?;
+        self.repository.delete_tab_invites(id)?;
+        self.repository.delete_tab_share_links(id)?;
+        self.repository.delete_tab_sync_pushes(id)?;
+        Ok(())
+    }
+
+    /// Delete all the invites which have expired as of `now`,
+    /// returning the number of invites deleted.
+    pub fn delete_expired_invites(&self, now: DateTime<Utc>) -> Result<usize, ServiceError> {
+        let _guard = self.lock_writes();
+        Ok(self.repository.delete_expired_invites(now)?)
+    }
+
+    /// Reclaim the space used by data which has been deleted from
+    /// the repository.
+    pub fn compact_storage(&self) -> Result<(), ServiceError> {
+        Ok(self.repository.compact()?)
+    }
+
+    /// Ensure that all the data written to the repository is stored
+    /// durably, e.g. before the server exits.
+    pub fn flush_storage(&self) -> Result<(), ServiceError> {
+        let _guard = self.lock_writes();
+        Ok(self.repository.flush()?)
+    }
+}
+
+/// Rebuild the `pushed_tab` from its `user_actions`, by performing
+/// them in turn on an empty tab with the same id, name and working
+/// currency, so that the stored tab only contains what its actions
+/// produce.
+fn rebuild_tab(pushed_tab: &Tab) -> Result<Tab, SyncError> {
+    let mut tab = Tab::new(
+        pushed_tab.id,
+        &pushed_tab.name,
+        pushed_tab.working_currency,
+        Vec::new(),
+        Vec::new(),
+    );
+    for (index, action) in pushed_tab.user_actions.iter().enumerate() {
+        action
+            .perform(&mut tab)
+            .map_err(|source| SyncError::InvalidTabAction {
+                tab_id: tab.id,
+                index,
+                source,
+            })?;
+        tab.user_actions.push(action.clone());
+    }
+    Ok(tab)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{ServiceError, TabService};
+    use crate::accounts::{tests::create_test_account, Account, AuthError, Session, TabMember};
+    use crate::error::ErrorCode;
+    use crate::events::TabEvents;
+    use crate::invites::InviteError;
+    use crate::repository::{
+        tests::create_test_tab, AccountRepository, KeyValueDBRepository, TabRepository,
+    };
+    use crate::shares::ShareLinkError;
+    use crate::sync::SyncError;
+    use chrono::{Duration, Utc};
+    use costing::actions::{
+        AddExpense, AddUser, ChangeTabName, EditExpense, RemoveExpense, RemoveUser, TabUserAction,
+        TabUserActionType,
+    };
+    use costing::{Tab, User};
+    use futures::StreamExt;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    /// Create a [TabService] storing the test tab, and two accounts:
+    /// Alice, who is linked to the tab's user `1`, and Bob, who isn't
+    /// a member of the tab. They are logged in to sessions with the
+    /// tokens `alice` and `bob`.
+    pub(crate) fn create_test_service() -> (TabService, Account, Account) {
+        let repository = KeyValueDBRepository::in_memory();
+        let tab = create_test_tab();
+        let alice = create_test_account("Alice");
+        let bob = create_test_account("Bob");
+
+        repository.add_tab(&tab).unwrap();
+        repository.add_account(&alice).unwrap();
+        repository.add_account(&bob).unwrap();
+        for (token, account) in &[("alice", &alice), ("bob", &bob)] {
+            let mut session = Session::new(account.id, Utc::now());
+            session.token = token.to_string();
+            repository.add_session(&session).unwrap();
+        }
+        repository
+            .add_tab_member(&TabMember {
+                tab_id: tab.id,
+                user_id: 1,
+                account_id: alice.id,
+            })
+            .unwrap();
+
+        let service = TabService::new(Arc::new(repository), TabEvents::new());
+        (service, alice, bob)
+    }
+
+    #[tokio::test]
+    async fn perform_stores_and_publishes_action() {
+        let (service, alice, _) = create_test_service();
+        let tab = create_test_tab();
+        let mut events = service.events().subscribe(tab.id).boxed();
+
+        service
+            .perform(&alice, &tab.id, |_, user_id| {
+                Ok(TabUserActionType::ChangeTabName(ChangeTabName::new(
+                    user_id, "Holiday",
+                )))
+            })
+            .unwrap();
+
+        let stored = service.tab(&alice, &tab.id).unwrap();
+        assert_eq!("Holiday", stored.name);
+        assert_eq!(1, stored.user_actions.len());
+        assert_eq!(tab.id, events.next().await.unwrap().tab_id);
+
+        // failed actions are not stored
+        let error = service
+            .perform(&alice, &tab.id, |_, user_id| {
+                Ok(TabUserActionType::RemoveExpense(RemoveExpense::new(
+                    user_id, 5,
+                )))
+            })
+            .unwrap_err();
+        assert!(matches!(error, ServiceError::Costing(_)));
+        assert_eq!("EXPENSE_NOT_FOUND", error.code());
+        assert_eq!(1, service.tab(&alice, &tab.id).unwrap().user_actions.len());
+    }
+
+    #[test]
+    fn write_after_panic() {
+        let (service, alice, _) = create_test_service();
+        let tab = create_test_tab();
+
+        let panicking = service.clone();
+        std::thread::spawn(move || {
+            let _guard = panicking.lock_writes();
+            panic!("a job panicked while modifying a tab");
+        })
+        .join()
+        .unwrap_err();
+
+        service
+            .perform(&alice, &tab.id, |_, user_id| {
+                Ok(TabUserActionType::ChangeTabName(ChangeTabName::new(
+                    user_id, "Holiday",
+                )))
+            })
+            .unwrap();
+        assert_eq!("Holiday", service.tab(&alice, &tab.id).unwrap().name);
+    }
+
+    #[test]
+    fn operations_require_membership() {
+        let (service, alice, bob) = create_test_service();
+        let tab = create_test_tab();
+
+        assert_eq!(1, service.tabs(&alice).unwrap().len());
+        assert!(service.tabs(&bob).unwrap().is_empty());
+        assert!(matches!(
+            service.tab(&bob, &tab.id),
+            Err(ServiceError::Auth(AuthError::NotTabMember(_)))
+        ));
+        assert!(matches!(
+            service.trash_tab(&bob, &tab.id, Utc::now()),
+            Err(ServiceError::Auth(AuthError::NotTabMember(_)))
+        ));
+
+        // actions can only be performed as the linked user
+        let error = service
+            .perform(&alice, &tab.id, |_, _| {
+                Ok(TabUserActionType::ChangeTabName(ChangeTabName::new(
+                    2, "Holiday",
+                )))
+            })
+            .unwrap_err();
+        assert_eq!("FORBIDDEN", error.code());
+
+        // linking Bob to user 2 makes him a member
+        service
+            .link_user(&alice, &tab.id, 2, "bob@example.com")
+            .unwrap();
+        assert_eq!(1, service.tabs(&bob).unwrap().len());
+        assert!(matches!(
+            service.link_user(&alice, &tab.id, 2, "alice@example.com"),
+            Err(ServiceError::Auth(AuthError::UserAlreadyLinked(_, 2)))
+        ));
+
+        // removing user 2 from the tab (once they aren't involved in
+        // any expenses) removes Bob's membership
+        service
+            .perform(&alice, &tab.id, |_, user_id| {
+                Ok(TabUserActionType::RemoveExpense(RemoveExpense::new(
+                    user_id, 1,
+                )))
+            })
+            .unwrap();
+        service
+            .perform(&alice, &tab.id, |_, user_id| {
+                Ok(TabUserActionType::RemoveUser(RemoveUser::new(user_id, 2)))
+            })
+            .unwrap();
+        assert!(service.tabs(&bob).unwrap().is_empty());
+    }
+
+    #[test]
+    fn register_login_and_logout() {
+        let (service, _, _) = create_test_service();
+
+        let (account, session) = service
+            .register("Carol", "carol@example.com", "correct horse")
+            .unwrap();
+        assert_eq!(account, service.authenticate(&session.token).unwrap());
+        assert!(matches!(
+            service.register("Carol", "Carol@example.com", "battery staple"),
+            Err(ServiceError::Auth(AuthError::EmailAlreadyRegistered(_)))
+        ));
+
+        assert!(matches!(
+            service.login("carol@example.com", "battery staple"),
+            Err(ServiceError::Auth(AuthError::InvalidCredentials))
+        ));
+        let (_, session) = service.login("carol@example.com", "correct horse").unwrap();
+
+        service.logout(&session.token).unwrap();
+        assert!(matches!(
+            service.authenticate(&session.token),
+            Err(ServiceError::Auth(AuthError::InvalidSession))
+        ));
+    }
+
+    #[test]
+    fn redeem_invites() {
+        let (service, alice, bob) = create_test_service();
+        let tab = create_test_tab();
+
+        assert!(matches!(
+            service.create_invite(&bob, &tab.id, None, false),
+            Err(ServiceError::Auth(AuthError::NotTabMember(_)))
+        ));
+        let invite = service.create_invite(&alice, &tab.id, None, true).unwrap();
+        assert_eq!(1, invite.created_by);
+        let (_, invited_tab) = service.invite(&invite.token).unwrap();
+        assert_eq!("Road Trip", invited_tab.name);
+
+        // redeeming without an account creates a guest account
+        let redemption = service
+            .redeem_invite(None, &invite.token, "Carol", None)
+            .unwrap();
+        assert_eq!(3, redemption.member.user_id);
+        assert!(redemption.account.is_guest());
+        let session = redemption.session.unwrap();
+        let guest = service.authenticate(&session.token).unwrap();
+        let tab = service.tab(&guest, &tab.id).unwrap();
+        assert_eq!("Carol", tab.user(&3).unwrap().name);
+        assert_eq!(3, tab.user_actions[0].metadata().user_id);
+
+        // single use invites can't be redeemed again
+        assert!(matches!(
+            service.redeem_invite(Some(&bob), &invite.token, "Carol", None),
+            Err(ServiceError::Invite(InviteError::NotFound))
+        ));
+
+        let invite = service.create_invite(&alice, &tab.id, None, false).unwrap();
+        assert!(matches!(
+            service.redeem_invite(Some(&alice), &invite.token, "Carol", None),
+            Err(ServiceError::Auth(AuthError::AlreadyTabMember(_)))
+        ));
+        let redemption = service
+            .redeem_invite(Some(&bob), &invite.token, "Carol", None)
+            .unwrap();
+        assert!(redemption.session.is_none());
+        assert_eq!(4, service.member(&bob, &tab.id).unwrap().user_id);
+        assert_eq!(1, service.tab_invites(&bob, &tab.id).unwrap().len());
+
+        service.revoke_invite(&bob, &tab.id, &invite.token).unwrap();
+        assert!(matches!(
+            service.invite(&invite.token),
+            Err(ServiceError::Invite(InviteError::NotFound))
+        ));
+    }
+
+    #[test]
+    fn expired_invites_are_deleted() {
+        let (service, alice, _) = create_test_service();
+        let tab = create_test_tab();
+        let mut invite = service.create_invite(&alice, &tab.id, None, false).unwrap();
+        invite.token = "expired".to_string();
+        invite.expires_at = Some(Utc::now() - Duration::hours(1));
+        service.repository.add_invite(&invite).unwrap();
+
+        assert!(matches!(
+            service.invite("expired"),
+            Err(ServiceError::Invite(InviteError::Expired(_)))
+        ));
+        assert_eq!(1, service.tab_invites(&alice, &tab.id).unwrap().len());
+    }
+
+    #[test]
+    fn share_links() {
+        let (service, alice, bob) = create_test_service();
+        let tab = create_test_tab();
+
+        assert!(matches!(
+            service.create_share_link(&bob, &tab.id),
+            Err(ServiceError::Auth(AuthError::NotTabMember(_)))
+        ));
+        let link = service.create_share_link(&alice, &tab.id).unwrap();
+        assert_eq!(1, link.created_by);
+        assert_eq!(
+            "Road Trip",
+            service
+                .shared_tab(&link.token, Utc::today().naive_utc())
+                .unwrap()
+                .name
+        );
+        assert_eq!(
+            vec![link.clone()],
+            service.tab_share_links(&alice, &tab.id).unwrap()
+        );
+
+        // tabs in the trash can't be read
+        service.trash_tab(&alice, &tab.id, Utc::now()).unwrap();
+        assert!(matches!(
+            service.shared_tab(&link.token, Utc::today().naive_utc()),
+            Err(ServiceError::ShareLink(ShareLinkError::NotFound))
+        ));
+        service.restore_tab(&alice, &tab.id).unwrap();
+
+        assert!(matches!(
+            service.revoke_share_link(&alice, &Uuid::nil(), &link.token),
+            Err(ServiceError::Auth(_)) | Err(ServiceError::Repository(_))
+        ));
+        service
+            .revoke_share_link(&alice, &tab.id, &link.token)
+            .unwrap();
+        assert!(matches!(
+            service.shared_tab(&link.token, Utc::today().naive_utc()),
+            Err(ServiceError::ShareLink(ShareLinkError::NotFound))
+        ));
+    }
+
+    #[test]
+    fn sync_merges_actions() {
+        let (service, alice, bob) = create_test_service();
+        let tab = create_test_tab();
+        let change_name =
+            |user_id, name| TabUserActionType::ChangeTabName(ChangeTabName::new(user_id, name));
+
+        // another client changes the name after this one last synced
+        service
+            .perform(&alice, &tab.id, |_, user_id| {
+                Ok(change_name(user_id, "Remote"))
+            })
+            .unwrap();
+
+        let actions = vec![
+            change_name(1, "Local"),
+            TabUserActionType::RemoveExpense(RemoveExpense::new(1, 5)),
+            change_name(2, "Wrong User"),
+        ];
+        let sync = service
+            .sync(&alice, &tab.id, None, 0, actions, None)
+            .unwrap();
+        assert_eq!(2, sync.cursor);
+        assert_eq!(2, sync.actions.len());
+        assert_eq!("Local", sync.tab.name);
+        let rejected: Vec<(usize, &str)> = sync
+            .rejected
+            .iter()
+            .map(|(index, error)| (*index, error.code.as_str()))
+            .collect();
+        assert_eq!(vec![(1, "EXPENSE_NOT_FOUND"), (2, "FORBIDDEN")], rejected);
+        assert_eq!("Local", service.tab(&alice, &tab.id).unwrap().name);
+
+        // pulling without pushing any actions
+        let sync = service
+            .sync(&alice, &tab.id, None, 1, vec![], None)
+            .unwrap();
+        assert_eq!(2, sync.cursor);
+        assert_eq!(1, sync.actions.len());
+
+        assert!(matches!(
+            service.sync(&alice, &tab.id, None, 3, vec![], None),
+            Err(ServiceError::Sync(SyncError::InvalidCursor {
+                cursor: 3,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            service.sync(&bob, &tab.id, None, 0, vec![], None),
+            Err(ServiceError::Auth(AuthError::NotTabMember(_)))
+        ));
+    }
+
+    #[test]
+    fn sync_reassigns_ids_and_ignores_retried_pushes() {
+        let (service, alice, _) = create_test_service();
+        let tab = create_test_tab();
+        let mut expense = tab.expenses[0].clone();
+        expense.id = 2;
+
+        // another client adds an expense with the id which this one
+        // used while it was offline
+        let remote_expense = expense.clone();
+        service
+            .perform(&alice, &tab.id, |_, user_id| {
+                Ok(TabUserActionType::AddExpense(AddExpense::new(
+                    user_id,
+                    remote_expense,
+                )))
+            })
+            .unwrap();
+
+        let mut edited = expense.clone();
+        edited.description = "Edited".to_string();
+        let actions = vec![
+            TabUserActionType::AddExpense(AddExpense::new(1, expense)),
+            TabUserActionType::EditExpense(EditExpense::new(1, edited)),
+        ];
+        let push_id = Some(Uuid::new_v4());
+        let sync = service
+            .sync(&alice, &tab.id, push_id, 0, actions.clone(), None)
+            .unwrap();
+        assert!(sync.rejected.is_empty());
+        assert_eq!(Some(&3), sync.remapped.expenses.get(&2));
+        assert_eq!(3, sync.tab.expenses.len());
+        assert_eq!("Edited", sync.tab.expenses[2].description);
+        assert_eq!(3, sync.tab.expenses[2].id);
+
+        // the response was lost, and the client retries the push
+        let retried = service
+            .sync(&alice, &tab.id, push_id, 0, actions, None)
+            .unwrap();
+        assert_eq!(sync.cursor, retried.cursor);
+        assert_eq!(sync.remapped, retried.remapped);
+        assert_eq!(3, retried.tab.expenses.len());
+    }
+
+    #[test]
+    fn sync_pushes_new_tabs() {
+        let (service, alice, bob) = create_test_service();
+        let currency = create_test_tab().working_currency;
+        let mut tab = Tab::new(Uuid::new_v4(), "Dinner", currency, vec![], vec![]);
+
+        assert_eq!(
+            "TAB_NOT_FOUND",
+            service
+                .sync(&bob, &tab.id, None, 0, vec![], None)
+                .unwrap_err()
+                .code()
+        );
+        assert!(matches!(
+            service.sync(&bob, &tab.id, None, 0, vec![], Some(tab.clone())),
+            Err(ServiceError::Sync(SyncError::NoUsers(_)))
+        ));
+
+        // the pushed tab is rebuilt from its actions, so a user which
+        // wasn't added by an action isn't stored
+        let mut unrecorded = tab.clone();
+        unrecorded.add_user(User::new(0, "Bob", None)).unwrap();
+        assert!(matches!(
+            service.sync(&bob, &tab.id, None, 0, vec![], Some(unrecorded)),
+            Err(ServiceError::Sync(SyncError::NoUsers(_)))
+        ));
+
+        let add_bob = TabUserActionType::AddUser(AddUser::new(0, User::new(0, "Bob", None)));
+        add_bob.perform(&mut tab).unwrap();
+        tab.user_actions.push(add_bob);
+
+        // a tab with an action which can't be performed is rejected
+        let mut invalid = tab.clone();
+        invalid
+            .user_actions
+            .push(TabUserActionType::RemoveUser(RemoveUser::new(0, 1)));
+        assert!(matches!(
+            service.sync(&bob, &tab.id, None, 0, vec![], Some(invalid)),
+            Err(ServiceError::Sync(SyncError::InvalidTabAction {
+                index: 1,
+                ..
+            }))
+        ));
+        assert!(service.tab(&bob, &tab.id).is_err());
+
+        let sync = service
+            .sync(&bob, &tab.id, None, 0, vec![], Some(tab.clone()))
+            .unwrap();
+        assert_eq!(1, sync.cursor);
+        assert_eq!(1, sync.tab.users.len());
+        assert_eq!(0, service.member(&bob, &tab.id).unwrap().user_id);
+
+        // pushing the tab again doesn't replace it
+        tab.name = "Lunch".to_string();
+        let tab_id = tab.id;
+        let sync = service
+            .sync(&bob, &tab_id, None, 0, vec![], Some(tab))
+            .unwrap();
+        assert_eq!("Dinner", sync.tab.name);
+        assert!(matches!(
+            service.sync(&alice, &sync.tab.id, None, 0, vec![], None),
+            Err(ServiceError::Auth(AuthError::NotTabMember(_)))
+        ));
+    }
+}
@@ -0,0 +1,79 @@
+//! Links which share a read-only summary of a tab with people who
+//! don't want to join it, or use the GUI. The members of a tab can
+//! create a share link, and anyone with the link can read the tab's
+//! members, expenses, balances and settlements, until the link is
+//! revoked.
+
+use crate::accounts::random_token;
+use crate::error::ErrorCode;
+use chrono::{DateTime, Utc};
+use costing::{TabID, UserID};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use warp::http::StatusCode;
+
+/// An error which occurs while reading a tab using a [ShareLink].
+#[derive(Error, Debug)]
+pub enum ShareLinkError {
+    #[error("the share link does not exist, or has been revoked")]
+    NotFound,
+}
+
+impl ErrorCode for ShareLinkError {
+    fn code(&self) -> &'static str {
+        match self {
+            ShareLinkError::NotFound => "SHARE_LINK_NOT_FOUND",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            ShareLinkError::NotFound => StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+/// A link to the read-only summary of a tab, identified by a random
+/// `token` which is part of the link, and is the only thing needed to
+/// read the tab.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ShareLink {
+    pub token: String,
+    pub tab_id: TabID,
+    /// The id of the user who created the link.
+    pub created_by: UserID,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ShareLink {
+    /// Create a new link to the tab with the specified `tab_id`, with
+    /// a new random token.
+    pub fn new(tab_id: TabID, created_by: UserID, created_at: DateTime<Utc>) -> Self {
+        ShareLink {
+            token: random_token(),
+            tab_id,
+            created_by,
+            created_at,
+        }
+    }
+
+    /// The path of the page showing the summary of the tab.
+    pub fn path(&self) -> String {
+        format!("/shared/{}", self.token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShareLink;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    #[test]
+    fn share_link_tokens() {
+        let link = ShareLink::new(Uuid::nil(), 1, Utc::now());
+        let other = ShareLink::new(Uuid::nil(), 1, Utc::now());
+        assert_ne!(link.token, other.token);
+        assert_eq!(format!("/shared/{}", link.token), link.path());
+    }
+}
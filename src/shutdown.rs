@@ -0,0 +1,142 @@
+//! Shutting down the server gracefully. When the process is asked to
+//! stop (see [stop_requested]), the server stops accepting
+//! connections, waits for the requests and background jobs which are
+//! in progress to complete (up to a timeout), and then flushes its
+//! storage before exiting.
+
+use log::error;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+
+/// Coordinates shutting down the tasks which hold one of its
+/// [ShutdownSignal]s.
+pub struct Shutdown {
+    requested: watch::Sender<bool>,
+    signal: ShutdownSignal,
+    /// Receives `None` once all the [ShutdownSignal]s are dropped.
+    active: mpsc::Receiver<()>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (requested, receiver) = watch::channel(false);
+        let (sender, active) = mpsc::channel(1);
+        Shutdown {
+            requested,
+            signal: ShutdownSignal {
+                receiver,
+                _active: sender,
+            },
+            active,
+        }
+    }
+
+    /// A signal for a task which needs to be completed before the
+    /// server shuts down. The task should stop once shutting down is
+    /// [requested](ShutdownSignal::requested), and drop the signal
+    /// when it has completed.
+    pub fn signal(&self) -> ShutdownSignal {
+        self.signal.clone()
+    }
+
+    /// Request the tasks holding a [ShutdownSignal] to stop, and wait
+    /// up to `timeout` for them to complete. Returns whether they all
+    /// completed in time.
+    pub async fn shutdown(self, timeout: Duration) -> bool {
+        let Shutdown {
+            requested,
+            signal,
+            mut active,
+        } = self;
+        drop(signal);
+        // this only fails if every signal has already been dropped
+        let _ = requested.broadcast(true);
+        tokio::time::timeout(timeout, active.recv()).await.is_ok()
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Held by a task which needs to be completed before the server shuts
+/// down, see [Shutdown::signal].
+#[derive(Clone)]
+pub struct ShutdownSignal {
+    receiver: watch::Receiver<bool>,
+    _active: mpsc::Sender<()>,
+}
+
+impl ShutdownSignal {
+    /// Whether shutting down has been requested.
+    pub fn is_requested(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Wait until shutting down has been requested.
+    pub async fn requested(&mut self) {
+        while !self.is_requested() {
+            if self.receiver.recv().await.is_none() {
+                return;
+            }
+        }
+    }
+}
+
+/// Wait until the process is asked to stop, by `SIGINT` (Ctrl+C) or,
+/// on Unix, by `SIGTERM` (which is sent by process supervisors).
+pub async fn stop_requested() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = terminate.recv() => {},
+                }
+                return;
+            }
+            Err(error) => error!("Unable to listen for SIGTERM: {}", error),
+        }
+    }
+
+    if let Err(error) = tokio::signal::ctrl_c().await {
+        error!(
+            "Unable to listen for Ctrl+C, the server can't be stopped gracefully: {}",
+            error
+        );
+        futures::future::pending::<()>().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Shutdown;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn shutdown() {
+        let shutdown = Shutdown::new();
+        let mut signal = shutdown.signal();
+        let (completed, completion) = oneshot::channel();
+        tokio::spawn(async move {
+            signal.requested().await;
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+            completed.send(()).unwrap();
+            drop(signal);
+        });
+        assert!(shutdown.shutdown(Duration::from_secs(5)).await);
+        completion.await.unwrap();
+
+        let shutdown = Shutdown::new();
+        let stuck = shutdown.signal();
+        assert!(!stuck.is_requested());
+        assert!(!shutdown.shutdown(Duration::from_millis(10)).await);
+        assert!(stuck.is_requested());
+    }
+}
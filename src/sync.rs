@@ -0,0 +1,109 @@
+//! Synchronisation of tabs with clients which store them locally, and
+//! record the actions performed on them while offline.
+//!
+//! A client keeps a `cursor` for each tab, the number of the tab's
+//! `user_actions` which it has received from the server. When it
+//! synchronises a tab, it pushes the actions which it has recorded
+//! locally since then, and the server merges them into the stored tab
+//! by performing them after any actions which other clients have
+//! pushed in the meantime. Actions which can no longer be performed
+//! are rejected. The client receives the actions after its cursor,
+//! along with the merged tab which replaces its local copy.
+//!
+//! A tab which was created by the client is pushed in its entirety
+//! the first time it is synchronised. The server rebuilds it by
+//! performing its `user_actions` in turn, and rejects the push if any
+//! of them can't be performed.
+//!
+//! Clients allocate the ids of the expenses and users they add while
+//! offline themselves, so another client may have already used them
+//! by the time the actions are merged. Such ids are reassigned (see
+//! [IdRemapping]), and the client receives the reassigned ids, to
+//! replace them in the actions which it recorded in the meantime.
+//!
+//! Each push of actions can be identified by an id chosen by the
+//! client. The result of the last push by each account to a tab is
+//! stored as a [SyncPush], so that if the client retries the push
+//! (e.g. because the response was lost) the actions are not merged
+//! again.
+
+use crate::accounts::AccountID;
+use crate::error::{ErrorBody, ErrorCode};
+use costing::actions::{IdRemapping, TabUserActionType};
+use costing::{CostingError, Tab, TabID};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+use warp::http::StatusCode;
+
+/// An error which occurs while synchronising a tab.
+#[derive(Error, Debug)]
+pub enum SyncError {
+    #[error("the cursor {cursor} is past the end of the tab's {actions} actions")]
+    InvalidCursor { cursor: usize, actions: usize },
+    #[error("the pushed tab has the id {0}, which doesn't match the tab being synchronised")]
+    TabIdMismatch(TabID),
+    #[error("the pushed tab {0} has no users which the account can be linked to")]
+    NoUsers(TabID),
+    #[error("the action {index} of the pushed tab {tab_id} couldn't be performed")]
+    InvalidTabAction {
+        tab_id: TabID,
+        index: usize,
+        #[source]
+        source: CostingError,
+    },
+}
+
+impl ErrorCode for SyncError {
+    fn code(&self) -> &'static str {
+        match self {
+            SyncError::InvalidCursor { .. } => "INVALID_CURSOR",
+            SyncError::TabIdMismatch(_) => "TAB_ID_MISMATCH",
+            SyncError::NoUsers(_) => "TAB_HAS_NO_USERS",
+            SyncError::InvalidTabAction { .. } => "INVALID_TAB_ACTION",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            SyncError::InvalidCursor { .. } | SyncError::TabIdMismatch(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            SyncError::NoUsers(_) | SyncError::InvalidTabAction { .. } => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+        }
+    }
+}
+
+/// The result of synchronising a tab.
+#[derive(Debug)]
+pub struct Synchronisation {
+    /// The cursor which the client sends the next time it
+    /// synchronises the tab.
+    pub cursor: usize,
+    /// The actions after the client's cursor, in the order they were
+    /// performed on the server, including those which were pushed by
+    /// the client and merged.
+    pub actions: Vec<TabUserActionType>,
+    /// The actions pushed by the client which couldn't be performed,
+    /// identified by their index in the pushed actions.
+    pub rejected: Vec<(usize, ErrorBody)>,
+    /// The ids added by the pushed actions which were reassigned.
+    pub remapped: IdRemapping,
+    /// The tab, after the pushed actions were merged.
+    pub tab: Tab,
+}
+
+/// The result of the last push of actions to a tab by an account.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SyncPush {
+    /// The id which the client chose for the push.
+    pub id: Uuid,
+    pub tab_id: TabID,
+    pub account_id: AccountID,
+    /// See [Synchronisation::rejected].
+    pub rejected: Vec<(usize, ErrorBody)>,
+    /// See [Synchronisation::remapped].
+    pub remapped: IdRemapping,
+}
@@ -0,0 +1,345 @@
+//! Serving HTTPS without a reverse proxy, using the certificate and
+//! key specified in the [TlsConfig](crate::config::TlsConfig).
+//!
+//! The certificate can be replaced while the server is running (e.g.
+//! after it has been renewed) by sending the process `SIGHUP`, which
+//! reloads it from the configured files for new connections. Plain
+//! HTTP requests can be redirected to the HTTPS server using
+//! [redirect_to_https].
+
+use crate::config::TlsConfig;
+use crate::server::serve_connection;
+use crate::shutdown::ShutdownSignal;
+use log::{debug, error, info};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::{
+    internal::pemfile, Certificate, NoClientAuth, PrivateKey, ServerConfig, TLSError,
+};
+use warp::filters::{path::FullPath, BoxedFilter};
+use warp::http::{header, StatusCode};
+use warp::{reply, Filter, Reply};
+
+/// An error which occurs while loading the TLS certificate.
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error("unable to read the TLS file {0:?}")]
+    ReadFile(PathBuf, #[source] std::io::Error),
+    #[error("no PEM encoded certificates were found in {0:?}")]
+    NoCertificates(PathBuf),
+    #[error("no PEM encoded PKCS#8 or RSA private key was found in {0:?}")]
+    NoPrivateKey(PathBuf),
+    #[error("the TLS certificate and key can't be used")]
+    InvalidCertificate(#[source] TLSError),
+}
+
+/// Accepts TLS connections using the configured certificate, which
+/// can be reloaded while the server is running.
+pub struct Acceptor {
+    config: TlsConfig,
+    acceptor: RwLock<tokio_rustls::TlsAcceptor>,
+}
+
+impl Acceptor {
+    /// Create an acceptor, loading the certificate and key files
+    /// specified in the `config`.
+    pub fn new(config: TlsConfig) -> Result<Self, TlsError> {
+        let acceptor = RwLock::new(load_acceptor(&config)?);
+        Ok(Acceptor { config, acceptor })
+    }
+
+    /// Load the certificate and key files again, which will be used
+    /// for new connections. If they can't be loaded, the previous
+    /// certificate continues to be used.
+    pub fn reload(&self) -> Result<(), TlsError> {
+        let acceptor = load_acceptor(&self.config)?;
+        *self
+            .acceptor
+            .write()
+            .expect("TLS acceptor lock is poisoned") = acceptor;
+        Ok(())
+    }
+
+    fn current(&self) -> tokio_rustls::TlsAcceptor {
+        self.acceptor
+            .read()
+            .expect("TLS acceptor lock is poisoned")
+            .clone()
+    }
+}
+
+fn load_acceptor(config: &TlsConfig) -> Result<tokio_rustls::TlsAcceptor, TlsError> {
+    let certificates = read_certificates(&config.certificate)?;
+    let key = read_private_key(&config.key)?;
+
+    let mut server_config = ServerConfig::new(NoClientAuth::new());
+    server_config
+        .set_single_cert(certificates, key)
+        .map_err(TlsError::InvalidCertificate)?;
+    server_config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    Ok(Arc::new(server_config).into())
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, TlsError> {
+    std::fs::read(path).map_err(|error| TlsError::ReadFile(path.to_path_buf(), error))
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<Certificate>, TlsError> {
+    let contents = read_file(path)?;
+    match pemfile::certs(&mut BufReader::new(contents.as_slice())) {
+        Ok(certificates) if !certificates.is_empty() => Ok(certificates),
+        _ => Err(TlsError::NoCertificates(path.to_path_buf())),
+    }
+}
+
+fn read_private_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    let contents = read_file(path)?;
+    let pkcs8_keys = pemfile::pkcs8_private_keys(&mut BufReader::new(contents.as_slice()));
+    let rsa_keys = pemfile::rsa_private_keys(&mut BufReader::new(contents.as_slice()));
+    pkcs8_keys
+        .unwrap_or_default()
+        .into_iter()
+        .chain(rsa_keys.unwrap_or_default())
+        .next()
+        .ok_or_else(|| TlsError::NoPrivateKey(path.to_path_buf()))
+}
+
+/// Serve the `filter` over HTTPS to the connections accepted by the
+/// `listener`. Each connection is handled by a separate task, so a
+/// slow TLS handshake doesn't delay the others. The requests are
+/// handled in the same way as those served by [crate::server::serve].
+pub async fn serve<F>(
+    filter: F,
+    mut listener: TcpListener,
+    acceptor: Arc<Acceptor>,
+    mut shutdown: ShutdownSignal,
+) where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.requested() => return,
+        };
+        let (stream, remote_address) = match accepted {
+            Ok(connection) => connection,
+            Err(error) => {
+                error!("Unable to accept a connection: {}", error);
+                continue;
+            }
+        };
+
+        let tls_acceptor = acceptor.current();
+        let filter = filter.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            match tls_acceptor.accept(stream).await {
+                Ok(stream) => serve_connection(filter, stream, remote_address, shutdown).await,
+                Err(error) => debug!("TLS handshake with {} failed: {}", remote_address, error),
+            }
+        });
+    }
+}
+
+/// Reload the certificate used by the `acceptor` whenever the
+/// process receives `SIGHUP`.
+#[cfg(unix)]
+pub async fn reload_on_hangup(acceptor: Arc<Acceptor>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(error) => {
+            error!(
+                "Unable to listen for SIGHUP to reload the TLS certificate: {}",
+                error
+            );
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        match acceptor.reload() {
+            Ok(()) => info!("Reloaded the TLS certificate"),
+            Err(error) => error!(
+                "Unable to reload the TLS certificate, the previous certificate is still in use: {}",
+                error
+            ),
+        }
+    }
+}
+
+/// Respond to every request with a permanent redirect to the same
+/// resource on the HTTPS server listening on `https_port`, at the
+/// host which the request was sent to.
+pub fn redirect_to_https(https_port: u16) -> BoxedFilter<(reply::Response,)> {
+    warp::header::optional::<String>(header::HOST.as_str())
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(move |host: Option<String>, path: FullPath, query: String| {
+            let host = match host {
+                Some(host) if !host.is_empty() => host,
+                _ => {
+                    return reply::with_status(
+                        "The Host header is missing",
+                        StatusCode::BAD_REQUEST,
+                    )
+                    .into_response()
+                }
+            };
+
+            let mut location = https_url(&host, https_port, path.as_str());
+            if !query.is_empty() {
+                location.push('?');
+                location.push_str(&query);
+            }
+            // 308 rather than 301, so the method and body of the
+            // request are preserved
+            reply::with_header(StatusCode::PERMANENT_REDIRECT, header::LOCATION, location)
+                .into_response()
+        })
+        .boxed()
+}
+
+/// The URL of the resource at `path` on the HTTPS server listening on
+/// `https_port`, at the `host` (which may contain the port that a
+/// request was sent to).
+fn https_url(host: &str, https_port: u16, path: &str) -> String {
+    let hostname = if host.starts_with('[') {
+        // an IPv6 address
+        match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host,
+        }
+    } else {
+        host.split(':').next().unwrap_or(host)
+    };
+
+    if https_port == 443 {
+        format!("https://{}{}", hostname, path)
+    } else {
+        format!("https://{}:{}{}", hostname, https_port, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{https_url, redirect_to_https, serve, Acceptor, TlsError};
+    use crate::config::TlsConfig;
+    use crate::shutdown::Shutdown;
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::rustls::ClientConfig;
+    use tokio_rustls::webpki::DNSNameRef;
+    use tokio_rustls::TlsConnector;
+    use warp::Filter;
+
+    /// A self signed certificate for `localhost`, created with
+    /// `openssl req -x509 -newkey ec`.
+    fn test_config() -> TlsConfig {
+        let data_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test-data");
+        TlsConfig {
+            certificate: data_dir.join("localhost.cert.pem"),
+            key: data_dir.join("localhost.key.pem"),
+            redirect_port: None,
+        }
+    }
+
+    #[test]
+    fn load_certificate() {
+        let config = test_config();
+        let acceptor = Acceptor::new(config.clone()).unwrap();
+        acceptor.reload().unwrap();
+
+        let swapped = TlsConfig {
+            certificate: config.key.clone(),
+            key: config.certificate.clone(),
+            redirect_port: None,
+        };
+        assert!(matches!(
+            Acceptor::new(swapped),
+            Err(TlsError::NoCertificates(_))
+        ));
+        let missing = TlsConfig {
+            key: PathBuf::from("missing.pem"),
+            ..config
+        };
+        assert!(matches!(
+            Acceptor::new(missing),
+            Err(TlsError::ReadFile(_, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn serve_https() {
+        let config = test_config();
+        let acceptor = Arc::new(Acceptor::new(config.clone()).unwrap());
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let address = listener.local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        tokio::spawn(serve(
+            warp::path("hello").map(|| "world"),
+            listener,
+            acceptor,
+            shutdown.signal(),
+        ));
+
+        let certificate = std::fs::read(&config.certificate).unwrap();
+        let mut client_config = ClientConfig::new();
+        client_config
+            .root_store
+            .add_pem_file(&mut certificate.as_slice())
+            .unwrap();
+        let connector = TlsConnector::from(Arc::new(client_config));
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut stream = connector
+            .connect(DNSNameRef::try_from_ascii_str("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("world"), "{}", response);
+        assert!(shutdown.shutdown(Duration::from_secs(5)).await);
+    }
+
+    #[tokio::test]
+    async fn redirect() {
+        let filter = redirect_to_https(8443);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/v1/tabs?limit=1")
+            .header("host", "coster.example.com:8080")
+            .reply(&filter)
+            .await;
+        assert_eq!(308, response.status());
+        assert_eq!(
+            "https://coster.example.com:8443/api/v1/tabs?limit=1",
+            response.headers()["location"]
+        );
+
+        let response = warp::test::request().path("/").reply(&filter).await;
+        assert_eq!(400, response.status());
+
+        assert_eq!("https://[::1]/", https_url("[::1]:80", 443, "/"));
+        assert_eq!(
+            "https://localhost:8443/a",
+            https_url("localhost", 8443, "/a")
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! The translations of the messages used in the pages, which are read
+//! from the gettext catalogs in `i18n/po/{language}/coster.po`. Like
+//! the GUI's catalogs, these are kept up to date using `cargo i18n`
+//! (see `i18n.toml`).
+
+use log::error;
+use rust_embed::RustEmbed;
+use std::collections::HashMap;
+
+#[derive(RustEmbed)]
+#[folder = "i18n/po/"]
+struct Catalogs;
+
+/// The gettext domain of the server's messages, which is the name of
+/// its crate.
+const DOMAIN: &str = "coster";
+
+/// The translations of the messages into a language, keyed by the
+/// original message.
+#[derive(Debug, Default)]
+pub struct Catalog(HashMap<String, String>);
+
+/// The part of a catalog entry which is being read.
+#[derive(Clone, Copy, PartialEq)]
+enum Field {
+    Id,
+    Translation,
+    /// A field which isn't used by the pages, such as the plural form
+    /// of a message.
+    Other,
+}
+
+impl Catalog {
+    /// Read the embedded catalog for the language with the BCP 47
+    /// `tag`. If there isn't one, the catalog is empty, so the
+    /// messages aren't translated.
+    pub fn load(tag: &str) -> Catalog {
+        let path = format!("{}/{}.po", tag, DOMAIN);
+        match Catalogs::get(&path) {
+            Some(po) => Catalog::parse(&String::from_utf8_lossy(&po)),
+            None => {
+                error!("The translations catalog {} is missing", path);
+                Catalog::default()
+            }
+        }
+    }
+
+    /// Parse the contents of a `.po` file. Entries which are marked as
+    /// fuzzy, or haven't been translated yet, are skipped.
+    pub fn parse(po: &str) -> Catalog {
+        let mut translations = HashMap::new();
+        let mut id = String::new();
+        let mut translation = String::new();
+        let mut fuzzy = false;
+        let mut field = None;
+
+        let mut finish = |id: &mut String, translation: &mut String, fuzzy: &mut bool| {
+            if !id.is_empty() && !translation.is_empty() && !*fuzzy {
+                translations.insert(std::mem::take(id), std::mem::take(translation));
+            }
+            id.clear();
+            translation.clear();
+            *fuzzy = false;
+        };
+
+        for line in po.lines().map(str::trim) {
+            if line.starts_with('"') {
+                match field {
+                    Some(Field::Id) => id.push_str(&unquote(line)),
+                    Some(Field::Translation) => translation.push_str(&unquote(line)),
+                    _ => {}
+                }
+                continue;
+            }
+
+            // any other line ends the entry whose translation was
+            // being read
+            if field == Some(Field::Translation) {
+                finish(&mut id, &mut translation, &mut fuzzy);
+                field = None;
+            }
+
+            if let Some(flags) = line.strip_prefix("#,") {
+                fuzzy |= flags.split(',').any(|flag| flag.trim() == "fuzzy");
+            } else if let Some(value) = line.strip_prefix("msgid ") {
+                id = unquote(value);
+                field = Some(Field::Id);
+            } else if let Some(value) = line.strip_prefix("msgstr ") {
+                translation = unquote(value);
+                field = Some(Field::Translation);
+            } else if line.starts_with("msg") {
+                field = Some(Field::Other);
+            }
+        }
+        finish(&mut id, &mut translation, &mut fuzzy);
+
+        Catalog(translations)
+    }
+
+    /// The translation of the `message`, if it has been translated.
+    pub fn get(&self, message: &str) -> Option<&str> {
+        self.0.get(message).map(String::as_str)
+    }
+}
+
+/// The contents of a quoted string in a `.po` file, with its escape
+/// sequences replaced.
+fn unquote(quoted: &str) -> String {
+    let quoted = quoted.trim();
+    let inner = quoted
+        .strip_prefix('"')
+        .and_then(|quoted| quoted.strip_suffix('"'))
+        .unwrap_or(quoted);
+
+    let mut value = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('t') => value.push('\t'),
+            Some(other) => value.push(other),
+            None => {}
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Catalog;
+
+    #[test]
+    fn parse_catalog() {
+        let catalog = Catalog::parse(
+            r#"
+msgid ""
+msgstr ""
+"Language: ru\n"
+
+#: templates/tab.html.tera:15
+msgid "Members"
+msgstr "Участники"
+
+msgid "There are no "
+"expenses yet."
+msgstr "Расходов "
+"пока нет."
+
+#, fuzzy
+msgid "Date"
+msgstr "Дата"
+
+msgid "Amount"
+msgstr ""
+
+msgid "Say \"hello\""
+msgstr "Скажите \"привет\""
+"#,
+        );
+        assert_eq!(Some("Участники"), catalog.get("Members"));
+        assert_eq!(
+            Some("Расходов пока нет."),
+            catalog.get("There are no expenses yet.")
+        );
+        assert_eq!(None, catalog.get("Date"));
+        assert_eq!(None, catalog.get("Amount"));
+        assert_eq!(None, catalog.get(""));
+        assert_eq!(Some("Скажите \"привет\""), catalog.get("Say \"hello\""));
+    }
+
+    #[test]
+    fn load_catalogs() {
+        assert_eq!(Some("Участники"), Catalog::load("ru").get("Members"));
+        assert_eq!(None, Catalog::load("fr").get("Members"));
+    }
+}
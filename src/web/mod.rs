@@ -1 +1,576 @@
+//! Pages which are rendered on the server using the [tera] templates
+//! in `templates/`: the shell which the GUI boots itself in, a page
+//! for addresses which don't exist, and a read-only summary of a tab
+//! which can be viewed (and printed) without JavaScript, either by
+//! its members, or by anyone with a
+//! [ShareLink](crate::shares::ShareLink). Pages are localized using
+//! the language preferred by the browser's `Accept-Language` header.
 
+use crate::accounts::{session_token, AuthError};
+use crate::error::ErrorCode;
+use crate::metrics::route;
+use crate::rest::error_response;
+use crate::service::{ServiceError, TabService};
+use chrono::{NaiveDate, Utc};
+use costing::{CostingError, Tab, TabID, UserID};
+use rust_decimal::Decimal;
+use rust_embed::RustEmbed;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock};
+use tera::{Context, Tera, Value};
+use thiserror::Error;
+use warp::filters::BoxedFilter;
+use warp::http::header::{self, HeaderValue};
+use warp::http::StatusCode;
+use warp::path::FullPath;
+use warp::reply::{self, Response};
+use warp::{Filter, Rejection, Reply};
+
+mod catalog;
+
+use catalog::Catalog;
+
+#[derive(RustEmbed)]
+#[folder = "templates/"]
+struct Templates;
+
+/// The first segment of the paths handled by the GUI's router, which
+/// are served the [Pages::app_shell].
+const APP_PATHS: &[&str] = &["", "tab", "new", "help", "about", "join"];
+
+#[derive(Error, Debug)]
+pub enum WebError {
+    #[error("unable to render the page")]
+    Template(#[from] tera::Error),
+    #[error(transparent)]
+    Costing(#[from] CostingError),
+}
+
+impl ErrorCode for WebError {
+    fn code(&self) -> &'static str {
+        match self {
+            WebError::Template(_) => "TEMPLATE_ERROR",
+            WebError::Costing(error) => error.code(),
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            WebError::Template(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            WebError::Costing(error) => error.status(),
+        }
+    }
+}
+
+/// A language which the pages are translated into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    English,
+    Russian,
+}
+
+impl Language {
+    /// The language which the templates are written in, which is used
+    /// when the browser doesn't prefer any of the others.
+    pub const DEFAULT: Language = Language::English;
+
+    /// The BCP 47 language tag, which matches the locales in
+    /// `i18n.toml`.
+    pub fn tag(self) -> &'static str {
+        match self {
+            Language::English => "en-GB",
+            Language::Russian => "ru",
+        }
+    }
+
+    /// The language with the primary subtag of the language `tag`,
+    /// e.g. `ru` for `ru-RU`.
+    fn from_tag(tag: &str) -> Option<Language> {
+        let primary = tag.split('-').next().unwrap_or_default();
+        if primary.eq_ignore_ascii_case("en") {
+            Some(Language::English)
+        } else if primary.eq_ignore_ascii_case("ru") {
+            Some(Language::Russian)
+        } else {
+            None
+        }
+    }
+
+    /// The language most preferred in the value of an
+    /// `Accept-Language` header, e.g. `ru-RU,ru;q=0.9,en;q=0.8`.
+    pub fn negotiate(accept_language: Option<&str>) -> Language {
+        let mut preferences: Vec<(f32, Language)> = accept_language
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|preference| {
+                let mut parts = preference.split(';');
+                let language = Language::from_tag(parts.next()?.trim())?;
+                let quality = parts
+                    .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+                    .next()
+                    .map_or(Some(1.0), |quality| quality.trim().parse().ok())?;
+                Some((quality, language))
+            })
+            .filter(|(quality, _)| *quality > 0.0)
+            .collect();
+        // the sort is stable, so equally preferred languages stay in
+        // the order they were listed
+        preferences.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        preferences
+            .first()
+            .map_or(Language::DEFAULT, |(_, language)| *language)
+    }
+
+    /// The translations of the messages used in the templates, or
+    /// `None` for the language which they are written in.
+    fn catalog(self) -> Option<&'static Catalog> {
+        static RUSSIAN: LazyLock<Catalog> =
+            LazyLock::new(|| Catalog::load(Language::Russian.tag()));
+        match self {
+            Language::English => None,
+            Language::Russian => Some(&RUSSIAN),
+        }
+    }
+
+    /// The translation of the `message`, or the `message` itself if
+    /// it hasn't been translated.
+    pub fn translate(self, message: &str) -> &str {
+        self.catalog()
+            .and_then(|catalog| catalog.get(message))
+            .unwrap_or(message)
+    }
+}
+
+/// The `tr` filter, which translates a message in a template into the
+/// language with the tag `lang`, e.g. `{{ "Members" | tr(lang=lang) }}`.
+fn translate_filter(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let message = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("the tr filter can only translate strings"))?;
+    let language = args
+        .get("lang")
+        .and_then(Value::as_str)
+        .and_then(Language::from_tag)
+        .ok_or_else(|| tera::Error::msg("the tr filter needs the lang argument"))?;
+    Ok(Value::String(language.translate(message).to_string()))
+}
+
+/// A tab as it is shown on its summary page, with the users referred
+/// to by their names, and the balances and settlements calculated as
+/// of `as_of`.
+#[derive(Serialize, Debug)]
+struct TabSummary {
+    name: String,
+    working_currency: String,
+    members: Vec<String>,
+    expenses: Vec<ExpenseSummary>,
+    as_of: String,
+    balances: Vec<BalanceSummary>,
+    settlements: Vec<SettlementSummary>,
+}
+
+#[derive(Serialize, Debug)]
+struct ExpenseSummary {
+    date: String,
+    description: String,
+    paid_by: String,
+    shared_by: Vec<String>,
+    amount: String,
+}
+
+#[derive(Serialize, Debug)]
+struct BalanceSummary {
+    user: String,
+    /// `is owed`, `owes`, or `settled up`, which is translated in the
+    /// template.
+    status: &'static str,
+    /// The amount owed by or to the user, which is never negative.
+    amount: String,
+}
+
+#[derive(Serialize, Debug)]
+struct SettlementSummary {
+    sender: String,
+    receiver: String,
+    amount: String,
+}
+
+impl TabSummary {
+    fn new(tab: &Tab, as_of: NaiveDate) -> Result<Self, CostingError> {
+        let user_name = |id: &UserID| {
+            tab.user(id)
+                .map_or_else(|_| id.to_string(), |user| user.name.clone())
+        };
+        let mut expenses: Vec<ExpenseSummary> = tab
+            .expenses
+            .iter()
+            .map(|expense| ExpenseSummary {
+                date: expense.date.to_string(),
+                description: expense.description.clone(),
+                paid_by: user_name(&expense.paid_by),
+                shared_by: expense.shared_by.iter().map(user_name).collect(),
+                amount: expense.amount.to_string(),
+            })
+            .collect();
+        // ISO 8601 dates sort chronologically
+        expenses.sort_by(|a, b| a.date.cmp(&b.date));
+
+        let user_balances = tab.user_balances(as_of)?;
+        let balances = tab
+            .users
+            .iter()
+            .filter_map(|user| {
+                let amount = user_balances.get(&user.id)?;
+                let status = if amount.value == Decimal::new(0, 0) {
+                    "settled up"
+                } else if amount.value.is_sign_negative() {
+                    "owes"
+                } else {
+                    "is owed"
+                };
+                Some(BalanceSummary {
+                    user: user.name.clone(),
+                    status,
+                    amount: amount.abs().to_string(),
+                })
+            })
+            .collect();
+
+        let settlements = tab
+            .balance_transactions(as_of)?
+            .iter()
+            .map(|settlement| SettlementSummary {
+                sender: user_name(&settlement.sender),
+                receiver: user_name(&settlement.receiver),
+                amount: settlement.amount.to_string(),
+            })
+            .collect();
+
+        Ok(TabSummary {
+            name: tab.name.clone(),
+            working_currency: tab.working_currency.to_string(),
+            members: tab.users.iter().map(|user| user.name.clone()).collect(),
+            expenses,
+            as_of: as_of.to_string(),
+            balances,
+            settlements,
+        })
+    }
+}
+
+/// The templates embedded in the binary, ready to be rendered.
+#[derive(Clone)]
+pub struct Pages(Arc<Tera>);
+
+impl Pages {
+    /// Parse the embedded templates. They are named by their path
+    /// within `templates/` without the `.tera` extension, e.g.
+    /// `error/404.html`, which tells tera to escape their HTML.
+    pub fn load() -> Result<Self, WebError> {
+        let mut tera = Tera::default();
+        let templates = Templates::iter()
+            .filter_map(|path| {
+                let name = path.strip_suffix(".tera")?.to_string();
+                let source = Templates::get(&path)?;
+                Some((name, String::from_utf8_lossy(&source).into_owned()))
+            })
+            .collect::<Vec<_>>();
+        tera.add_raw_templates(templates)?;
+        tera.register_filter("tr", translate_filter);
+        Ok(Pages(Arc::new(tera)))
+    }
+
+    fn render(
+        &self,
+        name: &str,
+        language: Language,
+        context: &mut Context,
+    ) -> Result<String, WebError> {
+        context.insert("lang", language.tag());
+        Ok(self.0.render(name, context)?)
+    }
+
+    /// The page which the GUI boots itself in.
+    pub fn app_shell(&self, language: Language) -> Result<String, WebError> {
+        self.render("index.html", language, &mut Context::new())
+    }
+
+    /// The page for the `path` which doesn't exist.
+    pub fn not_found(&self, language: Language, path: &str) -> Result<String, WebError> {
+        let mut context = Context::new();
+        context.insert("path", path);
+        self.render("error/404.html", language, &mut context)
+    }
+
+    /// The read-only summary of the `tab`, with the balances as of
+    /// `as_of`.
+    pub fn tab_summary(
+        &self,
+        language: Language,
+        tab: &Tab,
+        as_of: NaiveDate,
+    ) -> Result<String, WebError> {
+        let mut context = Context::new();
+        context.insert("tab", &TabSummary::new(tab, as_of)?);
+        self.render("tab.html", language, &mut context)
+    }
+}
+
+/// Extracts the [Language] preferred by the client.
+fn language() -> impl Filter<Extract = (Language,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("accept-language")
+        .map(|accept_language: Option<String>| Language::negotiate(accept_language.as_deref()))
+}
+
+/// Matches `GET` requests, and `HEAD` requests, whose response body
+/// is left out by the server.
+pub(crate) fn get_or_head() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::get().or(warp::head()).unify()
+}
+
+fn html_response(page: Result<String, WebError>, status: StatusCode) -> Response {
+    match page {
+        Ok(html) => {
+            let mut response = reply::with_status(reply::html(html), status).into_response();
+            // the page is translated into the client's language
+            response
+                .headers_mut()
+                .append(header::VARY, HeaderValue::from_static("Accept-Language"));
+            response
+        }
+        Err(error) => error_response(&error),
+    }
+}
+
+/// The summary page of the `tab` as of the specified date, or the
+/// [Pages::not_found] page for the `path` if it can't be read by the
+/// client, so that whether the tab exists isn't revealed.
+fn tab_response(
+    tab: Result<Tab, ServiceError>,
+    as_of: NaiveDate,
+    pages: &Pages,
+    language: Language,
+    path: &FullPath,
+) -> Response {
+    match tab {
+        Ok(tab) => html_response(pages.tab_summary(language, &tab, as_of), StatusCode::OK),
+        Err(error) if error.status().is_client_error() => html_response(
+            pages.not_found(language, path.as_str()),
+            StatusCode::NOT_FOUND,
+        ),
+        Err(error) => error_response(&error),
+    }
+}
+
+/// The routes serving the pages, which need to be mounted after all
+/// the other routes, because any address which isn't matched by the
+/// GUI's router is given the [Pages::not_found] page.
+pub fn routes(service: TabService, pages: Pages) -> BoxedFilter<(Response,)> {
+    let with_pages = warp::any().map(move || pages.clone());
+
+    let app_shell = get_or_head()
+        .and(warp::path::full())
+        .and(language())
+        .and(with_pages.clone())
+        .and_then(
+            |path: FullPath, language: Language, pages: Pages| async move {
+                let first = path.as_str().trim_start_matches('/').split('/').next();
+                if !APP_PATHS.contains(&first.unwrap_or_default()) {
+                    return Err(warp::reject::not_found());
+                }
+                Ok(html_response(pages.app_shell(language), StatusCode::OK))
+            },
+        );
+
+    let member_service = service.clone();
+    let tab_summary = warp::path!("tabs" / TabID)
+        .and(get_or_head())
+        .and(session_token())
+        .and(warp::path::full())
+        .and(language())
+        .and(with_pages.clone())
+        .and_then(
+            move |id: TabID, token: Option<String>, path: FullPath, language, pages: Pages| {
+                let service = member_service.clone();
+                async move {
+                    let response = service
+                        .blocking(move |service| {
+                            let today = Utc::today().naive_utc();
+                            let tab = token
+                                .ok_or_else(|| ServiceError::from(AuthError::NotAuthenticated))
+                                .and_then(|token| service.authenticate(&token))
+                                .and_then(|account| service.tab_as_of(&account, &id, today));
+                            tab_response(tab, today, &pages, language, &path)
+                        })
+                        .await;
+                    Ok::<_, Rejection>(response)
+                }
+            },
+        );
+
+    // the path of a share link
+    let shared_tab = warp::path!("shared" / String)
+        .and(get_or_head())
+        .and(warp::path::full())
+        .and(language())
+        .and(with_pages.clone())
+        .and_then(
+            move |token: String, path: FullPath, language, pages: Pages| {
+                let service = service.clone();
+                async move {
+                    let mut response = service
+                        .blocking(move |service| {
+                            let today = Utc::today().naive_utc();
+                            let tab = service.shared_tab(&token, today);
+                            tab_response(tab, today, &pages, language, &path)
+                        })
+                        .await;
+                    // the token in the address is a secret, so the page
+                    // shouldn't be indexed if the link is published
+                    response.headers_mut().insert(
+                        "x-robots-tag",
+                        HeaderValue::from_static("noindex, nofollow"),
+                    );
+                    Ok::<_, Rejection>(response)
+                }
+            },
+        );
+
+    let not_found = warp::path::full().and(language()).and(with_pages).map(
+        |path: FullPath, language, pages: Pages| {
+            html_response(
+                pages.not_found(language, path.as_str()),
+                StatusCode::NOT_FOUND,
+            )
+        },
+    );
+
+    route("index", app_shell)
+        .or(route("tab_summary", tab_summary))
+        .unify()
+        .or(route("shared_tab", shared_tab))
+        .unify()
+        .or(route("not_found", not_found))
+        .unify()
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{routes, Language, Pages};
+    use crate::events::TabEvents;
+    use crate::repository::{tests::create_test_tab, KeyValueDBRepository};
+    use crate::service::TabService;
+    use std::sync::Arc;
+
+    #[test]
+    fn negotiate_language() {
+        assert_eq!(Language::English, Language::negotiate(None));
+        assert_eq!(Language::English, Language::negotiate(Some("fr-FR, de")));
+        assert_eq!(
+            Language::Russian,
+            Language::negotiate(Some("ru-RU,ru;q=0.9"))
+        );
+        assert_eq!(
+            Language::English,
+            Language::negotiate(Some("ru;q=0.5, en-US;q=0.8"))
+        );
+        assert_eq!(
+            Language::English,
+            Language::negotiate(Some("ru;q=0, en-AU"))
+        );
+        assert_eq!("Участники", Language::Russian.translate("Members"));
+        assert_eq!("Members", Language::English.translate("Members"));
+    }
+
+    #[tokio::test]
+    async fn render_pages() {
+        let service = TabService::new(
+            Arc::new(KeyValueDBRepository::in_memory()),
+            TabEvents::new(),
+        );
+        let (account, session) = service
+            .register("Alice", "alice@example.com", "password123")
+            .unwrap();
+        let tab = create_test_tab();
+        service.add_tab(&account, &tab, 1).unwrap();
+        let link = service.create_share_link(&account, &tab.id).unwrap();
+        let pages = Pages::load().unwrap();
+        let routes = routes(service, pages);
+
+        let response = warp::test::request()
+            .path("/join/abc")
+            .header("accept-language", "ru")
+            .reply(&routes)
+            .await;
+        assert_eq!(200, response.status());
+        assert_eq!("Accept-Language", response.headers()["vary"]);
+        let body = String::from_utf8_lossy(response.body());
+        assert!(body.contains("<html lang=\"ru\">"), "{}", body);
+        assert!(body.contains("/dist/js/gui/gui.js"), "{}", body);
+        assert!(
+            body.contains("Для работы Coster нужен JavaScript"),
+            "{}",
+            body
+        );
+
+        let response = warp::test::request()
+            .path("/missing&path")
+            .reply(&routes)
+            .await;
+        assert_eq!(404, response.status());
+        let body = String::from_utf8_lossy(response.body());
+        assert!(
+            body.contains("<title>Page not found - Coster</title>"),
+            "{}",
+            body
+        );
+        assert!(body.contains("&amp;path</code>"), "{}", body);
+
+        let path = format!("/tabs/{}", tab.id);
+        let response = warp::test::request().path(&path).reply(&routes).await;
+        assert_eq!(404, response.status());
+
+        let response = warp::test::request()
+            .path(&path)
+            .header("cookie", format!("coster_session={}", session.token))
+            .reply(&routes)
+            .await;
+        assert_eq!(200, response.status());
+        let body = String::from_utf8_lossy(response.body());
+        assert!(
+            body.contains("<title>Road Trip - Coster</title>"),
+            "{}",
+            body
+        );
+        assert!(body.contains("<li>User 2</li>"), "{}", body);
+        assert!(body.contains("<td>Petrol</td>"), "{}", body);
+        assert!(body.contains("<td>User 1, User 2</td>"), "{}", body);
+        assert!(body.contains("<td>is owed</td>"), "{}", body);
+
+        // anyone with a share link can read the tab
+        let response = warp::test::request()
+            .path(&link.path())
+            .header("accept-language", "ru")
+            .reply(&routes)
+            .await;
+        assert_eq!(200, response.status());
+        assert_eq!("noindex, nofollow", response.headers()["x-robots-tag"]);
+        let body = String::from_utf8_lossy(response.body());
+        assert!(body.contains("<td>к оплате</td>"), "{}", body);
+        assert!(body.contains("/dist/css/print.css"), "{}", body);
+        let settlements = &body[body.find("Взаиморасчёты").unwrap()..];
+        assert!(
+            settlements.contains("<td>User 2</td>\n                            <td>User 1</td>"),
+            "{}",
+            body
+        );
+
+        let response = warp::test::request()
+            .path("/shared/invalid")
+            .reply(&routes)
+            .await;
+        assert_eq!(404, response.status());
+    }
+}